@@ -2,7 +2,12 @@ use proc_macro::{self, TokenStream};
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput, Data, Fields, Expr, Attribute, ExprAssign, Path};
 
+/// Splits `#[bin_ser(name = value)]` into name and value. A bare
+/// `#[bin_ser(name)]` is a flag and stands for `name = true`.
 fn parse_attr(attr: &Attribute) -> (Path, Expr) {
+    if let Ok(Expr::Path(path)) = attr.parse_args::<Expr>() {
+        return (path.path, syn::parse_quote!(true));
+    }
     let e: ExprAssign = attr.parse_args().unwrap();
     let path = if let Expr::Path(path) = *e.left { path.path } else { panic!(); };
     (path, *e.right)
@@ -41,6 +46,17 @@ pub fn derive_serialize(input: TokenStream) -> TokenStream {
             let repr = get_attr(&attrs, "repr").expect("need repr attr");
             let variant = enum_data.variants.iter().map(|v| {
                 let variantname = &v.ident;
+                if get_attr(&v.attrs, "fallback").is_some() {
+                    // The first field holds the tag, so it is written in its place.
+                    let fields = fallback_fields(&v.fields);
+                    let field = fields.iter().map(|f| &f.ident);
+                    let serialize_fields = field.clone();
+                    return quote! {
+                        #ident::#variantname { #( #field ),* } => {
+                            #( Serialize::serialize(#serialize_fields, writer)?; )*
+                        }
+                    };
+                }
                 let variantval = get_attr(&v.attrs, "val").expect("need val attr");
                 match v.fields {
                     Fields::Named(ref named_fields) => {
@@ -86,21 +102,52 @@ pub fn derive_serialize(input: TokenStream) -> TokenStream {
     output.into()
 }
 
+/// Fields of a `#[bin_ser(fallback = true)]` variant. It catches every tag
+/// no other variant matches; its first field is built from the tag with
+/// `From` and serialized in place of the tag.
+fn fallback_fields(fields: &Fields) -> Vec<&syn::Field> {
+    match fields {
+        Fields::Named(ref named_fields) if !named_fields.named.is_empty() => {
+            named_fields.named.iter().collect()
+        },
+        _ => panic!("fallback variant needs named fields, the first one holding the tag"),
+    }
+}
+
+/// Reads one field. Fields marked `#[bin_ser(optional)]` are left at their
+/// `Default` if the input ends before them, so peers that do not send them
+/// yet can still be understood. Only trailing fields may be optional.
+fn deserialize_field_values<'a>(fields: impl Iterator<Item = &'a syn::Field>) -> Vec<proc_macro2::TokenStream> {
+    let mut optional_seen = false;
+    fields.map(|f| {
+        if get_attr(&f.attrs, "optional").is_some() {
+            optional_seen = true;
+            quote! {
+                if input.is_empty() { ::std::default::Default::default() } else { Deserialize::deserialize(input)? }
+            }
+        } else {
+            assert!(!optional_seen, "only trailing fields can be optional");
+            quote!(Deserialize::deserialize(input)?)
+        }
+    }).collect()
+}
+
 fn deserialize_fields(fields: &Fields) -> proc_macro2::TokenStream {
     match fields {
         Fields::Named(ref named_fields) => {
             let name = named_fields.named.iter().map(|f| &f.ident);
+            let value = deserialize_field_values(named_fields.named.iter());
             quote! {
                 {
-                    #( #name: Deserialize::deserialize(input)? ),*
+                    #( #name: #value ),*
                 }
             }
         },
         Fields::Unnamed(ref unnamed_fields) => {
-            let name = unnamed_fields.unnamed.iter().map(|_| quote!(Deserialize::deserialize(input)?));
+            let value = deserialize_field_values(unnamed_fields.unnamed.iter());
             quote! {
                 (
-                    #( #name ),*
+                    #( #value ),*
                 )
             }
         },
@@ -123,20 +170,41 @@ pub fn derive_deserialize(input: TokenStream) -> TokenStream {
         },
         Data::Enum(ref enum_data) => {
             let repr = get_attr(&attrs, "repr").expect("need repr attr");
-            let variant = enum_data.variants.iter().map(|v| {
-                let variantname = &v.ident;
-                let variantval = get_attr(&v.attrs, "val").expect("need val attr");
-                let f = deserialize_fields(&v.fields);
-                quote! {
-                    #variantval => {
-                        #ident::#variantname #f
+            let variant = enum_data.variants.iter()
+                .filter(|v| get_attr(&v.attrs, "fallback").is_none())
+                .map(|v| {
+                    let variantname = &v.ident;
+                    let variantval = get_attr(&v.attrs, "val").expect("need val attr");
+                    let f = deserialize_fields(&v.fields);
+                    quote! {
+                        #variantval => {
+                            #ident::#variantname #f
+                        }
                     }
-                }
-            });
+                });
+            let fallback = match enum_data.variants.iter().find(|v| get_attr(&v.attrs, "fallback").is_some()) {
+                Some(v) => {
+                    let variantname = &v.ident;
+                    let fields = fallback_fields(&v.fields);
+                    let tag = &fields[0].ident;
+                    let rest = fields[1..].iter().map(|f| &f.ident);
+                    quote! {
+                        other => {
+                            #ident::#variantname {
+                                #tag: ::std::convert::From::from(other),
+                                #( #rest: Deserialize::deserialize(input)? ),*
+                            }
+                        }
+                    }
+                },
+                None => quote! {
+                    _ => return Err(anyhow::anyhow!("unknown enum variant")),
+                },
+            };
             quote! {
                 match <#repr>::deserialize(input)? {
                     #( #variant ),*
-                    _ => panic!("unknown enum variant"),
+                    #fallback
                 }
             }
         },
@@ -0,0 +1,553 @@
+//! An SFTP version 3 client over any byte stream, such as the `sftp`
+//! subsystem of an SSH channel.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use sha2::Digest;
+use anyhow::{anyhow, Result};
+
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+
+/// Largest response accepted from the server, like OpenSSH's client does.
+const MAX_PACKET_SIZE: u32 = 256 * 1024;
+
+/// Bytes sent per `Write` request and asked for per `Read` request.
+const CHUNK_LEN: usize = 32 * 1024;
+
+/// `Read` requests a download keeps outstanding at once.
+const READ_WINDOW: usize = 16;
+
+/// A request the server answered with a status other than `Ok`.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status_code: StatusCode,
+    pub error_message: String,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.status_code, self.error_message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+pub struct SftpClient<S> {
+    stream: S,
+    codec: SftpCodec,
+    /// Packets decoded from the stream but not returned by `recv` yet.
+    received: VecDeque<Vec<u8>>,
+    next_id: u32,
+    extensions: Vec<Extension>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SftpClient<S> {
+    /// Starts a session on `stream`, which must not have been used yet.
+    pub async fn new(stream: S) -> Result<Self> {
+        let mut client = Self {
+            stream,
+            codec: SftpCodec::new(MAX_PACKET_SIZE),
+            received: VecDeque::new(),
+            next_id: 0,
+            extensions: Vec::new(),
+        };
+        client.send(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await?;
+        match client.recv().await? {
+            SftpServerPacket::Version { extensions, .. } => client.extensions = extensions.0,
+            resp => return Err(unexpected(resp)),
+        }
+        Ok(client)
+    }
+
+    /// Extensions the server advertised.
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// The data the server advertised extension `name` with, if it did.
+    pub fn extension_data(&self, name: &str) -> Option<&str> {
+        self.extensions.iter()
+            .find(|ext| ext.name == name)
+            .map(|ext| ext.data.as_str())
+    }
+
+    /// Allocates an id for a request sent with `send`.
+    pub fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    pub async fn send(&mut self, packet: SftpClientPacket) -> Result<()> {
+        let buf = SftpCodec::encode(&packet)?;
+        self.stream.write_all(&buf).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Waits for the next packet from the server.
+    pub async fn recv(&mut self) -> Result<SftpServerPacket> {
+        let mut buf = vec![0; CHUNK_LEN];
+        while self.received.is_empty() {
+            let len = self.stream.read(&mut buf).await?;
+            if len == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            self.received.extend(self.codec.decode(&buf[..len])?);
+        }
+        let packet = self.received.pop_front().unwrap();
+        deserialize_packet(&packet)
+    }
+
+    /// Sends the request `build` makes for a fresh id and waits for the
+    /// answer. Only one request is outstanding at a time.
+    async fn request(&mut self, build: impl FnOnce(u32) -> SftpClientPacket) -> Result<SftpServerPacket> {
+        let id = self.next_id();
+        self.send(build(id)).await?;
+        let resp = self.recv().await?;
+        match response_id(&resp) {
+            Some(resp_id) if resp_id == id => Ok(resp),
+            _ => Err(unexpected(resp)),
+        }
+    }
+
+    pub async fn open(&mut self, filename: &str, pflags: Pflags, attrs: Attrs) -> Result<Handle> {
+        let filename = filename.to_string();
+        match self.request(|id| SftpClientPacket::Open { id, filename, pflags, attrs }).await? {
+            SftpServerPacket::Handle { handle, .. } => Ok(handle),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    pub async fn close(&mut self, handle: &str) -> Result<()> {
+        let handle = handle.to_string();
+        expect_ok(self.request(|id| SftpClientPacket::Close { id, handle }).await?)
+    }
+
+    /// Reads up to `len` bytes at `offset`. Returns an empty buffer at the
+    /// end of the file.
+    pub async fn read(&mut self, handle: &str, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let handle = handle.to_string();
+        match self.request(|id| SftpClientPacket::Read { id, handle, offset, len }).await? {
+            SftpServerPacket::Data { data, .. } => Ok(data.0),
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => Ok(Vec::new()),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// Like `read`, but also tells whether the data reaches the end of the
+    /// file, using the `read-eof@thrusftp` extension.
+    pub async fn read_eof(&mut self, handle: &str, offset: u64, len: u32) -> Result<(Vec<u8>, bool)> {
+        let extended_request = ExtendedRequest::ThrusftpReadEof { handle: handle.to_string(), offset, len };
+        match self.request(|id| SftpClientPacket::Extended { id, extended_request }).await? {
+            SftpServerPacket::ExtendedReply { data, .. } => {
+                let reply: ReadEofReply = deserialize_packet(&data.0)?;
+                Ok((reply.data.0, reply.eof))
+            },
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => Ok((Vec::new(), true)),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// Reads `len` bytes at `offset`, in as many requests as that takes.
+    /// Returns less only where the file ends first, and nothing if it ends
+    /// before `offset`.
+    pub async fn read_range(&mut self, handle: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_range_to(handle, offset, len, &mut data).await?;
+        Ok(data)
+    }
+
+    /// Writes `len` bytes at `offset` of `handle` to `writer`, one read at a
+    /// time so they arrive in order. Returns the number of bytes written.
+    async fn read_range_to<W: AsyncWrite + Unpin>(&mut self, handle: &str, offset: u64, len: u64, writer: &mut W) -> Result<u64> {
+        let end = offset.saturating_add(len);
+        let mut pos = offset;
+        while pos < end {
+            let len = (CHUNK_LEN as u64).min(end - pos) as u32;
+            let data = self.read(handle, pos, len).await?;
+            if data.is_empty() {
+                break;
+            }
+            if data.len() > len as usize {
+                return Err(anyhow!("server sent {} bytes for a read of {}", data.len(), len));
+            }
+            writer.write_all(&data).await?;
+            // Short reads are not necessarily the end; the next read tells.
+            pos += data.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(pos - offset)
+    }
+
+    pub async fn write(&mut self, handle: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+        let handle = handle.to_string();
+        let data = data.into();
+        expect_ok(self.request(|id| SftpClientPacket::Write { id, handle, offset, data }).await?)
+    }
+
+    pub async fn stat(&mut self, path: &str) -> Result<Attrs> {
+        let path = path.to_string();
+        match self.request(|id| SftpClientPacket::Stat { id, path }).await? {
+            SftpServerPacket::Attrs { attrs, .. } => Ok(attrs),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    pub async fn fstat(&mut self, handle: &str) -> Result<Attrs> {
+        let handle = handle.to_string();
+        match self.request(|id| SftpClientPacket::Fstat { id, handle }).await? {
+            SftpServerPacket::Attrs { attrs, .. } => Ok(attrs),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// The next entries of the directory `path`, using the
+    /// `readdir-from@thrusftp` extension, with the cookie to pass to get the
+    /// ones after them. Start with an empty cookie. Cookies stay valid in
+    /// later sessions, so a listing can go on after a reconnect. Returns
+    /// `None` once there are no more entries.
+    pub async fn readdir_from(&mut self, path: &str, cookie: &str) -> Result<Option<(Vec<Name>, String)>> {
+        let extended_request = ExtendedRequest::ThrusftpReaddirFrom { path: path.to_string(), cookie: cookie.to_string() };
+        match self.request(|id| SftpClientPacket::Extended { id, extended_request }).await? {
+            SftpServerPacket::ExtendedReply { data, .. } => {
+                let reply: ReaddirFromReply = deserialize_packet(&data.0)?;
+                Ok(Some((reply.names, reply.cookie)))
+            },
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => Ok(None),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// Hashes `length` bytes from `start_offset` of an open file on the
+    /// server, using the `check-file-handle` extension.
+    pub async fn check_file_handle(&mut self, handle: &str, algorithm: HashAlgorithm, start_offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.check_file(handle, algorithm, start_offset, length, 0).await
+    }
+
+    /// Like `check_file_handle`, but hashes each `block_size` bytes of the
+    /// range separately, so a client can tell which parts of a local copy
+    /// differ. The last block may be shorter. `block_size` must be at least
+    /// 256, and the server refuses ranges with more blocks than fit into
+    /// one response.
+    pub async fn check_file_blocks(&mut self, handle: &str, algorithm: HashAlgorithm, start_offset: u64, length: u64, block_size: u32) -> Result<Vec<Vec<u8>>> {
+        if block_size == 0 {
+            return Err(anyhow!("block size must not be zero"));
+        }
+        let hashes = self.check_file(handle, algorithm, start_offset, length, block_size).await?;
+        if hashes.len() % algorithm.digest_len() != 0 {
+            return Err(anyhow!("server sent a partial digest"));
+        }
+        Ok(hashes.chunks(algorithm.digest_len()).map(|hash| hash.to_vec()).collect())
+    }
+
+    async fn check_file(&mut self, handle: &str, algorithm: HashAlgorithm, start_offset: u64, length: u64, block_size: u32) -> Result<Vec<u8>> {
+        let extended_request = ExtendedRequest::CheckFileHandle {
+            handle: handle.to_string(),
+            hash_algorithms: algorithm.name().to_string(),
+            start_offset,
+            length,
+            block_size,
+        };
+        match self.request(|id| SftpClientPacket::Extended { id, extended_request }).await? {
+            SftpServerPacket::ExtendedReply { data, .. } => {
+                let reply: CheckFileReply = deserialize_packet(&data.0)?;
+                if reply.algorithm != algorithm.name() {
+                    return Err(anyhow!("server hashed with {} instead of {}", reply.algorithm, algorithm.name()));
+                }
+                Ok(reply.hashes.0)
+            },
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// Offset of the next data or hole at or after `offset` of an open file,
+    /// using the `seek-hole-data@thrusftp` extension. Returns `None` if
+    /// there is no more data.
+    pub async fn seek_hole_data(&mut self, handle: &str, offset: u64, whence: SeekWhence) -> Result<Option<u64>> {
+        let extended_request = ExtendedRequest::ThrusftpSeekHoleData { handle: handle.to_string(), offset, whence };
+        match self.request(|id| SftpClientPacket::Extended { id, extended_request }).await? {
+            SftpServerPacket::ExtendedReply { data, .. } => {
+                Ok(Some(deserialize_packet::<SeekHoleDataReply>(&data.0)?.offset))
+            },
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => Ok(None),
+            resp => Err(status_error(resp)),
+        }
+    }
+
+    /// Downloads `remote_path` to `local_path`, replacing it, and keeps the
+    /// copy sparse. Returns the number of bytes transferred.
+    ///
+    /// If the server offers `seek-hole-data@thrusftp`, only the data regions
+    /// are read. Otherwise the whole file is read, but chunks of zeroes are
+    /// still left as holes locally. If it offers `read-eof@thrusftp`, reads
+    /// use it to stop at the end of the file without a read past it.
+    pub async fn download<P: AsRef<Path>>(&mut self, remote_path: &str, local_path: P) -> Result<u64> {
+        let mut local = tokio::fs::File::create(local_path).await?;
+        let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+        let handle = self.open(remote_path, pflags, Attrs::default()).await?;
+        let res = self.download_handle(&mut local, &handle).await;
+        let closed = self.close(&handle).await;
+        let received = res?;
+        closed?;
+        Ok(received)
+    }
+
+    /// Downloads `len` bytes at `offset` of `remote_path` to `writer`, as
+    /// for seeking in media or retrying part of a transfer. Returns the
+    /// number of bytes written, which is less than `len` if the file ends
+    /// first.
+    pub async fn download_range<W: AsyncWrite + Unpin>(&mut self, remote_path: &str, offset: u64, len: u64, writer: &mut W) -> Result<u64> {
+        let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+        let handle = self.open(remote_path, pflags, Attrs::default()).await?;
+        let res = self.read_range_to(&handle, offset, len, writer).await;
+        let closed = self.close(&handle).await;
+        let received = res?;
+        closed?;
+        Ok(received)
+    }
+
+    async fn download_handle(&mut self, local: &mut tokio::fs::File, handle: &str) -> Result<u64> {
+        let size = self.fstat(handle).await?.size.unwrap_or(0);
+        let mut received = 0;
+        if self.extension_data("seek-hole-data@thrusftp").is_some() {
+            let mut pos = 0;
+            while let Some(start) = self.seek_hole_data(handle, pos, SeekWhence::Data).await? {
+                let end = self.seek_hole_data(handle, start, SeekWhence::Hole).await?.unwrap_or(size);
+                received += self.copy_range(local, handle, start, end).await?;
+                pos = end;
+            }
+        } else {
+            received = self.copy_range(local, handle, 0, u64::MAX).await?;
+        }
+        // Holes at the end are not written, so set the length explicitly.
+        local.set_len(size.max(local.metadata().await?.len())).await?;
+        local.flush().await?;
+        Ok(received)
+    }
+
+    /// Copies `start..end` of `handle` to the same offsets of `local`,
+    /// stopping early at the end of the remote file. Chunks that are all
+    /// zeroes are skipped rather than written.
+    ///
+    /// Up to `READ_WINDOW` reads are kept outstanding, and the server may
+    /// answer them in any order, so every response is written at the offset
+    /// its request id asked for. If a read comes back short, the rest of its
+    /// range is asked for again. Once a read hits the end of the file, no
+    /// reads past it are sent any more and the outstanding ones are only
+    /// drained. After a failed read, all of them are drained before the
+    /// error is returned, so the session stays usable.
+    async fn copy_range(&mut self, local: &mut tokio::fs::File, handle: &str, start: u64, mut end: u64) -> Result<u64> {
+        let read_eof = self.extension_data("read-eof@thrusftp").is_some();
+        // Offset and length each outstanding read asked for, by request id.
+        let mut outstanding: HashMap<u32, (u64, u32)> = HashMap::new();
+        // Rests of short reads, to be asked for before anything new.
+        let mut short: Vec<(u64, u32)> = Vec::new();
+        let mut next = start;
+        let mut received = 0;
+        let mut error = None;
+        loop {
+            while error.is_none() && outstanding.len() < READ_WINDOW {
+                let (offset, len) = match short.pop() {
+                    Some((offset, len)) if offset < end => (offset, (len as u64).min(end - offset) as u32),
+                    Some(_) => continue,
+                    None if next < end => {
+                        let len = (CHUNK_LEN as u64).min(end - next) as u32;
+                        next += len as u64;
+                        (next - len as u64, len)
+                    },
+                    None => break,
+                };
+                let id = self.next_id();
+                let handle = handle.to_string();
+                let request = if read_eof {
+                    SftpClientPacket::Extended { id, extended_request: ExtendedRequest::ThrusftpReadEof { handle, offset, len } }
+                } else {
+                    SftpClientPacket::Read { id, handle, offset, len }
+                };
+                self.send(request).await?;
+                outstanding.insert(id, (offset, len));
+            }
+            if outstanding.is_empty() {
+                break;
+            }
+
+            let resp = self.recv().await?;
+            let (offset, len) = match response_id(&resp).and_then(|id| outstanding.remove(&id)) {
+                Some(range) => range,
+                None => return Err(unexpected(resp)),
+            };
+            let (data, eof) = match resp {
+                SftpServerPacket::Data { data, .. } if data.0.len() <= len as usize => (data.0, false),
+                SftpServerPacket::ExtendedReply { data, .. } if read_eof => {
+                    match deserialize_packet::<ReadEofReply>(&data.0) {
+                        Ok(reply) if reply.data.0.len() <= len as usize => (reply.data.0, reply.eof),
+                        _ => {
+                            error.get_or_insert(anyhow!("malformed read-eof@thrusftp reply"));
+                            continue;
+                        },
+                    }
+                },
+                SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => {
+                    end = end.min(offset);
+                    continue;
+                },
+                resp => {
+                    error.get_or_insert(status_error(resp));
+                    continue;
+                },
+            };
+            if data.is_empty() {
+                end = end.min(offset);
+                continue;
+            }
+            // Data from past an end of file seen in the meantime would leave
+            // a gap before it, so it is dropped.
+            if offset >= end {
+                continue;
+            }
+            if data.iter().any(|&byte| byte != 0) {
+                local.seek(SeekFrom::Start(offset)).await?;
+                local.write_all(&data).await?;
+            }
+            received += data.len() as u64;
+            if eof {
+                end = end.min(offset + data.len() as u64);
+            } else if data.len() < len as usize {
+                short.push((offset + data.len() as u64, len - data.len() as u32));
+            }
+        }
+        match error {
+            Some(err) => Err(err),
+            None => Ok(received),
+        }
+    }
+
+    /// Uploads the rest of `local_path` to `remote_path`, continuing where
+    /// an earlier, interrupted upload stopped. The remote file is created if
+    /// it does not exist. Returns the number of bytes sent.
+    ///
+    /// Before anything is sent, the part already on the server is compared
+    /// with the local file: by hash if the server offers SHA-256 through
+    /// `check-file-handle`, otherwise by reading it back. If it differs, or
+    /// is longer than the local file, nothing is written and the upload
+    /// fails with `ErrorKind::InvalidData`.
+    pub async fn resume_upload<P: AsRef<Path>>(&mut self, local_path: P, remote_path: &str) -> Result<u64> {
+        let mut local = tokio::fs::File::open(local_path).await?;
+        let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+        let handle = self.open(remote_path, pflags, Attrs::default()).await?;
+        let res = self.resume_upload_handle(&mut local, &handle).await;
+        let closed = self.close(&handle).await;
+        let sent = res?;
+        closed?;
+        Ok(sent)
+    }
+
+    async fn resume_upload_handle(&mut self, local: &mut tokio::fs::File, handle: &str) -> Result<u64> {
+        // The size is taken from the open handle, so the file cannot be
+        // replaced between looking at it and writing to it.
+        let offset = self.fstat(handle).await?.size.unwrap_or(0);
+        if offset > local.metadata().await?.len() {
+            return Err(diverged());
+        }
+        self.verify_prefix(local, handle, offset).await?;
+
+        local.seek(SeekFrom::Start(offset)).await?;
+        let mut pos = offset;
+        let mut buf = vec![0; CHUNK_LEN];
+        loop {
+            let len = local.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            self.write(handle, pos, buf[..len].to_vec()).await?;
+            pos += len as u64;
+        }
+        Ok(pos - offset)
+    }
+
+    /// Checks that the first `len` bytes of `handle` match `local`.
+    async fn verify_prefix(&mut self, local: &mut tokio::fs::File, handle: &str, len: u64) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        local.seek(SeekFrom::Start(0)).await?;
+        let mut buf = vec![0; CHUNK_LEN];
+
+        let sha256 = HashAlgorithm::Sha256.name();
+        let hashed = self.extension_data("check-file-handle")
+            .is_some_and(|algorithms| algorithms.split(',').any(|name| name == sha256));
+        if hashed {
+            let remote = self.check_file_handle(handle, HashAlgorithm::Sha256, 0, len).await?;
+            let mut hasher = sha2::Sha256::new();
+            let mut pos = 0;
+            while pos < len {
+                let chunk = CHUNK_LEN.min((len - pos) as usize);
+                local.read_exact(&mut buf[..chunk]).await?;
+                hasher.update(&buf[..chunk]);
+                pos += chunk as u64;
+            }
+            if hasher.finalize().as_slice() != &remote[..] {
+                return Err(diverged());
+            }
+            return Ok(());
+        }
+
+        let mut pos = 0;
+        while pos < len {
+            let chunk = CHUNK_LEN.min((len - pos) as usize);
+            let remote = self.read(handle, pos, chunk as u32).await?;
+            if remote.is_empty() {
+                return Err(diverged());
+            }
+            local.read_exact(&mut buf[..remote.len()]).await?;
+            if buf[..remote.len()] != remote[..] {
+                return Err(diverged());
+            }
+            pos += remote.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+fn diverged() -> anyhow::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "remote file does not match the local one").into()
+}
+
+fn response_id(packet: &SftpServerPacket) -> Option<u32> {
+    match *packet {
+        SftpServerPacket::Version { .. } => None,
+        SftpServerPacket::Status { id, .. }
+        | SftpServerPacket::Handle { id, .. }
+        | SftpServerPacket::Data { id, .. }
+        | SftpServerPacket::Name { id, .. }
+        | SftpServerPacket::Attrs { id, .. }
+        | SftpServerPacket::ExtendedReply { id, .. } => Some(id),
+    }
+}
+
+fn unexpected(packet: SftpServerPacket) -> anyhow::Error {
+    anyhow!("unexpected response from server: {:?}", packet)
+}
+
+/// The error a `Status` response stands for, for requests that expected
+/// something else.
+fn status_error(packet: SftpServerPacket) -> anyhow::Error {
+    match packet {
+        SftpServerPacket::Status { status_code, error_message, .. } => {
+            StatusError { status_code, error_message }.into()
+        },
+        packet => unexpected(packet),
+    }
+}
+
+fn expect_ok(packet: SftpServerPacket) -> Result<()> {
+    match packet {
+        SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. } => Ok(()),
+        packet => Err(status_error(packet)),
+    }
+}
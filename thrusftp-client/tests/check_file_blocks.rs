@@ -0,0 +1,42 @@
+mod common;
+
+use sha2::{Digest, Sha256};
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use common::connect;
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+#[tokio::test]
+async fn check_file_blocks() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let data: Vec<u8> = (0..10_000).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(dir.join("file"), &data).unwrap();
+
+    let mut client = connect(LocalFs::new(dir)).await;
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let handle = client.open("/file", pflags.clone(), Attrs::default()).await.unwrap();
+
+    // The whole file, the last block shorter than the others.
+    let hashes = client.check_file_blocks(&handle, HashAlgorithm::Sha256, 0, 0, 4096).await.unwrap();
+    let expected: Vec<_> = data.chunks(4096).map(sha256).collect();
+    assert_eq!(hashes, expected);
+
+    // A range reaching past the end stops at the end.
+    let hashes = client.check_file_blocks(&handle, HashAlgorithm::Sha256, 9000, 5000, 512).await.unwrap();
+    let expected: Vec<_> = data[9000..].chunks(512).map(sha256).collect();
+    assert_eq!(hashes, expected);
+    assert!(client.check_file_blocks(&handle, HashAlgorithm::Sha256, 20_000, 0, 512).await.unwrap().is_empty());
+
+    // Too small blocks, and more digests than fit into a response.
+    assert!(client.check_file_blocks(&handle, HashAlgorithm::Sha256, 0, 0, 255).await.is_err());
+    let big = std::fs::File::create(dir.join("big")).unwrap();
+    big.set_len(1 << 30).unwrap();
+    let big = client.open("/big", pflags, Attrs::default()).await.unwrap();
+    let err = client.check_file_blocks(&big, HashAlgorithm::Sha256, 0, 0, 256).await.unwrap_err();
+    assert!(err.to_string().contains("Too many blocks"), "{}", err);
+}
@@ -0,0 +1,18 @@
+//! Helpers shared by the integration tests.
+
+use tokio::io::DuplexStream;
+
+use thrusftp_client::SftpClient;
+use thrusftp_protocol::Fs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+
+/// Serves `fs` on one end of a pipe and returns a client on the other.
+pub async fn connect<T: Fs + Send + Sync + 'static>(fs: T) -> SftpClient<DuplexStream> {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let (reader, writer) = tokio::io::split(server);
+        serve_stream(&SftpServer::new(fs), reader, writer).await
+    });
+    SftpClient::new(client).await.unwrap()
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::os::unix::fs::MetadataExt;
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use thrusftp_protocol::{Fs, FsHandle};
+use common::connect;
+
+const MIB: u64 = 1024 * 1024;
+
+/// A 4 MiB file with data only around 1 MiB and in its last byte.
+fn write_sparse(path: &std::path::Path) -> Vec<u8> {
+    let file = std::fs::File::create(path).unwrap();
+    file.set_len(4 * MIB).unwrap();
+    let mut expected = vec![0; 4 * MIB as usize];
+    for (offset, data) in [(MIB, vec![7; 10_000]), (4 * MIB - 1, vec![9])] {
+        std::os::unix::fs::FileExt::write_all_at(&file, &data, offset).unwrap();
+        expected[offset as usize..offset as usize + data.len()].copy_from_slice(&data);
+    }
+    expected
+}
+
+#[tokio::test]
+async fn download_skips_holes() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let remote = dir.join("remote");
+    let local = dir.join("local");
+    let expected = write_sparse(&remote);
+
+    let mut client = connect(LocalFs::default()).await;
+    assert!(client.extension_data("seek-hole-data@thrusftp").is_some());
+    let received = client.download(&remote.to_string_lossy(), &local).await.unwrap();
+    assert_eq!(std::fs::read(&local).unwrap(), expected);
+    // Filesystems without holes report everything as data; where there are
+    // holes, they are neither transferred nor written.
+    if std::fs::metadata(&remote).unwrap().blocks() * 512 < MIB {
+        assert!(received < MIB, "received {} bytes", received);
+        assert!(std::fs::metadata(&local).unwrap().blocks() * 512 < MIB);
+    }
+
+    // Past the end there is nothing to find.
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let handle = client.open(&remote.to_string_lossy(), pflags, Attrs::default()).await.unwrap();
+    assert_eq!(client.seek_hole_data(&handle, 4 * MIB, SeekWhence::Data).await.unwrap(), None);
+    assert_eq!(client.seek_hole_data(&handle, 4 * MIB - 1, SeekWhence::Hole).await.unwrap(), Some(4 * MIB));
+    client.close(&handle).await.unwrap();
+}
+
+#[tokio::test]
+async fn download_without_extension() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+
+    let fs = MemFs::default();
+    let mut data = vec![0; 200_000];
+    data[150_000] = 1;
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, data.clone()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+
+    let mut client = connect(fs).await;
+    assert!(client.extension_data("seek-hole-data@thrusftp").is_none());
+    assert_eq!(client.download("/file", &local).await.unwrap(), 200_000);
+    assert_eq!(std::fs::read(&local).unwrap(), data);
+}
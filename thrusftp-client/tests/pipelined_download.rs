@@ -0,0 +1,138 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use thrusftp_client::{SftpClient, StatusError};
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+
+/// How a fake server answers `Read` requests.
+struct Behaviour {
+    /// Most bytes returned per `Data` response.
+    max_data: usize,
+    /// Offset whose read fails, once.
+    fail_at: Option<u64>,
+}
+
+/// Serves `content` as the only file. Reads are held back until the client
+/// stops sending and then answered last one first, so responses never come
+/// in the order they were asked for. Offsets of all reads are logged.
+async fn fake_server(mut stream: DuplexStream, content: Vec<u8>, mut behaviour: Behaviour, reads: Arc<Mutex<Vec<u64>>>) {
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut pending = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = if pending.is_empty() {
+            stream.read(&mut buf).await.unwrap()
+        } else {
+            match tokio::time::timeout(Duration::from_millis(20), stream.read(&mut buf)).await {
+                Ok(len) => len.unwrap(),
+                Err(_) => {
+                    for (id, offset, len) in pending.drain(..).rev() {
+                        let resp = read_resp(&content, &mut behaviour, id, offset, len);
+                        stream.write_all(&SftpCodec::encode(&resp).unwrap()).await.unwrap();
+                    }
+                    continue;
+                },
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        for packet in codec.decode(&buf[..len]).unwrap() {
+            let resp = match deserialize_packet(&packet).unwrap() {
+                SftpClientPacket::Init { .. } => SftpServerPacket::Version { version: 3, extensions: vec![].into() },
+                SftpClientPacket::Open { id, .. } => SftpServerPacket::Handle { id, handle: "file".to_string() },
+                SftpClientPacket::Fstat { id, .. } => {
+                    let attrs = Attrs { size: Some(content.len() as u64), ..Attrs::default() };
+                    SftpServerPacket::Attrs { id, attrs }
+                },
+                SftpClientPacket::Close { id, .. } => status(id, StatusCode::r#Ok),
+                SftpClientPacket::Read { id, offset, len, .. } => {
+                    reads.lock().unwrap().push(offset);
+                    pending.push((id, offset, len));
+                    continue;
+                },
+                packet => panic!("unexpected request {:?}", packet),
+            };
+            stream.write_all(&SftpCodec::encode(&resp).unwrap()).await.unwrap();
+        }
+    }
+}
+
+fn read_resp(content: &[u8], behaviour: &mut Behaviour, id: u32, offset: u64, len: u32) -> SftpServerPacket {
+    if behaviour.fail_at == Some(offset) {
+        behaviour.fail_at = None;
+        return status(id, StatusCode::Failure);
+    }
+    if offset >= content.len() as u64 {
+        return status(id, StatusCode::Eof);
+    }
+    let offset = offset as usize;
+    let end = content.len().min(offset + (len as usize).min(behaviour.max_data));
+    SftpServerPacket::Data { id, data: content[offset..end].to_vec().into() }
+}
+
+fn status(id: u32, status_code: StatusCode) -> SftpServerPacket {
+    SftpServerPacket::Status { id, status_code, error_message: String::new(), language_tag: String::new() }
+}
+
+/// Data with a run of zeroes in it, so that skipping zero chunks is covered
+/// as well.
+fn content() -> Vec<u8> {
+    let mut content: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8 + 1).collect();
+    content[300_000..500_000].fill(0);
+    content
+}
+
+async fn connect(content: Vec<u8>, behaviour: Behaviour) -> (SftpClient<DuplexStream>, Arc<Mutex<Vec<u64>>>) {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let reads = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(fake_server(server, content, behaviour, reads.clone()));
+    (SftpClient::new(client).await.unwrap(), reads)
+}
+
+#[tokio::test]
+async fn reordered_responses() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+    let content = content();
+    let (mut client, reads) = connect(content.clone(), Behaviour { max_data: usize::MAX, fail_at: None }).await;
+
+    assert_eq!(client.download("/file", &local).await.unwrap(), content.len() as u64);
+    assert_eq!(std::fs::read(&local).unwrap(), content);
+    // Reads past the end stop once the end has been seen.
+    let past_end = reads.lock().unwrap().iter().filter(|&&offset| offset >= content.len() as u64).count();
+    assert!(past_end <= 32, "{} reads past the end", past_end);
+}
+
+#[tokio::test]
+async fn short_reads_are_continued() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+    let content = content();
+    let (mut client, reads) = connect(content.clone(), Behaviour { max_data: 10_000, fail_at: None }).await;
+
+    assert_eq!(client.download("/file", &local).await.unwrap(), content.len() as u64);
+    assert_eq!(std::fs::read(&local).unwrap(), content);
+    // The rests of short reads were asked for separately.
+    assert!(reads.lock().unwrap().contains(&10_000));
+}
+
+#[tokio::test]
+async fn failed_read_drains_the_window() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+    let content = content();
+    let (mut client, _) = connect(content.clone(), Behaviour { max_data: usize::MAX, fail_at: Some(5 * 32 * 1024) }).await;
+
+    let err = client.download("/file", &local).await.unwrap_err();
+    assert_eq!(err.downcast::<StatusError>().unwrap().status_code, StatusCode::Failure);
+    // Every outstanding response was consumed, so the session goes on.
+    assert_eq!(client.download("/file", &local).await.unwrap(), content.len() as u64);
+    assert_eq!(std::fs::read(&local).unwrap(), content);
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use common::connect;
+
+#[tokio::test]
+async fn read_eof() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let data: Vec<u8> = (0..10_000).map(|i| (i * 7 % 251) as u8).collect();
+    std::fs::write(dir.join("file"), &data).unwrap();
+
+    let mut client = connect(LocalFs::new(dir)).await;
+    assert!(client.extension_data("read-eof@thrusftp").is_some());
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let handle = client.open("/file", pflags, Attrs::default()).await.unwrap();
+
+    assert_eq!(client.read_eof(&handle, 0, 4096).await.unwrap(), (data[..4096].to_vec(), false));
+    // Reads ending exactly at the end say so, as do short ones.
+    assert_eq!(client.read_eof(&handle, 6000, 4000).await.unwrap(), (data[6000..].to_vec(), true));
+    assert_eq!(client.read_eof(&handle, 8000, 4096).await.unwrap(), (data[8000..].to_vec(), true));
+    assert_eq!(client.read_eof(&handle, 10_000, 4096).await.unwrap(), (vec![], true));
+
+    // Plain reads are answered as before: the end of the file is only
+    // reported to a read past it.
+    assert_eq!(client.read(&handle, 6000, 4000).await.unwrap(), data[6000..]);
+    assert!(client.read(&handle, 10_000, 4096).await.unwrap().is_empty());
+    client.close(&handle).await.unwrap();
+
+    assert_eq!(client.download("/file", dir.join("local")).await.unwrap(), 10_000);
+    assert_eq!(std::fs::read(dir.join("local")).unwrap(), data);
+}
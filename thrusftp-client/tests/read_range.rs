@@ -0,0 +1,58 @@
+mod common;
+
+use tokio::io::DuplexStream;
+
+use thrusftp_client::SftpClient;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use thrusftp_protocol::{Fs, FsHandle};
+use common::connect;
+
+/// Serves a `MemFs` holding `/file` with `data` and returns a client for it.
+async fn connect_to(data: &[u8]) -> SftpClient<DuplexStream> {
+    let fs = MemFs::default();
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, data.to_vec()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+    connect(fs).await
+}
+
+#[tokio::test]
+async fn read_range() {
+    let data: Vec<u8> = (0..100_000).map(|i| (i * 7 % 251) as u8).collect();
+    let mut client = connect_to(&data).await;
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let handle = client.open("/file", pflags, Attrs::default()).await.unwrap();
+
+    assert_eq!(client.read_range(&handle, 0, 10).await.unwrap(), data[..10]);
+    assert_eq!(client.read_range(&handle, 1000, 0).await.unwrap(), b"");
+    // More than one read's worth.
+    assert_eq!(client.read_range(&handle, 12_345, 70_000).await.unwrap(), data[12_345..82_345]);
+    // Past the end, what there is comes back.
+    assert_eq!(client.read_range(&handle, 90_000, 50_000).await.unwrap(), data[90_000..]);
+    assert_eq!(client.read_range(&handle, 0, u64::MAX).await.unwrap(), data);
+    assert_eq!(client.read_range(&handle, 100_000, 10).await.unwrap(), b"");
+    assert_eq!(client.read_range(&handle, 200_000, 10).await.unwrap(), b"");
+    client.close(&handle).await.unwrap();
+}
+
+#[tokio::test]
+async fn download_range() {
+    let data: Vec<u8> = (0..100_000).map(|i| (i * 7 % 251) as u8).collect();
+    let mut client = connect_to(&data).await;
+
+    let mut local = Vec::new();
+    assert_eq!(client.download_range("/file", 40_000, 50_000, &mut local).await.unwrap(), 50_000);
+    assert_eq!(local, data[40_000..90_000]);
+
+    let mut local = Vec::new();
+    assert_eq!(client.download_range("/file", 60_000, 50_000, &mut local).await.unwrap(), 40_000);
+    assert_eq!(local, data[60_000..]);
+
+    let mut local = Vec::new();
+    assert_eq!(client.download_range("/file", 150_000, 10, &mut local).await.unwrap(), 0);
+    assert!(local.is_empty());
+
+    assert!(client.download_range("/missing", 0, 10, &mut Vec::new()).await.is_err());
+}
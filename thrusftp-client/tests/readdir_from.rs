@@ -0,0 +1,54 @@
+mod common;
+
+use std::collections::HashSet;
+
+use thrusftp_client::StatusError;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use common::connect;
+
+#[tokio::test]
+async fn listing_goes_on_after_a_reconnect() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    for i in 0..300 {
+        std::fs::write(dir.join(format!("file{}", i)), b"").unwrap();
+    }
+
+    let mut client = connect(LocalFs::new(dir)).await;
+    assert!(client.extension_data("readdir-from@thrusftp").is_some());
+    let (first, mut cookie) = client.readdir_from("/", "").await.unwrap().unwrap();
+    assert!(!first.is_empty() && first.len() < 300);
+    let mut seen: HashSet<String> = first.into_iter().map(|name| name.filename).collect();
+    let listed_before = seen.clone();
+
+    // Files removed in the meantime are not listed; nothing else is
+    // missing or listed twice.
+    let removed = (0..300).map(|i| format!("file{}", i)).find(|name| !seen.contains(name)).unwrap();
+    std::fs::remove_file(dir.join(&removed)).unwrap();
+
+    let mut pages = 1;
+    loop {
+        // A new session for every page, as after a dropped connection.
+        let mut client = connect(LocalFs::new(dir)).await;
+        let (names, next) = match client.readdir_from("/", &cookie).await.unwrap() {
+            Some(page) => page,
+            None => break,
+        };
+        for name in names {
+            assert!(name.attrs.size.is_some());
+            assert!(seen.insert(name.filename.clone()), "{} listed twice", name.filename);
+        }
+        cookie = next;
+        pages += 1;
+    }
+    assert!(pages > 1);
+    assert!(!seen.contains(&removed));
+    assert!(seen.is_superset(&listed_before));
+    assert_eq!(seen.len(), 299);
+
+    let err = client.readdir_from("/", "not a cookie").await.unwrap_err();
+    assert_eq!(err.downcast_ref::<StatusError>().unwrap().status_code, StatusCode::BadMessage);
+    let err = client.readdir_from("/missing", "").await.unwrap_err();
+    assert_eq!(err.downcast_ref::<StatusError>().unwrap().status_code, StatusCode::NoSuchFile);
+}
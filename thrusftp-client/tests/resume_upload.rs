@@ -0,0 +1,76 @@
+mod common;
+
+use std::io::ErrorKind;
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use common::connect;
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn resume_upload_local() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+    let remote = dir.join("remote");
+    let remote_path = remote.to_string_lossy().into_owned();
+    let data = contents(100_000);
+    std::fs::write(&local, &data).unwrap();
+
+    let mut client = connect(LocalFs::default()).await;
+    assert!(client.extension_data("check-file-handle").unwrap().contains("sha256"));
+
+    // Nothing there yet.
+    assert_eq!(client.resume_upload(&local, &remote_path).await.unwrap(), 100_000);
+    assert_eq!(std::fs::read(&remote).unwrap(), data);
+
+    // Interrupted in the middle of a chunk.
+    std::fs::write(&remote, &data[..40_001]).unwrap();
+    assert_eq!(client.resume_upload(&local, &remote_path).await.unwrap(), 59_999);
+    assert_eq!(std::fs::read(&remote).unwrap(), data);
+
+    // Already complete.
+    assert_eq!(client.resume_upload(&local, &remote_path).await.unwrap(), 0);
+
+    // The part on the server was changed since.
+    let mut changed = data[..50_000].to_vec();
+    changed[10] ^= 1;
+    std::fs::write(&remote, &changed).unwrap();
+    let err = client.resume_upload(&local, &remote_path).await.unwrap_err();
+    assert_eq!(err.downcast_ref::<std::io::Error>().unwrap().kind(), ErrorKind::InvalidData);
+    assert_eq!(std::fs::read(&remote).unwrap(), changed);
+
+    // Longer on the server than locally.
+    std::fs::write(&remote, contents(100_001)).unwrap();
+    assert!(client.resume_upload(&local, &remote_path).await.is_err());
+}
+
+#[tokio::test]
+async fn resume_upload_without_check_file() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let local = dir.join("local");
+    let data = contents(70_000);
+    std::fs::write(&local, &data).unwrap();
+
+    let mut client = connect(MemFs::new()).await;
+    assert!(client.extension_data("check-file-handle").is_none());
+
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let handle = client.open("/remote", pflags.clone(), Attrs::default()).await.unwrap();
+    client.write(&handle, 0, data[..33_000].to_vec()).await.unwrap();
+    client.close(&handle).await.unwrap();
+
+    assert_eq!(client.resume_upload(&local, "/remote").await.unwrap(), 37_000);
+    assert_eq!(client.stat("/remote").await.unwrap().size, Some(70_000));
+
+    let handle = client.open("/remote", pflags, Attrs::default()).await.unwrap();
+    client.write(&handle, 69_999, vec![0]).await.unwrap();
+    client.close(&handle).await.unwrap();
+    let err = client.resume_upload(&local, "/remote").await.unwrap_err();
+    assert_eq!(err.downcast_ref::<std::io::Error>().unwrap().kind(), ErrorKind::InvalidData);
+}
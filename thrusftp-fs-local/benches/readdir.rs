@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+
+const ENTRIES: usize = 10_000;
+const ROUNDS: u32 = 20;
+
+/// Lists `dir` through `readdir`, batch by batch, and returns the number of
+/// entries.
+async fn readdir(fs: &LocalFs, dir: &str) -> usize {
+    let mut handle = fs.opendir(dir.to_string()).await.unwrap();
+    let mut count = 0;
+    while let Ok(names) = fs.readdir(&mut handle).await {
+        count += names.len();
+    }
+    fs.close(FsHandle::Dir(handle)).await.unwrap();
+    count
+}
+
+async fn read_dir_all(fs: &LocalFs, dir: &str) -> usize {
+    fs.read_dir_all(dir.to_string()).await.unwrap().len()
+}
+
+/// Times listing a directory of `ENTRIES` files, each stat'ed for its
+/// attributes, with `readdir` and with `read_dir_all`.
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("thrusftp-bench-readdir-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for i in 0..ENTRIES {
+        std::fs::write(dir.join(format!("file{}", i)), b"").unwrap();
+    }
+    let fs = LocalFs::new(&dir);
+
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        assert_eq!(readdir(&fs, "/").await, ENTRIES);
+        total += start.elapsed();
+    }
+    println!("readdir       {} entries: {:?} per listing", ENTRIES, total / ROUNDS);
+
+    let mut total = Duration::default();
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        assert_eq!(read_dir_all(&fs, "/").await, ENTRIES);
+        total += start.elapsed();
+    }
+    println!("read_dir_all  {} entries: {:?} per listing", ENTRIES, total / ROUNDS);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
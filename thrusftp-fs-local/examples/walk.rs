@@ -0,0 +1,28 @@
+use futures::StreamExt;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+
+/// Prints every path below the directory given as the first argument.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let root = std::env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let fs = LocalFs::default();
+    let mut dirs = vec![root];
+    while let Some(dir) = dirs.pop() {
+        let mut handle = fs.opendir(dir.clone()).await?;
+        let mut names = fs.readdir_stream(&mut handle);
+        while let Some(name) = names.next().await {
+            let name = name?;
+            if name.filename == "." || name.filename == ".." {
+                continue;
+            }
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name.filename);
+            println!("{}", path);
+            let is_dir = name.attrs.permissions.is_some_and(|mode| mode & libc::S_IFMT == libc::S_IFDIR);
+            if is_dir {
+                dirs.push(path);
+            }
+        }
+    }
+    Ok(())
+}
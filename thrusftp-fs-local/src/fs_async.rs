@@ -1,7 +1,32 @@
-use tokio::task::spawn_blocking;
+use tokio::task::{spawn_blocking, JoinError};
 use std::io::Result;
+use std::ffi::OsString;
 use std::path::PathBuf;
-use crate::fs_sync;
+use std::fs::{File, Metadata, Permissions};
+use std::sync::Arc;
+use thrusftp_protocol::types::{HashAlgorithm, SeekWhence, Timespec};
+use crate::fs_sync::{self, Cancel};
+
+/// Runs `f` on the blocking pool like `spawn_blocking`, for tasks that may
+/// take long. If the returned future is dropped before `f` is done, `f` is
+/// told through the `Cancel` it is passed.
+async fn spawn_cancellable<F, T>(f: F) -> std::result::Result<T, JoinError>
+where
+    F: FnOnce(&Cancel) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let cancel = Cancel::default();
+    let _on_drop = CancelOnDrop(cancel.clone());
+    spawn_blocking(move || f(&cancel)).await
+}
+
+struct CancelOnDrop(Cancel);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
 
 pub(crate) async fn statvfs<P: Into<PathBuf>>(path: P) -> Result<libc::statvfs> {
     let path: PathBuf = path.into();
@@ -16,3 +41,136 @@ pub(crate) async fn truncate64<P: Into<PathBuf>>(path: P, size: u64) -> Result<(
         fs_sync::truncate64(path, size)
     }).await?
 }
+
+pub(crate) async fn mknod<P: Into<PathBuf>>(path: P, mode: u32, dev: u64) -> Result<()> {
+    let path: PathBuf = path.into();
+    spawn_blocking(move || {
+        fs_sync::mknod(path, mode, dev)
+    }).await?
+}
+
+pub(crate) async fn utimens<P: Into<PathBuf>>(path: P, atime: Timespec, mtime: Timespec) -> Result<()> {
+    let path: PathBuf = path.into();
+    spawn_blocking(move || {
+        fs_sync::utimens(path, atime, mtime)
+    }).await?
+}
+
+pub(crate) async fn futimens(file: Arc<File>, atime: Timespec, mtime: Timespec) -> Result<()> {
+    spawn_blocking(move || {
+        fs_sync::futimens(&file, atime, mtime)
+    }).await?
+}
+
+pub(crate) async fn glob(pattern: String, limit: usize) -> Result<Vec<(PathBuf, Metadata)>> {
+    spawn_blocking(move || {
+        fs_sync::glob(&pattern, limit)
+    }).await?
+}
+
+pub(crate) async fn remove_tree(base: PathBuf, path: PathBuf, max_depth: usize) -> std::result::Result<(), (PathBuf, std::io::Error)> {
+    spawn_cancellable(move |cancel| {
+        fs_sync::remove_tree(&base, &path, max_depth, cancel)
+    }).await.map_err(|e| (PathBuf::new(), e.into()))?
+}
+
+pub(crate) async fn read_dir_all(path: PathBuf) -> Result<Vec<(OsString, Option<Metadata>)>> {
+    spawn_blocking(move || {
+        fs_sync::read_dir_all(&path)
+    }).await?
+}
+
+pub(crate) async fn read_dir_from(path: PathBuf, offset: i64, limit: usize) -> Result<Vec<(OsString, i64, Option<Metadata>)>> {
+    spawn_blocking(move || {
+        fs_sync::read_dir_from(&path, offset, limit)
+    }).await?
+}
+
+pub(crate) async fn realpath<P: Into<PathBuf>>(path: P) -> Result<PathBuf> {
+    let path: PathBuf = path.into();
+    spawn_blocking(move || {
+        fs_sync::realpath(path)
+    }).await?
+}
+
+pub(crate) async fn rename_noreplace<P: Into<PathBuf>, Q: Into<PathBuf>>(oldpath: P, newpath: Q) -> Result<()> {
+    let oldpath: PathBuf = oldpath.into();
+    let newpath: PathBuf = newpath.into();
+    spawn_blocking(move || {
+        fs_sync::rename_noreplace(oldpath, newpath)
+    }).await?
+}
+
+pub(crate) async fn read_at(file: Arc<File>, offset: u64, len: u32, chunk_len: u32) -> Result<Vec<u8>> {
+    spawn_blocking(move || {
+        fs_sync::read_at(&file, offset, len, chunk_len)
+    }).await?
+}
+
+pub(crate) async fn write_at(file: Arc<File>, offset: u64, data: Vec<u8>) -> Result<()> {
+    spawn_blocking(move || {
+        fs_sync::write_at(&file, offset, &data)
+    }).await?
+}
+
+pub(crate) async fn append(file: Arc<File>, data: Vec<u8>) -> Result<()> {
+    spawn_blocking(move || {
+        fs_sync::append(&file, &data)
+    }).await?
+}
+
+pub(crate) async fn seek_hole_data(file: Arc<File>, offset: u64, whence: SeekWhence, chunk_len: u32) -> Result<u64> {
+    spawn_cancellable(move |cancel| {
+        fs_sync::seek_hole_data(&file, offset, whence, chunk_len, cancel)
+    }).await?
+}
+
+pub(crate) async fn metadata(file: Arc<File>) -> Result<Metadata> {
+    spawn_blocking(move || {
+        file.metadata()
+    }).await?
+}
+
+pub(crate) async fn set_permissions(file: Arc<File>, permissions: Permissions) -> Result<()> {
+    spawn_blocking(move || {
+        file.set_permissions(permissions)
+    }).await?
+}
+
+pub(crate) async fn set_len(file: Arc<File>, size: u64) -> Result<()> {
+    spawn_blocking(move || {
+        file.set_len(size)
+    }).await?
+}
+
+pub(crate) async fn sync_all(file: Arc<File>) -> Result<()> {
+    spawn_blocking(move || {
+        file.sync_all()
+    }).await?
+}
+
+pub(crate) async fn sync_data(file: Arc<File>) -> Result<()> {
+    spawn_blocking(move || {
+        file.sync_data()
+    }).await?
+}
+
+pub(crate) async fn hash(file: Arc<File>, algorithm: HashAlgorithm, offset: u64, len: u64, chunk_len: u32) -> Result<Vec<u8>> {
+    spawn_cancellable(move |cancel| {
+        fs_sync::hash(&file, algorithm, offset, len, chunk_len, cancel)
+    }).await?
+}
+
+pub(crate) async fn hash_blocks(file: Arc<File>, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32, chunk_len: u32) -> Result<Vec<Vec<u8>>> {
+    spawn_cancellable(move |cancel| {
+        fs_sync::hash_blocks(&file, algorithm, offset, len, block_size, chunk_len, cancel)
+    }).await?
+}
+
+pub(crate) async fn resolve_ids(uids: Vec<u32>, gids: Vec<u32>) -> Result<(Vec<String>, Vec<String>)> {
+    spawn_blocking(move || {
+        let usernames = uids.into_iter().map(fs_sync::user_name).collect::<Result<_>>()?;
+        let groupnames = gids.into_iter().map(fs_sync::group_name).collect::<Result<_>>()?;
+        Ok((usernames, groupnames))
+    }).await?
+}
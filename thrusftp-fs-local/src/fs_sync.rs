@@ -1,9 +1,56 @@
 use std::mem::MaybeUninit;
 use std::os::unix::ffi::OsStrExt;
-use std::ffi::CString;
-use std::path::Path;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::fs::{File, Metadata};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::convert::TryInto;
-use std::io::{Result, Error, ErrorKind};
+use std::io::{Result, Error, ErrorKind, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use thrusftp_protocol::PartialWrite;
+use thrusftp_protocol::types::{HashAlgorithm, SeekWhence, Timespec};
+use crate::hash::Hasher;
+
+/// Runs `f`, a libc call that returns a negative value and sets `errno` on
+/// failure, again for as long as it fails with `EINTR`. A signal delivered
+/// to the blocking thread would otherwise fail the request for no reason
+/// the client could do anything about. Every raw call in this module goes
+/// through this.
+fn retry_on_eintr<T: Copy + PartialOrd + Default, F: FnMut() -> T>(mut f: F) -> Result<T> {
+    loop {
+        let res = f();
+        if res >= T::default() {
+            return Ok(res);
+        }
+        let err = Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Set once nobody waits for a blocking task any more, so long running ones
+/// can stop between steps instead of going on for nothing. A step already
+/// in a syscall still finishes.
+#[derive(Clone, Default)]
+pub(crate) struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Fails once cancelled.
+    fn check(&self) -> Result<()> {
+        if self.0.load(Ordering::Relaxed) {
+            return Err(Error::other("cancelled"));
+        }
+        Ok(())
+    }
+}
 
 pub(crate) fn statvfs<P: AsRef<Path>>(path: P) -> Result<libc::statvfs> {
     let cstr = match CString::new(path.as_ref().as_os_str().as_bytes()) {
@@ -13,21 +60,554 @@ pub(crate) fn statvfs<P: AsRef<Path>>(path: P) -> Result<libc::statvfs> {
 
 	let mut stat: MaybeUninit<libc::statvfs> = MaybeUninit::zeroed();
 
-	if unsafe { libc::statvfs(cstr.as_ptr(), stat.as_mut_ptr()) } != 0 {
-		Err(Error::last_os_error())
-	} else {
-		let stat = unsafe { stat.assume_init() };
-        Ok(stat)
-	}
+	retry_on_eintr(|| unsafe { libc::statvfs(cstr.as_ptr(), stat.as_mut_ptr()) })?;
+	let stat = unsafe { stat.assume_init() };
+	Ok(stat)
 }
 
 pub(crate) fn truncate64<P: AsRef<Path>>(path: P, size: u64) -> Result<()> {
     let cstr = CString::new(path.as_ref().as_os_str().as_bytes())?;
     let size = size.try_into().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
 
-    if unsafe { libc::truncate64(cstr.as_ptr(), size) } != 0 {
-        Err(Error::last_os_error())
+    retry_on_eintr(|| unsafe { libc::truncate64(cstr.as_ptr(), size) })?;
+    Ok(())
+}
+
+/// Renames `oldpath` to `newpath` unless `newpath` exists, atomically if the
+/// kernel and filesystem support `RENAME_NOREPLACE`. Elsewhere this falls
+/// back to checking for `newpath` first, which leaves a window in which a
+/// file created concurrently can still be replaced.
+pub(crate) fn rename_noreplace<P: AsRef<Path>, Q: AsRef<Path>>(oldpath: P, newpath: Q) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let old = CString::new(oldpath.as_ref().as_os_str().as_bytes())?;
+        let new = CString::new(newpath.as_ref().as_os_str().as_bytes())?;
+        let res = retry_on_eintr(|| unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                libc::AT_FDCWD, old.as_ptr(),
+                libc::AT_FDCWD, new.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        });
+        let err = match res {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        match err.raw_os_error() {
+            // Not supported by this kernel or filesystem.
+            Some(libc::ENOSYS) | Some(libc::EINVAL) => {},
+            _ => return Err(err),
+        }
+    }
+
+    // `symlink_metadata`, so a dangling symlink counts as an existing target
+    // and does not get replaced.
+    if std::fs::symlink_metadata(&newpath).is_ok() {
+        return Err(ErrorKind::AlreadyExists.into());
+    }
+    std::fs::rename(oldpath, newpath)
+}
+
+/// `mode_t` and `dev_t` are narrower than `u32` and `u64` on some systems;
+/// there `mode` and `dev` are truncated.
+pub(crate) fn mknod<P: AsRef<Path>>(path: P, mode: u32, dev: u64) -> Result<()> {
+    let cstr = CString::new(path.as_ref().as_os_str().as_bytes())?;
+
+    retry_on_eintr(|| unsafe { libc::mknod(cstr.as_ptr(), mode as libc::mode_t, dev as libc::dev_t) })?;
+    Ok(())
+}
+
+fn to_timespec(time: Timespec) -> Result<libc::timespec> {
+    if time.nsecs >= 1_000_000_000 {
+        return Err(Error::new(ErrorKind::InvalidInput, "nanoseconds out of range"));
+    }
+    Ok(libc::timespec {
+        tv_sec: time.secs.try_into().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        tv_nsec: time.nsecs as _,
+    })
+}
+
+pub(crate) fn utimens<P: AsRef<Path>>(path: P, atime: Timespec, mtime: Timespec) -> Result<()> {
+    let cstr = CString::new(path.as_ref().as_os_str().as_bytes())?;
+    let times = [to_timespec(atime)?, to_timespec(mtime)?];
+
+    retry_on_eintr(|| unsafe { libc::utimensat(libc::AT_FDCWD, cstr.as_ptr(), times.as_ptr(), 0) })?;
+    Ok(())
+}
+
+pub(crate) fn futimens(file: &File, atime: Timespec, mtime: Timespec) -> Result<()> {
+    let times = [to_timespec(atime)?, to_timespec(mtime)?];
+
+    retry_on_eintr(|| unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) })?;
+    Ok(())
+}
+
+/// A directory opened for `readdir(3)`, closed on drop.
+struct Dir(*mut libc::DIR);
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0) };
+    }
+}
+
+impl Dir {
+    /// Opens the directory `path`, following symlinks.
+    fn open(path: &CStr) -> Result<Self> {
+        let dir = unsafe { libc::opendir(path.as_ptr()) };
+        if dir.is_null() {
+            return Err(Error::last_os_error());
+        }
+        Ok(Dir(dir))
+    }
+
+    /// Opens the directory `name` relative to `dir_fd`, failing with `ELOOP`
+    /// if it is a symlink.
+    fn open_nofollow(dir_fd: RawFd, name: &CStr) -> Result<Self> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = retry_on_eintr(|| unsafe { libc::openat(dir_fd, name.as_ptr(), flags) })?;
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        Ok(Dir(dir))
+    }
+
+    fn fd(&self) -> RawFd {
+        unsafe { libc::dirfd(self.0) }
+    }
+
+    /// Continues reading at `offset`, the `d_off` of an entry read before,
+    /// possibly from another stream of the same directory.
+    fn seek(&mut self, offset: i64) {
+        unsafe { libc::seekdir(self.0, offset as libc::c_long) };
+    }
+
+    /// The next entry, with its `d_off`, where reading continues after it,
+    /// or `None` at the end.
+    fn next_entry(&mut self) -> Result<Option<(&CStr, i64)>> {
+        // `readdir` returns null at the end and on errors alike; only the
+        // latter set `errno`.
+        unsafe { *libc::__errno_location() = 0 };
+        let entry = unsafe { libc::readdir64(self.0) };
+        if entry.is_null() {
+            let err = Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(0) => Ok(None),
+                _ => Err(err),
+            };
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        Ok(Some((name, unsafe { (*entry).d_off })))
+    }
+
+    /// Names of all entries but `.` and `..`.
+    fn names(&mut self) -> Result<Vec<CString>> {
+        let mut names = Vec::new();
+        while let Some((name, _)) = self.next_entry()? {
+            if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                names.push(name.to_owned());
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Removes the directory `path`, relative to `base`, and everything in it,
+/// like `rm -r`, without ever following a symlink: links are removed rather
+/// than what they point to, and every directory, from `base` down to `path`
+/// and then within it, is opened with `O_NOFOLLOW` relative to its parent,
+/// so one swapped for a link while the tree is removed is not entered
+/// either. Only `base` itself may be a link. Directories more than
+/// `max_depth` levels below `path` are not entered.
+///
+/// Refuses to remove `base` or the filesystem root, i.e. any `path` whose
+/// last component is not a name.
+///
+/// Stops at the first failure, with the path it happened at relative to
+/// `path`, which is empty for `path` itself and the directories leading to
+/// it. Everything removed until then stays removed.
+pub(crate) fn remove_tree(base: &Path, path: &Path, max_depth: usize, cancel: &Cancel) -> std::result::Result<(), (PathBuf, Error)> {
+    let at_top = |err| (PathBuf::new(), err);
+    let mut start = base;
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => {
+                start = Path::new("/");
+                names.clear();
+            },
+            Component::Normal(name) => names.push(name),
+            Component::ParentDir => names.push(component.as_os_str()),
+            Component::CurDir | Component::Prefix(_) => {},
+        }
+    }
+    let cstr = |name: &OsStr| CString::new(name.as_bytes()).map_err(|e| at_top(e.into()));
+    let name = match names.pop() {
+        Some(name) if name != ".." => cstr(name)?,
+        _ => return Err(at_top(Error::new(ErrorKind::PermissionDenied, "cannot remove the served directory"))),
+    };
+    let mut parent = Dir::open(&cstr(start.as_os_str())?).map_err(at_top)?;
+    for dir_name in names {
+        parent = Dir::open_nofollow(parent.fd(), &cstr(dir_name)?).map_err(at_top)?;
+    }
+    let dir = match Dir::open_nofollow(parent.fd(), &name) {
+        Ok(dir) => dir,
+        // Like `rmdir(2)` on a symlink to a directory.
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Err(at_top(Error::from_raw_os_error(libc::ENOTDIR))),
+        Err(e) => return Err(at_top(e)),
+    };
+    empty_dir(dir, Path::new(""), max_depth, cancel)?;
+    retry_on_eintr(|| unsafe { libc::unlinkat(parent.fd(), name.as_ptr(), libc::AT_REMOVEDIR) }).map_err(at_top)?;
+    Ok(())
+}
+
+/// Removes everything in `dir`, which is at `path` in the tree.
+fn empty_dir(mut dir: Dir, path: &Path, depth_left: usize, cancel: &Cancel) -> std::result::Result<(), (PathBuf, Error)> {
+    // Read everything first: entries removed while a directory is read
+    // could make `readdir` skip others.
+    let names = dir.names().map_err(|e| (path.to_path_buf(), e))?;
+    for name in names {
+        let child = path.join(OsStr::from_bytes(name.to_bytes()));
+        cancel.check().map_err(|e| (child.clone(), e))?;
+        let mut stat: MaybeUninit<libc::stat64> = MaybeUninit::zeroed();
+        retry_on_eintr(|| unsafe { libc::fstatat64(dir.fd(), name.as_ptr(), stat.as_mut_ptr(), libc::AT_SYMLINK_NOFOLLOW) })
+            .map_err(|e| (child.clone(), e))?;
+        let is_dir = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT == libc::S_IFDIR;
+        if is_dir {
+            if depth_left == 0 {
+                return Err((child, Error::other("directory tree too deep")));
+            }
+            let subdir = Dir::open_nofollow(dir.fd(), &name).map_err(|e| (child.clone(), e))?;
+            empty_dir(subdir, &child, depth_left - 1, cancel)?;
+        }
+        let flags = if is_dir { libc::AT_REMOVEDIR } else { 0 };
+        retry_on_eintr(|| unsafe { libc::unlinkat(dir.fd(), name.as_ptr(), flags) }).map_err(|e| (child, e))?;
+    }
+    Ok(())
+}
+
+/// Names and metadata of the entries of the directory `path`, not following
+/// symlinks. Entries removed while the directory is read are left out;
+/// entries whose metadata cannot be read for another reason are listed
+/// without it.
+pub(crate) fn read_dir_all(path: &Path) -> Result<Vec<(OsString, Option<Metadata>)>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        match entry.metadata() {
+            Ok(metadata) => entries.push((entry.file_name(), Some(metadata))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(_) => entries.push((entry.file_name(), None)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Up to `limit` entries of the directory `path` but `.` and `..`, starting
+/// at `offset`: zero for the beginning, or the `d_off` of an entry returned
+/// before. Each entry comes with its `d_off` and, if it can be read, its
+/// metadata. Entries removed before they are stat'ed are left out.
+///
+/// The offsets are the filesystem's own directory cookies, as NFS uses
+/// them, so they stay valid when the directory is opened again.
+pub(crate) fn read_dir_from(path: &Path, offset: i64, limit: usize) -> Result<Vec<(OsString, i64, Option<Metadata>)>> {
+    let cstr = CString::new(path.as_os_str().as_bytes())?;
+    let mut dir = Dir::open(&cstr)?;
+    if offset != 0 {
+        dir.seek(offset);
+    }
+    let mut entries = Vec::new();
+    while entries.len() < limit {
+        let (name, offset) = match dir.next_entry()? {
+            Some((name, offset)) => (OsStr::from_bytes(name.to_bytes()).to_os_string(), offset),
+            None => break,
+        };
+        if name == "." || name == ".." {
+            continue;
+        }
+        match std::fs::symlink_metadata(path.join(&name)) {
+            Ok(metadata) => entries.push((name, offset, Some(metadata))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(_) => entries.push((name, offset, None)),
+        }
+    }
+    Ok(entries)
+}
+
+/// Paths matching `pattern`, with their metadata, not following symlinks.
+/// Hidden files only match patterns that start them with a literal dot, as
+/// in a shell. Directories that cannot be read are skipped. Fails once there
+/// are more than `limit` matches.
+pub(crate) fn glob(pattern: &str, limit: usize) -> Result<Vec<(PathBuf, Metadata)>> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let paths = glob::glob_with(pattern, options)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.msg))?;
+    let mut matches = Vec::new();
+    for path in paths.flatten() {
+        if matches.len() == limit {
+            return Err(Error::other(format!("more than {} matches", limit)));
+        }
+        // The file may have been removed since it was listed.
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            matches.push((path, metadata));
+        }
+    }
+    Ok(matches)
+}
+
+/// Most symlinks `realpath` follows for one path, like Linux does.
+const MAX_SYMLINKS: usize = 40;
+
+/// Like `canonicalize`, but `path` need not exist: it is walked one
+/// component at a time, following symlinks, dangling ones included, until
+/// a component does not exist. The rest is appended lexically, resolving
+/// `.` and `..` as it goes. So this is what `canonicalize` would return
+/// once the missing components are created.
+///
+/// Fails with `ELOOP` once more than `MAX_SYMLINKS` links were followed,
+/// which every loop gets to, and with `ENOTDIR` where a component that is
+/// not a directory is followed by another.
+pub(crate) fn realpath<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let mut res = if path.is_absolute() { PathBuf::from("/") } else { std::env::current_dir()?.canonicalize()? };
+    let mut pending: std::collections::VecDeque<OsString> = path.components()
+        .filter(|component| *component != Component::RootDir)
+        .map(|component| component.as_os_str().to_owned())
+        .collect();
+    let mut links = 0;
+    // Whether a component did not exist, after which there is nothing left
+    // to look up.
+    let mut missing = false;
+    while let Some(component) = pending.pop_front() {
+        if component == "." {
+            continue;
+        }
+        if component == ".." {
+            res.pop();
+            continue;
+        }
+        res.push(&component);
+        if missing {
+            continue;
+        }
+        match std::fs::symlink_metadata(&res) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(Error::from_raw_os_error(libc::ELOOP));
+                }
+                let target = std::fs::read_link(&res)?;
+                res.pop();
+                if target.is_absolute() {
+                    res = PathBuf::from("/");
+                }
+                for component in target.components().rev() {
+                    if component != Component::RootDir {
+                        pending.push_front(component.as_os_str().to_owned());
+                    }
+                }
+            },
+            Ok(metadata) if !metadata.is_dir() && !pending.is_empty() => {
+                return Err(Error::from_raw_os_error(libc::ENOTDIR));
+            },
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => missing = true,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(res)
+}
+
+/// Reads up to `len` bytes at `offset`, stopping early only at the end of
+/// the file. The buffer starts out as large as what is left of a regular
+/// file, so a huge `len` near the end does not allocate memory that is never
+/// filled, and grows by at least `chunk_len` while reads keep filling it, for
+/// files that grow or do not know their size.
+pub(crate) fn read_at(file: &File, offset: u64, len: u32, chunk_len: u32) -> Result<Vec<u8>> {
+    let min_buf_len = chunk_len as u64;
+    let len = len as u64;
+    let metadata = file.metadata()?;
+    let initial_len = if metadata.is_file() {
+        len.min(metadata.len().saturating_sub(offset))
     } else {
-        Ok(())
+        len.min(min_buf_len)
+    };
+    let mut data = vec![0u8; initial_len as usize];
+    let mut total_read_len = 0;
+    while (total_read_len as u64) < len {
+        if total_read_len == data.len() {
+            let new_len = (data.len() as u64 * 2).max(min_buf_len).min(len);
+            data.resize(new_len as usize, 0);
+        }
+        match file.read_at(&mut data[total_read_len..], offset + total_read_len as u64) {
+            Ok(0) => break,
+            Ok(read_len) => total_read_len += read_len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    data.truncate(total_read_len);
+    Ok(data)
+}
+
+pub(crate) fn write_at(file: &File, offset: u64, data: &[u8]) -> Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        match file.write_at(&data[written..], offset + written as u64) {
+            Ok(0) => return Err(PartialWrite::wrap(ErrorKind::WriteZero.into(), written as u64, data.len() as u64)),
+            Ok(write_len) => written += write_len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(PartialWrite::wrap(e, written as u64, data.len() as u64)),
+        }
+    }
+    Ok(())
+}
+
+/// `lseek(2)` with `SEEK_DATA` or `SEEK_HOLE`. `ENXIO`, for offsets past
+/// the end or past the last data, becomes `UnexpectedEof`. Where the
+/// kernel does not know these, the file is scanned instead.
+pub(crate) fn seek_hole_data(file: &File, offset: u64, whence: SeekWhence, chunk_len: u32, cancel: &Cancel) -> Result<u64> {
+    let raw_offset = offset.try_into().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let raw_whence = match whence {
+        SeekWhence::Data => libc::SEEK_DATA,
+        SeekWhence::Hole => libc::SEEK_HOLE,
+    };
+    // The position is not used otherwise, reads and writes give their own
+    // offset, so moving it does no harm.
+    let err = match retry_on_eintr(|| unsafe { libc::lseek64(file.as_raw_fd(), raw_offset, raw_whence) }) {
+        Ok(res) => return Ok(res as u64),
+        Err(err) => err,
+    };
+    match err.raw_os_error() {
+        Some(libc::ENXIO) => Err(ErrorKind::UnexpectedEof.into()),
+        Some(libc::EINVAL) => scan_hole_data(file, offset, whence, chunk_len, cancel),
+        _ => Err(err),
+    }
+}
+
+/// `seek_hole_data` by reading the file `chunk_len` bytes at a time, from
+/// the start of the chunk `offset` is in. Chunks of only zeros count as
+/// holes, so a client skipping them gets the same file.
+fn scan_hole_data(file: &File, offset: u64, whence: SeekWhence, chunk_len: u32, cancel: &Cancel) -> Result<u64> {
+    let size = file.metadata()?.len();
+    if offset >= size {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let mut pos = offset - offset % chunk_len as u64;
+    while pos < size {
+        cancel.check()?;
+        let data = read_at(file, pos, chunk_len, chunk_len)?;
+        if data.is_empty() {
+            break;
+        }
+        let hole = data.iter().all(|&byte| byte == 0);
+        if hole == matches!(whence, SeekWhence::Hole) {
+            return Ok(pos.max(offset));
+        }
+        pos += data.len() as u64;
+    }
+    match whence {
+        SeekWhence::Data => Err(ErrorKind::UnexpectedEof.into()),
+        SeekWhence::Hole => Ok(pos.max(offset)),
+    }
+}
+
+/// Writes to a file opened with `O_APPEND`, where the kernel places every
+/// write at the current end of the file.
+pub(crate) fn append(mut file: &File, data: &[u8]) -> Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        match file.write(&data[written..]) {
+            Ok(0) => return Err(PartialWrite::wrap(ErrorKind::WriteZero.into(), written as u64, data.len() as u64)),
+            Ok(write_len) => written += write_len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(PartialWrite::wrap(e, written as u64, data.len() as u64)),
+        }
+    }
+    Ok(())
+}
+
+/// Digest of `len` bytes starting at `offset`, or up to the end of the file
+/// if `len` is zero. The file is read `chunk_len` bytes at a time, so this
+/// works on files of any size.
+pub(crate) fn hash(file: &File, algorithm: HashAlgorithm, offset: u64, len: u64, chunk_len: u32, cancel: &Cancel) -> Result<Vec<u8>> {
+    let max_chunk_len = chunk_len as u64;
+    let mut hasher = Hasher::new(algorithm);
+    let mut pos = offset;
+    loop {
+        cancel.check()?;
+        let chunk_len = match len {
+            0 => max_chunk_len,
+            len => max_chunk_len.min(offset.saturating_add(len) - pos),
+        };
+        if chunk_len == 0 {
+            break;
+        }
+        let data = read_at(file, pos, chunk_len as u32, max_chunk_len as u32)?;
+        if data.is_empty() {
+            break;
+        }
+        hasher.update(&data);
+        pos += data.len() as u64;
+    }
+    Ok(hasher.finish())
+}
+
+/// Digests of the consecutive `block_size` byte blocks of `len` bytes
+/// starting at `offset`. Stops early at the end of the file.
+pub(crate) fn hash_blocks(file: &File, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32, chunk_len: u32, cancel: &Cancel) -> Result<Vec<Vec<u8>>> {
+    let end = offset.saturating_add(len);
+    let mut hashes = Vec::new();
+    let mut pos = offset;
+    while pos < end {
+        let block_len = (block_size as u64).min(end - pos);
+        hashes.push(hash(file, algorithm, pos, block_len, chunk_len, cancel)?);
+        pos += block_len;
+    }
+    Ok(hashes)
+}
+
+/// Name of the user `uid`, or an empty string if there is none.
+pub(crate) fn user_name(uid: u32) -> Result<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut passwd: MaybeUninit<libc::passwd> = MaybeUninit::zeroed();
+        let mut result = std::ptr::null_mut();
+        let res = unsafe { libc::getpwuid_r(uid, passwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result) };
+        match res {
+            0 if result.is_null() => return Ok(String::new()),
+            0 => {
+                let name = unsafe { std::ffi::CStr::from_ptr((*result).pw_name) };
+                return Ok(name.to_string_lossy().into_owned());
+            },
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// Name of the group `gid`, or an empty string if there is none.
+pub(crate) fn group_name(gid: u32) -> Result<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut group: MaybeUninit<libc::group> = MaybeUninit::zeroed();
+        let mut result = std::ptr::null_mut();
+        let res = unsafe { libc::getgrgid_r(gid, group.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result) };
+        match res {
+            0 if result.is_null() => return Ok(String::new()),
+            0 => {
+                let name = unsafe { std::ffi::CStr::from_ptr((*result).gr_name) };
+                return Ok(name.to_string_lossy().into_owned());
+            },
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(Error::from_raw_os_error(errno)),
+        }
     }
 }
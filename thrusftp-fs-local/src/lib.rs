@@ -1,48 +1,354 @@
 mod fs_sync;
 mod fs_async;
+mod hash;
+mod statvfs_cache;
 
 use std::fs::{Metadata, Permissions};
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
-use tokio::io::{AsyncSeekExt, AsyncReadExt, AsyncWriteExt, SeekFrom};
 use async_trait::async_trait;
-use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use thrusftp_protocol::Result;
 
-use thrusftp_protocol::{Fs, FsHandle};
-use thrusftp_protocol::types::{Attrs, Pflags, Name, FsStats};
+use thrusftp_protocol::{Fs, FsHandle, SftpError};
+use thrusftp_protocol::types::{Attrs, Disposition, ExtendedAttr, Pflags, Name, FsStats, HashAlgorithm, SeekWhence, Timespec};
+use thrusftp_protocol::types::{ATIME_EXTENDED_ATTR, INO_EXTENDED_ATTR, MTIME_EXTENDED_ATTR, NLINK_EXTENDED_ATTR};
+use thrusftp_protocol::types::{SSH2_FXE_STATVFS_ST_NOSUID, SSH2_FXE_STATVFS_ST_RDONLY};
 
-pub struct LocalFs;
+use statvfs_cache::StatvfsCache;
 
-async fn apply_attrs_path(path: String, attrs: Attrs) -> std::io::Result<()> {
-    if let Some(permissions) = attrs.permissions {
-        fs::set_permissions(&path, Permissions::from_mode(permissions)).await?;
+/// Number of directory entries returned per `readdir` call.
+const READDIR_BATCH_LEN: usize = 64;
+
+/// Most entries of a `readdir` batch stat'ed at the same time.
+const READDIR_STAT_CONCURRENCY: usize = 8;
+
+/// Most paths a `glob` returns; patterns matching more fail instead.
+pub const MAX_GLOB_MATCHES: usize = 1024;
+
+/// Deepest a `remove_tree` goes below the directory it removes. Deeper
+/// trees are removed down to this depth, then it fails.
+pub const MAX_TREE_DEPTH: usize = 256;
+
+/// Default for `LocalFs::chunk_len`.
+pub const DEFAULT_CHUNK_LEN: u32 = 64 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct LocalFs {
+    /// Directory client paths are resolved against, or `None` to use them
+    /// as they are, relative to the process's working directory.
+    root: Option<PathBuf>,
+    /// Client path clients start in, instead of the root or the working
+    /// directory.
+    home: Option<String>,
+    nofollow: bool,
+    umask: u32,
+    statvfs_cache: Option<Arc<StatvfsCache>>,
+    device_nodes: bool,
+    sync_on_close: bool,
+    /// `None` for `DEFAULT_CHUNK_LEN`.
+    chunk_len: Option<u32>,
+    /// Zero to not buffer writes.
+    write_buffer: usize,
+}
+
+/// An open file. Reads and writes use positional I/O, so concurrent requests
+/// on the same handle never race on a shared file cursor.
+pub struct LocalFile {
+    file: Arc<std::fs::File>,
+    /// Opened with `O_APPEND`: writes go to the end of the file, whatever
+    /// offset the client sent.
+    append: bool,
+    /// Opened for writing, so there may be data to sync on close.
+    write: bool,
+    /// Written by the client but not yet to the file, starting at
+    /// `buffered_offset`.
+    buffered: Vec<u8>,
+    buffered_offset: u64,
+    /// Most bytes `buffered` holds; zero to write straight through.
+    buffer_len: usize,
+}
+
+impl LocalFile {
+    /// Writes out what is buffered. If that fails, the buffered data is
+    /// dropped, so the error is reported once.
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut self.buffered);
+        if self.append {
+            fs_async::append(self.file.clone(), data).await
+        } else {
+            fs_async::write_at(self.file.clone(), self.buffered_offset, data).await
+        }
+    }
+}
+
+/// A handle dropped without `close`, say because its session ended, still
+/// writes out what is buffered: the client was told it was written. There
+/// is nobody left to report an error to, so errors are ignored.
+impl Drop for LocalFile {
+    fn drop(&mut self) {
+        if self.buffered.is_empty() {
+            return;
+        }
+        let _ = if self.append {
+            fs_sync::append(&self.file, &self.buffered)
+        } else {
+            fs_sync::write_at(&self.file, self.buffered_offset, &self.buffered)
+        };
+    }
+}
+
+impl LocalFs {
+    /// Serves the directory `root`: a client path `/a/b` is `root/a/b`, and
+    /// so are `a/b` and `/../a/b`.
+    ///
+    /// This is not a jail. `..` is resolved before the path is joined to
+    /// `root`, but symlinks below `root` are followed wherever they point,
+    /// and symlinks are created with the target the client sent.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: Some(root.into()),
+            ..Default::default()
+        }
+    }
+    /// Directory clients start in, as a client path: below the root if
+    /// there is one. Relative paths are resolved against it.
+    pub fn home<S: Into<String>>(mut self, home: S) -> Self {
+        self.home = Some(home.into());
+        self
+    }
+    /// Refuse to open files whose last path component is a symlink
+    /// (`O_NOFOLLOW`), so a client cannot read or write through a link it
+    /// planted.
+    pub fn nofollow(mut self, nofollow: bool) -> Self {
+        self.nofollow = nofollow;
+        self
+    }
+    /// Mask applied to the mode of newly created files and directories, on
+    /// top of the process umask. A later setstat still sets the exact mode
+    /// the client asks for.
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = umask;
+        self
+    }
+    /// Let clients create block and character devices with `mknod`. Off by
+    /// default; creating them also needs `CAP_MKNOD`. Fifos and sockets can
+    /// always be created.
+    pub fn device_nodes(mut self, device_nodes: bool) -> Self {
+        self.device_nodes = device_nodes;
+        self
     }
+    /// `fsync` files opened for writing when the client closes them, so an
+    /// upload is on disk once its `Close` is answered. Off by default, as
+    /// every close then waits for the disk; clients that need durability
+    /// for only some files can use `fsync@openssh.com` instead.
+    pub fn sync_on_close(mut self, sync_on_close: bool) -> Self {
+        self.sync_on_close = sync_on_close;
+        self
+    }
+    /// Answer `statvfs` for a path from a cache for up to `ttl` after the
+    /// last real call, instead of asking the kernel every time. Off by
+    /// default; clones of this `LocalFs` share the cache.
+    pub fn statvfs_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.statvfs_cache = Some(Arc::new(StatvfsCache::new(ttl)));
+        self
+    }
+    /// How many bytes to read at a time when going through a file on the
+    /// client's behalf: when hashing it for `check-file`, and when looking
+    /// for holes on filesystems the kernel cannot ask. Client reads and
+    /// writes use the length the client asked for, but a read whose size is
+    /// not known up front grows its buffer by this much at a time. Larger
+    /// chunks take fewer system calls on big files but hold more memory per
+    /// request in flight. Defaults to `DEFAULT_CHUNK_LEN`; zero counts as
+    /// one byte.
+    pub fn chunk_len(mut self, chunk_len: u32) -> Self {
+        self.chunk_len = Some(chunk_len.max(1));
+        self
+    }
+    /// Collect small writes on a handle that each start where the last one
+    /// ended, up to `len` bytes, and make them with one system call.
+    /// Anything else, a write elsewhere in the file or any other request on
+    /// the handle, writes out the buffer first, as does a full buffer and
+    /// `Close`. Off (zero) by default.
+    ///
+    /// A buffered write is answered before it reaches the file: if writing
+    /// it out fails, the request that flushed it gets the error. Path-based
+    /// requests, like `stat` of the file, do not see buffered data. A handle
+    /// that is dropped without `Close` writes out its buffer when dropped.
+    pub fn write_buffer(mut self, len: usize) -> Self {
+        self.write_buffer = len;
+        self
+    }
+}
+
+impl LocalFs {
+    fn effective_chunk_len(&self) -> u32 {
+        self.chunk_len.unwrap_or(DEFAULT_CHUNK_LEN)
+    }
+    /// The local path for the client path `path`.
+    fn path(&self, path: String) -> PathBuf {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return path.into(),
+        };
+        let mut res = root.clone();
+        for component in Path::new(&path).components() {
+            match component {
+                Component::Normal(name) => res.push(name),
+                // Never above `root`, like `..` in `/` stays in `/`.
+                Component::ParentDir => if res != *root { res.pop(); },
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => {},
+            }
+        }
+        res
+    }
+
+    /// The local path to ask `statvfs` for the client path `path`: the
+    /// path itself, or the root where a symlink leads it outside the root,
+    /// so clients only ever see the numbers of the filesystem they are
+    /// served from.
+    async fn statvfs_path(&self, path: String) -> std::io::Result<PathBuf> {
+        let path = self.path(path);
+        let root = match self.root {
+            Some(ref root) => fs_async::realpath(root.clone()).await?,
+            None => return Ok(path),
+        };
+        let resolved = fs_async::realpath(path).await?;
+        Ok(if resolved.starts_with(&root) { resolved } else { root })
+    }
+}
+
+/// The client path for the local path `path`, which must be below `root`.
+fn client_path(path: &Path, root: &Path) -> std::io::Result<String> {
+    match path.strip_prefix(root) {
+        Ok(relative) if relative.components().all(|c| matches!(c, Component::Normal(_))) => {
+            Ok(format!("/{}", relative.to_string_lossy()))
+        },
+        _ => Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "path leads outside the served directory")),
+    }
+}
+
+/// Only the attributes the client sent are applied. The size goes first,
+/// before a new mode could make the file read-only, and the times go last,
+/// as changing the size updates the modification time.
+async fn apply_attrs_path(path: PathBuf, attrs: Attrs) -> std::io::Result<()> {
     if let Some(size) = attrs.size {
         fs_async::truncate64(&path, size).await?;
     }
+    if let Some(permissions) = attrs.permissions {
+        fs::set_permissions(&path, Permissions::from_mode(permissions)).await?;
+    }
+    if let Some((atime, mtime)) = attrs.atime_mtime {
+        fs_async::utimens(path, whole_secs(atime), whole_secs(mtime)).await?;
+    }
     Ok(())
 }
 
-async fn apply_attrs_handle(handle: &mut fs::File, attrs: Attrs) -> std::io::Result<()> {
+/// Same order as `apply_attrs_path`.
+async fn apply_attrs_handle(handle: &mut LocalFile, attrs: Attrs) -> std::io::Result<()> {
+    if let Some(size) = attrs.size {
+        fs_async::set_len(handle.file.clone(), size).await?;
+    }
     if let Some(permissions) = attrs.permissions {
-        handle.set_permissions(Permissions::from_mode(permissions)).await?;
+        fs_async::set_permissions(handle.file.clone(), Permissions::from_mode(permissions)).await?;
     }
-    if let Some(size) = attrs.size {
-        handle.set_len(size).await?;
+    if let Some((atime, mtime)) = attrs.atime_mtime {
+        fs_async::futimens(handle.file.clone(), whole_secs(atime), whole_secs(mtime)).await?;
     }
     Ok(())
 }
 
+fn whole_secs(secs: u32) -> Timespec {
+    Timespec { secs: secs as u64, nsecs: 0 }
+}
+
+/// Names both paths in the message of a failed replacing rename and keeps
+/// the kind, so a directory over a file, a file over a directory and a
+/// non-empty target directory each reach the client as what they are.
+/// `rename(2)` may report the last one as `EEXIST`, which would read like a
+/// refusal to overwrite at all, so that becomes `ENOTEMPTY`.
+fn rename_error(err: std::io::Error, oldpath: &str, newpath: &str) -> std::io::Error {
+    let err = match err.raw_os_error() {
+        Some(libc::EEXIST) => std::io::Error::from_raw_os_error(libc::ENOTEMPTY),
+        _ => err,
+    };
+    std::io::Error::new(err.kind(), format!("cannot rename {} to {}: {}", oldpath, newpath, err))
+}
+
+/// Extended attribute carrying the device number of block and character
+/// devices, as `major:minor` in decimal.
+pub const RDEV_EXTENDED_ATTR: &str = "rdev@thrusftp";
+
+/// Major and minor number of the device `rdev`, in the encoding of glibc's
+/// `makedev`. `libc::major` and `libc::minor` do the same, but older `libc`
+/// releases only have them as `unsafe fn`.
+fn major_minor(rdev: u64) -> (u64, u64) {
+    let major = ((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0xfff);
+    let minor = ((rdev >> 12) & 0xffff_ff00) | (rdev & 0xff);
+    (major, minor)
+}
+
+/// `permissions` is the full `st_mode`, so its type bits tell devices, fifos
+/// and sockets apart from regular files.
 fn attrs_from_metadata(metadata: Metadata) -> Attrs {
+    let mut extended_attrs = vec![];
+    let file_type = metadata.file_type();
+    if file_type.is_block_device() || file_type.is_char_device() {
+        let (major, minor) = major_minor(metadata.rdev());
+        extended_attrs.push(ExtendedAttr {
+            r#type: RDEV_EXTENDED_ATTR.to_string(),
+            data: format!("{}:{}", major, minor),
+        });
+    }
+    // Times before the epoch are left out rather than reported wrongly.
+    let times = [
+        (ATIME_EXTENDED_ATTR, metadata.atime(), metadata.atime_nsec()),
+        (MTIME_EXTENDED_ATTR, metadata.mtime(), metadata.mtime_nsec()),
+    ];
+    for (r#type, secs, nsecs) in times {
+        if secs >= 0 {
+            extended_attrs.push(ExtendedAttr {
+                r#type: r#type.to_string(),
+                data: Timespec { secs: secs as u64, nsecs: nsecs as u32 }.to_string(),
+            });
+        }
+    }
+    extended_attrs.push(ExtendedAttr {
+        r#type: INO_EXTENDED_ATTR.to_string(),
+        data: metadata.ino().to_string(),
+    });
+    extended_attrs.push(ExtendedAttr {
+        r#type: NLINK_EXTENDED_ATTR.to_string(),
+        data: metadata.nlink().to_string(),
+    });
     Attrs {
         size: Some(metadata.len()),
         uid_gid: Some((metadata.uid(), metadata.gid())),
         permissions: Some(metadata.permissions().mode()),
         atime_mtime: Some((metadata.atime() as u32, metadata.mtime() as u32)),
-        extended_attrs: vec![],
+        extended_attrs,
     }
 }
 
+/// Translates the `ST_*` flags of `statvfs(3)` to those of the SFTP reply,
+/// dropping the ones it has no bit for, like `ST_NODEV`.
+fn statvfs_flags(flags: libc::c_ulong) -> u64 {
+    let mut res = 0;
+    if flags & libc::ST_RDONLY != 0 {
+        res |= SSH2_FXE_STATVFS_ST_RDONLY;
+    }
+    if flags & libc::ST_NOSUID != 0 {
+        res |= SSH2_FXE_STATVFS_ST_NOSUID;
+    }
+    res
+}
+
 fn fsstats_from_statvfs(f: libc::statvfs) -> FsStats {
     FsStats {
         f_bsize: f.f_bsize,
@@ -54,33 +360,63 @@ fn fsstats_from_statvfs(f: libc::statvfs) -> FsStats {
         f_ffree: f.f_ffree,
         f_favail: f.f_favail,
         f_fsid: f.f_fsid,
-        f_flag: f.f_flag,
+        f_flag: statvfs_flags(f.f_flag),
         f_namemax: f.f_namemax,
     }
 }
 
 #[async_trait]
 impl Fs for LocalFs {
-    type FileHandle = tokio::fs::File;
+    type FileHandle = LocalFile;
     type DirHandle = tokio::fs::ReadDir;
 
     async fn open(&self, filename: String, pflags: Pflags, attrs: Attrs) -> Result<Self::FileHandle> {
         let mut options = fs::OpenOptions::new();
-        if pflags.read   { options.read(true); }
+        let write = pflags.write || pflags.append;
+        // Neither read nor write, say to only create the file, means read.
+        if pflags.read || !write { options.read(true); }
         if pflags.write  { options.write(true); }
         if pflags.append { options.append(true); }
-        if pflags.creat  { options.create(true); }
-        if pflags.trunc  { options.truncate(true); }
-        if pflags.excl   { options.create_new(true); }
-        if let Some(permissions) = attrs.permissions {
-            options.mode(permissions);
+        let mut flags = 0;
+        match pflags.disposition() {
+            Disposition::CreateTruncate | Disposition::TruncateExisting if !write => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "truncating needs write access").into());
+            },
+            // `OpenOptions` only creates files it opens for writing, so
+            // read-only creation goes through the raw flags.
+            Disposition::CreateNew if !write => flags |= libc::O_CREAT | libc::O_EXCL,
+            Disposition::OpenOrCreate if !write => flags |= libc::O_CREAT,
+            Disposition::CreateNew => { options.create_new(true); },
+            Disposition::CreateTruncate => { options.create(true).truncate(true); },
+            Disposition::OpenExisting => {},
+            Disposition::OpenOrCreate => { options.create(true); },
+            Disposition::TruncateExisting => { options.truncate(true); },
+        }
+        options.mode(attrs.permissions.unwrap_or(0o666) & !self.umask);
+        if self.nofollow {
+            flags |= libc::O_NOFOLLOW;
         }
-        Ok(options.open(filename).await?)
+        options.custom_flags(flags);
+        let file = match options.open(self.path(filename)).await {
+            Err(err) if self.nofollow && err.raw_os_error() == Some(libc::ELOOP) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "refusing to open a symbolic link").into());
+            },
+            res => Arc::new(res?.into_std().await),
+        };
+        // Opening a directory read-only succeeds, only reading from it fails.
+        if fs_async::metadata(file.clone()).await?.is_dir() {
+            return Err(std::io::Error::from_raw_os_error(libc::EISDIR).into());
+        }
+        let buffer_len = if write { self.write_buffer } else { 0 };
+        Ok(LocalFile { file, append: pflags.append, write, buffered: Vec::new(), buffered_offset: 0, buffer_len })
     }
     async fn close(&self, handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()> {
         match handle {
             FsHandle::File(mut file) => {
                 file.flush().await?;
+                if self.sync_on_close && file.write {
+                    fs_async::sync_all(file.file.clone()).await?;
+                }
                 drop(file);
             },
             FsHandle::Dir(dir) => {
@@ -90,103 +426,294 @@ impl Fs for LocalFs {
         Ok(())
     }
     async fn read(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>> {
-        handle.seek(SeekFrom::Start(offset)).await?;
-        let mut data = vec![0u8; len as usize];
-        let mut read_len = 0;
-        let mut total_read_len = 0;
-        loop {
-            if total_read_len >= len as usize { break; }
-            read_len = handle.read(&mut data[read_len..]).await?;
-            total_read_len += read_len;
-            if read_len == 0 { break; }
-        }
-        if total_read_len == 0 {
+        handle.flush().await?;
+        let data = fs_async::read_at(handle.file.clone(), offset, len, self.effective_chunk_len()).await?;
+        // A zero-length read returns no data either way, so look at the file
+        // size to tell whether it started before the end of the file.
+        let at_eof = if len == 0 {
+            offset >= fs_async::metadata(handle.file.clone()).await?.len()
+        } else {
+            data.is_empty()
+        };
+        if at_eof {
             Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
         } else {
-            data.truncate(total_read_len);
             Ok(data)
         }
     }
     async fn write(&self, handle: &mut Self::FileHandle, offset: u64, data: Vec<u8>) -> Result<()> {
-        handle.seek(SeekFrom::Start(offset)).await?;
-        handle.write_all(&data).await?;
-        Ok(())
+        // Appends all go to the end, so they always follow on.
+        let follows = handle.append || offset == handle.buffered_offset + handle.buffered.len() as u64;
+        if !follows || handle.buffered.len() + data.len() > handle.buffer_len {
+            handle.flush().await?;
+        }
+        if data.len() < handle.buffer_len {
+            if handle.buffered.is_empty() {
+                handle.buffered_offset = offset;
+                handle.buffered.reserve(handle.buffer_len);
+            }
+            handle.buffered.extend_from_slice(&data);
+            return Ok(());
+        }
+        if handle.append {
+            Ok(fs_async::append(handle.file.clone(), data).await?)
+        } else {
+            // Past the end of the file, this leaves a hole before `data`
+            // on filesystems that have them.
+            Ok(fs_async::write_at(handle.file.clone(), offset, data).await?)
+        }
     }
     async fn lstat(&self, path: String) -> Result<Attrs> {
-        Ok(attrs_from_metadata(fs::symlink_metadata(path).await?))
+        Ok(attrs_from_metadata(fs::symlink_metadata(self.path(path)).await?))
     }
     async fn fstat(&self, handle: &mut Self::FileHandle) -> Result<Attrs> {
-        Ok(attrs_from_metadata(handle.metadata().await?))
+        handle.flush().await?;
+        Ok(attrs_from_metadata(fs_async::metadata(handle.file.clone()).await?))
     }
     async fn setstat(&self, path: String, attrs: Attrs) -> Result<()> {
-        Ok(apply_attrs_path(path, attrs).await?)
+        Ok(apply_attrs_path(self.path(path), attrs).await?)
     }
     async fn fsetstat(&self, handle: &mut Self::FileHandle, attrs: Attrs) -> Result<()> {
+        handle.flush().await?;
         Ok(apply_attrs_handle(handle, attrs).await?)
     }
+    async fn truncate(&self, handle: &mut Self::FileHandle, len: u64) -> Result<()> {
+        handle.flush().await?;
+        Ok(fs_async::set_len(handle.file.clone(), len).await?)
+    }
     async fn opendir(&self, path: String) -> Result<Self::DirHandle> {
-        Ok(fs::read_dir(path).await?)
+        Ok(fs::read_dir(self.path(path)).await?)
     }
+    /// Returns entries in the order the kernel lists them, which is
+    /// unspecified. The entries of a batch are stat'ed a few at a time.
+    /// Entries removed between being listed and stat'ed are left out.
     async fn readdir(&self, handle: &mut Self::DirHandle) -> Result<Vec<Name>> {
-        if let Some(e) = handle.next_entry().await? {
-            let metadata = e.metadata().await?;
-            Ok(vec![
-                Name {
-                    filename: e.file_name().to_string_lossy().to_string(),
-                    longname: e.file_name().to_string_lossy().to_string(),
-                    attrs: attrs_from_metadata(metadata),
+        loop {
+            let mut entries = Vec::new();
+            while entries.len() < READDIR_BATCH_LEN {
+                match handle.next_entry().await? {
+                    Some(entry) => entries.push(entry),
+                    None => break,
                 }
-            ])
-        } else {
-            Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+            }
+            if entries.is_empty() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+
+            // Entries are not followed if they are symlinks, so dangling ones
+            // are listed too. One whose metadata cannot be read, e.g. in a
+            // directory that may be read but not searched, is listed without
+            // attributes rather than failing the whole batch.
+            let names: Vec<Name> = stream::iter(entries)
+                .map(|e| async move {
+                    let attrs = match e.metadata().await {
+                        Ok(metadata) => attrs_from_metadata(metadata),
+                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+                        Err(_) => Attrs::default(),
+                    };
+                    Some(Name::new(e.file_name().to_string_lossy().to_string(), attrs))
+                })
+                .buffered(READDIR_STAT_CONCURRENCY)
+                .filter_map(|name| async move { name })
+                .collect()
+                .await;
+            // If the whole batch is gone, there may still be more entries.
+            if !names.is_empty() {
+                return Ok(names);
+            }
         }
     }
+    /// Reads the whole directory in one blocking task instead of batch by
+    /// batch.
+    async fn read_dir_all(&self, path: String) -> Result<Vec<Name>> {
+        Ok(fs_async::read_dir_all(self.path(path)).await?.into_iter()
+            .map(|(name, metadata)| {
+                let attrs = metadata.map(attrs_from_metadata).unwrap_or_default();
+                Name::new(name.to_string_lossy().to_string(), attrs)
+            })
+            .collect())
+    }
     async fn remove(&self, filename: String) -> Result<()> {
-        Ok(fs::remove_file(filename).await?)
+        Ok(fs::remove_file(self.path(filename)).await?)
     }
     async fn mkdir(&self, path: String, attrs: Attrs) -> Result<()> {
-        // TODO attrs
-        // https://github.com/rust-lang/rust/issues/22415
-        Ok(fs::create_dir(path).await?)
+        let mut builder = fs::DirBuilder::new();
+        builder.mode(attrs.permissions.unwrap_or(0o777) & !self.umask);
+        Ok(builder.create(self.path(path)).await?)
     }
     async fn rmdir(&self, path: String) -> Result<()> {
-        Ok(fs::remove_dir(path).await?)
+        Ok(fs::remove_dir(self.path(path)).await?)
     }
     async fn realpath(&self, path: String) -> Result<String> {
-        Ok(fs::canonicalize(path).await?.to_string_lossy().to_string())
+        let resolved = fs_async::realpath(self.path(path)).await?;
+        match self.root {
+            Some(ref root) => Ok(client_path(&resolved, &fs_async::realpath(root.clone()).await?)?),
+            None => Ok(resolved.to_string_lossy().to_string()),
+        }
+    }
+    async fn home_directory(&self) -> Result<String> {
+        let home = self.home.clone().unwrap_or_else(|| ".".to_string());
+        self.realpath(home).await
     }
     async fn stat(&self, path: String) -> Result<Attrs> {
-        Ok(attrs_from_metadata(fs::metadata(path).await?))
+        Ok(attrs_from_metadata(fs::metadata(self.path(path)).await?))
     }
+    // Both renames work across directories, but not across filesystems:
+    // those fail with `EXDEV` and the client has to copy instead.
     async fn rename(&self, oldpath: String, newpath: String) -> Result<()> {
-        if fs::metadata(&newpath).await.is_ok() {
-            Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into())
-        } else {
-            Ok(fs::rename(oldpath, newpath).await?)
-        }
+        Ok(fs_async::rename_noreplace(self.path(oldpath), self.path(newpath)).await?)
     }
     async fn readlink(&self, path: String) -> Result<String> {
-        Ok(fs::read_link(path).await
+        Ok(fs::read_link(self.path(path)).await
             .map(|target| target.to_string_lossy().to_string())?)
     }
     async fn symlink(&self, linkpath: String, targetpath: String) -> Result<()> {
-        Ok(fs::symlink(targetpath, linkpath).await?)
+        Ok(fs::symlink(targetpath, self.path(linkpath)).await?)
     }
     async fn posix_rename_supported(&self) -> bool { true }
     async fn posix_rename(&self, oldpath: String, newpath: String) -> Result<()> {
-        Ok(fs::rename(oldpath, newpath).await?)
+        fs::rename(self.path(oldpath.clone()), self.path(newpath.clone())).await
+            .map_err(|err| rename_error(err, &oldpath, &newpath).into())
     }
     async fn fsync_supported(&self) -> bool { true }
     async fn fsync(&self, handle: &mut Self::FileHandle) -> Result<()> {
-        Ok(handle.sync_all().await?)
+        handle.flush().await?;
+        Ok(fs_async::sync_all(handle.file.clone()).await?)
+    }
+    async fn fdatasync_supported(&self) -> bool { true }
+    async fn fdatasync(&self, handle: &mut Self::FileHandle) -> Result<()> {
+        handle.flush().await?;
+        // `sync_data` is `fdatasync(2)` on Linux.
+        Ok(fs_async::sync_data(handle.file.clone()).await?)
     }
     async fn statvfs_supported(&self) -> bool { true }
     async fn statvfs(&self, path: String) -> Result<FsStats> {
-        Ok(fsstats_from_statvfs(fs_async::statvfs(path).await?))
+        let cache = match self.statvfs_cache {
+            Some(ref cache) => cache,
+            None => return Ok(fsstats_from_statvfs(fs_async::statvfs(self.statvfs_path(path).await?).await?)),
+        };
+        if let Some(stats) = cache.get(&path) {
+            return Ok(stats);
+        }
+        let stats = fsstats_from_statvfs(fs_async::statvfs(self.statvfs_path(path.clone()).await?).await?);
+        cache.insert(path, stats.clone());
+        Ok(stats)
+    }
+    async fn mknod_supported(&self) -> bool { true }
+    async fn mknod(&self, path: String, mode: u32, dev: u64) -> Result<()> {
+        let allowed = match mode & libc::S_IFMT {
+            libc::S_IFIFO | libc::S_IFSOCK => true,
+            libc::S_IFBLK | libc::S_IFCHR => self.device_nodes,
+            _ => false,
+        };
+        if !allowed {
+            return Err(SftpError::Unsupported);
+        }
+        let mode = mode & (libc::S_IFMT | (0o7777 & !self.umask));
+        Ok(fs_async::mknod(self.path(path), mode, dev).await?)
+    }
+    async fn utimens_supported(&self) -> bool { true }
+    async fn utimens(&self, path: String, atime: Timespec, mtime: Timespec) -> Result<()> {
+        Ok(fs_async::utimens(self.path(path), atime, mtime).await?)
+    }
+    async fn glob_supported(&self) -> bool { true }
+    async fn glob(&self, pattern: String) -> Result<Vec<Name>> {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => {
+                return Ok(fs_async::glob(pattern, MAX_GLOB_MATCHES).await?.into_iter()
+                    .map(|(path, metadata)| Name::new(path.to_string_lossy().to_string(), attrs_from_metadata(metadata)))
+                    .collect());
+            },
+        };
+        // The root is matched literally, and `..` in the pattern is left to
+        // the glob, so it can lead out of the root: drop those matches.
+        let pattern = format!("{}/{}", glob::Pattern::escape(&root.to_string_lossy()), pattern.trim_start_matches('/'));
+        Ok(fs_async::glob(pattern, MAX_GLOB_MATCHES).await?.into_iter()
+            .filter_map(|(path, metadata)| {
+                let path = client_path(&path, root).ok()?;
+                Some(Name::new(path, attrs_from_metadata(metadata)))
+            })
+            .collect())
+    }
+    async fn read_eof_supported(&self) -> bool { true }
+    async fn read_eof(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<(Vec<u8>, bool)> {
+        let data = self.read(handle, offset, len).await?;
+        if data.len() < len as usize {
+            return Ok((data, true));
+        }
+        // A full read may still end right at the end. Only regular files
+        // have a size to tell by.
+        let metadata = fs_async::metadata(handle.file.clone()).await?;
+        let eof = metadata.is_file() && offset + data.len() as u64 >= metadata.len();
+        Ok((data, eof))
+    }
+    async fn readdir_from_supported(&self) -> bool { true }
+    /// Cookies are the kernel's directory offsets, in decimal.
+    async fn readdir_from(&self, path: String, cookie: String) -> Result<Vec<(Name, String)>> {
+        let offset = match cookie.as_str() {
+            "" => 0,
+            cookie => cookie.parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid cookie"))?,
+        };
+        let entries = fs_async::read_dir_from(self.path(path), offset, READDIR_BATCH_LEN).await?;
+        if entries.is_empty() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(entries.into_iter()
+            .map(|(name, offset, metadata)| {
+                let attrs = metadata.map(attrs_from_metadata).unwrap_or_default();
+                (Name::new(name.to_string_lossy().to_string(), attrs), offset.to_string())
+            })
+            .collect())
+    }
+    async fn remove_tree_supported(&self) -> bool { true }
+    async fn remove_tree(&self, path: String) -> Result<()> {
+        // Walked down from the root rather than opened by its full path, so
+        // that no symlink leading to it is followed either.
+        let (base, local_path) = match self.root {
+            Some(ref root) => {
+                let local_path = self.path(path.clone());
+                (root.clone(), local_path.strip_prefix(root).map(Path::to_path_buf).unwrap_or_default())
+            },
+            None => (PathBuf::from("."), PathBuf::from(&path)),
+        };
+        fs_async::remove_tree(base, local_path, MAX_TREE_DEPTH).await.map_err(|(failed, err)| {
+            let failed = match failed.to_str() {
+                Some("") => path.clone(),
+                _ => format!("{}/{}", path.trim_end_matches('/'), failed.to_string_lossy()),
+            };
+            std::io::Error::new(err.kind(), format!("cannot remove {}: {}", failed, err)).into()
+        })
+    }
+    async fn users_groups_by_id_supported(&self) -> bool { true }
+    async fn resolve_ids(&self, uids: Vec<u32>, gids: Vec<u32>) -> Result<(Vec<String>, Vec<String>)> {
+        Ok(fs_async::resolve_ids(uids, gids).await?)
+    }
+    async fn seek_hole_data_supported(&self) -> bool { true }
+    async fn seek_hole_data(&self, handle: &mut Self::FileHandle, offset: u64, whence: SeekWhence) -> Result<u64> {
+        handle.flush().await?;
+        Ok(fs_async::seek_hole_data(handle.file.clone(), offset, whence, self.effective_chunk_len()).await?)
+    }
+    async fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+        vec![
+            HashAlgorithm::Md5,
+            HashAlgorithm::Sha224,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha384,
+            HashAlgorithm::Sha512,
+        ]
+    }
+    async fn hash(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64) -> Result<Vec<u8>> {
+        handle.flush().await?;
+        Ok(fs_async::hash(handle.file.clone(), algorithm, offset, len, self.effective_chunk_len()).await?)
+    }
+    async fn hash_blocks(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32) -> Result<Vec<Vec<u8>>> {
+        handle.flush().await?;
+        Ok(fs_async::hash_blocks(handle.file.clone(), algorithm, offset, len, block_size, self.effective_chunk_len()).await?)
     }
     async fn hardlink_supported(&self) -> bool { true }
     async fn hardlink(&self, oldpath: String, newpath: String) -> Result<()> {
-        Ok(fs::hard_link(oldpath, newpath).await?)
+        Ok(fs::hard_link(self.path(oldpath), self.path(newpath)).await?)
     }
 }
 
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use thrusftp_protocol::types::FsStats;
+
+/// Remembers `statvfs` results per path for a fixed time. Entries are not
+/// invalidated by writes, so free space reported to clients may lag by up to
+/// `ttl`.
+#[derive(Debug)]
+pub(crate) struct StatvfsCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, FsStats)>>,
+}
+
+impl StatvfsCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Option<FsStats> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some((fetched, stats)) if fetched.elapsed() < self.ttl => Some(stats.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, path: String, stats: FsStats) {
+        let mut entries = self.entries.lock().unwrap();
+        // Drop stale entries so paths that are only asked for once do not
+        // accumulate.
+        let ttl = self.ttl;
+        entries.retain(|_, (fetched, _)| fetched.elapsed() < ttl);
+        entries.insert(path, (Instant::now(), stats));
+    }
+}
@@ -0,0 +1,22 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn appends_ignore_the_offset() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("log"), b"existing\n").unwrap();
+    let fs = LocalFs::new(dir);
+
+    for pflags in [
+        Pflags { read: false, write: false, append: true, creat: false, trunc: false, excl: false },
+        Pflags { read: true, write: true, append: true, creat: false, trunc: false, excl: false },
+    ] {
+        let mut file = fs.open("/log".to_string(), pflags, Attrs::default()).await.unwrap();
+        fs.write(&mut file, 0, b"first\n".to_vec()).await.unwrap();
+        fs.write(&mut file, 1 << 20, b"second\n".to_vec()).await.unwrap();
+        fs.close(FsHandle::File(file)).await.unwrap();
+    }
+    assert_eq!(std::fs::read(dir.join("log")).unwrap(), b"existing\nfirst\nsecond\nfirst\nsecond\n");
+}
@@ -0,0 +1,34 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, HashAlgorithm, Pflags};
+
+#[tokio::test]
+async fn chunk_len_does_not_change_hashes() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    std::fs::write(dir.join("file"), &data).unwrap();
+
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let mut hashes = Vec::new();
+    for fs in [LocalFs::new(dir), LocalFs::new(dir).chunk_len(7), LocalFs::new(dir).chunk_len(0)] {
+        let mut file = fs.open("/file".to_string(), pflags.clone(), Attrs::default()).await.unwrap();
+        let whole = fs.hash(&mut file, HashAlgorithm::Sha256, 0, 0).await.unwrap();
+        let blocks = fs.hash_blocks(&mut file, HashAlgorithm::Md5, 10, 90_000, 40_000).await.unwrap();
+        assert_eq!(blocks.len(), 3);
+        hashes.push((whole, blocks));
+        fs.close(FsHandle::File(file)).await.unwrap();
+    }
+    assert!(hashes.iter().all(|h| *h == hashes[0]));
+}
+
+#[tokio::test]
+async fn chunk_len_does_not_change_reads() {
+    // `/dev/zero` has no size, so the read buffer grows a chunk at a time.
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    for fs in [LocalFs::default(), LocalFs::default().chunk_len(7), LocalFs::default().chunk_len(0)] {
+        let mut file = fs.open("/dev/zero".to_string(), pflags.clone(), Attrs::default()).await.unwrap();
+        assert_eq!(fs.read(&mut file, 0, 100_000).await.unwrap(), vec![0; 100_000]);
+        fs.close(FsHandle::File(file)).await.unwrap();
+    }
+}
@@ -0,0 +1,34 @@
+use std::io::ErrorKind;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle, SftpError};
+use thrusftp_protocol::types::{Attrs, Pflags, StatusCode};
+
+fn is_eof(err: SftpError) -> bool {
+    err.status_code() == StatusCode::Eof && err.io_error().unwrap().kind() == ErrorKind::UnexpectedEof
+}
+
+#[tokio::test]
+async fn reads_at_the_end_of_the_file() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("file"), b"0123456789").unwrap();
+    std::fs::write(dir.join("empty"), b"").unwrap();
+    let fs = LocalFs::new(dir);
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+
+    let mut file = fs.open("/file".to_string(), pflags.clone(), Attrs::default()).await.unwrap();
+    // Crossing the end returns what is there, the next read is at the end.
+    assert_eq!(fs.read(&mut file, 6, 10).await.unwrap(), b"6789");
+    assert!(is_eof(fs.read(&mut file, 10, 10).await.unwrap_err()));
+    assert!(is_eof(fs.read(&mut file, 11, 10).await.unwrap_err()));
+    assert_eq!(fs.read(&mut file, 9, 1).await.unwrap(), b"9");
+    // Zero-length reads only fail at or past the end.
+    assert_eq!(fs.read(&mut file, 9, 0).await.unwrap(), b"");
+    assert!(is_eof(fs.read(&mut file, 10, 0).await.unwrap_err()));
+    fs.close(FsHandle::File(file)).await.unwrap();
+
+    let mut file = fs.open("/empty".to_string(), pflags, Attrs::default()).await.unwrap();
+    assert!(is_eof(fs.read(&mut file, 0, 10).await.unwrap_err()));
+    assert!(is_eof(fs.read(&mut file, 0, 0).await.unwrap_err()));
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
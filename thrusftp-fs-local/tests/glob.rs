@@ -0,0 +1,40 @@
+use thrusftp_fs_local::{LocalFs, MAX_GLOB_MATCHES};
+use thrusftp_protocol::Fs;
+
+#[tokio::test]
+async fn glob_matches_like_a_shell() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    for name in &["a.txt", "b.txt", "c.log", ".hidden.txt", "sub/d.txt"] {
+        std::fs::write(dir.join(name), b"x").unwrap();
+    }
+    let pattern = |p: &str| dir.join(p).to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    let names = fs.glob(pattern("*.txt")).await.unwrap();
+    let filenames: Vec<_> = names.iter().map(|name| name.filename.clone()).collect();
+    assert_eq!(filenames, [pattern("a.txt"), pattern("b.txt")]);
+    assert_eq!(names[0].attrs.size, Some(1));
+
+    let names = fs.glob(pattern("*/*.txt")).await.unwrap();
+    assert_eq!(names.len(), 1);
+    assert_eq!(names[0].filename, pattern("sub/d.txt"));
+
+    assert!(fs.glob(pattern("*.none")).await.unwrap().is_empty());
+    assert!(fs.glob(pattern("[")).await.is_err());
+}
+
+#[tokio::test]
+async fn glob_refuses_too_many_matches() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    for i in 0..=MAX_GLOB_MATCHES {
+        std::fs::write(dir.join(i.to_string()), b"").unwrap();
+    }
+
+    let fs = LocalFs::default();
+    assert!(fs.glob(dir.join("*").to_string_lossy().into_owned()).await.is_err());
+    let names = fs.glob(dir.join("1*").to_string_lossy().into_owned()).await.unwrap();
+    assert!(!names.is_empty());
+}
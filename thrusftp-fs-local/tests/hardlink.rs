@@ -0,0 +1,33 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::{Attrs, INO_EXTENDED_ATTR, NLINK_EXTENDED_ATTR};
+
+fn ino_nlink(attrs: &Attrs) -> (u64, u64) {
+    let get = |r#type| attrs.extended_attr(r#type).unwrap().parse().unwrap();
+    (get(INO_EXTENDED_ATTR), get(NLINK_EXTENDED_ATTR))
+}
+
+#[tokio::test]
+async fn hardlinks_share_inode() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("a"), b"data").unwrap();
+    std::fs::write(dir.join("other"), b"data").unwrap();
+
+    let fs = LocalFs::new(dir);
+    assert_eq!(ino_nlink(&fs.lstat("a".to_string()).await.unwrap()).1, 1);
+    fs.hardlink("a".to_string(), "b".to_string()).await.unwrap();
+
+    let (a_ino, a_nlink) = ino_nlink(&fs.lstat("a".to_string()).await.unwrap());
+    let (b_ino, b_nlink) = ino_nlink(&fs.stat("b".to_string()).await.unwrap());
+    let (other_ino, _) = ino_nlink(&fs.lstat("other".to_string()).await.unwrap());
+    assert_eq!(a_ino, b_ino);
+    assert_ne!(a_ino, other_ino);
+    assert!(a_nlink >= 2 && b_nlink >= 2);
+
+    // Listings show the link count too.
+    let mut dir_handle = fs.opendir("/".to_string()).await.unwrap();
+    let names = fs.readdir(&mut dir_handle).await.unwrap();
+    let b = names.iter().find(|name| name.filename == "b").unwrap();
+    assert_eq!(b.longname.split_whitespace().nth(1), Some("2"));
+}
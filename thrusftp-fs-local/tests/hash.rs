@@ -0,0 +1,29 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, HashAlgorithm, Pflags};
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[tokio::test]
+async fn hashes_cover_the_requested_range() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("file"), b"xxabcabc").unwrap();
+    let fs = LocalFs::new(dir);
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    let md5_abc = "900150983cd24fb0d6963f7d28e17f72";
+    let sha256_abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(hex(&fs.hash(&mut file, HashAlgorithm::Md5, 2, 3).await.unwrap()), md5_abc);
+    assert_eq!(hex(&fs.hash(&mut file, HashAlgorithm::Sha256, 5, 3).await.unwrap()), sha256_abc);
+    // A length of 0 runs to the end of the file, as does one past it.
+    assert_eq!(hex(&fs.hash(&mut file, HashAlgorithm::Md5, 5, 0).await.unwrap()), md5_abc);
+    assert_eq!(hex(&fs.hash(&mut file, HashAlgorithm::Md5, 5, 100).await.unwrap()), md5_abc);
+
+    let blocks = fs.hash_blocks(&mut file, HashAlgorithm::Sha256, 2, 6, 3).await.unwrap();
+    assert_eq!(blocks.iter().map(|block| hex(block)).collect::<Vec<_>>(), [sha256_abc, sha256_abc]);
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
@@ -0,0 +1,41 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn open_directory_fails() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+
+    let fs = LocalFs::default();
+    for &write in &[false, true] {
+        let pflags = Pflags { read: true, write, append: false, creat: false, trunc: false, excl: false };
+        let err = fs.open(dir.to_string_lossy().into_owned(), pflags, Attrs::default()).await.err().unwrap();
+        assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::IsADirectory);
+    }
+}
+
+#[tokio::test]
+async fn open_without_access_flags_reads() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir);
+
+    let pflags = Pflags { read: false, write: false, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/created".to_string(), pflags, Attrs::default()).await.unwrap();
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(0));
+    assert!(fs.write(&mut file, 0, b"data".to_vec()).await.is_err());
+    std::fs::write(dir.join("created"), b"data").unwrap();
+    assert_eq!(fs.read(&mut file, 0, 64).await.unwrap(), b"data");
+
+    // Exclusive creation works without write access too.
+    let pflags = Pflags { read: false, write: false, append: false, creat: true, trunc: false, excl: true };
+    assert!(fs.open("/created".to_string(), pflags.clone(), Attrs::default()).await.is_err());
+    fs.open("/new".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    // Truncating does not.
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: true, excl: false };
+    let err = fs.open("/created".to_string(), pflags, Attrs::default()).await.err().unwrap();
+    assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(std::fs::read(dir.join("created")).unwrap(), b"data");
+}
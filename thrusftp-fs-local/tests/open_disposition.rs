@@ -0,0 +1,48 @@
+use std::io::ErrorKind;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::*;
+
+/// Opens `path` for writing with the version 5 `disposition` and returns
+/// the error kind, if it failed, and what the file holds afterwards.
+async fn open_v5(path: &std::path::Path, disposition: u32) -> (Option<ErrorKind>, Option<Vec<u8>>) {
+    let pflags = Pflags::from_v5(ACE4_WRITE_DATA, disposition).unwrap();
+    let res = LocalFs::default().open(path.to_string_lossy().into_owned(), pflags, Attrs::default()).await;
+    (res.err().map(|err| err.io_error().unwrap().kind()), std::fs::read(path).ok())
+}
+
+#[tokio::test]
+async fn dispositions() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let existing = dir.join("existing");
+    let missing = dir.join("missing");
+
+    let cases = [
+        (SSH_FXF_CREATE_NEW, (Some(ErrorKind::AlreadyExists), Some(b"data".to_vec())), (None, Some(Vec::new()))),
+        (SSH_FXF_CREATE_TRUNCATE, (None, Some(Vec::new())), (None, Some(Vec::new()))),
+        (SSH_FXF_OPEN_EXISTING, (None, Some(b"data".to_vec())), (Some(ErrorKind::NotFound), None)),
+        (SSH_FXF_OPEN_OR_CREATE, (None, Some(b"data".to_vec())), (None, Some(Vec::new()))),
+        (SSH_FXF_TRUNCATE_EXISTING, (None, Some(Vec::new())), (Some(ErrorKind::NotFound), None)),
+    ];
+    for (disposition, on_existing, on_missing) in cases {
+        std::fs::write(&existing, b"data").unwrap();
+        let _ = std::fs::remove_file(&missing);
+        assert_eq!(open_v5(&existing, disposition).await, on_existing, "disposition {} on an existing file", disposition);
+        assert_eq!(open_v5(&missing, disposition).await, on_missing, "disposition {} on a missing file", disposition);
+    }
+}
+
+#[test]
+fn v5_flags() {
+    let pflags = Pflags::from_v5(ACE4_READ_DATA | ACE4_APPEND_DATA, SSH_FXF_OPEN_OR_CREATE).unwrap();
+    assert!(pflags.read && pflags.write && pflags.append && pflags.creat && !pflags.trunc && !pflags.excl);
+    assert_eq!(pflags.disposition(), Disposition::OpenOrCreate);
+
+    let pflags = Pflags::from_v5(ACE4_WRITE_DATA, SSH_FXF_CREATE_NEW | SSH_FXF_APPEND_DATA).unwrap();
+    assert!(!pflags.read && pflags.write && pflags.append && pflags.excl);
+    assert_eq!(pflags.disposition(), Disposition::CreateNew);
+
+    assert!(Pflags::from_v5(ACE4_READ_DATA, 5).is_err());
+    assert!(Pflags::from_v5(ACE4_READ_DATA, SSH_FXF_OPEN_EXISTING | 0x20).is_err());
+}
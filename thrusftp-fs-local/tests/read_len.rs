@@ -0,0 +1,29 @@
+use std::io::Write;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn huge_reads_allocate_what_is_there() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("tiny"), vec![7u8; 1024]).unwrap();
+    let fs = LocalFs::new(dir);
+
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let mut file = fs.open("/tiny".to_string(), pflags, Attrs::default()).await.unwrap();
+    let data = fs.read(&mut file, 0, u32::MAX).await.unwrap();
+    assert_eq!(data, vec![7u8; 1024]);
+    assert!(data.capacity() <= 1024 * 1024, "allocated {} bytes", data.capacity());
+
+    let data = fs.read(&mut file, 1000, u32::MAX).await.unwrap();
+    assert_eq!(data, vec![7u8; 24]);
+    assert!(data.capacity() <= 1024 * 1024, "allocated {} bytes", data.capacity());
+
+    // Data appended after the file was opened is still read.
+    std::fs::OpenOptions::new().append(true).open(dir.join("tiny")).unwrap().write_all(&[8u8; 100]).unwrap();
+    let data = fs.read(&mut file, 1000, u32::MAX).await.unwrap();
+    assert_eq!(data.len(), 124);
+    assert!(fs.read(&mut file, 1124, u32::MAX).await.is_err());
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeSet;
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+
+#[tokio::test]
+async fn readdir_lists_every_entry_once() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let expected: BTreeSet<String> = (0..1000).map(|i| format!("file{}", i)).collect();
+    for name in &expected {
+        std::fs::write(dir.join(name), name.as_bytes()).unwrap();
+    }
+
+    let fs = LocalFs::default();
+    let mut handle = fs.opendir(dir.to_string_lossy().into_owned()).await.unwrap();
+    let mut seen = BTreeSet::new();
+    loop {
+        match fs.readdir(&mut handle).await {
+            Ok(names) => {
+                assert!(!names.is_empty());
+                for name in names {
+                    assert_eq!(name.attrs.size, Some(name.filename.len() as u64));
+                    assert!(seen.insert(name.filename), "entry listed twice");
+                }
+            },
+            Err(err) => {
+                let err = err.io_error().unwrap();
+                assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+                break;
+            },
+        }
+    }
+    assert_eq!(seen, expected);
+}
+
+#[tokio::test]
+async fn read_dir_all_matches_readdir() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    for i in 0..200 {
+        std::fs::write(dir.join(format!("file{}", i)), b"").unwrap();
+    }
+    std::os::unix::fs::symlink("missing", dir.join("dangling")).unwrap();
+
+    let fs = LocalFs::new(dir);
+    let all: BTreeSet<String> = fs.read_dir_all("/".to_string()).await.unwrap()
+        .into_iter().map(|name| name.filename).collect();
+    let mut handle = fs.opendir("/".to_string()).await.unwrap();
+    let mut listed = BTreeSet::new();
+    while let Ok(names) = fs.readdir(&mut handle).await {
+        listed.extend(names.into_iter().map(|name| name.filename));
+    }
+    assert_eq!(all.len(), 202);
+    assert_eq!(all, listed);
+    assert!(fs.read_dir_all("/file0".to_string()).await.is_err());
+    assert!(fs.read_dir_all("/missing".to_string()).await.is_err());
+}
+
+/// Every entry of `path`, through `readdir`.
+async fn list(fs: &LocalFs, path: &std::path::Path) -> Vec<thrusftp_protocol::types::Name> {
+    let mut handle = fs.opendir(path.to_string_lossy().into_owned()).await.unwrap();
+    let mut names = Vec::new();
+    while let Ok(batch) = fs.readdir(&mut handle).await {
+        names.extend(batch);
+    }
+    fs.close(thrusftp_protocol::FsHandle::Dir(handle)).await.unwrap();
+    names
+}
+
+#[tokio::test]
+async fn entries_without_metadata_are_still_listed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("file"), b"data").unwrap();
+    std::os::unix::fs::symlink("missing", dir.join("link")).unwrap();
+
+    // A dangling symlink is listed as the link it is.
+    let fs = LocalFs::default();
+    for names in [list(&fs, dir).await, fs.read_dir_all(dir.to_string_lossy().into_owned()).await.unwrap()] {
+        let mut names = names;
+        names.sort_by(|a, b| a.filename.cmp(&b.filename));
+        assert_eq!(names.iter().map(|name| name.filename.as_str()).collect::<Vec<_>>(), ["file", "link"]);
+        assert_eq!(names[0].attrs.size, Some(4));
+        assert_eq!(names[1].attrs.permissions.unwrap() & 0o170000, 0o120000);
+    }
+
+    // Without search permission the names can be read, but not their
+    // metadata. Root may search anyway, then the attributes are there.
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o400)).unwrap();
+    let unsearchable = std::fs::symlink_metadata(dir.join("file")).is_err();
+    for names in [list(&fs, dir).await, fs.read_dir_all(dir.to_string_lossy().into_owned()).await.unwrap()] {
+        let mut names = names;
+        names.sort_by(|a, b| a.filename.cmp(&b.filename));
+        assert_eq!(names.iter().map(|name| name.filename.as_str()).collect::<Vec<_>>(), ["file", "link"]);
+        assert_eq!(names[0].attrs.size.is_none(), unsearchable);
+    }
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+}
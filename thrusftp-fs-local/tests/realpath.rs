@@ -0,0 +1,70 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+
+#[tokio::test]
+async fn realpath_of_missing_paths() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = std::fs::canonicalize(&temp).unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::os::unix::fs::symlink("sub", dir.join("link")).unwrap();
+    let base = dir.to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    let realpath = |path: &str| fs.realpath(format!("{}/{}", base, path));
+    assert_eq!(realpath("sub").await.unwrap(), format!("{}/sub", base));
+    assert_eq!(realpath("new").await.unwrap(), format!("{}/new", base));
+    assert_eq!(realpath("link/new").await.unwrap(), format!("{}/sub/new", base));
+    assert_eq!(realpath("missing/./deeper/../new").await.unwrap(), format!("{}/missing/new", base));
+    assert_eq!(realpath("missing/../../new").await.unwrap(), format!("{}/new", dir.parent().unwrap().to_string_lossy()));
+}
+
+#[tokio::test]
+async fn realpath_through_dangling_symlinks() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = std::fs::canonicalize(&temp).unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("file"), b"").unwrap();
+    let base = dir.to_string_lossy().into_owned();
+    std::os::unix::fs::symlink("sub/missing/deeper", dir.join("dangling")).unwrap();
+    std::os::unix::fs::symlink(dir.join("sub/gone"), dir.join("absolute")).unwrap();
+
+    let fs = LocalFs::default();
+    let realpath = |path: &str| fs.realpath(format!("{}/{}", base, path));
+    // The link is followed although its target does not exist, so `..`
+    // leads to the parent of the target, not of the link.
+    assert_eq!(realpath("dangling").await.unwrap(), format!("{}/sub/missing/deeper", base));
+    assert_eq!(realpath("dangling/../new").await.unwrap(), format!("{}/sub/missing/new", base));
+    assert_eq!(realpath("absolute/../new").await.unwrap(), format!("{}/sub/new", base));
+    let err = realpath("file/new").await.unwrap_err();
+    assert_eq!(err.io_error().and_then(|err| err.raw_os_error()), Some(libc::ENOTDIR));
+}
+
+#[tokio::test]
+async fn realpath_of_symlink_loops() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = std::fs::canonicalize(&temp).unwrap();
+    std::fs::create_dir_all(dir.join("target")).unwrap();
+    std::os::unix::fs::symlink("self", dir.join("self")).unwrap();
+    std::os::unix::fs::symlink("pong", dir.join("ping")).unwrap();
+    std::os::unix::fs::symlink("ping", dir.join("pong")).unwrap();
+    // `link0` points at `target`, every further link at the one before.
+    std::os::unix::fs::symlink("target", dir.join("link0")).unwrap();
+    for i in 1..50 {
+        std::os::unix::fs::symlink(format!("link{}", i - 1), dir.join(format!("link{}", i))).unwrap();
+    }
+    let base = dir.to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    let realpath = |path: &str| fs.realpath(format!("{}/{}", base, path));
+    let is_eloop = |err: thrusftp_protocol::SftpError| err.io_error().and_then(|err| err.raw_os_error()) == Some(libc::ELOOP);
+    assert!(is_eloop(realpath("self").await.unwrap_err()));
+    assert!(is_eloop(realpath("ping").await.unwrap_err()));
+    // A missing path below a loop does not get past it either.
+    assert!(is_eloop(realpath("self/new").await.unwrap_err()));
+    assert!(is_eloop(realpath("ping/new/deeper").await.unwrap_err()));
+
+    // Chains are followed up to the kernel's limit, not beyond.
+    assert_eq!(realpath("link20/new").await.unwrap(), format!("{}/target/new", base));
+    assert!(is_eloop(realpath("link49").await.unwrap_err()));
+    assert!(is_eloop(realpath("link49/new").await.unwrap_err()));
+}
@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+use std::io::ErrorKind;
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+
+fn path(dir: &Path, name: &str) -> String {
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+#[tokio::test]
+async fn rename_refuses_to_overwrite() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("old"), b"old").unwrap();
+    std::fs::write(dir.join("new"), b"new").unwrap();
+
+    let err = LocalFs::default().rename(path(dir, "old"), path(dir, "new")).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read(dir.join("old")).unwrap(), b"old");
+    assert_eq!(std::fs::read(dir.join("new")).unwrap(), b"new");
+}
+
+#[tokio::test]
+async fn rename_refuses_to_overwrite_dangling_symlink() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("old"), b"old").unwrap();
+    std::os::unix::fs::symlink("missing", dir.join("new")).unwrap();
+
+    assert!(LocalFs::default().rename(path(dir, "old"), path(dir, "new")).await.is_err());
+    assert_eq!(std::fs::read_link(dir.join("new")).unwrap(), PathBuf::from("missing"));
+}
+
+#[tokio::test]
+async fn posix_rename_replaces_target() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("old"), b"old").unwrap();
+    std::fs::write(dir.join("new"), b"new").unwrap();
+
+    LocalFs::default().posix_rename(path(dir, "old"), path(dir, "new")).await.unwrap();
+    assert!(!dir.join("old").exists());
+    assert_eq!(std::fs::read(dir.join("new")).unwrap(), b"old");
+}
+
+#[tokio::test]
+async fn posix_rename_refuses_mismatched_targets() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("file"), b"file").unwrap();
+    std::fs::create_dir(dir.join("dir")).unwrap();
+    std::fs::create_dir(dir.join("full")).unwrap();
+    std::fs::write(dir.join("full/entry"), b"entry").unwrap();
+
+    let fs = LocalFs::default();
+    for (old, new, kind) in [
+        ("dir", "file", ErrorKind::NotADirectory),
+        ("file", "dir", ErrorKind::IsADirectory),
+        ("dir", "full", ErrorKind::DirectoryNotEmpty),
+    ] {
+        let err = fs.posix_rename(path(dir, old), path(dir, new)).await.unwrap_err();
+        assert_eq!(err.io_error().unwrap().kind(), kind, "{} to {}", old, new);
+        assert!(err.to_string().starts_with(&format!("cannot rename {} to {}: ", path(dir, old), path(dir, new))));
+    }
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"file");
+    assert!(dir.join("dir").is_dir());
+    assert_eq!(std::fs::read(dir.join("full/entry")).unwrap(), b"entry");
+}
+
+#[tokio::test]
+async fn rename_across_directories() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir(dir.join("a")).unwrap();
+    std::fs::create_dir(dir.join("b")).unwrap();
+    std::fs::write(dir.join("a/file"), b"data").unwrap();
+
+    LocalFs::default().rename(path(dir, "a/file"), path(dir, "b/file")).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("b/file")).unwrap(), b"data");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_renames_never_clobber() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    for round in 0..20 {
+        let target = path(dir, &format!("target{}", round));
+        let renames: Vec<_> = (0..8)
+            .map(|i| {
+                let source = path(dir, &format!("source{}-{}", round, i));
+                std::fs::write(&source, format!("{}", i)).unwrap();
+                let target = target.clone();
+                tokio::spawn(async move { LocalFs::default().rename(source, target).await.is_ok() })
+            })
+            .collect();
+        let mut succeeded = 0;
+        for rename in renames {
+            if rename.await.unwrap() {
+                succeeded += 1;
+            }
+        }
+        assert_eq!(succeeded, 1, "round {}", round);
+    }
+}
@@ -0,0 +1,58 @@
+use thrusftp_fs_local::{LocalFs, MAX_TREE_DEPTH};
+use thrusftp_protocol::Fs;
+
+#[tokio::test]
+async fn remove_tree() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("served/tree/a/b")).unwrap();
+    std::fs::create_dir_all(dir.join("outside")).unwrap();
+    std::fs::write(dir.join("outside/keep"), b"keep").unwrap();
+    std::fs::write(dir.join("served/tree/file"), b"x").unwrap();
+    std::fs::write(dir.join("served/tree/a/b/file"), b"x").unwrap();
+    std::os::unix::fs::symlink(dir.join("outside"), dir.join("served/tree/a/link")).unwrap();
+    std::os::unix::fs::symlink(dir.join("outside"), dir.join("served/link")).unwrap();
+    let fs = LocalFs::new(dir.join("served"));
+
+    // Links are removed, never followed.
+    fs.remove_tree("/tree".to_string()).await.unwrap();
+    assert!(!dir.join("served/tree").exists());
+    assert_eq!(std::fs::read(dir.join("outside/keep")).unwrap(), b"keep");
+    let err = fs.remove_tree("/link".to_string()).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::NotADirectory);
+    assert!(dir.join("outside/keep").exists());
+
+    assert!(fs.remove_tree("/missing".to_string()).await.is_err());
+    let err = fs.remove_tree("/..".to_string()).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(dir.join("served").exists());
+
+    // Nor is a link on the way to the directory.
+    std::fs::create_dir_all(dir.join("outside/victim")).unwrap();
+    std::fs::write(dir.join("outside/victim/keep"), b"keep").unwrap();
+    assert!(fs.remove_tree("/link/victim".to_string()).await.is_err());
+    assert_eq!(std::fs::read(dir.join("outside/victim/keep")).unwrap(), b"keep");
+
+    // Without a root, nothing that ends in a parent is removed either.
+    let unrooted = LocalFs::default();
+    let err = unrooted.remove_tree(format!("{}/served/tree2/..", dir.display())).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(dir.join("served").exists());
+    std::fs::create_dir_all(dir.join("served/tree2/sub")).unwrap();
+    unrooted.remove_tree(format!("{}/served/tree2", dir.display())).await.unwrap();
+    assert!(!dir.join("served/tree2").exists());
+
+    // Too deep a tree is removed down to the limit, then the rest is left
+    // and the error names where it stopped.
+    let mut deep = dir.join("served/deep");
+    for _ in 0..=MAX_TREE_DEPTH {
+        deep.push("d");
+    }
+    std::fs::create_dir_all(&deep).unwrap();
+    std::fs::write(dir.join("served/deep/file"), b"x").unwrap();
+    let err = fs.remove_tree("/deep".to_string()).await.unwrap_err();
+    let expected = format!("cannot remove /deep{}", "/d".repeat(MAX_TREE_DEPTH + 1));
+    assert!(err.to_string().starts_with(&expected), "{}", err);
+    assert!(!dir.join("served/deep/file").exists());
+    assert!(deep.exists());
+}
@@ -0,0 +1,37 @@
+use std::io::ErrorKind;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn paths_are_resolved_against_the_root() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("served/sub")).unwrap();
+    std::fs::write(dir.join("outside"), b"secret").unwrap();
+    std::os::unix::fs::symlink("../outside", dir.join("served/escape")).unwrap();
+    let fs = LocalFs::new(dir.join("served"));
+
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert!(dir.join("served/file").exists());
+
+    // Relative paths and `..` stay below the root.
+    assert!(fs.stat("file".to_string()).await.is_ok());
+    assert!(fs.stat("/../../file".to_string()).await.is_ok());
+    assert!(fs.stat("sub/../../outside".to_string()).await.is_err());
+    fs.rename("file".to_string(), "sub/moved".to_string()).await.unwrap();
+    assert!(dir.join("served/sub/moved").exists());
+
+    assert_eq!(fs.realpath(".".to_string()).await.unwrap(), "/");
+    assert_eq!(fs.realpath("/..".to_string()).await.unwrap(), "/");
+    assert_eq!(fs.realpath("sub/new".to_string()).await.unwrap(), "/sub/new");
+    let err = fs.realpath("escape".to_string()).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().kind(), ErrorKind::PermissionDenied);
+
+    let mut names: Vec<_> = fs.glob("/*".to_string()).await.unwrap().into_iter().map(|name| name.filename).collect();
+    names.sort();
+    assert_eq!(names, ["/escape", "/sub"]);
+    assert!(fs.glob("../*".to_string()).await.unwrap().is_empty());
+}
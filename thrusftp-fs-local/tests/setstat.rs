@@ -0,0 +1,53 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn fsetstat_mode_keeps_content() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+    let filename = path.to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: true, excl: false };
+    let mut file = fs.open(filename, pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"uploaded".to_vec()).await.unwrap();
+    let attrs = Attrs {
+        permissions: Some(0o444),
+        atime_mtime: Some((1_000_000_000, 1_000_000_000)),
+        ..Default::default()
+    };
+    fs.fsetstat(&mut file, attrs).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"uploaded");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o444);
+    assert_eq!(metadata.mtime(), 1_000_000_000);
+}
+
+#[tokio::test]
+async fn setstat_size_mode_and_times() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+    std::fs::write(&path, b"uploaded").unwrap();
+
+    // Truncating must neither be blocked by the new mode nor bump the
+    // modification time that is set along with it.
+    let attrs = Attrs {
+        size: Some(6),
+        permissions: Some(0o400),
+        atime_mtime: Some((1_000_000_000, 1_100_000_000)),
+        ..Default::default()
+    };
+    LocalFs::default().setstat(path.to_string_lossy().into_owned(), attrs).await.unwrap();
+
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"upload");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o400);
+    assert_eq!(metadata.mtime(), 1_100_000_000);
+}
@@ -0,0 +1,37 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, PartialWrite};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+// Mounting a small tmpfs needs privileges tests cannot count on, so a file
+// size limit stands in for a full disk: writes past it fail with EFBIG the
+// same way they would with ENOSPC. This is the only test in this binary
+// because the limit applies to the whole process.
+#[tokio::test]
+async fn failed_write_reports_bytes_written() {
+    const LIMIT: u64 = 4096;
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        let limit = libc::rlimit { rlim_cur: LIMIT, rlim_max: libc::RLIM_INFINITY };
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+    }
+
+    let fs = LocalFs::default();
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open(path.to_string_lossy().into_owned(), pflags, Attrs::default()).await.unwrap();
+    let err = fs.write(&mut file, 0, vec![0x55; 10000]).await.unwrap_err();
+    let io_err = err.io_error().unwrap();
+    let partial = io_err.get_ref().unwrap().downcast_ref::<PartialWrite>().unwrap();
+    assert_eq!(partial.written, LIMIT);
+    assert_eq!(partial.len, 10000);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), LIMIT);
+
+    // Nothing at all written: the plain OS error comes through.
+    let err = fs.write(&mut file, LIMIT, vec![0x55; 10]).await.unwrap_err();
+    let io_err = err.io_error().unwrap();
+    assert_eq!(io_err.raw_os_error(), Some(libc::EFBIG));
+}
@@ -0,0 +1,28 @@
+use std::os::unix::fs::MetadataExt;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn writing_past_the_end_leaves_a_hole() {
+    const OFFSET: u64 = 1 << 30;
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir);
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/sparse".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"start".to_vec()).await.unwrap();
+    fs.write(&mut file, OFFSET, b"end".to_vec()).await.unwrap();
+
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(OFFSET + 3));
+    assert_eq!(fs.read(&mut file, OFFSET - 2, 5).await.unwrap(), b"\0\0end");
+    assert_eq!(fs.read(&mut file, 4096, 4).await.unwrap(), [0; 4]);
+
+    // A gibibyte of zeros would take that many blocks; the hole takes none.
+    let metadata = std::fs::metadata(dir.join("sparse")).unwrap();
+    assert_eq!(metadata.len(), OFFSET + 3);
+    assert!(metadata.blocks() * 512 < 1 << 20, "{} blocks", metadata.blocks());
+
+    fs.close(thrusftp_protocol::FsHandle::File(file)).await.unwrap();
+}
@@ -0,0 +1,38 @@
+use thrusftp_fs_local::{LocalFs, RDEV_EXTENDED_ATTR};
+use thrusftp_protocol::{Fs, SftpError};
+
+#[tokio::test]
+async fn stat_char_device() {
+    let attrs = LocalFs::default().stat("/dev/null".to_string()).await.unwrap();
+    assert_eq!(attrs.permissions.unwrap() & libc::S_IFMT, libc::S_IFCHR);
+    let rdev: Vec<_> = attrs.extended_attrs.iter()
+        .filter(|attr| attr.r#type == RDEV_EXTENDED_ATTR)
+        .map(|attr| attr.data.as_str())
+        .collect();
+    assert_eq!(rdev, ["1:3"]);
+}
+
+#[tokio::test]
+async fn stat_regular_file_has_no_rdev() {
+    let attrs = LocalFs::default().stat("/proc/self/exe".to_string()).await.unwrap();
+    assert_eq!(attrs.permissions.unwrap() & libc::S_IFMT, libc::S_IFREG);
+    assert!(attrs.extended_attrs.iter().all(|attr| attr.r#type != RDEV_EXTENDED_ATTR));
+}
+
+#[tokio::test]
+async fn mknod_fifo_but_not_device() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::default();
+
+    let fifo = dir.join("fifo").to_string_lossy().into_owned();
+    fs.mknod(fifo.clone(), libc::S_IFIFO | 0o600, 0).await.unwrap();
+    let attrs = fs.lstat(fifo).await.unwrap();
+    assert_eq!(attrs.permissions.unwrap() & libc::S_IFMT, libc::S_IFIFO);
+
+    // `makedev(1, 3)`, spelled out as older `libc` releases only have it as
+    // an `unsafe fn`.
+    let dev = dir.join("null").to_string_lossy().into_owned();
+    let err = fs.mknod(dev, libc::S_IFCHR | 0o600, (1 << 8) | 3).await.unwrap_err();
+    assert!(matches!(err, SftpError::Unsupported));
+}
@@ -0,0 +1,69 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::{SSH2_FXE_STATVFS_ST_NOSUID, SSH2_FXE_STATVFS_ST_RDONLY};
+
+#[tokio::test]
+async fn statvfs_reports_the_served_filesystem() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::os::unix::fs::symlink("/dev/shm", dir.join("shm")).unwrap();
+    let unserved = LocalFs::default();
+    let served_fsid = unserved.statvfs(dir.to_string_lossy().to_string()).await.unwrap().f_fsid;
+    let fs = LocalFs::new(dir);
+
+    // Paths are those below the root, not the host's.
+    assert_eq!(fs.statvfs("/".to_string()).await.unwrap().f_fsid, served_fsid);
+    assert_eq!(fs.statvfs("sub".to_string()).await.unwrap().f_fsid, served_fsid);
+    assert!(fs.statvfs("/missing".to_string()).await.is_err());
+
+    // A symlink out of the root does not reveal where it leads.
+    assert_eq!(fs.statvfs("/shm".to_string()).await.unwrap().f_fsid, served_fsid);
+    assert_eq!(unserved.statvfs(dir.join("shm").to_string_lossy().to_string()).await.unwrap().f_fsid,
+        unserved.statvfs("/dev/shm".to_string()).await.unwrap().f_fsid);
+}
+
+fn host_flags(path: &str) -> libc::c_ulong {
+    let cstr = std::ffi::CString::new(path).unwrap();
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::zeroed();
+    assert_eq!(unsafe { libc::statvfs(cstr.as_ptr(), stat.as_mut_ptr()) }, 0);
+    unsafe { stat.assume_init() }.f_flag
+}
+
+#[tokio::test]
+async fn statvfs_flags_are_translated() {
+    // Usually mounted `nosuid,nodev`, and `/proc/sys` read-only in
+    // containers, so between them most bits are exercised.
+    for path in ["/dev/shm", "/proc/sys", "/"] {
+        if !std::path::Path::new(path).exists() {
+            continue;
+        }
+        let host = host_flags(path);
+        let flags = LocalFs::default().statvfs(path.to_string()).await.unwrap().f_flag;
+        assert_eq!(flags & SSH2_FXE_STATVFS_ST_RDONLY != 0, host & libc::ST_RDONLY != 0, "{}", path);
+        assert_eq!(flags & SSH2_FXE_STATVFS_ST_NOSUID != 0, host & libc::ST_NOSUID != 0, "{}", path);
+        // Flags the reply has no bit for, like `ST_NODEV`, are not passed on.
+        assert_eq!(flags & !(SSH2_FXE_STATVFS_ST_RDONLY | SSH2_FXE_STATVFS_ST_NOSUID), 0, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn statvfs_cache_expires() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    let ttl = std::time::Duration::from_millis(200);
+    let cached = LocalFs::new(dir).statvfs_cache_ttl(ttl);
+    let uncached = LocalFs::new(dir);
+    cached.statvfs("/sub".to_string()).await.unwrap();
+
+    // Gone, but the cache still answers for it until the entry expires.
+    std::fs::remove_dir(dir.join("sub")).unwrap();
+    assert!(uncached.statvfs("/sub".to_string()).await.is_err());
+    assert!(cached.statvfs("/sub".to_string()).await.is_ok());
+    tokio::time::sleep(ttl).await;
+    assert!(cached.statvfs("/sub".to_string()).await.is_err());
+    // Failures are not cached.
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    assert!(cached.statvfs("/sub".to_string()).await.is_ok());
+}
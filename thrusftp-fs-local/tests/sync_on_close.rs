@@ -0,0 +1,39 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn close_syncs_written_files() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir).sync_on_close(true);
+
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/upload".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"durable".to_vec()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"durable");
+
+    // Read-only handles have nothing to sync.
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let mut file = fs.open("/upload".to_string(), pflags, Attrs::default()).await.unwrap();
+    assert_eq!(fs.read(&mut file, 0, 64).await.unwrap(), b"durable");
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
+
+#[tokio::test]
+async fn close_syncs_append_only_handles() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    // Fifos cannot be synced, so a close that syncs fails.
+    LocalFs::new(dir).mknod("/fifo".to_string(), libc::S_IFIFO | 0o600, 0).await.unwrap();
+    let pflags = Pflags { read: true, write: false, append: true, creat: false, trunc: false, excl: false };
+
+    let fs = LocalFs::new(dir).sync_on_close(true);
+    let file = fs.open("/fifo".to_string(), pflags.clone(), Attrs::default()).await.unwrap();
+    assert!(fs.close(FsHandle::File(file)).await.is_err());
+
+    let fs = LocalFs::new(dir);
+    let file = fs.open("/fifo".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
@@ -0,0 +1,62 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::{Attrs, Timespec, ATIME_EXTENDED_ATTR, MTIME_EXTENDED_ATTR};
+
+fn times(attrs: &Attrs) -> (Timespec, Timespec) {
+    let get = |r#type| attrs.extended_attrs.iter()
+        .find(|attr| attr.r#type == r#type)
+        .unwrap()
+        .data
+        .parse()
+        .unwrap();
+    (get(ATIME_EXTENDED_ATTR), get(MTIME_EXTENDED_ATTR))
+}
+
+#[tokio::test]
+async fn utimens_keeps_nanoseconds() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+    std::fs::write(&path, b"").unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    let atime = Timespec { secs: 1_000_000_000, nsecs: 123_456_789 };
+    let mtime = Timespec { secs: 1_500_000_000, nsecs: 987_654_321 };
+    fs.utimens(path.clone(), atime, mtime).await.unwrap();
+
+    let attrs = fs.stat(path.clone()).await.unwrap();
+    assert_eq!(times(&attrs), (atime, mtime));
+    assert_eq!(attrs.atime_mtime, Some((1_000_000_000, 1_500_000_000)));
+}
+
+#[tokio::test]
+async fn setstat_sets_whole_seconds() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+    std::fs::write(&path, b"").unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    let fs = LocalFs::default();
+    fs.utimens(path.clone(), Timespec { secs: 1, nsecs: 1 }, Timespec { secs: 2, nsecs: 2 }).await.unwrap();
+    let attrs = Attrs {
+        atime_mtime: Some((1_200_000_000, 1_300_000_000)),
+        ..Default::default()
+    };
+    fs.setstat(path.clone(), attrs).await.unwrap();
+
+    let attrs = fs.stat(path.clone()).await.unwrap();
+    assert_eq!(times(&attrs), (
+        Timespec { secs: 1_200_000_000, nsecs: 0 },
+        Timespec { secs: 1_300_000_000, nsecs: 0 },
+    ));
+}
+
+#[test]
+fn timespec_round_trips_as_string() {
+    let time = Timespec { secs: 42, nsecs: 5 };
+    assert_eq!(time.to_string(), "42.000000005");
+    assert_eq!("42.000000005".parse::<Timespec>().unwrap(), time);
+    assert!("42.5".parse::<Timespec>().is_err());
+}
@@ -0,0 +1,42 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn truncate_shrinks_and_grows() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir);
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"abcdef".to_vec()).await.unwrap();
+
+    fs.truncate(&mut file, 3).await.unwrap();
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(3));
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"abc");
+
+    // Growing reads back as zeros, also where the old content was.
+    fs.truncate(&mut file, 8).await.unwrap();
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(8));
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"abc\0\0\0\0\0");
+
+    fs.truncate(&mut file, 0).await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("file")).unwrap().len(), 0);
+    fs.close(FsHandle::File(file)).await.unwrap();
+}
+
+#[tokio::test]
+async fn truncate_sees_buffered_writes() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir).write_buffer(64);
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    // Still in the buffer when the file is cut, so cut along with it.
+    fs.write(&mut file, 0, b"abcdef".to_vec()).await.unwrap();
+    fs.truncate(&mut file, 2).await.unwrap();
+    fs.truncate(&mut file, 4).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"ab\0\0");
+}
@@ -0,0 +1,45 @@
+use std::os::unix::fs::PermissionsExt;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+fn mode(permissions: Option<u32>) -> Attrs {
+    Attrs { permissions, ..Default::default() }
+}
+
+/// The only test in this binary, as it briefly changes the process umask
+/// to read it.
+#[tokio::test]
+async fn umask_applies_to_new_files_and_directories() {
+    let process_umask = unsafe {
+        let umask = libc::umask(0);
+        libc::umask(umask);
+        umask as u32
+    };
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mode_of = |name: &str| std::fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o7777;
+
+    // Umask, requested mode, and the modes files and directories get.
+    let cases = [
+        (0, Some(0o777), 0o777, 0o777),
+        (0o027, None, 0o640, 0o750),
+        (0o077, Some(0o755), 0o700, 0o700),
+    ];
+    for (umask, requested, file_mode, dir_mode) in cases {
+        let fs = LocalFs::new(dir).umask(umask);
+        let name = format!("file-{:o}", umask);
+        let file = fs.open(format!("/{}", name), pflags.clone(), mode(requested)).await.unwrap();
+        fs.close(FsHandle::File(file)).await.unwrap();
+        assert_eq!(mode_of(&name), file_mode & !process_umask, "file with umask {:o}", umask);
+
+        let name = format!("dir-{:o}", umask);
+        fs.mkdir(format!("/{}", name), mode(requested)).await.unwrap();
+        assert_eq!(mode_of(&name), dir_mode & !process_umask, "directory with umask {:o}", umask);
+
+        // Only creation is masked; setstat sets exactly what it is given.
+        fs.setstat(format!("/{}", name), mode(Some(0o777))).await.unwrap();
+        assert_eq!(mode_of(&name), 0o777);
+    }
+}
@@ -0,0 +1,98 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags};
+
+#[tokio::test]
+async fn sequential_writes_are_coalesced() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir).write_buffer(16);
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/upload".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    fs.write(&mut file, 0, b"abcd".to_vec()).await.unwrap();
+    fs.write(&mut file, 4, b"efgh".to_vec()).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"");
+    // Going past 16 bytes writes out the first eight.
+    fs.write(&mut file, 8, b"ijklmnopq".to_vec()).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"abcdefgh");
+    // Any other request on the handle sees everything written before it.
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(17));
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"abcdefghijklmnopq");
+
+    // Writes as long as the buffer go straight through.
+    fs.write(&mut file, 17, vec![b'r'; 16]).await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("upload")).unwrap().len(), 33);
+
+    fs.write(&mut file, 33, b"end".to_vec()).await.unwrap();
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert_eq!(std::fs::metadata(dir.join("upload")).unwrap().len(), 36);
+}
+
+#[tokio::test]
+async fn appends_are_coalesced() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("log"), b"old\n").unwrap();
+    let fs = LocalFs::new(dir).write_buffer(64);
+    let pflags = Pflags { read: false, write: false, append: true, creat: false, trunc: false, excl: false };
+    let mut file = fs.open("/log".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    // Offsets do not matter to appends.
+    fs.write(&mut file, 0, b"one\n".to_vec()).await.unwrap();
+    fs.write(&mut file, 0, b"two\n".to_vec()).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("log")).unwrap(), b"old\n");
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("log")).unwrap(), b"old\none\ntwo\n");
+}
+
+#[tokio::test]
+async fn scattered_writes_land_where_they_were_sent() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let fs = LocalFs::new(dir).write_buffer(64);
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/file".to_string(), pflags, Attrs::default()).await.unwrap();
+
+    // Overlapping, backwards and contiguous writes, checked against the
+    // same writes made to a plain buffer.
+    let mut expected = Vec::new();
+    let mut state = 12345u32;
+    for i in 0..500u32 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        let offset = (state >> 8) as usize % 300;
+        let len = (state >> 20) as usize % 40;
+        let data = vec![i as u8; len];
+        fs.write(&mut file, offset as u64, data.clone()).await.unwrap();
+        if expected.len() < offset + len {
+            expected.resize(offset + len, 0);
+        }
+        expected[offset..offset + len].copy_from_slice(&data);
+
+        if i % 50 == 0 {
+            let read = fs.read(&mut file, 0, 1024).await.unwrap_or_default();
+            assert_eq!(read, expected, "after write {}", i);
+        }
+    }
+    fs.close(FsHandle::File(file)).await.unwrap();
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn dropped_handles_write_out_their_buffer() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("log"), b"old\n").unwrap();
+    let fs = LocalFs::new(dir).write_buffer(64);
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/upload".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 2, b"abcd".to_vec()).await.unwrap();
+    let pflags = Pflags { read: false, write: false, append: true, creat: false, trunc: false, excl: false };
+    let mut log = fs.open("/log".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut log, 0, b"new\n".to_vec()).await.unwrap();
+
+    drop(file);
+    drop(log);
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"\0\0abcd");
+    assert_eq!(std::fs::read(dir.join("log")).unwrap(), b"old\nnew\n");
+}
@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+use thrusftp_protocol::Result;
+
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags, Name};
+
+const ROOT: u64 = 0;
+const MAX_SYMLINKS: usize = 40;
+
+/// Default for `MemFs::max_file_size`.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+enum Node {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, u64>),
+    Symlink(String),
+}
+
+struct Inode {
+    node: Node,
+    permissions: u32,
+    atime: u32,
+    mtime: u32,
+}
+
+impl Inode {
+    fn new(node: Node, permissions: u32) -> Self {
+        let now = now();
+        Self { node, permissions: permissions & 0o7777, atime: now, mtime: now }
+    }
+
+    fn attrs(&self) -> Attrs {
+        let (size, file_type) = match self.node {
+            Node::File(ref data) => (data.len() as u64, S_IFREG),
+            Node::Dir(ref children) => (children.len() as u64, S_IFDIR),
+            Node::Symlink(ref target) => (target.len() as u64, S_IFLNK),
+        };
+        Attrs {
+            size: Some(size),
+            uid_gid: Some((0, 0)),
+            permissions: Some(file_type | self.permissions),
+            atime_mtime: Some((self.atime, self.mtime)),
+            extended_attrs: vec![],
+        }
+    }
+}
+
+struct Tree {
+    inodes: HashMap<u64, Inode>,
+    next_ino: u64,
+}
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0)
+}
+
+fn errno(errno: i32) -> Error {
+    Error::from_raw_os_error(errno)
+}
+
+fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+/// Splits `path` into the directory part and the last component.
+fn split_last(path: &str) -> std::io::Result<(&str, &str)> {
+    let trimmed = path.trim_end_matches('/');
+    let (dir, name) = match trimmed.rfind('/') {
+        Some(pos) => (&trimmed[..pos + 1], &trimmed[pos + 1..]),
+        None => ("", trimmed),
+    };
+    match name {
+        "" | "." | ".." => Err(errno(libc::EINVAL)),
+        _ => Ok((dir, name)),
+    }
+}
+
+impl Tree {
+    fn new() -> Self {
+        let mut inodes = HashMap::new();
+        inodes.insert(ROOT, Inode::new(Node::Dir(BTreeMap::new()), 0o755));
+        Self { inodes, next_ino: ROOT + 1 }
+    }
+
+    fn inode(&self, ino: u64) -> std::io::Result<&Inode> {
+        self.inodes.get(&ino).ok_or_else(|| errno(libc::ENOENT))
+    }
+
+    fn inode_mut(&mut self, ino: u64) -> std::io::Result<&mut Inode> {
+        self.inodes.get_mut(&ino).ok_or_else(|| errno(libc::ENOENT))
+    }
+
+    fn children(&self, ino: u64) -> std::io::Result<&BTreeMap<String, u64>> {
+        match self.inode(ino)?.node {
+            Node::Dir(ref children) => Ok(children),
+            _ => Err(errno(libc::ENOTDIR)),
+        }
+    }
+
+    fn children_mut(&mut self, ino: u64) -> std::io::Result<&mut BTreeMap<String, u64>> {
+        match self.inode_mut(ino)?.node {
+            Node::Dir(ref mut children) => Ok(children),
+            _ => Err(errno(libc::ENOTDIR)),
+        }
+    }
+
+    /// Walks `path` from the root. Symlinks are followed in every component
+    /// but the last, which is only followed if `follow` is set. Returns the
+    /// chain of `(inode, name)` pairs from the root to the target.
+    fn walk(&self, path: &str, follow: bool) -> std::io::Result<Vec<(u64, String)>> {
+        let mut stack = vec![(ROOT, String::new())];
+        let mut pending: VecDeque<String> = components(path).map(str::to_string).collect();
+        let mut links = 0;
+        while let Some(component) = pending.pop_front() {
+            match component.as_str() {
+                "." => continue,
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                    continue;
+                },
+                _ => {},
+            }
+            let dir = stack.last().unwrap().0;
+            let ino = *self.children(dir)?.get(&component).ok_or_else(|| errno(libc::ENOENT))?;
+            if let Node::Symlink(ref target) = self.inode(ino)?.node {
+                if follow || !pending.is_empty() {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(errno(libc::ELOOP));
+                    }
+                    if target.starts_with('/') {
+                        stack.truncate(1);
+                    }
+                    for c in components(target).rev() {
+                        pending.push_front(c.to_string());
+                    }
+                    continue;
+                }
+            }
+            stack.push((ino, component));
+        }
+        Ok(stack)
+    }
+
+    fn lookup(&self, path: &str, follow: bool) -> std::io::Result<u64> {
+        Ok(self.walk(path, follow)?.last().unwrap().0)
+    }
+
+    /// Resolves the directory containing the last component of `path`.
+    fn lookup_parent<'a>(&self, path: &'a str) -> std::io::Result<(u64, &'a str)> {
+        let (dir, name) = split_last(path)?;
+        let dir = self.lookup(dir, true)?;
+        self.children(dir)?;
+        Ok((dir, name))
+    }
+
+    fn insert(&mut self, dir: u64, name: &str, inode: Inode) -> std::io::Result<u64> {
+        let ino = self.next_ino;
+        let children = self.children_mut(dir)?;
+        if children.contains_key(name) {
+            return Err(errno(libc::EEXIST));
+        }
+        children.insert(name.to_string(), ino);
+        self.next_ino += 1;
+        self.inodes.insert(ino, inode);
+        self.inode_mut(dir)?.mtime = now();
+        Ok(ino)
+    }
+
+    fn unlink(&mut self, dir: u64, name: &str) -> std::io::Result<u64> {
+        let ino = self.children_mut(dir)?.remove(name).ok_or_else(|| errno(libc::ENOENT))?;
+        self.inode_mut(dir)?.mtime = now();
+        Ok(ino)
+    }
+
+    fn rename(&mut self, oldpath: &str, newpath: &str, overwrite: bool) -> std::io::Result<()> {
+        let (old_dir, old_name) = self.lookup_parent(oldpath)?;
+        let (new_dir, new_name) = self.lookup_parent(newpath)?;
+        let ino = *self.children(old_dir)?.get(old_name).ok_or_else(|| errno(libc::ENOENT))?;
+        // A directory cannot be moved into itself or one of its descendants.
+        if self.walk(split_last(newpath)?.0, true)?.iter().any(|(i, _)| *i == ino) {
+            return Err(errno(libc::EINVAL));
+        }
+        if let Some(&existing) = self.children(new_dir)?.get(new_name) {
+            if existing == ino {
+                return Ok(());
+            }
+            if !overwrite {
+                return Err(errno(libc::EEXIST));
+            }
+            match (&self.inode(ino)?.node, &self.inode(existing)?.node) {
+                (Node::Dir(_), Node::Dir(children)) if !children.is_empty() => return Err(errno(libc::ENOTEMPTY)),
+                (Node::Dir(_), Node::Dir(_)) => {},
+                (Node::Dir(_), _) => return Err(errno(libc::ENOTDIR)),
+                (_, Node::Dir(_)) => return Err(errno(libc::EISDIR)),
+                _ => {},
+            }
+            self.unlink(new_dir, new_name)?;
+            self.inodes.remove(&existing);
+        }
+        self.unlink(old_dir, old_name)?;
+        self.children_mut(new_dir)?.insert(new_name.to_string(), ino);
+        self.inode_mut(new_dir)?.mtime = now();
+        Ok(())
+    }
+}
+
+fn apply_attrs(inode: &mut Inode, attrs: Attrs, max_file_size: u64) -> std::io::Result<()> {
+    if let Some(size) = attrs.size {
+        match inode.node {
+            Node::File(_) if size > max_file_size => return Err(errno(libc::EFBIG)),
+            Node::File(ref mut data) => data.resize(size as usize, 0),
+            Node::Dir(_) => return Err(errno(libc::EISDIR)),
+            Node::Symlink(_) => return Err(errno(libc::EINVAL)),
+        }
+        inode.mtime = now();
+    }
+    if let Some(permissions) = attrs.permissions {
+        inode.permissions = permissions & 0o7777;
+    }
+    if let Some((atime, mtime)) = attrs.atime_mtime {
+        inode.atime = atime;
+        inode.mtime = mtime;
+    }
+    Ok(())
+}
+
+/// A filesystem that lives entirely in memory, for tests and examples.
+///
+/// Paths are resolved from a single root directory; relative paths are
+/// relative to it. Ownership is always reported as uid and gid 0 and
+/// permissions are stored but not enforced.
+pub struct MemFs {
+    tree: Mutex<Tree>,
+    max_file_size: u64,
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        Self { tree: Mutex::new(Tree::new()), max_file_size: DEFAULT_MAX_FILE_SIZE }
+    }
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest size, in bytes, a file may grow to, by writing or by setting
+    /// its size. Anything beyond fails with `EFBIG`, before any memory is
+    /// allocated for it. Defaults to `DEFAULT_MAX_FILE_SIZE`.
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+}
+
+/// An open file. It refers to the file itself, not its name, so it stays
+/// valid when the file is renamed.
+pub struct MemFile {
+    ino: u64,
+    /// Writes go to the end of the file, whatever offset the client sent.
+    append: bool,
+}
+
+/// A snapshot of a directory's entries, taken when it was opened.
+pub struct MemDir {
+    names: Vec<Name>,
+}
+
+#[async_trait]
+impl Fs for MemFs {
+    type FileHandle = MemFile;
+    type DirHandle = MemDir;
+
+    async fn open(&self, filename: String, pflags: Pflags, attrs: Attrs) -> Result<Self::FileHandle> {
+        let write = pflags.write || pflags.append;
+        if pflags.trunc && !pflags.excl && !write {
+            return Err(Error::new(ErrorKind::InvalidInput, "truncating needs write access").into());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        let ino = match tree.lookup(&filename, true) {
+            Ok(_) if pflags.creat && pflags.excl => return Err(errno(libc::EEXIST).into()),
+            Ok(ino) => ino,
+            Err(err) if err.raw_os_error() == Some(libc::ENOENT) && pflags.creat => {
+                // Creating through a dangling symlink is not supported, the
+                // link itself is in the way.
+                let (dir, name) = tree.lookup_parent(&filename)?;
+                let inode = Inode::new(Node::File(vec![]), attrs.permissions.unwrap_or(0o644));
+                tree.insert(dir, name, inode)?
+            },
+            Err(err) => return Err(err.into()),
+        };
+        let inode = tree.inode_mut(ino)?;
+        match inode.node {
+            Node::File(ref mut data) => {
+                if pflags.trunc && write {
+                    data.clear();
+                    inode.mtime = now();
+                }
+            },
+            Node::Dir(_) => return Err(errno(libc::EISDIR).into()),
+            Node::Symlink(_) => unreachable!(),
+        }
+        Ok(MemFile { ino, append: pflags.append })
+    }
+    async fn close(&self, handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()> {
+        drop(handle);
+        Ok(())
+    }
+    async fn read(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>> {
+        let tree = self.tree.lock().unwrap();
+        match tree.inode(handle.ino)?.node {
+            Node::File(ref data) if offset < data.len() as u64 => {
+                let end = data.len().min(offset as usize + len as usize);
+                Ok(data[offset as usize..end].to_vec())
+            },
+            Node::File(_) => Err(Error::from(ErrorKind::UnexpectedEof).into()),
+            _ => Err(errno(libc::EISDIR).into()),
+        }
+    }
+    async fn write(&self, handle: &mut Self::FileHandle, offset: u64, data: Vec<u8>) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let inode = tree.inode_mut(handle.ino)?;
+        match inode.node {
+            Node::File(ref mut contents) => {
+                let offset = if handle.append { contents.len() as u64 } else { offset };
+                // Gaps are stored as zeros; there are no holes in memory.
+                let end = match usize::try_from(offset).ok().and_then(|offset| offset.checked_add(data.len())) {
+                    Some(end) if end as u64 <= self.max_file_size => end,
+                    _ => return Err(errno(libc::EFBIG).into()),
+                };
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[offset as usize..end].copy_from_slice(&data);
+            },
+            _ => return Err(errno(libc::EISDIR).into()),
+        }
+        inode.mtime = now();
+        Ok(())
+    }
+    async fn lstat(&self, path: String) -> Result<Attrs> {
+        let tree = self.tree.lock().unwrap();
+        let ino = tree.lookup(&path, false)?;
+        Ok(tree.inode(ino)?.attrs())
+    }
+    async fn fstat(&self, handle: &mut Self::FileHandle) -> Result<Attrs> {
+        let tree = self.tree.lock().unwrap();
+        Ok(tree.inode(handle.ino)?.attrs())
+    }
+    async fn setstat(&self, path: String, attrs: Attrs) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let ino = tree.lookup(&path, true)?;
+        Ok(apply_attrs(tree.inode_mut(ino)?, attrs, self.max_file_size)?)
+    }
+    async fn fsetstat(&self, handle: &mut Self::FileHandle, attrs: Attrs) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        Ok(apply_attrs(tree.inode_mut(handle.ino)?, attrs, self.max_file_size)?)
+    }
+    async fn opendir(&self, path: String) -> Result<Self::DirHandle> {
+        let tree = self.tree.lock().unwrap();
+        let ino = tree.lookup(&path, true)?;
+        let mut names = vec![];
+        for (filename, ino) in tree.children(ino)? {
+            names.push(Name::new(filename.clone(), tree.inode(*ino)?.attrs()));
+        }
+        Ok(MemDir { names })
+    }
+    async fn readdir(&self, handle: &mut Self::DirHandle) -> Result<Vec<Name>> {
+        if handle.names.is_empty() {
+            Err(Error::from(ErrorKind::UnexpectedEof).into())
+        } else {
+            Ok(std::mem::take(&mut handle.names))
+        }
+    }
+    async fn remove(&self, filename: String) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (dir, name) = tree.lookup_parent(&filename)?;
+        let ino = *tree.children(dir)?.get(name).ok_or_else(|| errno(libc::ENOENT))?;
+        if let Node::Dir(_) = tree.inode(ino)?.node {
+            return Err(errno(libc::EISDIR).into());
+        }
+        tree.unlink(dir, name)?;
+        tree.inodes.remove(&ino);
+        Ok(())
+    }
+    async fn mkdir(&self, path: String, attrs: Attrs) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (dir, name) = tree.lookup_parent(&path)?;
+        let inode = Inode::new(Node::Dir(BTreeMap::new()), attrs.permissions.unwrap_or(0o755));
+        tree.insert(dir, name, inode)?;
+        Ok(())
+    }
+    async fn rmdir(&self, path: String) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (dir, name) = tree.lookup_parent(&path)?;
+        let ino = *tree.children(dir)?.get(name).ok_or_else(|| errno(libc::ENOENT))?;
+        if !tree.children(ino)?.is_empty() {
+            return Err(errno(libc::ENOTEMPTY).into());
+        }
+        tree.unlink(dir, name)?;
+        tree.inodes.remove(&ino);
+        Ok(())
+    }
+    async fn realpath(&self, path: String) -> Result<String> {
+        let tree = self.tree.lock().unwrap();
+        let chain = tree.walk(&path, true)?;
+        if chain.len() == 1 {
+            return Ok("/".to_string());
+        }
+        Ok(chain.iter().skip(1).map(|(_, name)| format!("/{}", name)).collect())
+    }
+    async fn stat(&self, path: String) -> Result<Attrs> {
+        let tree = self.tree.lock().unwrap();
+        let ino = tree.lookup(&path, true)?;
+        Ok(tree.inode(ino)?.attrs())
+    }
+    async fn rename(&self, oldpath: String, newpath: String) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        Ok(tree.rename(&oldpath, &newpath, false)?)
+    }
+    async fn readlink(&self, path: String) -> Result<String> {
+        let tree = self.tree.lock().unwrap();
+        let ino = tree.lookup(&path, false)?;
+        match tree.inode(ino)?.node {
+            Node::Symlink(ref target) => Ok(target.clone()),
+            _ => Err(errno(libc::EINVAL).into()),
+        }
+    }
+    async fn symlink(&self, linkpath: String, targetpath: String) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        let (dir, name) = tree.lookup_parent(&linkpath)?;
+        tree.insert(dir, name, Inode::new(Node::Symlink(targetpath), 0o777))?;
+        Ok(())
+    }
+    async fn posix_rename_supported(&self) -> bool { true }
+    async fn posix_rename(&self, oldpath: String, newpath: String) -> Result<()> {
+        let mut tree = self.tree.lock().unwrap();
+        Ok(tree.rename(&oldpath, &newpath, true)?)
+    }
+}
@@ -0,0 +1,71 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags, StatusCode};
+
+fn pflags(append: bool) -> Pflags {
+    Pflags { read: true, write: !append, append, creat: true, trunc: false, excl: false }
+}
+
+fn size(size: u64) -> Attrs {
+    Attrs { size: Some(size), ..Default::default() }
+}
+
+#[tokio::test]
+async fn read_and_write() {
+    let fs = MemFs::new();
+    let mut file = fs.open("/file".to_string(), pflags(false), Attrs::default()).await.unwrap();
+    let err = fs.read(&mut file, 0, 10).await.unwrap_err();
+    assert_eq!(err.status_code(), StatusCode::Eof);
+
+    fs.write(&mut file, 0, b"hello".to_vec()).await.unwrap();
+    // Past the end, the gap reads back as zeros.
+    fs.write(&mut file, 8, b"world".to_vec()).await.unwrap();
+    fs.write(&mut file, 1, b"E".to_vec()).await.unwrap();
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"hEllo\0\0\0world");
+    assert_eq!(fs.read(&mut file, 3, 4).await.unwrap(), b"lo\0\0");
+    assert_eq!(fs.read(&mut file, 13, 10).await.unwrap_err().status_code(), StatusCode::Eof);
+    assert_eq!(fs.stat("/file".to_string()).await.unwrap().size, Some(13));
+    fs.close(FsHandle::File(file)).await.unwrap();
+
+    // Appends go to the end, whatever the offset.
+    let mut file = fs.open("/file".to_string(), pflags(true), Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"!".to_vec()).await.unwrap();
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"hEllo\0\0\0world!");
+}
+
+#[tokio::test]
+async fn truncate() {
+    let fs = MemFs::new();
+    let mut file = fs.open("/file".to_string(), pflags(false), Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"abcdef".to_vec()).await.unwrap();
+
+    fs.fsetstat(&mut file, size(3)).await.unwrap();
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"abc");
+    fs.setstat("/file".to_string(), size(5)).await.unwrap();
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), b"abc\0\0");
+
+    let trunc = Pflags { trunc: true, ..pflags(false) };
+    let mut file = fs.open("/file".to_string(), trunc, Attrs::default()).await.unwrap();
+    assert_eq!(fs.fstat(&mut file).await.unwrap().size, Some(0));
+}
+
+#[tokio::test]
+async fn files_stay_within_the_size_limit() {
+    let fs = MemFs::new().max_file_size(10);
+    let mut file = fs.open("/file".to_string(), pflags(false), Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, vec![1; 10]).await.unwrap();
+
+    let err = fs.write(&mut file, 5, vec![2; 6]).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().raw_os_error(), Some(libc::EFBIG));
+    let err = fs.write(&mut file, u64::MAX - 1, vec![2; 6]).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().raw_os_error(), Some(libc::EFBIG));
+    let err = fs.fsetstat(&mut file, size(u64::MAX)).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().raw_os_error(), Some(libc::EFBIG));
+    let err = fs.setstat("/file".to_string(), size(11)).await.unwrap_err();
+    assert_eq!(err.io_error().unwrap().raw_os_error(), Some(libc::EFBIG));
+
+    // The file is unchanged, and still usable.
+    assert_eq!(fs.read(&mut file, 0, 100).await.unwrap(), [1; 10]);
+    fs.fsetstat(&mut file, size(4)).await.unwrap();
+    assert_eq!(fs.stat("/file".to_string()).await.unwrap().size, Some(4));
+}
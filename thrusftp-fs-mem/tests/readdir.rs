@@ -0,0 +1,31 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::types::{Attrs, Pflags, StatusCode};
+
+#[tokio::test]
+async fn readdir_lists_a_snapshot() {
+    let fs = MemFs::new();
+    fs.mkdir("/dir".to_string(), Attrs::default()).await.unwrap();
+    fs.mkdir("/dir/sub".to_string(), Attrs::default()).await.unwrap();
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let mut file = fs.open("/dir/file".to_string(), pflags, Attrs::default()).await.unwrap();
+    fs.write(&mut file, 0, b"abc".to_vec()).await.unwrap();
+    fs.symlink("/dir/link".to_string(), "file".to_string()).await.unwrap();
+
+    let mut dir = fs.opendir("/dir".to_string()).await.unwrap();
+    // Created after `opendir`, so not listed.
+    fs.mkdir("/dir/later".to_string(), Attrs::default()).await.unwrap();
+    let names = fs.readdir(&mut dir).await.unwrap();
+    let filenames: Vec<_> = names.iter().map(|name| name.filename.as_str()).collect();
+    assert_eq!(filenames, ["file", "link", "sub"]);
+    assert_eq!(names[0].attrs.size, Some(3));
+    assert_eq!(names[1].attrs.permissions.unwrap() & 0o170000, 0o120000);
+    assert_eq!(names[2].attrs.permissions.unwrap() & 0o170000, 0o040000);
+    assert_eq!(fs.readdir(&mut dir).await.unwrap_err().status_code(), StatusCode::Eof);
+    fs.close(FsHandle::Dir(dir)).await.unwrap();
+
+    let mut dir = fs.opendir("/dir/sub".to_string()).await.unwrap();
+    assert_eq!(fs.readdir(&mut dir).await.unwrap_err().status_code(), StatusCode::Eof);
+    assert!(fs.opendir("/dir/file".to_string()).await.is_err());
+    assert!(fs.opendir("/missing".to_string()).await.is_err());
+}
@@ -0,0 +1,378 @@
+//! Several filesystems served side by side, each as a top-level directory
+//! of a read-only virtual root.
+
+use std::io::{Error, ErrorKind};
+use async_trait::async_trait;
+use thrusftp_protocol::{Capabilities, Fs, FsHandle, Operation, Result, SftpError};
+use thrusftp_protocol::types::{Attrs, Extension, FsStats, HashAlgorithm, Name, Pflags, SeekWhence, Timespec};
+
+const S_IFDIR: u32 = 0o040000;
+
+/// Serves filesystems of type `T` under virtual top-level names: with
+/// `incoming` mounted, the client path `/incoming/a/b` is `/a/b` of that
+/// filesystem. Mounted filesystems should resolve paths from a root of
+/// their own, like `LocalFs::new` does, since the paths they return from
+/// `realpath` and `glob` are shown to clients below the mount.
+///
+/// The root itself only lists the mounts. It cannot be changed, and
+/// neither can the mount points: they cannot be removed, renamed or
+/// replaced. Renames and hard links between two mounts fail with
+/// `OpUnsupported`, like between two filesystems; clients copy instead.
+///
+/// `..` is resolved before a path is routed, so it never leads from one
+/// mount into another, even through a symlink. Extensions are advertised
+/// if every mount supports them.
+pub struct VirtualFs<T> {
+    mounts: Vec<(String, T)>,
+}
+
+impl<T> Default for VirtualFs<T> {
+    fn default() -> Self {
+        Self { mounts: Vec::new() }
+    }
+}
+
+impl<T> VirtualFs<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves `fs` as the directory `/name`.
+    ///
+    /// Panics if `name` is not a single path component or is already
+    /// mounted.
+    pub fn mount<S: Into<String>>(mut self, name: S, fs: T) -> Self {
+        let name = name.into();
+        assert!(!name.is_empty() && name != "." && name != ".." && !name.contains('/'), "invalid mount name {:?}", name);
+        assert!(self.mounts.iter().all(|(mounted, _)| *mounted != name), "{:?} is mounted twice", name);
+        self.mounts.push((name, fs));
+        self
+    }
+}
+
+/// An open file of one of the mounts.
+pub struct VirtualFile<F> {
+    mount: usize,
+    handle: F,
+}
+
+/// An open directory: the root, with the entries not returned yet, or a
+/// directory of one of the mounts.
+pub enum VirtualDir<D> {
+    Root(Vec<Name>),
+    Mount(usize, D),
+}
+
+/// Where a client path leads.
+enum Route {
+    Root,
+    /// Index of the mount and the path within it.
+    Mount(usize, String),
+}
+
+fn root_attrs() -> Attrs {
+    Attrs { permissions: Some(S_IFDIR | 0o555), ..Default::default() }
+}
+
+fn read_only() -> SftpError {
+    Error::new(ErrorKind::PermissionDenied, "the virtual root and its mount points cannot be changed").into()
+}
+
+impl<T: Fs + Send + Sync> VirtualFs<T> {
+    fn route(&self, path: &str) -> Result<Route> {
+        let mut components = Vec::new();
+        for component in path.split('/') {
+            match component {
+                "" | "." => {},
+                ".." => { components.pop(); },
+                name => components.push(name),
+            }
+        }
+        let (name, rest) = match components.split_first() {
+            Some(split) => split,
+            None => return Ok(Route::Root),
+        };
+        match self.mounts.iter().position(|(mounted, _)| mounted == name) {
+            Some(mount) => Ok(Route::Mount(mount, format!("/{}", rest.join("/")))),
+            None => Err(Error::from(ErrorKind::NotFound).into()),
+        }
+    }
+
+    /// The mount `path` is in and the path within it, for requests that
+    /// cannot be made on the root.
+    fn inner(&self, path: &str) -> Result<(&T, String)> {
+        match self.route(path)? {
+            Route::Root => Err(read_only()),
+            Route::Mount(mount, path) => Ok((&self.mounts[mount].1, path)),
+        }
+    }
+
+    /// Like `inner`, for requests that would also change a mount point
+    /// itself.
+    fn inner_below(&self, path: &str) -> Result<(usize, String)> {
+        match self.route(path)? {
+            Route::Mount(mount, path) if path != "/" => Ok((mount, path)),
+            _ => Err(read_only()),
+        }
+    }
+
+    /// Both paths of a rename or link, which must be in the same mount.
+    fn inner_pair(&self, oldpath: &str, newpath: &str) -> Result<(&T, String, String)> {
+        let (old_mount, oldpath) = self.inner_below(oldpath)?;
+        let (new_mount, newpath) = self.inner_below(newpath)?;
+        if old_mount != new_mount {
+            return Err(SftpError::Unsupported);
+        }
+        Ok((&self.mounts[old_mount].1, oldpath, newpath))
+    }
+
+    /// The client path for `path` within mount `mount`.
+    fn outer(&self, mount: usize, path: &str) -> String {
+        let name = &self.mounts[mount].0;
+        match path.trim_start_matches('/') {
+            "" => format!("/{}", name),
+            rest => format!("/{}/{}", name, rest),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Fs + Send + Sync> Fs for VirtualFs<T> {
+    type FileHandle = VirtualFile<T::FileHandle>;
+    type DirHandle = VirtualDir<T::DirHandle>;
+
+    async fn authorize(&self, op: Operation, path: &str) -> Result<()> {
+        match self.route(path) {
+            Ok(Route::Mount(mount, path)) => self.mounts[mount].1.authorize(op, &path).await,
+            // Missing mounts fail in the request itself, with the right
+            // status code.
+            Ok(Route::Root) | Err(_) => Ok(()),
+        }
+    }
+    async fn on_init(&self, client_extensions: &[Extension]) {
+        for (_, fs) in &self.mounts {
+            fs.on_init(client_extensions).await;
+        }
+    }
+
+    async fn open(&self, filename: String, pflags: Pflags, attrs: Attrs) -> Result<Self::FileHandle> {
+        match self.route(&filename)? {
+            Route::Root => Err(Error::from(ErrorKind::IsADirectory).into()),
+            Route::Mount(mount, path) => {
+                let handle = self.mounts[mount].1.open(path, pflags, attrs).await?;
+                Ok(VirtualFile { mount, handle })
+            },
+        }
+    }
+    async fn close(&self, handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()> {
+        match handle {
+            FsHandle::File(file) => self.mounts[file.mount].1.close(FsHandle::File(file.handle)).await,
+            FsHandle::Dir(VirtualDir::Root(_)) => Ok(()),
+            FsHandle::Dir(VirtualDir::Mount(mount, dir)) => self.mounts[mount].1.close(FsHandle::Dir(dir)).await,
+        }
+    }
+    async fn read(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>> {
+        self.mounts[handle.mount].1.read(&mut handle.handle, offset, len).await
+    }
+    async fn write(&self, handle: &mut Self::FileHandle, offset: u64, data: Vec<u8>) -> Result<()> {
+        self.mounts[handle.mount].1.write(&mut handle.handle, offset, data).await
+    }
+    async fn lstat(&self, path: String) -> Result<Attrs> {
+        match self.route(&path)? {
+            Route::Root => Ok(root_attrs()),
+            Route::Mount(mount, path) => self.mounts[mount].1.lstat(path).await,
+        }
+    }
+    async fn fstat(&self, handle: &mut Self::FileHandle) -> Result<Attrs> {
+        self.mounts[handle.mount].1.fstat(&mut handle.handle).await
+    }
+    async fn setstat(&self, path: String, attrs: Attrs) -> Result<()> {
+        let (fs, path) = self.inner(&path)?;
+        fs.setstat(path, attrs).await
+    }
+    async fn fsetstat(&self, handle: &mut Self::FileHandle, attrs: Attrs) -> Result<()> {
+        self.mounts[handle.mount].1.fsetstat(&mut handle.handle, attrs).await
+    }
+    async fn truncate(&self, handle: &mut Self::FileHandle, len: u64) -> Result<()> {
+        self.mounts[handle.mount].1.truncate(&mut handle.handle, len).await
+    }
+    async fn opendir(&self, path: String) -> Result<Self::DirHandle> {
+        match self.route(&path)? {
+            Route::Root => {
+                let mut names = Vec::new();
+                for (name, fs) in &self.mounts {
+                    let attrs = fs.stat("/".to_string()).await.unwrap_or_else(|_| root_attrs());
+                    names.push(Name::new(name.clone(), attrs));
+                }
+                Ok(VirtualDir::Root(names))
+            },
+            Route::Mount(mount, path) => Ok(VirtualDir::Mount(mount, self.mounts[mount].1.opendir(path).await?)),
+        }
+    }
+    async fn readdir(&self, handle: &mut Self::DirHandle) -> Result<Vec<Name>> {
+        match handle {
+            VirtualDir::Root(names) if names.is_empty() => Err(Error::from(ErrorKind::UnexpectedEof).into()),
+            VirtualDir::Root(names) => Ok(std::mem::take(names)),
+            VirtualDir::Mount(mount, dir) => self.mounts[*mount].1.readdir(dir).await,
+        }
+    }
+    async fn remove(&self, filename: String) -> Result<()> {
+        let (mount, path) = self.inner_below(&filename)?;
+        self.mounts[mount].1.remove(path).await
+    }
+    async fn mkdir(&self, path: String, attrs: Attrs) -> Result<()> {
+        let (mount, path) = self.inner_below(&path)?;
+        self.mounts[mount].1.mkdir(path, attrs).await
+    }
+    async fn rmdir(&self, path: String) -> Result<()> {
+        let (mount, path) = self.inner_below(&path)?;
+        self.mounts[mount].1.rmdir(path).await
+    }
+    async fn realpath(&self, path: String) -> Result<String> {
+        match self.route(&path)? {
+            Route::Root => Ok("/".to_string()),
+            Route::Mount(mount, path) => {
+                let resolved = self.mounts[mount].1.realpath(path).await?;
+                Ok(self.outer(mount, &resolved))
+            },
+        }
+    }
+    async fn stat(&self, path: String) -> Result<Attrs> {
+        match self.route(&path)? {
+            Route::Root => Ok(root_attrs()),
+            Route::Mount(mount, path) => self.mounts[mount].1.stat(path).await,
+        }
+    }
+    async fn rename(&self, oldpath: String, newpath: String) -> Result<()> {
+        let (fs, oldpath, newpath) = self.inner_pair(&oldpath, &newpath)?;
+        fs.rename(oldpath, newpath).await
+    }
+    async fn readlink(&self, path: String) -> Result<String> {
+        match self.route(&path)? {
+            Route::Root => Err(Error::from(ErrorKind::InvalidInput).into()),
+            Route::Mount(mount, path) => self.mounts[mount].1.readlink(path).await,
+        }
+    }
+    // Targets are stored as sent, so an absolute one is followed within
+    // the mount the link is in.
+    async fn symlink(&self, linkpath: String, targetpath: String) -> Result<()> {
+        let (mount, linkpath) = self.inner_below(&linkpath)?;
+        self.mounts[mount].1.symlink(linkpath, targetpath).await
+    }
+
+    /// What every mount supports, and the lowest handle limit of any.
+    /// Nothing without mounts.
+    async fn capabilities(&self) -> Capabilities {
+        let mut mounts = self.mounts.iter();
+        let mut capabilities = match mounts.next() {
+            Some((_, fs)) => fs.capabilities().await,
+            None => return Capabilities::default(),
+        };
+        for (_, fs) in mounts {
+            let other = fs.capabilities().await;
+            capabilities.posix_rename &= other.posix_rename;
+            capabilities.fsync &= other.fsync;
+            capabilities.fdatasync &= other.fdatasync;
+            capabilities.statvfs &= other.statvfs;
+            capabilities.hardlink &= other.hardlink;
+            capabilities.mknod &= other.mknod;
+            capabilities.utimens &= other.utimens;
+            capabilities.glob &= other.glob;
+            capabilities.users_groups_by_id &= other.users_groups_by_id;
+            capabilities.seek_hole_data &= other.seek_hole_data;
+            capabilities.read_eof &= other.read_eof;
+            capabilities.readdir_from &= other.readdir_from;
+            capabilities.remove_tree &= other.remove_tree;
+            capabilities.hash_algorithms.retain(|algorithm| other.hash_algorithms.contains(algorithm));
+            capabilities.max_handles = match (capabilities.max_handles, other.max_handles) {
+                (Some(max_handles), Some(other_max_handles)) => Some(max_handles.min(other_max_handles)),
+                (max_handles, other_max_handles) => max_handles.or(other_max_handles),
+            };
+        }
+        // Their requests carry no path to route them to a mount by.
+        capabilities.custom_extensions.clear();
+        capabilities
+    }
+
+    async fn posix_rename(&self, oldpath: String, newpath: String) -> Result<()> {
+        let (fs, oldpath, newpath) = self.inner_pair(&oldpath, &newpath)?;
+        fs.posix_rename(oldpath, newpath).await
+    }
+    async fn fsync(&self, handle: &mut Self::FileHandle) -> Result<()> {
+        self.mounts[handle.mount].1.fsync(&mut handle.handle).await
+    }
+    async fn fdatasync(&self, handle: &mut Self::FileHandle) -> Result<()> {
+        self.mounts[handle.mount].1.fdatasync(&mut handle.handle).await
+    }
+    async fn statvfs(&self, path: String) -> Result<FsStats> {
+        let (fs, path) = self.inner(&path)?;
+        fs.statvfs(path).await
+    }
+    async fn hash(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64) -> Result<Vec<u8>> {
+        self.mounts[handle.mount].1.hash(&mut handle.handle, algorithm, offset, len).await
+    }
+    async fn hash_blocks(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32) -> Result<Vec<Vec<u8>>> {
+        self.mounts[handle.mount].1.hash_blocks(&mut handle.handle, algorithm, offset, len, block_size).await
+    }
+    async fn hardlink(&self, oldpath: String, newpath: String) -> Result<()> {
+        let (fs, oldpath, newpath) = self.inner_pair(&oldpath, &newpath)?;
+        fs.hardlink(oldpath, newpath).await
+    }
+    async fn mknod(&self, path: String, mode: u32, dev: u64) -> Result<()> {
+        let (mount, path) = self.inner_below(&path)?;
+        self.mounts[mount].1.mknod(path, mode, dev).await
+    }
+    async fn utimens(&self, path: String, atime: Timespec, mtime: Timespec) -> Result<()> {
+        let (fs, path) = self.inner(&path)?;
+        fs.utimens(path, atime, mtime).await
+    }
+    /// The mount has to be named literally in `pattern`; glob characters
+    /// only match within it.
+    async fn glob(&self, pattern: String) -> Result<Vec<Name>> {
+        let (mount, pattern) = match self.route(&pattern)? {
+            Route::Root => return Err(read_only()),
+            Route::Mount(mount, pattern) => (mount, pattern),
+        };
+        let names = self.mounts[mount].1.glob(pattern).await?;
+        Ok(names.into_iter()
+            .map(|name| Name::new(self.outer(mount, &name.filename), name.attrs))
+            .collect())
+    }
+    /// All mounts are on the same host, so the first one answers.
+    async fn resolve_ids(&self, uids: Vec<u32>, gids: Vec<u32>) -> Result<(Vec<String>, Vec<String>)> {
+        match self.mounts.first() {
+            Some((_, fs)) => fs.resolve_ids(uids, gids).await,
+            None => Err(SftpError::Unsupported),
+        }
+    }
+    async fn seek_hole_data(&self, handle: &mut Self::FileHandle, offset: u64, whence: SeekWhence) -> Result<u64> {
+        self.mounts[handle.mount].1.seek_hole_data(&mut handle.handle, offset, whence).await
+    }
+    async fn read_eof(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<(Vec<u8>, bool)> {
+        self.mounts[handle.mount].1.read_eof(&mut handle.handle, offset, len).await
+    }
+    /// Cookies of the root are the number of mounts listed so far; those of
+    /// a mount are its own.
+    async fn readdir_from(&self, path: String, cookie: String) -> Result<Vec<(Name, String)>> {
+        if let Route::Mount(mount, path) = self.route(&path)? {
+            return self.mounts[mount].1.readdir_from(path, cookie).await;
+        }
+        let start: usize = match cookie.as_str() {
+            "" => 0,
+            cookie => cookie.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid cookie"))?,
+        };
+        if start >= self.mounts.len() {
+            return Err(Error::from(ErrorKind::UnexpectedEof).into());
+        }
+        let mut names = Vec::new();
+        for (i, (name, fs)) in self.mounts.iter().enumerate().skip(start) {
+            let attrs = fs.stat("/".to_string()).await.unwrap_or_else(|_| root_attrs());
+            names.push((Name::new(name.clone(), attrs), (i + 1).to_string()));
+        }
+        Ok(names)
+    }
+    async fn remove_tree(&self, path: String) -> Result<()> {
+        let (mount, path) = self.inner_below(&path)?;
+        self.mounts[mount].1.remove_tree(path).await
+    }
+}
@@ -0,0 +1,93 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_fs_virtual::VirtualFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::{SftpServer, SftpSession};
+
+fn fs() -> VirtualFs<MemFs> {
+    VirtualFs::new()
+        .mount("public", MemFs::new())
+        .mount("incoming", MemFs::new())
+}
+
+async fn status(session: &mut SftpSession<VirtualFs<MemFs>>, packet: SftpClientPacket) -> StatusCode {
+    match session.process(packet).await {
+        SftpServerPacket::Status { status_code, .. } => status_code,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+async fn names(session: &mut SftpSession<VirtualFs<MemFs>>, path: &str) -> Vec<String> {
+    let handle = match session.process(SftpClientPacket::Opendir { id: 1, path: path.to_string() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let mut filenames = Vec::new();
+    loop {
+        match session.process(SftpClientPacket::Readdir { id: 2, handle: handle.clone() }).await {
+            SftpServerPacket::Name { names, .. } => filenames.extend(names.into_iter().map(|name| name.filename)),
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => break,
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+    assert_eq!(status(session, SftpClientPacket::Close { id: 3, handle }).await, StatusCode::r#Ok);
+    filenames
+}
+
+fn mkdir(path: &str) -> SftpClientPacket {
+    SftpClientPacket::Mkdir { id: 4, path: path.to_string(), attrs: Attrs::default() }
+}
+
+fn rename(oldpath: &str, newpath: &str) -> SftpClientPacket {
+    SftpClientPacket::Rename { id: 5, oldpath: oldpath.to_string(), newpath: newpath.to_string() }
+}
+
+#[tokio::test]
+async fn mounts_are_separate_directories() {
+    let mut session = SftpServer::new(fs()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert_eq!(names(&mut session, "/").await, vec!["public", "incoming"]);
+
+    assert_eq!(status(&mut session, mkdir("/public/docs")).await, StatusCode::r#Ok);
+    assert_eq!(status(&mut session, mkdir("/incoming/uploads")).await, StatusCode::r#Ok);
+    assert_eq!(names(&mut session, "/public").await, vec!["docs"]);
+    assert_eq!(names(&mut session, "/incoming/").await, vec!["uploads"]);
+
+    match session.process(SftpClientPacket::Realpath { id: 6, path: "/public/../incoming/./uploads".to_string() }).await {
+        SftpServerPacket::Name { names, .. } => assert_eq!(names[0].filename, "/incoming/uploads"),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    match session.process(SftpClientPacket::Realpath { id: 7, path: ".".to_string() }).await {
+        SftpServerPacket::Name { names, .. } => assert_eq!(names[0].filename, "/"),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    match session.process(SftpClientPacket::Stat { id: 8, path: "/".to_string() }).await {
+        SftpServerPacket::Attrs { attrs, .. } => assert_eq!(attrs.permissions.unwrap() & 0o170000, 0o040000),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn renames_stay_within_a_mount() {
+    let mut session = SftpServer::new(fs()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert_eq!(status(&mut session, mkdir("/incoming/new")).await, StatusCode::r#Ok);
+
+    assert_eq!(status(&mut session, rename("/incoming/new", "/public/new")).await, StatusCode::OpUnsupported);
+    let extended_request = ExtendedRequest::OpensshHardlink { oldpath: "/incoming/new".to_string(), newpath: "/public/new".to_string() };
+    assert_eq!(status(&mut session, SftpClientPacket::Extended { id: 9, extended_request }).await, StatusCode::OpUnsupported);
+
+    assert_eq!(status(&mut session, rename("/incoming/new", "/incoming/done")).await, StatusCode::r#Ok);
+    assert_eq!(names(&mut session, "/incoming").await, vec!["done"]);
+}
+
+#[tokio::test]
+async fn root_and_mount_points_are_fixed() {
+    let mut session = SftpServer::new(fs()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    assert_eq!(status(&mut session, mkdir("/other")).await, StatusCode::NoSuchFile);
+    assert_eq!(status(&mut session, SftpClientPacket::Rmdir { id: 10, path: "/public".to_string() }).await, StatusCode::PermissionDenied);
+    assert_eq!(status(&mut session, rename("/public", "/incoming/public")).await, StatusCode::PermissionDenied);
+    assert_eq!(status(&mut session, rename("/public/..", "/elsewhere")).await, StatusCode::PermissionDenied);
+    assert_eq!(names(&mut session, "/").await, vec!["public", "incoming"]);
+}
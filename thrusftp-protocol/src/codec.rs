@@ -0,0 +1,114 @@
+//! Splitting a byte stream into SFTP packets and back, independent of the
+//! transport the stream arrives on.
+
+use std::io;
+
+use crate::parse::Serialize;
+use anyhow::Result;
+
+/// Reassembles length-prefixed SFTP packets from data that may arrive in
+/// arbitrarily small pieces.
+///
+/// Between packets a codec holds no memory besides itself. While a packet
+/// is being received, its body is allocated at the length the prefix
+/// announced, which is at most `max_packet_size`, and handed out as is
+/// once complete. So a codec never keeps more than one packet's worth of
+/// buffer, and keeps none between packets, however large earlier packets
+/// were. Bounding how many complete packets wait for an answer is up to
+/// the caller.
+#[derive(Debug)]
+pub struct SftpCodec {
+    max_packet_size: u32,
+    /// Length prefix of the packet being received, `header_len` bytes of it
+    /// so far.
+    header: [u8; 4],
+    header_len: usize,
+    body: Vec<u8>,
+}
+
+impl SftpCodec {
+    /// Creates a codec that refuses packets longer than `max_packet_size`
+    /// bytes, not counting the length prefix.
+    pub fn new(max_packet_size: u32) -> Self {
+        Self {
+            max_packet_size,
+            header: [0; 4],
+            header_len: 0,
+            body: Vec::new(),
+        }
+    }
+
+    /// Appends `data` to what was received so far and returns every packet
+    /// that is now complete, without its length prefix. Bytes of a packet
+    /// that is still incomplete are kept for the next call.
+    ///
+    /// Fails with `InvalidData` once a length prefix announces a packet
+    /// longer than the limit. The stream cannot be resynchronized after
+    /// that, so the caller should end the session.
+    pub fn decode(&mut self, mut data: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        loop {
+            if self.header_len < 4 {
+                let take = (4 - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + take].copy_from_slice(&data[..take]);
+                self.header_len += take;
+                data = &data[take..];
+                if self.header_len < 4 {
+                    break;
+                }
+                if u32::from_be_bytes(self.header) > self.max_packet_size {
+                    self.reset();
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "packet too long"));
+                }
+            }
+
+            let missing = u32::from_be_bytes(self.header) as usize - self.body.len();
+            let take = missing.min(data.len());
+            if take > 0 {
+                // Allocates the whole body once, when its first bytes arrive.
+                self.body.reserve_exact(missing);
+                self.body.extend_from_slice(&data[..take]);
+                data = &data[take..];
+            }
+            if take < missing {
+                break;
+            }
+            packets.push(std::mem::take(&mut self.body));
+            self.header_len = 0;
+        }
+        Ok(packets)
+    }
+
+    /// Number of bytes of an incomplete packet held back so far, including
+    /// its length prefix.
+    pub fn buffered(&self) -> usize {
+        self.header_len + self.body.len()
+    }
+
+    /// Forgets a partially received packet and frees its buffer.
+    pub fn reset(&mut self) {
+        self.header_len = 0;
+        self.body = Vec::new();
+    }
+
+    /// Serializes `packet` with its length prefix.
+    pub fn encode<P: Serialize>(packet: &P) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        Self::encode_into(packet, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Like `encode`, but appends to `buf`, so a transport can reuse one
+    /// buffer for all its responses.
+    pub fn encode_into<P: Serialize>(packet: &P, buf: &mut Vec<u8>) -> Result<()> {
+        let start = buf.len();
+        buf.extend_from_slice(&[0; 4]);
+        if let Err(err) = packet.serialize(buf) {
+            buf.truncate(start);
+            return Err(err);
+        }
+        let len = (buf.len() - start - 4) as u32;
+        buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+use crate::types::StatusCode;
+
+/// Error returned by `Fs` implementations. The variant decides the status
+/// code the client is sent, see `status_code`.
+#[derive(Debug, thiserror::Error)]
+pub enum SftpError {
+    /// A failed filesystem operation, sent with the status code for its
+    /// `ErrorKind`.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The request was malformed or did not make sense.
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    /// The implementation does not support the operation at all.
+    #[error("operation not supported")]
+    Unsupported,
+    /// Sent as exactly this status code, with the message as the error
+    /// message, for implementations that know better than the mapping from
+    /// `io::ErrorKind`. Codes the client's protocol version does not have
+    /// are still replaced with `Failure`.
+    #[error("{1}")]
+    Status(StatusCode, String),
+    /// Anything else, sent as `Failure`.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = SftpError> = std::result::Result<T, E>;
+
+impl SftpError {
+    /// Status code to answer the failed request with, before adjusting it
+    /// to the protocol version with `StatusCode::for_version`.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            SftpError::Io(err) => StatusCode::from_io_error_kind(err.kind()),
+            SftpError::Protocol(_) => StatusCode::BadMessage,
+            SftpError::Unsupported => StatusCode::OpUnsupported,
+            SftpError::Status(status_code, _) => *status_code,
+            SftpError::Other(_) => StatusCode::Failure,
+        }
+    }
+
+    /// The underlying `io::Error`, if this is an `Io` error.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            SftpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Lets implementations use `?` on `anyhow` results. An `io::Error` or
+/// `SftpError` inside is unwrapped so its status code is kept.
+impl From<anyhow::Error> for SftpError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return SftpError::Io(err),
+            Err(err) => err,
+        };
+        match err.downcast::<SftpError>() {
+            Ok(err) => err,
+            Err(err) => SftpError::Other(err),
+        }
+    }
+}
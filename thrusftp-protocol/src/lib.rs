@@ -1,54 +1,398 @@
 use async_trait::async_trait;
-use anyhow::Result;
-use crate::types::{Attrs, Pflags, Name, FsStats};
+use futures::stream::{self, BoxStream};
+use crate::types::{Attrs, Pflags, Name, FsStats, Extension, HashAlgorithm, SeekWhence, StatusCode, Timespec};
 
+pub mod codec;
+mod error;
+mod longname;
 pub mod parse;
 pub mod types;
 
+pub use error::{SftpError, Result};
+
 pub enum FsHandle<F, D> {
     File(F),
     Dir(D),
 }
 
+/// A write that failed after only the first `written` of `len` bytes made it
+/// to the file. `Fs` implementations return it wrapped in an `io::Error` of
+/// the same kind as `error`, so clients see the right status code and the
+/// message tells them where to resume.
+#[derive(Debug)]
+pub struct PartialWrite {
+    pub written: u64,
+    pub len: u64,
+    pub error: std::io::Error,
+}
+
+impl PartialWrite {
+    /// Wraps `error` if any bytes were written, or returns it as is
+    /// otherwise.
+    pub fn wrap(error: std::io::Error, written: u64, len: u64) -> std::io::Error {
+        if written == 0 {
+            return error;
+        }
+        std::io::Error::new(error.kind(), PartialWrite { written, len, error })
+    }
+}
+
+impl std::fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} after writing {} of {} bytes", self.error, self.written, self.len)
+    }
+}
+
+impl std::error::Error for PartialWrite {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+fn is_eof(err: &SftpError) -> bool {
+    err.status_code() == StatusCode::Eof
+}
+
+/// What an `Fs` supports beyond the operations every implementation has,
+/// as returned by `Fs::capabilities`. The server advertises an extension
+/// exactly when it is supported here, and answers requests for the others
+/// with `OpUnsupported` without calling into the implementation.
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    pub posix_rename: bool,
+    pub fsync: bool,
+    pub fdatasync: bool,
+    pub statvfs: bool,
+    pub hardlink: bool,
+    pub mknod: bool,
+    pub utimens: bool,
+    pub glob: bool,
+    pub users_groups_by_id: bool,
+    pub seek_hole_data: bool,
+    pub read_eof: bool,
+    pub readdir_from: bool,
+    pub remove_tree: bool,
+    /// Digests `hash` can compute. The `check-file-*` extensions are
+    /// advertised if this is not empty.
+    pub hash_algorithms: Vec<HashAlgorithm>,
+    /// Extensions beyond the ones above that the implementation answers,
+    /// advertised to clients as given. Requests for them are passed to
+    /// `handle_extension`.
+    pub custom_extensions: Vec<Extension>,
+    /// Most handles a session may have open at once, on top of the
+    /// server's own limit, or `None` for no limit of the implementation's.
+    pub max_handles: Option<usize>,
+}
+
+/// Kind of request a path is used in, as passed to `Fs::authorize`.
+#[derive(Clone, Debug)]
+pub enum Operation {
+    Open(Pflags),
+    Opendir,
+    Stat,
+    Lstat,
+    Setstat,
+    Remove,
+    Mkdir,
+    Rmdir,
+    /// Both the old and the new path of a `rename` or `posix-rename`.
+    Rename,
+    /// The path of the link.
+    Symlink,
+    /// The target of a new symlink. A relative target is checked as the
+    /// path it leads to from the link's directory, with `.` and `..`
+    /// resolved lexically, but stored verbatim.
+    SymlinkTarget,
+    Readlink,
+    Realpath,
+    Statvfs,
+    /// Both the existing and the new path.
+    Hardlink,
+    Mknod,
+    Utimens,
+    CheckFile,
+    /// The pattern, with glob characters still in it. Each directory the
+    /// glob lists is checked as `Opendir` and each match as `Lstat` too.
+    Glob,
+    /// The top of the tree only; nothing below it is checked.
+    RemoveTree,
+}
+
 #[async_trait]
 pub trait Fs {
     type FileHandle: Send + Sync;
     type DirHandle: Send + Sync;
 
+    /// Called before every request that takes a path, with the path resolved
+    /// against the session's working directory. Returning an error refuses
+    /// the request with `PermissionDenied`, whatever the error is. Requests
+    /// on handles are not checked again, the path was when it was opened.
+    async fn authorize(&self, _op: Operation, _path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with the extensions a client advertised in its `Init`, before
+    /// the server answers it, so an implementation can adapt to what the
+    /// client understands. The same `Fs` may serve several sessions, which
+    /// each call this once.
+    async fn on_init(&self, _client_extensions: &[Extension]) {}
+
+    /// Opens a file. Opening a directory must fail with
+    /// `ErrorKind::IsADirectory`; clients list directories with `opendir`.
+    ///
+    /// Flags with neither `read` nor `write`, as when a client only means to
+    /// create a file, open it for reading, like OpenSSH does. Truncating
+    /// needs `write` or `append` and fails with `ErrorKind::InvalidInput`
+    /// without.
     async fn open(&self, filename: String, pflags: Pflags, attrs: Attrs) -> Result<Self::FileHandle>;
     async fn close(&self, handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()>;
+    /// Reads up to `len` bytes starting at `offset`. Fewer bytes may only be
+    /// returned when the end of the file is reached. A read that starts at or
+    /// past the end of the file must fail with `ErrorKind::UnexpectedEof`.
     async fn read(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>>;
+    /// Writes `data` at `offset`, or at the end of the file if the handle was
+    /// opened with the `append` flag. If the write fails after part of
+    /// `data` was written, the error should wrap a `PartialWrite`.
+    ///
+    /// Writing past the end of the file extends it, and the gap reads as
+    /// zeros. Where the storage has holes, the gap should be left one, so
+    /// sparse files stay sparse when clients upload them.
     async fn write(&self, handle: &mut Self::FileHandle, offset: u64, data: Vec<u8>) -> Result<()>;
     async fn lstat(&self, path: String) -> Result<Attrs>;
     async fn fstat(&self, handle: &mut Self::FileHandle) -> Result<Attrs>;
+    /// Changes only the attributes that are `Some` in `attrs`. A client may
+    /// send none at all, which must succeed without touching the file.
     async fn setstat(&self, path: String, attrs: Attrs) -> Result<()>;
+    /// Like `setstat`, on an open file.
     async fn fsetstat(&self, handle: &mut Self::FileHandle, attrs: Attrs) -> Result<()>;
+    /// Sets the length of an open file. Growing a file fills the new space
+    /// with zeros, which most filesystems store as a hole.
+    async fn truncate(&self, handle: &mut Self::FileHandle, len: u64) -> Result<()> {
+        let attrs = Attrs { size: Some(len), ..Default::default() };
+        self.fsetstat(handle, attrs).await
+    }
     async fn opendir(&self, path: String) -> Result<Self::DirHandle>;
+    /// Returns the next batch of entries of an open directory, failing with
+    /// `ErrorKind::UnexpectedEof` once all have been returned. An empty
+    /// batch ends the listing too.
     async fn readdir(&self, handle: &mut Self::DirHandle) -> Result<Vec<Name>>;
+    /// The remaining entries of an open directory, one at a time. The stream
+    /// ends after the last entry or after the first error. By default this
+    /// calls `readdir` until it reports the end of the directory.
+    fn readdir_stream<'a>(&'a self, handle: &'a mut Self::DirHandle) -> BoxStream<'a, Result<Name>>
+        where Self: Sync
+    {
+        let state = (handle, Vec::new().into_iter(), false);
+        Box::pin(stream::unfold(state, move |(handle, mut batch, done)| async move {
+            loop {
+                if let Some(name) = batch.next() {
+                    return Some((Ok(name), (handle, batch, done)));
+                }
+                if done {
+                    return None;
+                }
+                match self.readdir(handle).await {
+                    Ok(names) if names.is_empty() => return None,
+                    Ok(names) => batch = names.into_iter(),
+                    Err(err) if is_eof(&err) => return None,
+                    Err(err) => return Some((Err(err), (handle, batch, true))),
+                }
+            }
+        }))
+    }
+    /// Every entry of the directory `path` at once. By default the directory
+    /// is opened, read to the end and closed again.
+    async fn read_dir_all(&self, path: String) -> Result<Vec<Name>> {
+        let mut handle = self.opendir(path).await?;
+        let mut names = Vec::new();
+        let res = loop {
+            match self.readdir(&mut handle).await {
+                Ok(batch) if batch.is_empty() => break Ok(()),
+                Ok(batch) => names.extend(batch),
+                Err(err) if is_eof(&err) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        let closed = self.close(FsHandle::Dir(handle)).await;
+        res?;
+        closed?;
+        Ok(names)
+    }
     async fn remove(&self, filename: String) -> Result<()>;
     async fn mkdir(&self, path: String, attrs: Attrs) -> Result<()>;
     async fn rmdir(&self, path: String) -> Result<()>;
     async fn realpath(&self, path: String) -> Result<String>;
+    /// The directory a client starts in: what `realpath(".")` returns to it
+    /// and what relative paths are resolved against. By default that is
+    /// whatever `realpath` makes of `.`.
+    async fn home_directory(&self) -> Result<String> {
+        self.realpath(".".to_string()).await
+    }
     async fn stat(&self, path: String) -> Result<Attrs>;
+    /// Renames `oldpath` to `newpath`, failing with `AlreadyExists` if
+    /// `newpath` exists, as SFTP v3 requires. Use `posix_rename` to replace
+    /// the target instead.
     async fn rename(&self, oldpath: String, newpath: String) -> Result<()>;
     async fn readlink(&self, path: String) -> Result<String>;
     async fn symlink(&self, linkpath: String, targetpath: String) -> Result<()>;
 
+    /// What this implementation supports, see `Capabilities`. The server
+    /// asks once, on the first `Init`, and keeps the answer for every
+    /// session of the same `Fs`, so it must not change afterwards.
+    ///
+    /// By default this is put together from the `*_supported` methods,
+    /// `hash_algorithms` and `custom_extensions`, which are not asked
+    /// otherwise. Implementations can override either this or those.
+    async fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            posix_rename: self.posix_rename_supported().await,
+            fsync: self.fsync_supported().await,
+            fdatasync: self.fdatasync_supported().await,
+            statvfs: self.statvfs_supported().await,
+            hardlink: self.hardlink_supported().await,
+            mknod: self.mknod_supported().await,
+            utimens: self.utimens_supported().await,
+            glob: self.glob_supported().await,
+            users_groups_by_id: self.users_groups_by_id_supported().await,
+            seek_hole_data: self.seek_hole_data_supported().await,
+            read_eof: self.read_eof_supported().await,
+            readdir_from: self.readdir_from_supported().await,
+            remove_tree: self.remove_tree_supported().await,
+            hash_algorithms: self.hash_algorithms().await,
+            custom_extensions: self.custom_extensions().await,
+            max_handles: None,
+        }
+    }
+
+    // Optional OpenSSH extensions, each supported if its `*_supported`
+    // method returns true, unless `capabilities` is overridden.
     async fn posix_rename_supported(&self) -> bool { false }
+    /// Renames `oldpath` to `newpath`, atomically replacing `newpath` if it
+    /// exists, like `rename(2)`. A replacement that is not allowed should
+    /// fail with `ErrorKind::NotADirectory`, `IsADirectory` or
+    /// `DirectoryNotEmpty`, as the matching errno would.
     async fn posix_rename(&self, _oldpath: String, _newpath: String) -> Result<()> {
-        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        Err(SftpError::Unsupported)
     }
     async fn fsync_supported(&self) -> bool { false }
     async fn fsync(&self, _handle: &mut Self::FileHandle) -> Result<()> {
-        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        Err(SftpError::Unsupported)
+    }
+    async fn fdatasync_supported(&self) -> bool { false }
+    /// Like `fsync`, but only flushes the file's data and the metadata needed
+    /// to read it back, not e.g. its timestamps.
+    async fn fdatasync(&self, _handle: &mut Self::FileHandle) -> Result<()> {
+        Err(SftpError::Unsupported)
     }
     async fn statvfs_supported(&self) -> bool { false }
     async fn statvfs(&self, _path: String) -> Result<FsStats> {
-        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        Err(SftpError::Unsupported)
+    }
+    /// Digests `hash` can compute, see `Capabilities::hash_algorithms`.
+    async fn hash_algorithms(&self) -> Vec<HashAlgorithm> { vec![] }
+    /// Digest of `len` bytes of the file starting at `offset`, or of
+    /// everything from `offset` to the end of the file if `len` is zero.
+    async fn hash(&self, _handle: &mut Self::FileHandle, _algorithm: HashAlgorithm, _offset: u64, _len: u64) -> Result<Vec<u8>> {
+        Err(SftpError::Unsupported)
+    }
+    /// Digests of the consecutive `block_size` byte blocks of the `len`
+    /// bytes starting at `offset`, the last of which may be shorter. The
+    /// range is always within the file and `len` is not zero. By default
+    /// every block is hashed with its own call to `hash`.
+    async fn hash_blocks(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32) -> Result<Vec<Vec<u8>>> {
+        let end = offset + len;
+        let mut hashes = Vec::new();
+        let mut pos = offset;
+        while pos < end {
+            let block_len = (block_size as u64).min(end - pos);
+            hashes.push(self.hash(handle, algorithm, pos, block_len).await?);
+            pos += block_len;
+        }
+        Ok(hashes)
     }
     async fn hardlink_supported(&self) -> bool { false }
     async fn hardlink(&self, _oldpath: String, _newpath: String) -> Result<()> {
-        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        Err(SftpError::Unsupported)
+    }
+    async fn mknod_supported(&self) -> bool { false }
+    /// Creates a fifo, socket or device node, like `mknod(2)`. Kinds of
+    /// special files the implementation does not create should fail with
+    /// `SftpError::Unsupported`.
+    async fn mknod(&self, _path: String, _mode: u32, _dev: u64) -> Result<()> {
+        Err(SftpError::Unsupported)
+    }
+    async fn utimens_supported(&self) -> bool { false }
+    /// Sets access and modification time of `path` with nanosecond
+    /// precision. Implementations that support this should also report both
+    /// times in `ATIME_EXTENDED_ATTR` and `MTIME_EXTENDED_ATTR` from `stat`,
+    /// `lstat` and `fstat`. The server only passes those on to clients that
+    /// announce `utimens@thrusftp` in their `Init`.
+    async fn utimens(&self, _path: String, _atime: Timespec, _mtime: Timespec) -> Result<()> {
+        Err(SftpError::Unsupported)
+    }
+    async fn glob_supported(&self) -> bool { false }
+    /// Lists the paths matching the shell glob `pattern`, with the full path
+    /// as `filename`. Implementations should fail rather than return a
+    /// truncated list when there are more matches than they are willing to
+    /// send.
+    async fn glob(&self, _pattern: String) -> Result<Vec<Name>> {
+        Err(SftpError::Unsupported)
+    }
+    async fn users_groups_by_id_supported(&self) -> bool { false }
+    /// Names of the users `uids` and groups `gids`, in the same order. Ids
+    /// without a name get an empty string.
+    async fn resolve_ids(&self, _uids: Vec<u32>, _gids: Vec<u32>) -> Result<(Vec<String>, Vec<String>)> {
+        Err(SftpError::Unsupported)
+    }
+    async fn seek_hole_data_supported(&self) -> bool { false }
+    /// Offset of the first data or hole, as `whence` says, at or after
+    /// `offset`, so clients can skip the holes of sparse files. The end of
+    /// the file counts as a hole. Fails with `UnexpectedEof` when `offset`
+    /// is at or past the end of the file, or there is no data after it.
+    ///
+    /// Implementations that cannot tell where holes are may report the whole
+    /// file as data: `offset` itself for `Data` and the file size for
+    /// `Hole`. Clients then simply transfer everything.
+    async fn seek_hole_data(&self, _handle: &mut Self::FileHandle, _offset: u64, _whence: SeekWhence) -> Result<u64> {
+        Err(SftpError::Unsupported)
+    }
+    async fn read_eof_supported(&self) -> bool { false }
+    /// Like `read`, and also tells whether the data returned reaches the
+    /// end of the file, so clients need not send another read only to be
+    /// told `Eof`. Implementations that cannot tell may return false, but
+    /// never true for data that ends before the end of the file. Plain
+    /// reads are answered with `read` either way.
+    async fn read_eof(&self, _handle: &mut Self::FileHandle, _offset: u64, _len: u32) -> Result<(Vec<u8>, bool)> {
+        Err(SftpError::Unsupported)
+    }
+    async fn readdir_from_supported(&self) -> bool { false }
+    /// Entries of the directory `path` from `cookie` on, for clients that
+    /// cannot keep a handle open while they list it. `cookie` is empty to
+    /// start at the beginning, or one returned with an entry before, to go
+    /// on after that entry, possibly in another session. Fails with
+    /// `UnexpectedEof` at the end, like `readdir`, and with `InvalidInput`
+    /// for a cookie the implementation never handed out.
+    ///
+    /// Entries added or removed between two calls may or may not be listed.
+    /// Every other entry must be listed exactly once.
+    async fn readdir_from(&self, _path: String, _cookie: String) -> Result<Vec<(Name, String)>> {
+        Err(SftpError::Unsupported)
+    }
+    async fn remove_tree_supported(&self) -> bool { false }
+    /// Removes the directory `path` and everything in it, without following
+    /// symlinks. If something cannot be removed, the error should name it;
+    /// what was removed before stays removed.
+    async fn remove_tree(&self, _path: String) -> Result<()> {
+        Err(SftpError::Unsupported)
+    }
+    /// Extensions this implementation answers itself, see
+    /// `Capabilities::custom_extensions`.
+    async fn custom_extensions(&self) -> Vec<Extension> { vec![] }
+    /// Answers a request for one of the `custom_extensions`. `data` is the
+    /// request after the extension name; the returned bytes are sent back
+    /// as the body of an extended reply.
+    async fn handle_extension(&self, _name: String, _data: Vec<u8>) -> Result<Vec<u8>> {
+        Err(SftpError::Unsupported)
     }
 }
 
@@ -0,0 +1,90 @@
+//! The `ls -l` style line sent as `longname` in directory listings, which
+//! clients such as OpenSSH's `sftp` print verbatim for `ls -l`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::{Attrs, NLINK_EXTENDED_ATTR};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats `filename` and `attrs` the way OpenSSH's sftp-server does.
+/// Owner and group are numeric, as `Attrs` carries no names, and the link
+/// count is 1 unless `NLINK_EXTENDED_ATTR` says otherwise; times are in UTC.
+pub(crate) fn format(filename: &str, attrs: &Attrs) -> String {
+    let (uid, gid) = attrs.uid_gid.unwrap_or((0, 0));
+    let nlink = attrs.extended_attr(NLINK_EXTENDED_ATTR)
+        .and_then(|nlink| nlink.parse::<u64>().ok())
+        .unwrap_or(1);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mtime = match attrs.atime_mtime {
+        Some((_, mtime)) => format_time(mtime as u64, now),
+        None => format_time(0, now),
+    };
+    format!(
+        "{} {:>3} {:<8} {:<8} {:>8} {} {}",
+        mode_string(attrs.permissions.unwrap_or(0)),
+        nlink,
+        uid,
+        gid,
+        attrs.size.unwrap_or(0),
+        mtime,
+        filename,
+    )
+}
+
+/// Like `strmode(3)`: the file type followed by the permission bits.
+fn mode_string(mode: u32) -> String {
+    let file_type = match mode & 0o170000 {
+        0o140000 => 's',
+        0o120000 => 'l',
+        0o060000 => 'b',
+        0o040000 => 'd',
+        0o020000 => 'c',
+        0o010000 => 'p',
+        _ => '-',
+    };
+    let bit = |mask: u32, c: char| if mode & mask != 0 { c } else { '-' };
+    // The execute position also shows setuid, setgid and sticky bits, in
+    // lower case if the execute bit is set as well.
+    let exec = |mask: u32, special: u32, set: char, unset: char| {
+        match (mode & mask != 0, mode & special != 0) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        }
+    };
+    [
+        file_type,
+        bit(0o400, 'r'), bit(0o200, 'w'), exec(0o100, 0o4000, 's', 'S'),
+        bit(0o040, 'r'), bit(0o020, 'w'), exec(0o010, 0o2000, 's', 'S'),
+        bit(0o004, 'r'), bit(0o002, 'w'), exec(0o001, 0o1000, 't', 'T'),
+    ].iter().collect()
+}
+
+/// `Mon dd HH:MM` for times within the last six months, `Mon dd  YYYY`
+/// otherwise, like `ls` does.
+fn format_time(secs: u64, now: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let six_months = 365 * 86400 / 2;
+    if secs + six_months > now && secs < now + six_months {
+        format!("{} {:>2} {:02}:{:02}", MONTHS[month - 1], day, secs % 86400 / 3600, secs % 3600 / 60)
+    } else {
+        format!("{} {:>2}  {}", MONTHS[month - 1], day, year)
+    }
+}
+
+/// Year, month and day of the day `days` after 1970-01-01, in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, usize, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as usize;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
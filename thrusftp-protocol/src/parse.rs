@@ -10,6 +10,30 @@ pub trait Deserialize: Sized {
     fn deserialize(input: &mut &[u8]) -> Result<Self>;
 }
 
+/// Deserializes a whole packet, as cut out by the codec. Bytes left over
+/// after the last field are an error, not ignored: they mean a length field,
+/// such as the one of `Write` data, claims less than the packet carries.
+pub fn deserialize_packet<T: Deserialize>(packet: &[u8]) -> Result<T> {
+    let mut input = packet;
+    let res = T::deserialize(&mut input)?;
+    if !input.is_empty() {
+        let msg = format!("{} bytes after the end of the packet", input.len());
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into());
+    }
+    Ok(res)
+}
+
+/// Splits the first `len` bytes off `input`, failing instead of panicking if
+/// the packet is shorter than that.
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "packet too short").into());
+    }
+    let (res, rest) = input.split_at(len);
+    *input = rest;
+    Ok(res)
+}
+
 impl Serialize for u8 {
     fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
         writer.write_all(&[*self; 1])?;
@@ -18,9 +42,19 @@ impl Serialize for u8 {
 }
 impl Deserialize for u8 {
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
-        let res = input[0];
-        *input = &mut &input[1..];
-        Ok(res)
+        Ok(take(input, 1)?[0])
+    }
+}
+
+/// One byte, zero for false. Anything else reads as true.
+impl Serialize for bool {
+    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
+        (*self as u8).serialize(writer)
+    }
+}
+impl Deserialize for bool {
+    fn deserialize(input: &mut &[u8]) -> Result<Self> {
+        Ok(u8::deserialize(input)? != 0)
     }
 }
 
@@ -32,9 +66,7 @@ impl Serialize for u32 {
 }
 impl Deserialize for u32 {
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
-        let res = Self::from_be_bytes((input[..4]).try_into()?);
-        *input = &mut &input[4..];
-        Ok(res)
+        Ok(Self::from_be_bytes(take(input, 4)?.try_into()?))
     }
 }
 
@@ -46,9 +78,7 @@ impl Serialize for u64 {
 }
 impl Deserialize for u64 {
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
-        let res = Self::from_be_bytes((input[..8]).try_into()?);
-        *input = &mut &input[8..];
-        Ok(res)
+        Ok(Self::from_be_bytes(take(input, 8)?.try_into()?))
     }
 }
 
@@ -63,9 +93,7 @@ impl Serialize for String {
 impl Deserialize for String {
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
         let len = u32::deserialize(input)? as usize;
-        let res = String::from_utf8((&input[..len]).to_vec())?;
-        *input = &mut &input[len..];
-        Ok(res.to_string())
+        Ok(String::from_utf8(take(input, len)?.to_vec())?)
     }
 }
 
@@ -80,7 +108,49 @@ impl Serialize for VecU8 {
 impl Deserialize for VecU8 {
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
         let len = u32::deserialize(input)? as usize;
-        Ok(VecU8(input[..len].to_vec()))
+        Ok(VecU8(take(input, len)?.to_vec()))
+    }
+}
+
+impl Serialize for IdList {
+    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
+        let mut packed = Vec::with_capacity(self.0.len() * 4);
+        for id in &self.0 {
+            id.serialize(&mut packed)?;
+        }
+        VecU8(packed).serialize(writer)
+    }
+}
+impl Deserialize for IdList {
+    fn deserialize(input: &mut &[u8]) -> Result<Self> {
+        let packed = VecU8::deserialize(input)?.0;
+        let mut packed = &packed[..];
+        let mut ids = Vec::with_capacity(packed.len() / 4);
+        while !packed.is_empty() {
+            ids.push(u32::deserialize(&mut packed)?);
+        }
+        Ok(IdList(ids))
+    }
+}
+
+impl Serialize for NameList {
+    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
+        let mut packed = Vec::new();
+        for name in &self.0 {
+            name.serialize(&mut packed)?;
+        }
+        VecU8(packed).serialize(writer)
+    }
+}
+impl Deserialize for NameList {
+    fn deserialize(input: &mut &[u8]) -> Result<Self> {
+        let packed = VecU8::deserialize(input)?.0;
+        let mut packed = &packed[..];
+        let mut names = Vec::new();
+        while !packed.is_empty() {
+            names.push(String::deserialize(&mut packed)?);
+        }
+        Ok(NameList(names))
     }
 }
 
@@ -219,15 +289,33 @@ impl Deserialize for Pflags {
     }
 }
 
-impl Serialize for ExtendedRequestType {
-    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
-        let s = match self {
+impl From<ExtendedRequestType> for String {
+    fn from(request_type: ExtendedRequestType) -> Self {
+        let s = match request_type {
             ExtendedRequestType::OpensshStatvfs => "statvfs@openssh.com",
             ExtendedRequestType::OpensshPosixRename => "posix-rename@openssh.com",
             ExtendedRequestType::OpensshHardlink => "hardlink@openssh.com",
             ExtendedRequestType::OpensshFsync => "fsync@openssh.com",
+            ExtendedRequestType::ThrusftpFdatasync => "fdatasync@thrusftp",
+            ExtendedRequestType::CheckFileHandle => "check-file-handle",
+            ExtendedRequestType::CheckFileName => "check-file-name",
+            ExtendedRequestType::ThrusftpMknod => "mknod@thrusftp",
+            ExtendedRequestType::ThrusftpUtimens => "utimens@thrusftp",
+            ExtendedRequestType::ThrusftpGlob => "glob@thrusftp",
+            ExtendedRequestType::OpensshUsersGroupsById => "users-groups-by-id@openssh.com",
+            ExtendedRequestType::ThrusftpSeekHoleData => "seek-hole-data@thrusftp",
+            ExtendedRequestType::ThrusftpRmtree => "rmtree@thrusftp",
+            ExtendedRequestType::ThrusftpReadEof => "read-eof@thrusftp",
+            ExtendedRequestType::ThrusftpReaddirFrom => "readdir-from@thrusftp",
+            ExtendedRequestType::Other(name) => return name,
         };
-        s.to_string().serialize(writer)
+        s.to_string()
+    }
+}
+
+impl Serialize for ExtendedRequestType {
+    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
+        String::from(self.clone()).serialize(writer)
     }
 }
 
@@ -238,11 +326,66 @@ impl Deserialize for ExtendedRequestType {
             "posix-rename@openssh.com" => ExtendedRequestType::OpensshPosixRename,
             "hardlink@openssh.com" => ExtendedRequestType::OpensshHardlink,
             "fsync@openssh.com" => ExtendedRequestType::OpensshFsync,
-            _ => panic!("unexpected extended request"),
+            "fdatasync@thrusftp" => ExtendedRequestType::ThrusftpFdatasync,
+            "check-file-handle" => ExtendedRequestType::CheckFileHandle,
+            "check-file-name" => ExtendedRequestType::CheckFileName,
+            "mknod@thrusftp" => ExtendedRequestType::ThrusftpMknod,
+            "utimens@thrusftp" => ExtendedRequestType::ThrusftpUtimens,
+            "glob@thrusftp" => ExtendedRequestType::ThrusftpGlob,
+            "users-groups-by-id@openssh.com" => ExtendedRequestType::OpensshUsersGroupsById,
+            "seek-hole-data@thrusftp" => ExtendedRequestType::ThrusftpSeekHoleData,
+            "rmtree@thrusftp" => ExtendedRequestType::ThrusftpRmtree,
+            "read-eof@thrusftp" => ExtendedRequestType::ThrusftpReadEof,
+            "readdir-from@thrusftp" => ExtendedRequestType::ThrusftpReaddirFrom,
+            _ => ExtendedRequestType::Other(s),
         })
     }
 }
 
+/// Only the body of the reply, without anything telling which it is.
+impl Serialize for ExtendedReply {
+    fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
+        match self {
+            ExtendedReply::Statvfs(reply) => reply.serialize(writer),
+            ExtendedReply::CheckFile(reply) => reply.serialize(writer),
+            ExtendedReply::UsersGroupsById(reply) => reply.serialize(writer),
+            ExtendedReply::SeekHoleData(reply) => reply.serialize(writer),
+            ExtendedReply::ReadEof(reply) => reply.serialize(writer),
+            ExtendedReply::ReaddirFrom(reply) => reply.serialize(writer),
+            ExtendedReply::Other(data) => {
+                writer.write_all(data)?;
+                Ok(())
+            },
+        }
+    }
+}
+
+impl ExtendedReply {
+    /// Parses `data`, the body of an `ExtendedReply` packet answering a
+    /// request of `request_type`. All of it must belong to the reply.
+    pub fn parse(request_type: &ExtendedRequestType, data: &[u8]) -> Result<Self> {
+        Ok(match request_type {
+            ExtendedRequestType::OpensshStatvfs => ExtendedReply::Statvfs(deserialize_packet(data)?),
+            ExtendedRequestType::CheckFileHandle
+            | ExtendedRequestType::CheckFileName => ExtendedReply::CheckFile(deserialize_packet(data)?),
+            ExtendedRequestType::OpensshUsersGroupsById => ExtendedReply::UsersGroupsById(deserialize_packet(data)?),
+            ExtendedRequestType::ThrusftpSeekHoleData => ExtendedReply::SeekHoleData(deserialize_packet(data)?),
+            ExtendedRequestType::ThrusftpReadEof => ExtendedReply::ReadEof(deserialize_packet(data)?),
+            ExtendedRequestType::ThrusftpReaddirFrom => ExtendedReply::ReaddirFrom(deserialize_packet(data)?),
+            _ => ExtendedReply::Other(data.to_vec()),
+        })
+    }
+}
+
+impl From<ExtendedReply> for VecEos<u8> {
+    fn from(reply: ExtendedReply) -> Self {
+        let mut data = vec![];
+        // Writing to a `Vec` does not fail.
+        reply.serialize(&mut data).unwrap();
+        data.into()
+    }
+}
+
 impl<T> Serialize for VecEos<T> where T: Serialize {
     fn serialize(&self, writer: &mut dyn Write) -> Result<()> {
         for ext in &self.0 {
@@ -252,10 +395,20 @@ impl<T> Serialize for VecEos<T> where T: Serialize {
     }
 }
 impl<T> Deserialize for VecEos<T> where T: Deserialize {
+    /// Fails if the input does not end with a complete element, e.g. an
+    /// extension name without its data, rather than dropping the rest.
     fn deserialize(input: &mut &[u8]) -> Result<Self> {
         let mut res = Vec::new();
-        while input.len() > 0 {
-            res.push(T::deserialize(input)?);
+        while !input.is_empty() {
+            let remaining = input.len();
+            let elem = T::deserialize(input).map_err(|err| {
+                anyhow::anyhow!("trailing {} bytes are not a complete element: {}", remaining, err)
+            })?;
+            // An element that takes up no bytes would be parsed forever.
+            if input.len() == remaining {
+                return Err(anyhow::anyhow!("empty element in a list without length"));
+            }
+            res.push(elem);
         }
         Ok(res.into())
     }
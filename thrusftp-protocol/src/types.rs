@@ -12,6 +12,81 @@ pub struct Pflags {
     pub excl: bool,
 }
 
+/// What `open` does depending on whether the file exists, as named by SFTP
+/// version 5.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Disposition {
+    /// Create the file, failing if it exists.
+    CreateNew,
+    /// Create the file, or truncate it if it exists.
+    CreateTruncate,
+    /// Open the file, failing if it does not exist.
+    OpenExisting,
+    /// Open the file, creating it if it does not exist.
+    OpenOrCreate,
+    /// Truncate the file, failing if it does not exist.
+    TruncateExisting,
+}
+
+impl Pflags {
+    /// The disposition these flags stand for. `excl` wins over `trunc`, as
+    /// an exclusively created file is empty anyway.
+    pub fn disposition(&self) -> Disposition {
+        match (self.creat, self.trunc, self.excl) {
+            (_, _, true) => Disposition::CreateNew,
+            (true, true, false) => Disposition::CreateTruncate,
+            (true, false, false) => Disposition::OpenOrCreate,
+            (false, true, false) => Disposition::TruncateExisting,
+            (false, false, false) => Disposition::OpenExisting,
+        }
+    }
+
+    /// Translates the `desired-access` mask and `flags` of a version 5 open
+    /// request. Every disposition has a version 3 equivalent; the version 5
+    /// flags without one, such as text mode and locking, are refused.
+    pub fn from_v5(desired_access: u32, flags: u32) -> anyhow::Result<Self> {
+        let unsupported = flags & !(SSH_FXF_ACCESS_DISPOSITION | SSH_FXF_APPEND_DATA | SSH_FXF_APPEND_DATA_ATOMIC);
+        if unsupported != 0 {
+            return Err(anyhow::anyhow!("unsupported open flags {:#x}", unsupported));
+        }
+        let disposition = match flags & SSH_FXF_ACCESS_DISPOSITION {
+            SSH_FXF_CREATE_NEW => Disposition::CreateNew,
+            SSH_FXF_CREATE_TRUNCATE => Disposition::CreateTruncate,
+            SSH_FXF_OPEN_EXISTING => Disposition::OpenExisting,
+            SSH_FXF_OPEN_OR_CREATE => Disposition::OpenOrCreate,
+            SSH_FXF_TRUNCATE_EXISTING => Disposition::TruncateExisting,
+            other => return Err(anyhow::anyhow!("invalid open disposition {}", other)),
+        };
+        let append = flags & (SSH_FXF_APPEND_DATA | SSH_FXF_APPEND_DATA_ATOMIC) != 0
+            || desired_access & ACE4_APPEND_DATA != 0 && desired_access & ACE4_WRITE_DATA == 0;
+        Ok(Pflags {
+            read: desired_access & ACE4_READ_DATA != 0,
+            write: desired_access & (ACE4_WRITE_DATA | ACE4_APPEND_DATA) != 0,
+            append,
+            creat: matches!(disposition, Disposition::CreateNew | Disposition::CreateTruncate | Disposition::OpenOrCreate),
+            trunc: matches!(disposition, Disposition::CreateTruncate | Disposition::TruncateExisting),
+            excl: disposition == Disposition::CreateNew,
+        })
+    }
+}
+
+/// Bits of the version 5 `desired-access` mask that `Pflags::from_v5`
+/// looks at.
+pub const ACE4_READ_DATA: u32 = 0x0000_0001;
+pub const ACE4_WRITE_DATA: u32 = 0x0000_0002;
+pub const ACE4_APPEND_DATA: u32 = 0x0000_0004;
+
+/// Version 5 open flags: the disposition in the lowest three bits, then
+/// the append modes.
+pub const SSH_FXF_ACCESS_DISPOSITION: u32 = 0x0000_0007;
+pub const SSH_FXF_CREATE_NEW: u32 = 0;
+pub const SSH_FXF_CREATE_TRUNCATE: u32 = 1;
+pub const SSH_FXF_OPEN_EXISTING: u32 = 2;
+pub const SSH_FXF_OPEN_OR_CREATE: u32 = 3;
+pub const SSH_FXF_TRUNCATE_EXISTING: u32 = 4;
+pub const SSH_FXF_APPEND_DATA: u32 = 0x0000_0008;
+pub const SSH_FXF_APPEND_DATA_ATOMIC: u32 = 0x0000_0010;
+
 #[derive(Clone, Debug)]
 pub struct Attrsflags {
     pub size: bool,
@@ -36,7 +111,16 @@ pub struct Attrs {
     pub extended_attrs: Vec<ExtendedAttr>,
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+impl Attrs {
+    /// Data of the extended attribute `r#type`, if there is one.
+    pub fn extended_attr(&self, r#type: &str) -> Option<&str> {
+        self.extended_attrs.iter()
+            .find(|attr| attr.r#type == r#type)
+            .map(|attr| attr.data.as_str())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[bin_ser(repr = u32)]
 pub enum StatusCode {
     #[bin_ser(val = 0)]
@@ -57,6 +141,168 @@ pub enum StatusCode {
     ConnectionLost,
     #[bin_ser(val = 8)]
     OpUnsupported,
+    // Codes from here on are only defined from SFTP v4 on. Use `for_version`
+    // before sending a code to a client.
+    #[bin_ser(val = 11)]
+    FileAlreadyExists,
+    #[bin_ser(val = 14)]
+    NoSpaceOnFilesystem,
+    #[bin_ser(val = 15)]
+    QuotaExceeded,
+    #[bin_ser(val = 18)]
+    DirNotEmpty,
+    #[bin_ser(val = 19)]
+    NotADirectory,
+    #[bin_ser(val = 24)]
+    FileIsADirectory,
+}
+
+impl StatusCode {
+    /// The most specific status code for an I/O error, in any protocol
+    /// version.
+    pub fn from_io_error_kind(kind: std::io::ErrorKind) -> Self {
+        use std::io::ErrorKind;
+        match kind {
+            ErrorKind::NotFound => StatusCode::NoSuchFile,
+            ErrorKind::UnexpectedEof => StatusCode::Eof,
+            ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+            ErrorKind::Unsupported => StatusCode::OpUnsupported,
+            ErrorKind::InvalidInput => StatusCode::BadMessage,
+            ErrorKind::InvalidData => StatusCode::BadMessage,
+            ErrorKind::AlreadyExists => StatusCode::FileAlreadyExists,
+            ErrorKind::StorageFull => StatusCode::NoSpaceOnFilesystem,
+            ErrorKind::QuotaExceeded => StatusCode::QuotaExceeded,
+            ErrorKind::DirectoryNotEmpty => StatusCode::DirNotEmpty,
+            ErrorKind::NotADirectory => StatusCode::NotADirectory,
+            ErrorKind::IsADirectory => StatusCode::FileIsADirectory,
+            _ => StatusCode::Failure,
+        }
+    }
+
+    /// Replaces codes that protocol `version` does not know with `Failure`.
+    pub fn for_version(self, version: u32) -> Self {
+        match self {
+            StatusCode::FileAlreadyExists
+            | StatusCode::NoSpaceOnFilesystem
+            | StatusCode::QuotaExceeded
+            | StatusCode::DirNotEmpty
+            | StatusCode::NotADirectory
+            | StatusCode::FileIsADirectory if version < 4 => StatusCode::Failure,
+            code => code,
+        }
+    }
+}
+
+impl ExtendedRequest {
+    /// The extension name the request was sent with.
+    pub fn name(&self) -> String {
+        let request_type = match self {
+            ExtendedRequest::OpensshStatvfs { .. } => ExtendedRequestType::OpensshStatvfs,
+            ExtendedRequest::OpensshPosixRename { .. } => ExtendedRequestType::OpensshPosixRename,
+            ExtendedRequest::OpensshHardlink { .. } => ExtendedRequestType::OpensshHardlink,
+            ExtendedRequest::OpensshFsync { .. } => ExtendedRequestType::OpensshFsync,
+            ExtendedRequest::ThrusftpFdatasync { .. } => ExtendedRequestType::ThrusftpFdatasync,
+            ExtendedRequest::CheckFileHandle { .. } => ExtendedRequestType::CheckFileHandle,
+            ExtendedRequest::CheckFileName { .. } => ExtendedRequestType::CheckFileName,
+            ExtendedRequest::ThrusftpMknod { .. } => ExtendedRequestType::ThrusftpMknod,
+            ExtendedRequest::ThrusftpUtimens { .. } => ExtendedRequestType::ThrusftpUtimens,
+            ExtendedRequest::ThrusftpGlob { .. } => ExtendedRequestType::ThrusftpGlob,
+            ExtendedRequest::OpensshUsersGroupsById { .. } => ExtendedRequestType::OpensshUsersGroupsById,
+            ExtendedRequest::ThrusftpSeekHoleData { .. } => ExtendedRequestType::ThrusftpSeekHoleData,
+            ExtendedRequest::ThrusftpRmtree { .. } => ExtendedRequestType::ThrusftpRmtree,
+            ExtendedRequest::ThrusftpReadEof { .. } => ExtendedRequestType::ThrusftpReadEof,
+            ExtendedRequest::ThrusftpReaddirFrom { .. } => ExtendedRequestType::ThrusftpReaddirFrom,
+            ExtendedRequest::Unknown { name, .. } => return name.clone(),
+        };
+        request_type.into()
+    }
+}
+
+/// Digests offered by the `check-file-*` extensions, named as in the SFTP
+/// filexfer extensions draft.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha224 => "sha224",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// Length of a digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Md5 => 16,
+            HashAlgorithm::Sha224 => 28,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha384 => 48,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "md5" => Some(HashAlgorithm::Md5),
+            "sha224" => Some(HashAlgorithm::Sha224),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha384" => Some(HashAlgorithm::Sha384),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// A point in time with nanosecond precision, in seconds since the epoch
+/// plus the nanoseconds since that second.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timespec {
+    pub secs: u64,
+    pub nsecs: u32,
+}
+
+/// Extended attributes carrying access and modification time with
+/// nanosecond precision, formatted like `Timespec`'s `Display`. The
+/// `atime_mtime` field of `Attrs` only holds whole seconds.
+pub const ATIME_EXTENDED_ATTR: &str = "atime@thrusftp";
+pub const MTIME_EXTENDED_ATTR: &str = "mtime@thrusftp";
+
+/// Extended attributes carrying the inode number and the hard link count of
+/// a file, in decimal. Files with the same inode on the same filesystem are
+/// hard links to each other.
+pub const INO_EXTENDED_ATTR: &str = "ino@thrusftp";
+pub const NLINK_EXTENDED_ATTR: &str = "nlink@thrusftp";
+
+/// Formats as `secs.nnnnnnnnn`.
+impl std::fmt::Display for Timespec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{:09}", self.secs, self.nsecs)
+    }
+}
+
+impl std::str::FromStr for Timespec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (secs, nsecs) = s.split_once('.').ok_or_else(|| anyhow::anyhow!("missing fraction"))?;
+        if nsecs.len() != 9 {
+            anyhow::bail!("fraction must have nine digits");
+        }
+        Ok(Timespec {
+            secs: secs.parse()?,
+            nsecs: nsecs.parse()?,
+        })
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -72,12 +318,34 @@ pub struct Name {
     pub attrs: Attrs,
 }
 
+impl Name {
+    /// A directory entry whose `longname` is formatted from `attrs` like a
+    /// line of `ls -l`.
+    pub fn new(filename: String, attrs: Attrs) -> Self {
+        let longname = crate::longname::format(&filename, &attrs);
+        Self { filename, longname, attrs }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ExtendedRequestType {
     OpensshStatvfs,
     OpensshPosixRename,
     OpensshHardlink,
     OpensshFsync,
+    ThrusftpFdatasync,
+    CheckFileHandle,
+    CheckFileName,
+    ThrusftpMknod,
+    ThrusftpUtimens,
+    ThrusftpGlob,
+    OpensshUsersGroupsById,
+    ThrusftpSeekHoleData,
+    ThrusftpRmtree,
+    ThrusftpReadEof,
+    ThrusftpReaddirFrom,
+    /// Any extension not listed above, by name.
+    Other(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -101,6 +369,101 @@ pub enum ExtendedRequest {
     OpensshFsync {
         handle: String,
     },
+    #[bin_ser(val = ExtendedRequestType::ThrusftpFdatasync)]
+    ThrusftpFdatasync {
+        handle: String,
+    },
+    /// Hash a range of an open file. `hash_algorithms` is a comma-separated
+    /// list in order of preference; a `length` of zero hashes to the end of
+    /// the file. A `block_size` of zero hashes the range as a whole,
+    /// anything else, at least 256, hashes each block of that size
+    /// separately and replies with the digests one after the other.
+    #[bin_ser(val = ExtendedRequestType::CheckFileHandle)]
+    CheckFileHandle {
+        handle: String,
+        hash_algorithms: String,
+        start_offset: u64,
+        length: u64,
+        block_size: u32,
+    },
+    /// Like `CheckFileHandle`, but for a file by name.
+    #[bin_ser(val = ExtendedRequestType::CheckFileName)]
+    CheckFileName {
+        filename: String,
+        hash_algorithms: String,
+        start_offset: u64,
+        length: u64,
+        block_size: u32,
+    },
+    /// Create a special file. `mode` includes the file type bits, `dev` is
+    /// only used for block and character devices.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpMknod)]
+    ThrusftpMknod {
+        path: String,
+        mode: u32,
+        dev: u64,
+    },
+    /// Set access and modification time of a file with nanosecond
+    /// precision, following symlinks like `Setstat`.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpUtimens)]
+    ThrusftpUtimens {
+        path: String,
+        atime: Timespec,
+        mtime: Timespec,
+    },
+    /// List the paths matching a shell glob pattern, answered with a `Name`
+    /// packet like `Readdir`, but with full paths as file names.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpGlob)]
+    ThrusftpGlob {
+        pattern: String,
+    },
+    /// Look up the names of users and groups, answered with a
+    /// `UsersGroupsByIdReply`.
+    #[bin_ser(val = ExtendedRequestType::OpensshUsersGroupsById)]
+    OpensshUsersGroupsById {
+        uids: IdList,
+        gids: IdList,
+    },
+    /// Find the next data or hole at or after `offset` of an open file,
+    /// like `lseek(2)` with `SEEK_DATA` or `SEEK_HOLE`. Answered with a
+    /// `SeekHoleDataReply`, or with `Eof` if there is no more data.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpSeekHoleData)]
+    ThrusftpSeekHoleData {
+        handle: String,
+        offset: u64,
+        whence: SeekWhence,
+    },
+    /// Remove a directory and everything in it, like `rm -r`. Symlinks in
+    /// the tree are removed, not followed.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpRmtree)]
+    ThrusftpRmtree {
+        path: String,
+    },
+    /// Read from an open file like `Read`, answered with a `ReadEofReply`
+    /// that also says whether the data reaches the end of the file, or with
+    /// `Eof` like `Read` if `offset` is at or past it.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpReadEof)]
+    ThrusftpReadEof {
+        handle: String,
+        offset: u64,
+        len: u32,
+    },
+    /// List a directory without a handle, from where an earlier reply left
+    /// off: `cookie` is empty to start at the beginning, or the one from a
+    /// `ReaddirFromReply`, possibly of an earlier session. Answered with
+    /// `Eof` once there are no more entries.
+    #[bin_ser(val = ExtendedRequestType::ThrusftpReaddirFrom)]
+    ThrusftpReaddirFrom {
+        path: String,
+        cookie: String,
+    },
+    /// An extension thrusftp does not know itself. `data` is the rest of the
+    /// request, undecoded.
+    #[bin_ser(fallback = true)]
+    Unknown {
+        name: String,
+        data: VecEos<u8>,
+    },
 }
 
 pub type Handle = String;
@@ -208,11 +571,13 @@ pub enum SftpClientPacket {
         id: u32,
         path: String,
     },
+    /// OpenSSH sends the target before the link path, the reverse of what
+    /// the draft specifies. Every client follows OpenSSH, so this does too.
     #[bin_ser(val = 20)]
     Symlink {
         id: u32,
-        linkpath: String,
         targetpath: String,
+        linkpath: String,
     },
 
     #[bin_ser(val = 200)]
@@ -222,6 +587,34 @@ pub enum SftpClientPacket {
     },
 }
 
+impl SftpClientPacket {
+    /// The request id, which every packet but `Init` carries.
+    pub fn id(&self) -> Option<u32> {
+        match *self {
+            SftpClientPacket::Init { .. } => None,
+            SftpClientPacket::Open { id, .. }
+            | SftpClientPacket::Close { id, .. }
+            | SftpClientPacket::Read { id, .. }
+            | SftpClientPacket::Write { id, .. }
+            | SftpClientPacket::Lstat { id, .. }
+            | SftpClientPacket::Fstat { id, .. }
+            | SftpClientPacket::Setstat { id, .. }
+            | SftpClientPacket::Fsetstat { id, .. }
+            | SftpClientPacket::Opendir { id, .. }
+            | SftpClientPacket::Readdir { id, .. }
+            | SftpClientPacket::Remove { id, .. }
+            | SftpClientPacket::Mkdir { id, .. }
+            | SftpClientPacket::Rmdir { id, .. }
+            | SftpClientPacket::Realpath { id, .. }
+            | SftpClientPacket::Stat { id, .. }
+            | SftpClientPacket::Rename { id, .. }
+            | SftpClientPacket::Readlink { id, .. }
+            | SftpClientPacket::Symlink { id, .. }
+            | SftpClientPacket::Extended { id, .. } => Some(id),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[bin_ser(repr = u8)]
 pub enum SftpServerPacket {
@@ -261,7 +654,7 @@ pub enum SftpServerPacket {
     #[bin_ser(val = 201)]
     ExtendedReply {
         id: u32,
-        data: VecU8,
+        data: VecEos<u8>,
     },
 }
 
@@ -276,10 +669,16 @@ pub struct FsStats {
     pub f_ffree: u64,
     pub f_favail: u64,
     pub f_fsid: u64,
+    /// `SSH2_FXE_STATVFS_ST_RDONLY` and `SSH2_FXE_STATVFS_ST_NOSUID`, not
+    /// the platform's `ST_*` flags, which only happen to match on Linux.
     pub f_flag: u64,
     pub f_namemax: u64,
 }
 
+/// Bits of `FsStats::f_flag`, as defined by OpenSSH.
+pub const SSH2_FXE_STATVFS_ST_RDONLY: u64 = 0x1;
+pub const SSH2_FXE_STATVFS_ST_NOSUID: u64 = 0x2;
+
 /// Vec that has no length on-wire. It ends when the stream ends.
 #[derive(Clone, Debug)]
 pub struct VecEos<T>(pub Vec<T>);
@@ -290,6 +689,90 @@ impl<T> From<Vec<T>> for VecEos<T> {
     }
 }
 
+/// Body of an `ExtendedReply` packet, by the extension it answers. Replies
+/// carry no tag of their own, so they are parsed with
+/// `ExtendedReply::parse` for the type of the request they answer.
+#[derive(Clone, Debug)]
+pub enum ExtendedReply {
+    Statvfs(FsStats),
+    CheckFile(CheckFileReply),
+    UsersGroupsById(UsersGroupsByIdReply),
+    SeekHoleData(SeekHoleDataReply),
+    ReadEof(ReadEofReply),
+    ReaddirFrom(ReaddirFromReply),
+    /// Reply to an extension without a type of its own here, undecoded.
+    Other(Vec<u8>),
+}
+
+/// Reply to `check-file-handle` and `check-file-name`: the algorithm used
+/// and the digest, or the digests of all blocks one after the other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckFileReply {
+    /// Always `check-file`.
+    pub reply_name: String,
+    pub algorithm: String,
+    pub hashes: VecEos<u8>,
+}
+
+impl CheckFileReply {
+    pub fn new(algorithm: HashAlgorithm, hashes: Vec<u8>) -> Self {
+        Self {
+            reply_name: "check-file".to_string(),
+            algorithm: algorithm.name().to_string(),
+            hashes: hashes.into(),
+        }
+    }
+}
+
+/// Reply to `users-groups-by-id@openssh.com`: one name per requested id, in
+/// the same order, empty for ids without a name.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsersGroupsByIdReply {
+    pub usernames: NameList,
+    pub groupnames: NameList,
+}
+
+/// What `seek-hole-data@thrusftp` looks for, numbered like `SEEK_DATA` and
+/// `SEEK_HOLE` on Linux.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[bin_ser(repr = u32)]
+pub enum SeekWhence {
+    #[bin_ser(val = 3)]
+    Data,
+    #[bin_ser(val = 4)]
+    Hole,
+}
+
+/// Reply to `seek-hole-data@thrusftp`: where the data or hole starts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SeekHoleDataReply {
+    pub offset: u64,
+}
+
+/// Reply to `read-eof@thrusftp`: the data read, and whether it ends at the
+/// end of the file, so the client need not ask again only to get `Eof`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReadEofReply {
+    pub data: VecU8,
+    pub eof: bool,
+}
+
+/// Reply to `readdir-from@thrusftp`: the next entries, and the opaque
+/// cookie to ask for the ones after them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReaddirFromReply {
+    pub names: Vec<Name>,
+    pub cookie: String,
+}
+
+/// User or group ids packed into a single string.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdList(pub Vec<u32>);
+
+/// Strings packed into a single string.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NameList(pub Vec<String>);
+
 #[derive(Clone, Debug)]
 pub struct VecU8(pub Vec<u8>);
 
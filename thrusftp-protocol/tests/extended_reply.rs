@@ -0,0 +1,104 @@
+use thrusftp_protocol::parse::Serialize;
+use thrusftp_protocol::types::*;
+
+fn bytes(reply: ExtendedReply) -> Vec<u8> {
+    VecEos::from(reply).0
+}
+
+/// Parses `reply` back for `request_type` and checks it serializes to the
+/// same bytes again.
+fn round_trip(request_type: ExtendedRequestType, reply: ExtendedReply) -> ExtendedReply {
+    let data = bytes(reply);
+    let parsed = ExtendedReply::parse(&request_type, &data).unwrap();
+    assert_eq!(bytes(parsed.clone()), data);
+    parsed
+}
+
+#[test]
+fn replies_round_trip() {
+    let stats = FsStats {
+        f_bsize: 4096, f_frsize: 4096, f_blocks: 1000, f_bfree: 500, f_bavail: 400, f_files: 100,
+        f_ffree: 50, f_favail: 40, f_fsid: 0x1234, f_flag: 1, f_namemax: 255,
+    };
+    match round_trip(ExtendedRequestType::OpensshStatvfs, ExtendedReply::Statvfs(stats)) {
+        ExtendedReply::Statvfs(stats) => assert_eq!((stats.f_bavail, stats.f_fsid), (400, 0x1234)),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    let hashes = vec![7; 2 * HashAlgorithm::Sha256.digest_len()];
+    let reply = ExtendedReply::CheckFile(CheckFileReply::new(HashAlgorithm::Sha256, hashes.clone()));
+    for request_type in [ExtendedRequestType::CheckFileHandle, ExtendedRequestType::CheckFileName] {
+        match round_trip(request_type, reply.clone()) {
+            ExtendedReply::CheckFile(reply) => {
+                assert_eq!(reply.reply_name, "check-file");
+                assert_eq!(reply.algorithm, "sha256");
+                assert_eq!(reply.hashes.0, hashes);
+            },
+            reply => panic!("unexpected reply {:?}", reply),
+        }
+    }
+
+    let reply = UsersGroupsByIdReply {
+        usernames: NameList(vec!["root".to_string(), String::new()]),
+        groupnames: NameList(vec!["wheel".to_string()]),
+    };
+    match round_trip(ExtendedRequestType::OpensshUsersGroupsById, ExtendedReply::UsersGroupsById(reply)) {
+        ExtendedReply::UsersGroupsById(reply) => {
+            assert_eq!(reply.usernames, NameList(vec!["root".to_string(), String::new()]));
+            assert_eq!(reply.groupnames, NameList(vec!["wheel".to_string()]));
+        },
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    let reply = ExtendedReply::SeekHoleData(SeekHoleDataReply { offset: 1 << 40 });
+    match round_trip(ExtendedRequestType::ThrusftpSeekHoleData, reply) {
+        ExtendedReply::SeekHoleData(reply) => assert_eq!(reply.offset, 1 << 40),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    let reply = ExtendedReply::ReadEof(ReadEofReply { data: vec![1, 2, 3].into(), eof: true });
+    match round_trip(ExtendedRequestType::ThrusftpReadEof, reply) {
+        ExtendedReply::ReadEof(reply) => assert_eq!((reply.data.0, reply.eof), (vec![1, 2, 3], true)),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    let names = vec![Name::new("file".to_string(), Attrs { size: Some(3), ..Default::default() })];
+    let reply = ExtendedReply::ReaddirFrom(ReaddirFromReply { names, cookie: "42".to_string() });
+    match round_trip(ExtendedRequestType::ThrusftpReaddirFrom, reply) {
+        ExtendedReply::ReaddirFrom(reply) => {
+            assert_eq!(reply.names.len(), 1);
+            assert_eq!((reply.names[0].filename.as_str(), reply.names[0].attrs.size), ("file", Some(3)));
+            assert_eq!(reply.cookie, "42");
+        },
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    // Replies to extensions without a type are passed through as they are.
+    let request_type = ExtendedRequestType::Other("custom@example.com".to_string());
+    match round_trip(request_type, ExtendedReply::Other(vec![0, 1, 2])) {
+        ExtendedReply::Other(data) => assert_eq!(data, [0, 1, 2]),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+}
+
+#[test]
+fn check_file_reply_layout() {
+    // As in the filexfer extensions draft: the reply name and the
+    // algorithm as strings, then the digests without a length.
+    let mut expected = vec![];
+    "check-file".to_string().serialize(&mut expected).unwrap();
+    "md5".to_string().serialize(&mut expected).unwrap();
+    expected.extend([9; 16]);
+    assert_eq!(bytes(ExtendedReply::CheckFile(CheckFileReply::new(HashAlgorithm::Md5, vec![9; 16]))), expected);
+}
+
+#[test]
+fn replies_must_be_complete() {
+    let mut data = bytes(ExtendedReply::SeekHoleData(SeekHoleDataReply { offset: 5 }));
+    assert!(ExtendedReply::parse(&ExtendedRequestType::ThrusftpSeekHoleData, &data[..7]).is_err());
+    data.push(0);
+    assert!(ExtendedReply::parse(&ExtendedRequestType::ThrusftpSeekHoleData, &data).is_err());
+
+    let data = bytes(ExtendedReply::ReadEof(ReadEofReply { data: vec![1, 2, 3].into(), eof: false }));
+    assert!(ExtendedReply::parse(&ExtendedRequestType::ThrusftpReadEof, &data[..data.len() - 1]).is_err());
+}
@@ -0,0 +1,126 @@
+use thrusftp_protocol::parse::{Deserialize, Serialize};
+use thrusftp_protocol::types::*;
+use thrusftp_protocol::codec::SftpCodec;
+
+fn packets() -> Vec<SftpClientPacket> {
+    vec![
+        SftpClientPacket::Init {
+            version: 3,
+            extensions: vec![].into(),
+        },
+        SftpClientPacket::Lstat {
+            id: 1,
+            path: "/some/path".to_string(),
+        },
+        SftpClientPacket::Write {
+            id: 2,
+            handle: "0".to_string(),
+            offset: 4096,
+            data: (0..=255).cycle().take(70_000).collect::<Vec<u8>>().into(),
+        },
+        SftpClientPacket::Close {
+            id: 3,
+            handle: "0".to_string(),
+        },
+    ]
+}
+
+fn stream() -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut stream = Vec::new();
+    let mut bodies = Vec::new();
+    for packet in packets() {
+        let mut body = Vec::new();
+        packet.serialize(&mut body).unwrap();
+        stream.extend_from_slice(&SftpCodec::encode(&packet).unwrap());
+        bodies.push(body);
+    }
+    (stream, bodies)
+}
+
+fn decode_in_chunks(stream: &[u8], chunk_lens: impl Iterator<Item = usize>) -> Vec<Vec<u8>> {
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut decoded = Vec::new();
+    let mut rest = stream;
+    for len in chunk_lens {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(len.min(rest.len()));
+        rest = tail;
+        decoded.extend(codec.decode(chunk).unwrap());
+    }
+    assert!(rest.is_empty());
+    assert_eq!(codec.buffered(), 0);
+    decoded
+}
+
+#[test]
+fn one_byte_at_a_time() {
+    let (stream, bodies) = stream();
+    let decoded = decode_in_chunks(&stream, std::iter::repeat(1));
+    assert_eq!(decoded, bodies);
+    for (body, packet) in decoded.iter().zip(packets()) {
+        let parsed = SftpClientPacket::deserialize(&mut &body[..]).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", packet));
+    }
+}
+
+#[test]
+fn whole_stream_at_once() {
+    let (stream, bodies) = stream();
+    assert_eq!(decode_in_chunks(&stream, std::iter::once(stream.len())), bodies);
+}
+
+#[test]
+fn irregular_splits() {
+    let (stream, bodies) = stream();
+    // Cuts land inside length prefixes as well as between them.
+    let mut state = 0x2545_f491_u32;
+    let lens = std::iter::from_fn(move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        Some(1 + state as usize % 7)
+    });
+    assert_eq!(decode_in_chunks(&stream, lens), bodies);
+}
+
+#[test]
+fn empty_packet() {
+    let mut codec = SftpCodec::new(16);
+    assert_eq!(codec.decode(&[0, 0]).unwrap(), Vec::<Vec<u8>>::new());
+    assert_eq!(codec.decode(&[0, 0]).unwrap(), vec![Vec::<u8>::new()]);
+    assert_eq!(codec.decode(&[0, 0, 0, 1, 7]).unwrap(), vec![vec![7]]);
+}
+
+#[test]
+fn oversized_packet() {
+    let mut codec = SftpCodec::new(16);
+    assert_eq!(codec.decode(&[0, 0, 0, 16]).unwrap(), Vec::<Vec<u8>>::new());
+    codec.reset();
+    let err = codec.decode(&[0, 0, 0, 17]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(codec.buffered(), 0);
+}
+
+#[test]
+fn partial_packet_is_buffered() {
+    let mut codec = SftpCodec::new(16);
+    assert_eq!(codec.decode(&[0, 0, 0, 3, 1, 2]).unwrap(), Vec::<Vec<u8>>::new());
+    assert_eq!(codec.buffered(), 6);
+    assert_eq!(codec.decode(&[3, 0, 0]).unwrap(), vec![vec![1, 2, 3]]);
+    assert_eq!(codec.buffered(), 2);
+    codec.reset();
+    assert_eq!(codec.buffered(), 0);
+}
+
+#[test]
+fn encode_into_appends() {
+    let (stream, bodies) = stream();
+    let mut buf = Vec::new();
+    for packet in packets() {
+        SftpCodec::encode_into(&packet, &mut buf).unwrap();
+    }
+    assert_eq!(buf, stream);
+    assert_eq!(decode_in_chunks(&buf, std::iter::once(buf.len())), bodies);
+}
@@ -0,0 +1,38 @@
+use thrusftp_protocol::types::*;
+
+#[test]
+fn formats_like_ls() {
+    let attrs = Attrs {
+        size: Some(1234),
+        uid_gid: Some((1000, 100)),
+        permissions: Some(0o100644),
+        // 2001-09-09 01:46:40 UTC
+        atime_mtime: Some((1_000_000_000, 1_000_000_000)),
+        extended_attrs: vec![],
+    };
+    let name = Name::new("file.txt".to_string(), attrs);
+    assert_eq!(name.longname, "-rw-r--r--   1 1000     100          1234 Sep  9  2001 file.txt");
+}
+
+#[test]
+fn recent_times_show_the_time_of_day() {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    // Midnight UTC a day ago, plus 13:05.
+    let mtime = (now / 86400 - 1) * 86400 + 13 * 3600 + 5 * 60;
+    let attrs = Attrs { atime_mtime: Some((mtime as u32, mtime as u32)), ..Default::default() };
+    assert!(Name::new("f".to_string(), attrs).longname.ends_with(" 13:05 f"));
+}
+
+#[test]
+fn file_types_and_special_bits() {
+    let mode = |permissions: u32| {
+        let attrs = Attrs { permissions: Some(permissions), ..Default::default() };
+        Name::new("f".to_string(), attrs).longname[..10].to_string()
+    };
+    assert_eq!(mode(0o040755), "drwxr-xr-x");
+    assert_eq!(mode(0o120777), "lrwxrwxrwx");
+    assert_eq!(mode(0o041777), "drwxrwxrwt");
+    assert_eq!(mode(0o104755), "-rwsr-xr-x");
+    assert_eq!(mode(0o102644), "-rw-r-Sr--");
+    assert_eq!(mode(0o010600), "prw-------");
+}
@@ -0,0 +1,108 @@
+use thrusftp_protocol::parse::{deserialize_packet, Serialize, Deserialize};
+use thrusftp_protocol::types::*;
+
+/// Small deterministic PRNG (xorshift64), so failures are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[test]
+fn garbage_does_not_panic() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..100_000 {
+        let len = (rng.next() % 64) as usize;
+        let mut packet: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        // Mostly use valid packet types, so the fields get parsed too.
+        if let Some(packet_type) = packet.first_mut() {
+            *packet_type = [1, 3, 4, 5, 6, 9, 17, 20, 200, 255][*packet_type as usize % 10];
+        }
+        let _ = SftpClientPacket::deserialize(&mut &packet[..]);
+    }
+}
+
+#[test]
+fn truncated_packets_are_errors() {
+    let packet = SftpClientPacket::Open {
+        id: 1,
+        filename: "/some/file".to_string(),
+        pflags: Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false },
+        attrs: Attrs { size: Some(4), permissions: Some(0o644), ..Default::default() },
+    };
+    let mut buf = Vec::new();
+    packet.serialize(&mut buf).unwrap();
+    for len in 0..buf.len() {
+        assert!(SftpClientPacket::deserialize(&mut &buf[..len]).is_err(), "prefix of length {} parsed", len);
+    }
+    assert!(SftpClientPacket::deserialize(&mut &buf[..]).is_ok());
+}
+
+#[test]
+fn unknown_packet_type_is_an_error() {
+    assert!(SftpClientPacket::deserialize(&mut &[42u8, 0, 0, 0, 1][..]).is_err());
+}
+
+fn init_with(tail: &[u8]) -> Vec<u8> {
+    let mut packet = vec![1, 0, 0, 0, 3];
+    Extension { name: "posix-rename@openssh.com".to_string(), data: "1".to_string() }
+        .serialize(&mut packet).unwrap();
+    packet.extend_from_slice(tail);
+    packet
+}
+
+#[test]
+fn misaligned_extension_lists_are_errors() {
+    // A name without its data.
+    let mut name_only = Vec::new();
+    "statvfs@openssh.com".to_string().serialize(&mut name_only).unwrap();
+    // A few bytes that do not even make a string length.
+    for tail in [&name_only[..], &[0, 0, 1][..], &[0, 0, 0, 9, b'x'][..]] {
+        let packet = init_with(tail);
+        let err = SftpClientPacket::deserialize(&mut &packet[..]).unwrap_err();
+        assert!(err.to_string().contains("not a complete element"), "{}", err);
+
+        let mut version = packet.clone();
+        version[0] = 2;
+        assert!(SftpServerPacket::deserialize(&mut &version[..]).is_err());
+    }
+
+    match SftpClientPacket::deserialize(&mut &init_with(&[])[..]).unwrap() {
+        SftpClientPacket::Init { version: 3, extensions } => {
+            assert_eq!(extensions.0.len(), 1);
+            assert_eq!(extensions.0[0].name, "posix-rename@openssh.com");
+        },
+        packet => panic!("unexpected packet {:?}", packet),
+    }
+}
+
+/// `packet` with the length of its data, the last field, changed by `delta`.
+fn with_data_len(packet: &[u8], data_len: usize, delta: i64) -> Vec<u8> {
+    let mut packet = packet.to_vec();
+    let at = packet.len() - data_len - 4;
+    let len = (data_len as i64 + delta) as u32;
+    packet[at..at + 4].copy_from_slice(&len.to_be_bytes());
+    packet
+}
+
+#[test]
+fn data_lengths_must_match_the_packet() {
+    let mut write = Vec::new();
+    SftpClientPacket::Write { id: 1, handle: "0".to_string(), offset: 0, data: vec![1, 2, 3, 4].into() }
+        .serialize(&mut write).unwrap();
+    let mut data = Vec::new();
+    SftpServerPacket::Data { id: 1, data: vec![1, 2, 3, 4].into() }.serialize(&mut data).unwrap();
+
+    assert!(deserialize_packet::<SftpClientPacket>(&write).is_ok());
+    assert!(deserialize_packet::<SftpServerPacket>(&data).is_ok());
+    for delta in [1, 1000, -1, -4] {
+        let err = deserialize_packet::<SftpClientPacket>(&with_data_len(&write, 4, delta)).unwrap_err();
+        assert_eq!(err.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::InvalidData);
+        assert!(deserialize_packet::<SftpServerPacket>(&with_data_len(&data, 4, delta)).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::Write;
+use bin_ser::{Serialize, Deserialize};
+use thrusftp_protocol::parse::{Serialize, Deserialize};
+
+/// A reply that gained `flags` and `comment` in a later version.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Reply {
+    offset: u64,
+    #[bin_ser(optional)]
+    flags: u32,
+    #[bin_ser(optional = true)]
+    comment: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Pair(u32, #[bin_ser(optional)] u32);
+
+#[test]
+fn missing_trailing_fields_are_defaulted() {
+    let full = Reply { offset: 7, flags: 3, comment: "new".to_string() };
+    let mut buf = Vec::new();
+    full.serialize(&mut buf).unwrap();
+    assert_eq!(Reply::deserialize(&mut &buf[..]).unwrap(), full);
+
+    // Sent by a peer that only knows `offset`, or `offset` and `flags`.
+    let old = Reply { offset: 7, flags: 0, comment: String::new() };
+    assert_eq!(Reply::deserialize(&mut &buf[..8]).unwrap(), old);
+    let newer = Reply { offset: 7, flags: 3, comment: String::new() };
+    assert_eq!(Reply::deserialize(&mut &buf[..12]).unwrap(), newer);
+
+    // A field that is there but cut short is still an error, and so is a
+    // missing field that is not optional.
+    assert!(Reply::deserialize(&mut &buf[..10]).is_err());
+    assert!(Reply::deserialize(&mut &buf[..4]).is_err());
+
+    assert_eq!(Pair::deserialize(&mut &[0, 0, 0, 1][..]).unwrap(), Pair(1, 0));
+    assert_eq!(Pair::deserialize(&mut &[0, 0, 0, 1, 0, 0, 0, 2][..]).unwrap(), Pair(1, 2));
+}
@@ -0,0 +1,74 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::executor::block_on;
+use futures::StreamExt;
+use thrusftp_protocol::{Fs, FsHandle, Result, SftpError};
+use thrusftp_protocol::types::*;
+
+fn unused() -> SftpError {
+    anyhow!("not used by this test").into()
+}
+
+/// Lists `.0` as a single batch, then only ever returns empty batches.
+struct OneBatch(Vec<&'static str>);
+
+#[async_trait]
+impl Fs for OneBatch {
+    type FileHandle = ();
+    type DirHandle = bool;
+
+    async fn opendir(&self, _path: String) -> Result<Self::DirHandle> {
+        Ok(false)
+    }
+    async fn readdir(&self, done: &mut Self::DirHandle) -> Result<Vec<Name>> {
+        if std::mem::replace(done, true) {
+            return Ok(vec![]);
+        }
+        Ok(self.0.iter().map(|name| Name::new(name.to_string(), Attrs::default())).collect())
+    }
+    async fn close(&self, _handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn open(&self, _filename: String, _pflags: Pflags, _attrs: Attrs) -> Result<Self::FileHandle> { Err(unused()) }
+    async fn read(&self, _handle: &mut Self::FileHandle, _offset: u64, _len: u32) -> Result<Vec<u8>> { Err(unused()) }
+    async fn write(&self, _handle: &mut Self::FileHandle, _offset: u64, _data: Vec<u8>) -> Result<()> { Err(unused()) }
+    async fn lstat(&self, _path: String) -> Result<Attrs> { Err(unused()) }
+    async fn fstat(&self, _handle: &mut Self::FileHandle) -> Result<Attrs> { Err(unused()) }
+    async fn setstat(&self, _path: String, _attrs: Attrs) -> Result<()> { Err(unused()) }
+    async fn fsetstat(&self, _handle: &mut Self::FileHandle, _attrs: Attrs) -> Result<()> { Err(unused()) }
+    async fn remove(&self, _filename: String) -> Result<()> { Err(unused()) }
+    async fn mkdir(&self, _path: String, _attrs: Attrs) -> Result<()> { Err(unused()) }
+    async fn rmdir(&self, _path: String) -> Result<()> { Err(unused()) }
+    async fn realpath(&self, _path: String) -> Result<String> { Err(unused()) }
+    async fn stat(&self, _path: String) -> Result<Attrs> { Err(unused()) }
+    async fn rename(&self, _oldpath: String, _newpath: String) -> Result<()> { Err(unused()) }
+    async fn readlink(&self, _path: String) -> Result<String> { Err(unused()) }
+    async fn symlink(&self, _linkpath: String, _targetpath: String) -> Result<()> { Err(unused()) }
+}
+
+fn filenames(names: Vec<Name>) -> Vec<String> {
+    names.into_iter().map(|name| name.filename).collect()
+}
+
+#[test]
+fn empty_batch_ends_readdir_stream() {
+    block_on(async {
+        for entries in [vec![], vec!["a", "b"]] {
+            let fs = OneBatch(entries.clone());
+            let mut handle = fs.opendir("/".to_string()).await.unwrap();
+            let names: Vec<_> = fs.readdir_stream(&mut handle).map(Result::unwrap).collect().await;
+            assert_eq!(filenames(names), entries);
+        }
+    });
+}
+
+#[test]
+fn empty_batch_ends_read_dir_all() {
+    block_on(async {
+        for entries in [vec![], vec!["a", "b"]] {
+            let fs = OneBatch(entries.clone());
+            assert_eq!(filenames(fs.read_dir_all("/".to_string()).await.unwrap()), entries);
+        }
+    });
+}
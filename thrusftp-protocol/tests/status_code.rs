@@ -0,0 +1,52 @@
+use std::io;
+
+use thrusftp_protocol::SftpError;
+use thrusftp_protocol::types::StatusCode;
+
+fn status_for_errno(errno: i32, version: u32) -> StatusCode {
+    StatusCode::from_io_error_kind(io::Error::from_raw_os_error(errno).kind()).for_version(version)
+}
+
+// errno values as on Linux.
+const EISDIR: i32 = 21;
+const ENOSPC: i32 = 28;
+const EDQUOT: i32 = 122;
+
+#[test]
+fn v4_codes_for_errno() {
+    assert_eq!(status_for_errno(ENOSPC, 4), StatusCode::NoSpaceOnFilesystem);
+    assert_eq!(status_for_errno(EDQUOT, 4), StatusCode::QuotaExceeded);
+    assert_eq!(status_for_errno(EISDIR, 4), StatusCode::FileIsADirectory);
+}
+
+#[test]
+fn v3_falls_back_to_failure() {
+    assert_eq!(status_for_errno(ENOSPC, 3), StatusCode::Failure);
+    assert_eq!(status_for_errno(EDQUOT, 3), StatusCode::Failure);
+    assert_eq!(status_for_errno(EISDIR, 3), StatusCode::Failure);
+}
+
+#[test]
+fn v3_codes_are_kept() {
+    assert_eq!(StatusCode::from_io_error_kind(io::ErrorKind::NotFound).for_version(3), StatusCode::NoSuchFile);
+    assert_eq!(StatusCode::from_io_error_kind(io::ErrorKind::PermissionDenied).for_version(3), StatusCode::PermissionDenied);
+    assert_eq!(StatusCode::from_io_error_kind(io::ErrorKind::UnexpectedEof).for_version(3), StatusCode::Eof);
+}
+
+#[test]
+fn sftp_error_status_codes() {
+    assert_eq!(SftpError::from(io::Error::from(io::ErrorKind::NotFound)).status_code(), StatusCode::NoSuchFile);
+    assert_eq!(SftpError::Protocol("bad".to_string()).status_code(), StatusCode::BadMessage);
+    assert_eq!(SftpError::Unsupported.status_code(), StatusCode::OpUnsupported);
+    assert_eq!(SftpError::Status(StatusCode::Eof, "done".to_string()).status_code(), StatusCode::Eof);
+    assert_eq!(SftpError::from(anyhow::anyhow!("opaque")).status_code(), StatusCode::Failure);
+}
+
+#[test]
+fn sftp_error_from_anyhow_keeps_cause() {
+    let err = SftpError::from(anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied)));
+    assert_eq!(err.io_error().unwrap().kind(), io::ErrorKind::PermissionDenied);
+
+    let err = SftpError::from(anyhow::Error::from(SftpError::Status(StatusCode::Eof, "done".to_string())));
+    assert!(matches!(err, SftpError::Status(StatusCode::Eof, _)));
+}
@@ -0,0 +1,42 @@
+//! Serves a `MemFs` over an in-memory pipe and sends it a few requests by
+//! hand.
+
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::Deserialize;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (mut client, server_end) = tokio::io::duplex(64 * 1024);
+    let server = tokio::spawn(async move {
+        let (reader, writer) = tokio::io::split(server_end);
+        serve_stream(&SftpServer::new(MemFs::new()), reader, writer).await
+    });
+
+    let requests = vec![
+        SftpClientPacket::Init { version: 3, extensions: vec![].into() },
+        SftpClientPacket::Mkdir { id: 1, path: "/hello".to_string(), attrs: Attrs::default() },
+        SftpClientPacket::Realpath { id: 2, path: "/hello/../hello".to_string() },
+    ];
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut buf = vec![0; 64 * 1024];
+    for request in requests {
+        client.write_all(&SftpCodec::encode(&request)?).await?;
+        let mut responses = Vec::new();
+        while responses.is_empty() {
+            let len = client.read(&mut buf).await?;
+            responses = codec.decode(&buf[..len])?;
+        }
+        for response in responses {
+            println!("{:?}", SftpServerPacket::deserialize(&mut &response[..])?);
+        }
+    }
+
+    drop(client);
+    server.await??;
+    Ok(())
+}
@@ -1,9 +1,46 @@
+//! Serves a local directory over SSH.
+//!
+//! ```text
+//! server [-v] [--listen ADDR] [--host-key PATH]... [--banner PATH] [ROOT]
+//! ```
+//!
+//! `ROOT` defaults to the working directory and `ADDR` to `0.0.0.0:2222`.
+//! `--host-key` can be given several times; without it a new key is made
+//! on every start. `--banner` shows the contents of a file to clients
+//! before they log in. `-v` prints every request and the response it got, as
+//! does setting `RUST_LOG=thrusftp_server=debug`.
+
 use thrusftp_server::SftpServer;
-use thrusftp_server::thrussh::start_server;
+use thrusftp_server::thrussh::{start_server, ServerConfig};
 use thrusftp_fs_local::LocalFs;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    start_server(SftpServer::new(LocalFs)).await;
-    Ok(())
+    let mut ssh_config = ServerConfig::default();
+    let mut root = None;
+    let mut verbose = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--listen" => ssh_config.listen_addr = value()?,
+            "--host-key" => ssh_config.host_keys.push(value()?.into()),
+            "--banner" => ssh_config.banner_file = Some(value()?.into()),
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option {}", arg),
+            _ => root = Some(arg),
+        }
+    }
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if verbose {
+        logger.filter_module("thrusftp_server", log::LevelFilter::Debug);
+    }
+    logger.init();
+
+    let fs = match root {
+        Some(root) => LocalFs::new(root),
+        None => LocalFs::default(),
+    };
+    start_server(SftpServer::builder(fs).ssh_config(ssh_config).build()).await
 }
@@ -1,85 +1,531 @@
+pub use thrusftp_protocol::codec;
+pub mod metrics;
+pub mod stream;
 #[cfg(feature = "thrussh-server")]
 pub mod thrussh;
 
-use tokio::sync::RwLock;
-use std::sync::Arc;
+use tokio::sync::{OwnedMutexGuard, RwLock};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::collections::HashMap;
+use std::time::Duration;
 
-use thrusftp_protocol::{Fs, FsHandle};
+use thrusftp_protocol::{Capabilities, Fs, FsHandle, Operation, SftpError};
 use thrusftp_protocol::types::*;
-use thrusftp_protocol::parse::Serialize;
+use thrusftp_protocol::parse::{deserialize_packet, Serialize};
 
-struct SftpClient<T: Fs + Send + Sync> {
-    handles: HashMap<String, FsHandle<T::FileHandle, T::DirHandle>>,
+use metrics::Metrics;
+
+/// Settings for an `SftpServer`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Maximum number of file and directory handles a single client may
+    /// have open at the same time, or `None` for no limit. An `Fs` can set
+    /// a lower limit of its own with `Capabilities::max_handles`.
+    pub max_handles: Option<usize>,
+    /// Largest request, in bytes without the length prefix, a transport
+    /// accepts from a client. A connection's `codec::SftpCodec` holds at
+    /// most one incomplete request of this size. Complete requests that
+    /// wait for others to finish are held besides: `stream::serve_stream`
+    /// reads no more until they are started, and over SSH they count
+    /// against the channel's window, which is only re-opened once they are.
+    ///
+    /// Responses are kept within the same size. A directory listing that
+    /// does not fit is split over several `readdir` calls. An entry, or the
+    /// entries of a `Name` or `Attrs` response, that would not fit on its
+    /// own loses its `longname` and extended attributes first. If it still
+    /// does not fit, a listing skips the entry and any other request fails
+    /// with `Failure`.
+    pub max_packet_size: u32,
+    /// Refuse every request that would modify the filesystem. `statvfs`
+    /// then reports the filesystem as read-only.
+    pub read_only: bool,
+    /// Extensions that are neither advertised nor answered, even if the `Fs`
+    /// supports them.
+    pub disabled_extensions: Vec<String>,
+    /// Maximum number of sessions the server serves at the same time, or
+    /// `None` for no limit. Only `try_new_session` and the transports built
+    /// on it refuse clients over the limit.
+    pub max_clients: Option<usize>,
+    /// Time after which a request still waiting for the `Fs` is answered
+    /// with `Failure`, or `None` to wait indefinitely. A stalled backend,
+    /// such as a hung network filesystem, then only fails the requests
+    /// touching it instead of blocking the session for good.
+    ///
+    /// The `Fs` future is dropped when the time is up. Work it already
+    /// handed to another thread may still complete afterwards, so a
+    /// timed-out request that modifies the filesystem may or may not have
+    /// taken effect. `Init` is never timed out.
+    pub request_timeout: Option<Duration>,
 }
 
-pub struct SftpServer<T: Fs + Send + Sync> {
-    clients: RwLock<HashMap<String, Arc<RwLock<SftpClient<T>>>>>,
-    fs: T,
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_handles: Some(1024),
+            max_packet_size: 256 * 1024,
+            read_only: false,
+            disabled_extensions: vec![],
+            max_clients: None,
+            request_timeout: None,
+        }
+    }
 }
 
-impl<T: Fs + Send + Sync> SftpServer<T> {
-    pub fn new(fs: T) -> Arc<Self> {
-        Arc::new(Self { fs, clients: RwLock::new(HashMap::new()) })
+/// The state of a single SFTP connection: the filesystem it is served from
+/// and the handles it has open.
+///
+/// A session does not depend on any transport. Feed it the packets read from
+/// the client and send back whatever `process` returns. As the protocol
+/// requires, the first packet must be `Init`: requests before it fail, and
+/// so does a second `Init`.
+///
+/// Requests can also be answered concurrently with `process_concurrent`,
+/// which the bundled transports use.
+pub struct SftpSession<T: Fs + Send + Sync> {
+    fs: Arc<T>,
+    config: Arc<Config>,
+    handles: Mutex<HashMap<String, SharedHandle<T>>>,
+    next_handle: AtomicU64,
+    /// Directory relative paths are resolved against. Looked up with
+    /// `Fs::home_directory` the first time a client sends a relative path.
+    cwd: Mutex<Option<String>>,
+    /// Entries read from a directory handle that did not fit into the last
+    /// `Name` response for it.
+    pending_names: Mutex<HashMap<Handle, Vec<Name>>>,
+    /// Where requests are counted. Shared with the `SftpServer` that
+    /// created the session, if any.
+    metrics: Arc<Metrics>,
+    /// Number of open handles last reported to `metrics`.
+    reported_handles: AtomicUsize,
+    /// Whether the client sent its `Init`.
+    initialized: AtomicBool,
+    /// Whether the client announced `utimens@thrusftp` in its `Init`, and so
+    /// gets the nanosecond times of `ATIME_EXTENDED_ATTR` and
+    /// `MTIME_EXTENDED_ATTR`.
+    nanosecond_times: AtomicBool,
+    /// What `fs` supports, once asked. Shared by the sessions of an
+    /// `SftpServer` that use its `Fs`.
+    capabilities: Arc<OnceLock<Capabilities>>,
+    /// Keeps the session counted in `SftpServer::client_count` while it is
+    /// alive. Sessions not created by an `SftpServer` are not counted.
+    _slot: Option<ClientSlot>,
+}
+
+/// An open handle. Requests using it lock it in turn, so those on the same
+/// handle run one at a time, in the order they were started. `None` once
+/// the handle is closed.
+type SharedHandle<T> = Arc<tokio::sync::Mutex<Option<FsHandle<<T as Fs>::FileHandle, <T as Fs>::DirHandle>>>>;
+
+/// One session's share of `SftpServer::client_count`, given back on drop.
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
-    pub async fn create_client_handle(self: Arc<Self>, start_str: &str) -> String {
-        let mut clients = self.clients.write().await;
-        let mut num = 0u64;
-        let mut handle;
-        loop {
-            handle = format!("{}{}", start_str, num);
-            if !clients.contains_key(&handle) { break; }
-            num += 1;
+}
+
+impl<T: Fs + Send + Sync> Drop for SftpSession<T> {
+    fn drop(&mut self) {
+        self.metrics.handles_changed(*self.reported_handles.get_mut(), 0);
+    }
+}
+
+impl<T: Fs + Send + Sync> SftpSession<T> {
+    pub fn new(fs: Arc<T>, config: Arc<Config>) -> Self {
+        Self {
+            fs,
+            config,
+            handles: Default::default(),
+            next_handle: AtomicU64::new(0),
+            cwd: Mutex::new(None),
+            pending_names: Default::default(),
+            metrics: Default::default(),
+            reported_handles: AtomicUsize::new(0),
+            initialized: AtomicBool::new(false),
+            nanosecond_times: AtomicBool::new(false),
+            capabilities: Default::default(),
+            _slot: None,
         }
-        clients.insert(handle.clone(), Arc::new(RwLock::new(SftpClient { handles: Default::default() })));
-        handle
     }
 
-    pub async fn process(self: Arc<Self>, client_handle: &str, packet: SftpClientPacket) -> SftpServerPacket {
-        let client = {
-            let clients = self.clients.read().await;
-            let client = clients.get(client_handle).unwrap().clone();
-            client
+    /// Makes `path` absolute by prefixing the session's working directory.
+    /// If the working directory cannot be determined, `path` is passed on
+    /// unchanged and left to the `Fs`.
+    async fn resolve(&self, path: String) -> String {
+        if path.starts_with('/') {
+            return path;
+        }
+        let cwd = self.cwd.lock().unwrap().clone();
+        let cwd = match cwd {
+            Some(cwd) => cwd,
+            None => match self.fs.home_directory().await {
+                Ok(cwd) => {
+                    *self.cwd.lock().unwrap() = Some(cwd.clone());
+                    cwd
+                },
+                Err(_) => return path,
+            },
+        };
+        if path.is_empty() || path == "." {
+            cwd
+        } else {
+            format!("{}/{}", cwd.trim_end_matches('/'), path)
+        }
+    }
+
+    /// `Fs::glob` for `pattern`, expanded one component with glob characters
+    /// at a time, so that each directory is authorized as `Opendir` before
+    /// it is listed and each match as `Lstat` before it is returned. What is
+    /// refused is left out. Fails once more than `MAX_GLOB_VISITS` paths
+    /// were looked at.
+    async fn glob(&self, pattern: &str) -> thrusftp_protocol::Result<Vec<Name>> {
+        let components: Vec<&str> = pattern.split('/').filter(|component| !component.is_empty()).collect();
+        // Paths matched so far, as they are, not escaped.
+        let mut dirs = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+        let mut matches = Vec::new();
+        let mut visited = 0;
+        for (i, component) in components.iter().enumerate() {
+            let last = i + 1 == components.len();
+            if !last && !component.contains(&['*', '?', '['][..]) {
+                for dir in &mut dirs {
+                    *dir = join_path(dir, component);
+                }
+                continue;
+            }
+            let mut next = Vec::new();
+            for dir in &dirs {
+                let listed = if dir.is_empty() { "." } else { dir };
+                if self.fs.authorize(Operation::Opendir, listed).await.is_err() {
+                    continue;
+                }
+                let names = self.fs.glob(join_path(&escape_glob(dir), component)).await?;
+                visited += names.len();
+                if visited > MAX_GLOB_VISITS {
+                    return Err(std::io::Error::other(format!("more than {} paths visited", MAX_GLOB_VISITS)).into());
+                }
+                if last {
+                    for name in names {
+                        if self.fs.authorize(Operation::Lstat, &name.filename).await.is_ok() {
+                            matches.push(name);
+                        }
+                    }
+                } else {
+                    next.extend(names.into_iter().map(|name| name.filename));
+                }
+            }
+            dirs = next;
+        }
+        Ok(matches)
+    }
+
+    /// The `Fs`'s capabilities, asked for the first time they are needed.
+    async fn capabilities(&self) -> &Capabilities {
+        if let Some(capabilities) = self.capabilities.get() {
+            return capabilities;
+        }
+        let capabilities = self.fs.capabilities().await;
+        self.capabilities.get_or_init(|| capabilities)
+    }
+
+    /// The lower of `Config::max_handles` and the `Fs`'s own limit. Known
+    /// by the time handles can be opened, as `Init` asks for capabilities.
+    fn max_handles(&self) -> Option<usize> {
+        let fs_max_handles = self.capabilities.get().and_then(|capabilities| capabilities.max_handles);
+        match (self.config.max_handles, fs_max_handles) {
+            (Some(max_handles), Some(fs_max_handles)) => Some(max_handles.min(fs_max_handles)),
+            (max_handles, fs_max_handles) => max_handles.or(fs_max_handles),
+        }
+    }
+
+    fn handle_limit_reached(&self) -> bool {
+        match self.max_handles() {
+            Some(max_handles) => self.handles.lock().unwrap().len() >= max_handles,
+            None => false,
+        }
+    }
+
+    /// Stores `fs_handle` under a handle string that has never been used by
+    /// this client, or gives it back if other requests opened
+    /// the most handles allowed in the meantime. Handles are opaque and
+    /// do not reveal the path they were opened for.
+    fn insert_handle(&self, fs_handle: FsHandle<T::FileHandle, T::DirHandle>) -> std::result::Result<Handle, FsHandle<T::FileHandle, T::DirHandle>> {
+        let mut handles = self.handles.lock().unwrap();
+        if self.max_handles().is_some_and(|max_handles| handles.len() >= max_handles) {
+            return Err(fs_handle);
+        }
+        let handle = format!("{:x}", self.next_handle.fetch_add(1, Ordering::SeqCst));
+        handles.insert(handle.clone(), Arc::new(tokio::sync::Mutex::new(Some(fs_handle))));
+        Ok(handle)
+    }
+
+    /// Answers with a new handle for `fs_handle`, which is closed again if
+    /// there is no room for it.
+    async fn handle_resp(&self, id: u32, fs_handle: FsHandle<T::FileHandle, T::DirHandle>) -> SftpServerPacket {
+        match self.insert_handle(fs_handle) {
+            Ok(handle) => SftpServerPacket::Handle { id, handle },
+            Err(fs_handle) => {
+                let _ = self.fs.close(fs_handle).await;
+                failure_resp(id, "Too many open handles")
+            },
+        }
+    }
+
+    /// Waits for the requests started earlier on `handle` and locks it for
+    /// this one, or returns the response for a handle that is not open.
+    async fn lock_handle(&self, id: u32, handle: &str) -> std::result::Result<OwnedMutexGuard<Option<FsHandle<T::FileHandle, T::DirHandle>>>, SftpServerPacket> {
+        let shared = self.handles.lock().unwrap().get(handle).cloned();
+        let guard = match shared {
+            Some(shared) => shared.lock_owned().await,
+            None => return Err(no_such_handle_resp(id)),
+        };
+        match *guard {
+            Some(_) => Ok(guard),
+            // Closed while this request waited for it.
+            None => Err(no_such_handle_resp(id)),
+        }
+    }
+
+    /// Closes every handle the client left open with `Fs::close`, as if it
+    /// had sent a `Close` for each, ignoring errors. Transports call this
+    /// when a connection ends. Dropping the session instead drops the
+    /// handles without `close`, which releases their resources but skips
+    /// what only `close` does, like `LocalFs::sync_on_close`.
+    pub async fn close_all(&mut self) {
+        self.pending_names.get_mut().unwrap().clear();
+        let handles: Vec<_> = self.handles.get_mut().unwrap().drain().map(|(_, handle)| handle).collect();
+        self.report_handles();
+        for shared in handles {
+            let fs_handle = shared.lock().await.take();
+            if let Some(fs_handle) = fs_handle {
+                let _ = self.fs.close(fs_handle).await;
+            }
+        }
+    }
+
+    /// Reports the change in open handles since the last report to the
+    /// metrics.
+    fn report_handles(&self) {
+        let handles = self.handles.lock().unwrap();
+        let reported = self.reported_handles.swap(handles.len(), Ordering::SeqCst);
+        self.metrics.handles_changed(reported, handles.len());
+    }
+
+    /// Answers `packet`. Every request and the status or type of its
+    /// response is logged at debug level.
+    ///
+    /// Dropping the returned future cancels the request: the `Fs` call in
+    /// progress is dropped at its current await point. A handle being
+    /// opened or closed is closed, and the session stays usable for the
+    /// next request.
+    pub async fn process(&mut self, packet: SftpClientPacket) -> SftpServerPacket {
+        self.process_concurrent(packet).await
+    }
+
+    /// Like `process`, but only borrows the session, so a transport can
+    /// have several requests in progress and send each response, with the
+    /// id of its request, as soon as it is ready. A slow `stat` then does
+    /// not hold up the reads behind it.
+    ///
+    /// Requests on the same handle still run one at a time, in the order
+    /// their futures were first polled, as the protocol requires; an `Init`
+    /// counts once its future was first polled. Requests on paths are not
+    /// ordered against each other, so clients that depend on the order of,
+    /// say, a `mkdir` and an `open` below it wait for the first response.
+    pub async fn process_concurrent(&self, packet: SftpClientPacket) -> SftpServerPacket {
+        // Catches up after a cancelled request, which may have removed a
+        // handle without reporting it.
+        self.report_handles();
+        self.metrics.record_request(&packet);
+        let request = log::log_enabled!(log::Level::Debug).then(|| describe_request(&packet));
+        let write_len = match packet {
+            SftpClientPacket::Write { ref data, .. } => data.0.len(),
+            _ => 0,
         };
-        self.process_internal(client, packet).await
+        let resp = match (self.config.request_timeout, packet.id()) {
+            (Some(timeout), Some(id)) => {
+                tokio::time::timeout(timeout, self.process_unbounded(packet)).await
+                    .unwrap_or_else(|_| failure_resp(id, "Request timed out"))
+            },
+            _ => self.process_unbounded(packet).await,
+        };
+        let resp = if self.nanosecond_times.load(Ordering::SeqCst) {
+            resp
+        } else {
+            without_nanosecond_times(resp)
+        };
+        let resp = bound_response(resp, self.config.max_packet_size as usize);
+        self.metrics.record_response(&resp, write_len);
+        self.report_handles();
+        if let Some(request) = request {
+            log::debug!("{} -> {}", request, describe_response(&resp));
+        }
+        resp
     }
 
-    async fn process_internal(self: Arc<Self>, client: Arc<RwLock<SftpClient<T>>>, packet: SftpClientPacket) -> SftpServerPacket {
-        let mut client = client.write().await;
+    async fn process_unbounded(&self, packet: SftpClientPacket) -> SftpServerPacket {
+        let fs = self.fs.clone();
+        // Like OpenSSH, an empty path asks for the working directory.
+        let packet = match packet {
+            SftpClientPacket::Realpath { id, path } if path.is_empty() => {
+                SftpClientPacket::Realpath { id, path: ".".to_string() }
+            },
+            packet => packet,
+        };
+        match packet.id() {
+            Some(id) if !self.initialized.load(Ordering::SeqCst) => return failure_resp(id, "Init expected first"),
+            // Only one `Init` gets through, also of several sent at once.
+            // `Init` has no id to answer with.
+            None if self.initialized.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() => return SftpServerPacket::Status {
+                id: 0,
+                status_code: StatusCode::BadMessage,
+                error_message: "Session already initialized".to_string(),
+                language_tag: "en".to_string(),
+            },
+            _ => {},
+        }
+        if self.config.read_only {
+            if let Some(id) = modifying_request_id(&packet) {
+                return SftpServerPacket::Status {
+                    id,
+                    status_code: StatusCode::PermissionDenied,
+                    error_message: "Server is read-only".to_string(),
+                    language_tag: "en".to_string(),
+                };
+            }
+        }
+        if let SftpClientPacket::Symlink { id, ref targetpath, .. } = packet {
+            if let Some(resp) = invalid_path_resp(id, targetpath) {
+                return resp;
+            }
+        }
+        if let Some((id, paths)) = request_paths(&packet) {
+            for (op, path) in paths {
+                if let Some(resp) = invalid_path_resp(id, path) {
+                    return resp;
+                }
+                let path = self.resolve(path.to_string()).await;
+                if let Err(err) = fs.authorize(op, &path).await {
+                    return permission_denied_resp(id, err);
+                }
+            }
+        }
         match packet {
-            SftpClientPacket::Init { .. } => {
-                let mut extensions = vec![];
-                if self.fs.statvfs_supported().await {
+            SftpClientPacket::Init { extensions: client_extensions, .. } => {
+                let nanosecond_times = client_extensions.0.iter().any(|ext| ext.name == "utimens@thrusftp");
+                self.nanosecond_times.store(nanosecond_times, Ordering::SeqCst);
+                fs.on_init(&client_extensions.0).await;
+                let capabilities = self.capabilities().await;
+                // Files are transferred byte for byte, without CRLF
+                // translation; this only tells text-mode clients which line
+                // ending to convert to and from themselves.
+                let mut extensions = vec![
+                    Extension {
+                        name: "newline@vandyke.com".to_string(),
+                        data: "\n".to_string(),
+                    },
+                ];
+                if capabilities.statvfs {
                     extensions.push(Extension {
                         name: "statvfs@openssh.com".to_string(),
                         data: "2".to_string(),
                     });
                 }
-                if self.fs.posix_rename_supported().await {
+                if capabilities.posix_rename {
                     extensions.push(Extension {
                         name: "posix-rename@openssh.com".to_string(),
                         data: "1".to_string(),
                     });
                 }
-                if self.fs.fsync_supported().await {
+                if capabilities.fsync {
                     extensions.push(Extension {
                         name: "fsync@openssh.com".to_string(),
                         data: "1".to_string(),
                     });
                 }
-                if self.fs.hardlink_supported().await {
+                if capabilities.hardlink {
                     extensions.push(Extension {
                         name: "hardlink@openssh.com".to_string(),
                         data: "1".to_string(),
                     });
                 }
+                if capabilities.fdatasync {
+                    extensions.push(Extension {
+                        name: "fdatasync@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.mknod {
+                    extensions.push(Extension {
+                        name: "mknod@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.utimens {
+                    extensions.push(Extension {
+                        name: "utimens@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.glob {
+                    extensions.push(Extension {
+                        name: "glob@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.users_groups_by_id {
+                    extensions.push(Extension {
+                        name: "users-groups-by-id@openssh.com".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.seek_hole_data {
+                    extensions.push(Extension {
+                        name: "seek-hole-data@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.read_eof {
+                    extensions.push(Extension {
+                        name: "read-eof@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.readdir_from {
+                    extensions.push(Extension {
+                        name: "readdir-from@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if capabilities.remove_tree {
+                    extensions.push(Extension {
+                        name: "rmtree@thrusftp".to_string(),
+                        data: "1".to_string(),
+                    });
+                }
+                if !capabilities.hash_algorithms.is_empty() {
+                    let names = capabilities.hash_algorithms.iter()
+                        .map(|algorithm| algorithm.name())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    for name in &["check-file-handle", "check-file-name"] {
+                        extensions.push(Extension {
+                            name: name.to_string(),
+                            data: names.clone(),
+                        });
+                    }
+                }
+                extensions.extend(capabilities.custom_extensions.iter().cloned());
+                extensions.retain(|ext| !self.config.disabled_extensions.contains(&ext.name));
                 SftpServerPacket::Version {
-                    version: 3,
+                    version: SFTP_VERSION,
                     extensions: extensions.into(),
                 }
             },
             SftpClientPacket::Realpath { id, path } => {
-                self.fs.realpath(path).await
+                let path = self.resolve(path).await;
+                fs.realpath(path).await
                     .map(|filename| {
                         SftpServerPacket::Name {
                             id,
@@ -94,121 +540,172 @@ impl<T: Fs + Send + Sync> SftpServer<T> {
                     .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Opendir { id, path } => {
-                let mut num = 0u64;
-                let mut handle;
-                loop {
-                    handle = format!("{}{}", path, num);
-                    if !client.handles.contains_key(&handle) { break; }
-                    num += 1;
+                let path = self.resolve(path).await;
+                if self.handle_limit_reached() {
+                    return failure_resp(id, "Too many open handles");
+                }
+                match fs.opendir(path).await {
+                    Ok(dir) => self.handle_resp(id, FsHandle::Dir(dir)).await,
+                    Err(err) => error_resp(id, err),
                 }
-
-                self.fs.opendir(path).await
-                    .map(|dir| {
-                        client.handles.insert(handle.clone(), FsHandle::Dir(dir));
-                        SftpServerPacket::Handle { id, handle }
-                    })
-                    .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Readdir { id, handle } => {
-                match client.handles.get_mut(&handle) {
-                    Some(FsHandle::Dir(dir)) => {
-                        self.fs.readdir(dir).await
-                            .map(|names| SftpServerPacket::Name { id, names })
-                            .unwrap_or_else(|err| error_resp(id, err))
-                    },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                let mut guard = match self.lock_handle(id, &handle).await {
+                    Ok(guard) => guard,
+                    Err(resp) => return resp,
+                };
+                let dir = match *guard {
+                    Some(FsHandle::Dir(ref mut dir)) => dir,
+                    _ => return not_a_dir_resp(id),
+                };
+                let max_len = self.config.max_packet_size as usize;
+                let mut names = self.pending_names.lock().unwrap().remove(&handle).unwrap_or_default();
+                loop {
+                    if names.is_empty() {
+                        names = match fs.readdir(dir).await {
+                            Ok(names) if names.is_empty() => return status_resp(id, StatusCode::Eof),
+                            Ok(names) => names,
+                            Err(err) => return error_resp(id, err),
+                        };
+                    }
+                    let (fitting, rest) = fit_names(names, max_len);
+                    if !fitting.is_empty() {
+                        if !rest.is_empty() {
+                            self.pending_names.lock().unwrap().insert(handle, rest);
+                        }
+                        return SftpServerPacket::Name { id, names: fitting };
+                    }
+                    // Every entry of the batch was skipped. Clients take an
+                    // empty `Name` for the end of the directory, so read on.
+                    names = rest;
                 }
             },
             SftpClientPacket::Close { id, handle } => {
-                match client.handles.remove(&handle) {
-                    Some(fs_handle) => {
-                        result_resp(id, self.fs.close(fs_handle).await)
-                    },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                self.pending_names.lock().unwrap().remove(&handle);
+                let shared = self.handles.lock().unwrap().remove(&handle);
+                let shared = match shared {
+                    Some(shared) => shared,
+                    None => return no_such_handle_resp(id),
+                };
+                // Requests started on the handle before are answered first.
+                let fs_handle = shared.lock().await.take();
+                match fs_handle {
+                    Some(fs_handle) => result_resp(id, fs.close(fs_handle).await),
+                    None => no_such_handle_resp(id),
                 }
             },
             SftpClientPacket::Lstat { id, path } => {
-                self.fs.lstat(path).await
-                    .map(|attrs| SftpServerPacket::Attrs { id, attrs: attrs.into() })
+                let path = self.resolve(path).await;
+                fs.lstat(path).await
+                    .map(|attrs| SftpServerPacket::Attrs { id, attrs })
                     .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Stat { id, path } => {
-                self.fs.stat(path).await
-                    .map(|attrs| SftpServerPacket::Attrs { id, attrs: attrs.into() })
+                let path = self.resolve(path).await;
+                fs.stat(path).await
+                    .map(|attrs| SftpServerPacket::Attrs { id, attrs })
                     .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Fstat { id, handle } => {
-                match client.handles.get_mut(&handle) {
-                    Some(FsHandle::File(file)) => {
-                        self.fs.fstat(file).await
-                            .map(|attrs| SftpServerPacket::Attrs { id, attrs: attrs.into() })
-                            .unwrap_or_else(|err| error_resp(id, err))
+                match self.lock_handle(id, &handle).await {
+                    Ok(mut guard) => match *guard {
+                        Some(FsHandle::File(ref mut file)) => {
+                            fs.fstat(file).await
+                                .map(|attrs| SftpServerPacket::Attrs { id, attrs })
+                                .unwrap_or_else(|err| error_resp(id, err))
+                        },
+                        _ => not_a_file_resp(id),
                     },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                    Err(resp) => resp,
                 }
             },
             SftpClientPacket::Open { id, filename, pflags, attrs } => {
-                let mut num = 0u64;
-                let mut handle;
-                loop {
-                    handle = format!("{}{}", filename, num);
-                    if !client.handles.contains_key(&handle) { break; }
-                    num += 1;
+                let filename = self.resolve(filename).await;
+                if self.handle_limit_reached() {
+                    return failure_resp(id, "Too many open handles");
+                }
+                match fs.open(filename, pflags, attrs).await {
+                    Ok(file) => self.handle_resp(id, FsHandle::File(file)).await,
+                    Err(err) => error_resp(id, err),
                 }
-
-                self.fs.open(filename, pflags, attrs).await
-                    .map(|file| {
-                        client.handles.insert(handle.clone(), FsHandle::File(file));
-                        SftpServerPacket::Handle { id, handle }
-                    })
-                    .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Read { id, handle, offset, len } => {
-                match client.handles.get_mut(&handle) {
-                    Some(FsHandle::File(file)) => {
-                        self.fs.read(file, offset, len).await
-                            .map(|data| SftpServerPacket::Data { id, data: data.into() })
-                            .unwrap_or_else(|err| error_resp(id, err))
+                match self.lock_handle(id, &handle).await {
+                    Ok(mut guard) => match *guard {
+                        Some(FsHandle::File(ref mut file)) => {
+                            // Short reads are allowed, so a read too large to
+                            // answer in one packet gets what fits.
+                            let len = len.min(self.config.max_packet_size.saturating_sub(DATA_HEADER_LEN));
+                            fs.read(file, offset, len).await
+                                .map(|data| SftpServerPacket::Data { id, data: data.into() })
+                                .unwrap_or_else(|err| error_resp(id, err))
+                        },
+                        _ => not_a_file_resp(id),
                     },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                    Err(resp) => resp,
                 }
             },
             SftpClientPacket::Write { id, handle, offset, data } => {
-                match client.handles.get_mut(&handle) {
-                    Some(FsHandle::File(file)) => {
-                        result_resp(id, self.fs.write(file, offset, data.0).await)
+                match self.lock_handle(id, &handle).await {
+                    Ok(mut guard) => match *guard {
+                        Some(FsHandle::File(ref mut file)) => {
+                            result_resp(id, fs.write(file, offset, data.0).await)
+                        },
+                        _ => not_a_file_resp(id),
                     },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                    Err(resp) => resp,
                 }
             },
             SftpClientPacket::Setstat { id, path, attrs } => {
-                result_resp(id, self.fs.setstat(path, attrs).await)
+                let path = self.resolve(path).await;
+                result_resp(id, fs.setstat(path, attrs).await)
             },
             SftpClientPacket::Fsetstat { id, handle, attrs } => {
-                match client.handles.get_mut(&handle) {
-                    Some(FsHandle::File(file)) => {
-                        result_resp(id, self.fs.fsetstat(file, attrs).await)
+                match self.lock_handle(id, &handle).await {
+                    Ok(mut guard) => match *guard {
+                        Some(FsHandle::File(ref mut file)) => {
+                            result_resp(id, fs.fsetstat(file, attrs).await)
+                        },
+                        _ => not_a_file_resp(id),
                     },
-                    _ => status_resp(id, StatusCode::BadMessage),
+                    Err(resp) => resp,
                 }
             },
             SftpClientPacket::Remove { id, filename } => {
-                result_resp(id, self.fs.remove(filename).await)
+                let filename = self.resolve(filename).await;
+                result_resp(id, fs.remove(filename).await)
             },
             SftpClientPacket::Mkdir { id, path, attrs } => {
-                result_resp(id, self.fs.mkdir(path, attrs).await)
+                let path = self.resolve(path).await;
+                result_resp(id, fs.mkdir(path, attrs).await)
             },
             SftpClientPacket::Rmdir { id, path } => {
-                result_resp(id, self.fs.rmdir(path).await)
+                let path = self.resolve(path).await;
+                result_resp(id, fs.rmdir(path).await)
             },
             SftpClientPacket::Rename { id, oldpath, newpath } => {
-                result_resp(id, self.fs.rename(oldpath, newpath).await)
+                let oldpath = self.resolve(oldpath).await;
+                let newpath = self.resolve(newpath).await;
+                result_resp(id, fs.rename(oldpath, newpath).await)
             },
             SftpClientPacket::Symlink { id, linkpath, targetpath } => {
-                result_resp(id, self.fs.symlink(linkpath, targetpath).await)
+                // The target is stored verbatim: a relative target is relative
+                // to the link's directory, not to the session. Absolute
+                // targets were passed to `Fs::authorize` above, relative ones
+                // are as where they lead from that directory.
+                let linkpath = self.resolve(linkpath).await;
+                if !targetpath.starts_with('/') {
+                    let link_dir = linkpath.rsplit_once('/').map_or("", |(dir, _)| if dir.is_empty() { "/" } else { dir });
+                    let target = normalize_path(&join_path(link_dir, &targetpath));
+                    if let Err(err) = fs.authorize(Operation::SymlinkTarget, &target).await {
+                        return permission_denied_resp(id, err);
+                    }
+                }
+                result_resp(id, fs.symlink(linkpath, targetpath).await)
             },
             SftpClientPacket::Readlink { id, path } => {
-                self.fs.readlink(path).await
+                let path = self.resolve(path).await;
+                fs.readlink(path).await
                     .map(|filename| {
                         SftpServerPacket::Name {
                             id,
@@ -223,39 +720,884 @@ impl<T: Fs + Send + Sync> SftpServer<T> {
                     .unwrap_or_else(|err| error_resp(id, err))
             },
             SftpClientPacket::Extended { id, extended_request } => {
+                let capabilities = self.capabilities().await;
+                let supported = match extended_request {
+                    ExtendedRequest::OpensshStatvfs { .. } => capabilities.statvfs,
+                    ExtendedRequest::OpensshPosixRename { .. } => capabilities.posix_rename,
+                    ExtendedRequest::OpensshHardlink { .. } => capabilities.hardlink,
+                    ExtendedRequest::OpensshFsync { .. } => capabilities.fsync,
+                    ExtendedRequest::ThrusftpFdatasync { .. } => capabilities.fdatasync,
+                    ExtendedRequest::CheckFileHandle { .. } | ExtendedRequest::CheckFileName { .. } => {
+                        !capabilities.hash_algorithms.is_empty()
+                    },
+                    ExtendedRequest::ThrusftpMknod { .. } => capabilities.mknod,
+                    ExtendedRequest::ThrusftpUtimens { .. } => capabilities.utimens,
+                    ExtendedRequest::ThrusftpGlob { .. } => capabilities.glob,
+                    ExtendedRequest::OpensshUsersGroupsById { .. } => capabilities.users_groups_by_id,
+                    ExtendedRequest::ThrusftpSeekHoleData { .. } => capabilities.seek_hole_data,
+                    ExtendedRequest::ThrusftpRmtree { .. } => capabilities.remove_tree,
+                    ExtendedRequest::ThrusftpReadEof { .. } => capabilities.read_eof,
+                    ExtendedRequest::ThrusftpReaddirFrom { .. } => capabilities.readdir_from,
+                    ExtendedRequest::Unknown { ref name, .. } => {
+                        capabilities.custom_extensions.iter().any(|ext| &ext.name == name)
+                    },
+                };
+                if !supported || self.config.disabled_extensions.contains(&extended_request.name()) {
+                    return status_resp(id, StatusCode::OpUnsupported);
+                }
                 match extended_request {
                     ExtendedRequest::OpensshStatvfs { path } => {
-                        self.fs.statvfs(path).await
-                            .map(|stats| {
-                                let mut data = vec![];
-                                stats.serialize(&mut data).unwrap();
-                                SftpServerPacket::ExtendedReply {
-                                    id,
-                                    data: data.into(),
+                        let path = self.resolve(path).await;
+                        fs.statvfs(path).await
+                            .map(|mut stats| {
+                                // Lets clients tell before they try to write.
+                                if self.config.read_only {
+                                    stats.f_flag |= SSH2_FXE_STATVFS_ST_RDONLY;
                                 }
+                                SftpServerPacket::ExtendedReply { id, data: ExtendedReply::Statvfs(stats).into() }
                             })
                             .unwrap_or_else(|err| error_resp(id, err))
                     },
                     ExtendedRequest::OpensshPosixRename { oldpath, newpath } => {
-                        result_resp(id, self.fs.posix_rename(oldpath, newpath).await)
+                        let oldpath = self.resolve(oldpath).await;
+                        let newpath = self.resolve(newpath).await;
+                        result_resp(id, fs.posix_rename(oldpath, newpath).await)
                     },
                     ExtendedRequest::OpensshHardlink { oldpath, newpath } => {
-                        result_resp(id, self.fs.hardlink(oldpath, newpath).await)
+                        let oldpath = self.resolve(oldpath).await;
+                        let newpath = self.resolve(newpath).await;
+                        result_resp(id, fs.hardlink(oldpath, newpath).await)
                     },
                     ExtendedRequest::OpensshFsync { handle } => {
-                        match client.handles.get_mut(&handle) {
-                            Some(FsHandle::File(file)) => {
-                                result_resp(id, self.fs.fsync(file).await)
+                        match self.lock_handle(id, &handle).await {
+                            Ok(mut guard) => match *guard {
+                                Some(FsHandle::File(ref mut file)) => {
+                                    result_resp(id, fs.fsync(file).await)
+                                },
+                                _ => not_a_file_resp(id),
+                            },
+                            Err(resp) => resp,
+                        }
+                    },
+                    ExtendedRequest::ThrusftpFdatasync { handle } => {
+                        match self.lock_handle(id, &handle).await {
+                            Ok(mut guard) => match *guard {
+                                Some(FsHandle::File(ref mut file)) => {
+                                    result_resp(id, fs.fdatasync(file).await)
+                                },
+                                _ => not_a_file_resp(id),
+                            },
+                            Err(resp) => resp,
+                        }
+                    },
+                    ExtendedRequest::CheckFileHandle { handle, hash_algorithms, start_offset, length, block_size } => {
+                        let max_len = self.config.max_packet_size as usize;
+                        match self.lock_handle(id, &handle).await {
+                            Ok(mut guard) => match *guard {
+                                Some(FsHandle::File(ref mut file)) => {
+                                    check_file_resp(&*fs, &capabilities.hash_algorithms, id, file, &hash_algorithms, HashRange { start_offset, length, block_size }, max_len).await
+                                },
+                                _ => not_a_file_resp(id),
+                            },
+                            Err(resp) => resp,
+                        }
+                    },
+                    ExtendedRequest::CheckFileName { filename, hash_algorithms, start_offset, length, block_size } => {
+                        let max_len = self.config.max_packet_size as usize;
+                        let filename = self.resolve(filename).await;
+                        let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+                        let mut file = match fs.open(filename, pflags, Attrs::default()).await {
+                            Ok(file) => file,
+                            Err(err) => return error_resp(id, err),
+                        };
+                        let resp = check_file_resp(&*fs, &capabilities.hash_algorithms, id, &mut file, &hash_algorithms, HashRange { start_offset, length, block_size }, max_len).await;
+                        let _ = fs.close(FsHandle::File(file)).await;
+                        resp
+                    },
+                    ExtendedRequest::ThrusftpMknod { path, mode, dev } => {
+                        let path = self.resolve(path).await;
+                        result_resp(id, fs.mknod(path, mode, dev).await)
+                    },
+                    ExtendedRequest::ThrusftpUtimens { path, atime, mtime } => {
+                        let path = self.resolve(path).await;
+                        result_resp(id, fs.utimens(path, atime, mtime).await)
+                    },
+                    ExtendedRequest::ThrusftpGlob { pattern } => {
+                        // The working directory is taken literally, not as
+                        // part of the pattern.
+                        let pattern = match self.resolve(String::new()).await {
+                            cwd if !pattern.starts_with('/') && !cwd.is_empty() => {
+                                format!("{}/{}", escape_glob(&cwd).trim_end_matches('/'), pattern)
+                            },
+                            _ => pattern,
+                        };
+                        self.glob(&pattern).await
+                            .map(|names| SftpServerPacket::Name { id, names })
+                            .unwrap_or_else(|err| error_resp(id, err))
+                    },
+                    ExtendedRequest::OpensshUsersGroupsById { uids, gids } => {
+                        let (uid_count, gid_count) = (uids.0.len(), gids.0.len());
+                        match fs.resolve_ids(uids.0, gids.0).await {
+                            Ok((usernames, groupnames)) => {
+                                // Names are matched to ids by position only.
+                                if usernames.len() != uid_count || groupnames.len() != gid_count {
+                                    return failure_resp(id, "Wrong number of names");
+                                }
+                                let reply = UsersGroupsByIdReply {
+                                    usernames: NameList(usernames),
+                                    groupnames: NameList(groupnames),
+                                };
+                                SftpServerPacket::ExtendedReply { id, data: ExtendedReply::UsersGroupsById(reply).into() }
+                            },
+                            Err(err) => error_resp(id, err),
+                        }
+                    },
+                    ExtendedRequest::ThrusftpSeekHoleData { handle, offset, whence } => {
+                        match self.lock_handle(id, &handle).await {
+                            Ok(mut guard) => match *guard {
+                                Some(FsHandle::File(ref mut file)) => {
+                                    fs.seek_hole_data(file, offset, whence).await
+                                        .map(|offset| {
+                                            let reply = ExtendedReply::SeekHoleData(SeekHoleDataReply { offset });
+                                            SftpServerPacket::ExtendedReply { id, data: reply.into() }
+                                        })
+                                        .unwrap_or_else(|err| error_resp(id, err))
+                                },
+                                _ => not_a_file_resp(id),
                             },
-                            _ => status_resp(id, StatusCode::BadMessage),
+                            Err(resp) => resp,
+                        }
+                    },
+                    ExtendedRequest::ThrusftpRmtree { path } => {
+                        let path = self.resolve(path).await;
+                        result_resp(id, fs.remove_tree(path).await)
+                    },
+                    ExtendedRequest::ThrusftpReadEof { handle, offset, len } => {
+                        match self.lock_handle(id, &handle).await {
+                            Ok(mut guard) => match *guard {
+                                Some(FsHandle::File(ref mut file)) => {
+                                    // Shortened to fit like a plain `Read`.
+                                    let len = len.min(self.config.max_packet_size.saturating_sub(READ_EOF_HEADER_LEN));
+                                    fs.read_eof(file, offset, len).await
+                                        .map(|(data, eof)| {
+                                            self.metrics.record_read(data.len());
+                                            let reply = ExtendedReply::ReadEof(ReadEofReply { data: data.into(), eof });
+                                            SftpServerPacket::ExtendedReply { id, data: reply.into() }
+                                        })
+                                        .unwrap_or_else(|err| error_resp(id, err))
+                                },
+                                _ => not_a_file_resp(id),
+                            },
+                            Err(resp) => resp,
+                        }
+                    },
+                    ExtendedRequest::ThrusftpReaddirFrom { path, mut cookie } => {
+                        let path = self.resolve(path).await;
+                        let max_len = self.config.max_packet_size as usize;
+                        loop {
+                            let entries = match fs.readdir_from(path.clone(), cookie).await {
+                                Ok(entries) => entries,
+                                Err(err) => return error_resp(id, err),
+                            };
+                            match fit_cookie_names(entries, max_len) {
+                                (names, Some(cookie)) if !names.is_empty() => {
+                                    let reply = ExtendedReply::ReaddirFrom(ReaddirFromReply { names, cookie });
+                                    return SftpServerPacket::ExtendedReply { id, data: reply.into() };
+                                },
+                                // Every entry was skipped; go on after them.
+                                (_, Some(next)) => cookie = next,
+                                (_, None) => return status_resp(id, StatusCode::Eof),
+                            }
                         }
                     },
+                    ExtendedRequest::Unknown { name, data } => {
+                        fs.handle_extension(name, data.0).await
+                            .map(|data| SftpServerPacket::ExtendedReply { id, data: data.into() })
+                            .unwrap_or_else(|err| error_resp(id, err))
+                    },
                 }
             },
         }
     }
 }
 
+/// Serves any number of clients from one `Fs`, keeping their sessions in a
+/// map keyed by client handle. Transports that keep per-connection state
+/// themselves can use `new_session` and drive the `SftpSession` directly.
+pub struct SftpServer<T: Fs + Send + Sync> {
+    clients: RwLock<HashMap<String, Arc<SftpSession<T>>>>,
+    client_count: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
+    fs: Arc<T>,
+    /// Capabilities of `fs`, asked by the first session that needs them.
+    capabilities: Arc<OnceLock<Capabilities>>,
+    config: Arc<Config>,
+    #[cfg(feature = "thrussh-server")]
+    ssh_config: thrussh::ServerConfig,
+    #[cfg(feature = "thrussh-server")]
+    provider: Option<Arc<dyn thrussh::FsProvider<T>>>,
+    #[cfg(feature = "thrussh-server")]
+    exec_handler: Option<Arc<dyn thrussh::ExecHandler>>,
+}
+
+impl<T: Fs + Send + Sync> SftpServer<T> {
+    pub fn new(fs: T) -> Arc<Self> {
+        Self::with_config(fs, Config::default())
+    }
+    pub fn with_config(fs: T, config: Config) -> Arc<Self> {
+        Self::builder(fs).config(config).build()
+    }
+    pub fn builder(fs: T) -> SftpServerBuilder<T> {
+        SftpServerBuilder::new(fs)
+    }
+    /// Number of sessions created by this server that are still alive.
+    pub fn client_count(&self) -> usize {
+        self.client_count.load(Ordering::SeqCst)
+    }
+    /// Counters over all sessions created by this server.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+    /// Counts `session` towards `client_count`, unless `limited` and the
+    /// server already serves `Config::max_clients` sessions.
+    fn counted(&self, mut session: SftpSession<T>, limited: bool) -> Option<SftpSession<T>> {
+        let mut count = self.client_count.load(Ordering::SeqCst);
+        loop {
+            if limited && self.config.max_clients.is_some_and(|max| count >= max) {
+                return None;
+            }
+            match self.client_count.compare_exchange(count, count + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(current) => count = current,
+            }
+        }
+        session._slot = Some(ClientSlot(self.client_count.clone()));
+        session.metrics = self.metrics.clone();
+        Some(session)
+    }
+    /// A session served by the server's own `Fs`, whose capabilities are
+    /// only asked once for all of them.
+    fn shared_fs_session(&self) -> SftpSession<T> {
+        let mut session = SftpSession::new(self.fs.clone(), self.config.clone());
+        session.capabilities = self.capabilities.clone();
+        session
+    }
+    /// Creates a session that counts towards `client_count` but is never
+    /// refused; see `try_new_session` to respect `Config::max_clients`.
+    pub fn new_session(&self) -> SftpSession<T> {
+        self.counted(self.shared_fs_session(), false).unwrap()
+    }
+    /// Like `new_session`, but the session is served by its own `fs`
+    /// instead of the one the server was created with.
+    pub fn new_session_with_fs(&self, fs: T) -> SftpSession<T> {
+        self.counted(SftpSession::new(Arc::new(fs), self.config.clone()), false).unwrap()
+    }
+    /// Like `new_session`, but returns `None` if the server already serves
+    /// `Config::max_clients` sessions.
+    pub fn try_new_session(&self) -> Option<SftpSession<T>> {
+        self.counted(self.shared_fs_session(), true)
+    }
+    /// Like `try_new_session`, but the session is served by its own `fs`.
+    pub fn try_new_session_with_fs(&self, fs: T) -> Option<SftpSession<T>> {
+        self.counted(SftpSession::new(Arc::new(fs), self.config.clone()), true)
+    }
+    pub async fn create_client_handle(self: Arc<Self>, start_str: &str) -> String {
+        let session = self.new_session();
+        self.insert_client(start_str, session).await
+    }
+    /// Like `create_client_handle`, but the client is served by its own `fs`
+    /// instead of the one the server was created with.
+    pub async fn create_client_handle_with_fs(self: Arc<Self>, start_str: &str, fs: T) -> String {
+        let session = self.new_session_with_fs(fs);
+        self.insert_client(start_str, session).await
+    }
+    async fn insert_client(self: Arc<Self>, start_str: &str, session: SftpSession<T>) -> String {
+        let mut clients = self.clients.write().await;
+        let mut num = 0u64;
+        let mut handle;
+        loop {
+            handle = format!("{}{}", start_str, num);
+            if !clients.contains_key(&handle) { break; }
+            num += 1;
+        }
+        clients.insert(handle.clone(), Arc::new(session));
+        handle
+    }
+    /// Drops the session of `client_handle`, closing all of its handles once
+    /// the requests in progress for it are answered.
+    pub async fn remove_client_handle(&self, client_handle: &str) {
+        let mut session = match self.clients.write().await.remove(client_handle) {
+            Some(session) => session,
+            None => return,
+        };
+        // Requests in progress hold on to the session until they are answered.
+        let mut session = loop {
+            match Arc::try_unwrap(session) {
+                Ok(session) => break session,
+                Err(shared) => {
+                    session = shared;
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                },
+            }
+        };
+        session.close_all().await;
+    }
+
+    /// Answers `packet` for `client_handle`. Calls for the same client may
+    /// run concurrently, see `SftpSession::process_concurrent`.
+    pub async fn process(self: Arc<Self>, client_handle: &str, packet: SftpClientPacket) -> SftpServerPacket {
+        let client = {
+            let clients = self.clients.read().await;
+            let client = clients.get(client_handle).unwrap().clone();
+            client
+        };
+        client.process_concurrent(packet).await
+    }
+}
+
+/// The id of `packet` if it is a request that modifies the filesystem. Custom
+/// extensions are left to the `Fs`.
+fn modifying_request_id(packet: &SftpClientPacket) -> Option<u32> {
+    match *packet {
+        SftpClientPacket::Open { id, ref pflags, .. }
+            if pflags.write || pflags.append || pflags.creat || pflags.trunc => Some(id),
+        SftpClientPacket::Write { id, .. }
+        | SftpClientPacket::Setstat { id, .. }
+        | SftpClientPacket::Fsetstat { id, .. }
+        | SftpClientPacket::Remove { id, .. }
+        | SftpClientPacket::Mkdir { id, .. }
+        | SftpClientPacket::Rmdir { id, .. }
+        | SftpClientPacket::Rename { id, .. }
+        | SftpClientPacket::Symlink { id, .. }
+        | SftpClientPacket::Extended { id, extended_request: ExtendedRequest::OpensshPosixRename { .. } }
+        | SftpClientPacket::Extended { id, extended_request: ExtendedRequest::OpensshHardlink { .. } }
+        | SftpClientPacket::Extended { id, extended_request: ExtendedRequest::ThrusftpMknod { .. } }
+        | SftpClientPacket::Extended { id, extended_request: ExtendedRequest::ThrusftpUtimens { .. } }
+        | SftpClientPacket::Extended { id, extended_request: ExtendedRequest::ThrusftpRmtree { .. } } => Some(id),
+        _ => None,
+    }
+}
+
+/// Paths a request operates on, for `Fs::authorize`, with the request id.
+fn request_paths(packet: &SftpClientPacket) -> Option<(u32, Vec<(Operation, &str)>)> {
+    let (id, paths) = match packet {
+        SftpClientPacket::Open { id, filename, pflags, .. } => (id, vec![(Operation::Open(pflags.clone()), filename)]),
+        SftpClientPacket::Opendir { id, path } => (id, vec![(Operation::Opendir, path)]),
+        SftpClientPacket::Stat { id, path } => (id, vec![(Operation::Stat, path)]),
+        SftpClientPacket::Lstat { id, path } => (id, vec![(Operation::Lstat, path)]),
+        SftpClientPacket::Setstat { id, path, .. } => (id, vec![(Operation::Setstat, path)]),
+        SftpClientPacket::Remove { id, filename } => (id, vec![(Operation::Remove, filename)]),
+        SftpClientPacket::Mkdir { id, path, .. } => (id, vec![(Operation::Mkdir, path)]),
+        SftpClientPacket::Rmdir { id, path } => (id, vec![(Operation::Rmdir, path)]),
+        SftpClientPacket::Rename { id, oldpath, newpath } => {
+            (id, vec![(Operation::Rename, oldpath), (Operation::Rename, newpath)])
+        },
+        SftpClientPacket::Symlink { id, linkpath, targetpath } => {
+            let mut paths = vec![(Operation::Symlink, linkpath)];
+            if targetpath.starts_with('/') {
+                paths.push((Operation::SymlinkTarget, targetpath));
+            }
+            (id, paths)
+        },
+        SftpClientPacket::Readlink { id, path } => (id, vec![(Operation::Readlink, path)]),
+        SftpClientPacket::Realpath { id, path } => (id, vec![(Operation::Realpath, path)]),
+        SftpClientPacket::Extended { id, extended_request } => {
+            let paths = match extended_request {
+                ExtendedRequest::OpensshStatvfs { path } => vec![(Operation::Statvfs, path)],
+                ExtendedRequest::OpensshPosixRename { oldpath, newpath } => {
+                    vec![(Operation::Rename, oldpath), (Operation::Rename, newpath)]
+                },
+                ExtendedRequest::OpensshHardlink { oldpath, newpath } => {
+                    vec![(Operation::Hardlink, oldpath), (Operation::Hardlink, newpath)]
+                },
+                ExtendedRequest::CheckFileName { filename, .. } => vec![(Operation::CheckFile, filename)],
+                ExtendedRequest::ThrusftpMknod { path, .. } => vec![(Operation::Mknod, path)],
+                ExtendedRequest::ThrusftpUtimens { path, .. } => vec![(Operation::Utimens, path)],
+                ExtendedRequest::ThrusftpGlob { pattern } => vec![(Operation::Glob, pattern)],
+                ExtendedRequest::ThrusftpRmtree { path } => vec![(Operation::RemoveTree, path)],
+                ExtendedRequest::ThrusftpReaddirFrom { path, .. } => vec![(Operation::Opendir, path)],
+                _ => return None,
+            };
+            (id, paths)
+        },
+        _ => return None,
+    };
+    Some((*id, paths.into_iter().map(|(op, path)| (op, path.as_str())).collect()))
+}
+
+/// Refuses paths no filesystem could have a file at before they reach the
+/// `Fs`: empty ones as `NoSuchFile`, like the kernel does, and ones with a
+/// NUL byte, which cannot be passed to a system call, as `BadMessage`.
+fn invalid_path_resp(id: u32, path: &str) -> Option<SftpServerPacket> {
+    let (status_code, error_message) = if path.is_empty() {
+        (StatusCode::NoSuchFile, "Empty path")
+    } else if path.contains('\0') {
+        (StatusCode::BadMessage, "Path contains a NUL byte")
+    } else {
+        return None;
+    };
+    Some(SftpServerPacket::Status {
+        id,
+        status_code,
+        error_message: error_message.to_string(),
+        language_tag: "en".to_string(),
+    })
+}
+
+/// Quotes the characters `*`, `?`, `[` and `]` so a glob matches them
+/// literally.
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '*' | '?' | '[' | ']' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            },
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `path` with `name` appended, `name` itself if `path` is empty.
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path.trim_end_matches('/'), name)
+    }
+}
+
+/// `path` with `.` and `..` resolved lexically, without following
+/// symlinks. `..` in `/` stays in `/`; in a relative path with nothing
+/// left to remove, it is kept.
+fn normalize_path(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => match components.last() {
+                Some(&last) if last != ".." => { components.pop(); },
+                _ if absolute => {},
+                _ => components.push(".."),
+            },
+            component => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else if joined.is_empty() {
+        ".".to_string()
+    } else {
+        joined
+    }
+}
+
+/// Most paths a `glob@thrusftp` request looks at, counting the directories
+/// it descends into as well as its matches.
+const MAX_GLOB_VISITS: usize = 16 * 1024;
+
+/// Smallest block size `check-file-*` hashes in, as the filexfer extensions
+/// draft requires.
+const MIN_HASH_BLOCK_SIZE: u32 = 256;
+
+/// The part of a file a `check-file-*` request hashes, as the client sent
+/// it. A `length` of 0 runs to the end of the file, a `block_size` of 0
+/// hashes the range as one block.
+struct HashRange {
+    start_offset: u64,
+    length: u64,
+    block_size: u32,
+}
+
+/// Answers `check-file-handle` and `check-file-name` with the digest of the
+/// requested range of `file`, or of each of its blocks, using the first of
+/// the client's algorithms the `Fs` supports. Block lists that would not
+/// fit into `max_len` bytes are refused before anything is hashed.
+async fn check_file_resp<T: Fs + Send + Sync>(
+    fs: &T,
+    supported: &[HashAlgorithm],
+    id: u32,
+    file: &mut T::FileHandle,
+    hash_algorithms: &str,
+    range: HashRange,
+    max_len: usize,
+) -> SftpServerPacket {
+    let HashRange { start_offset, length, block_size } = range;
+    if block_size != 0 && block_size < MIN_HASH_BLOCK_SIZE {
+        return failure_resp(id, "Block size must be at least 256 bytes");
+    }
+    let algorithm = hash_algorithms.split(',')
+        .filter_map(HashAlgorithm::from_name)
+        .find(|algorithm| supported.contains(algorithm));
+    let algorithm = match algorithm {
+        Some(algorithm) => algorithm,
+        None => return status_resp(id, StatusCode::OpUnsupported),
+    };
+    let reply = |hashes: Vec<u8>| SftpServerPacket::ExtendedReply {
+        id,
+        data: ExtendedReply::CheckFile(CheckFileReply::new(algorithm, hashes)).into(),
+    };
+    if block_size == 0 {
+        return fs.hash(file, algorithm, start_offset, length).await
+            .map(reply)
+            .unwrap_or_else(|err| error_resp(id, err));
+    }
+
+    // Only blocks within the file are hashed.
+    let size = match fs.fstat(file).await {
+        Ok(Attrs { size: Some(size), .. }) => size,
+        Ok(_) => return failure_resp(id, "File size unknown"),
+        Err(err) => return error_resp(id, err),
+    };
+    let end = match length {
+        0 => size,
+        length => start_offset.saturating_add(length).min(size),
+    };
+    let len = end.saturating_sub(start_offset);
+    let blocks = len.div_ceil(block_size as u64);
+    // Type, id, and the reply without digests.
+    let header_len = serialized_len(&ExtendedReply::CheckFile(CheckFileReply::new(algorithm, vec![])));
+    let resp_len = (1 + 4 + header_len as u64)
+        .saturating_add(blocks.saturating_mul(algorithm.digest_len() as u64));
+    if resp_len > max_len as u64 {
+        return failure_resp(id, "Too many blocks for one response");
+    }
+    if len == 0 {
+        return reply(vec![]);
+    }
+    fs.hash_blocks(file, algorithm, start_offset, len, block_size).await
+        .map(|hashes| reply(hashes.concat()))
+        .unwrap_or_else(|err| error_resp(id, err))
+}
+
+/// Collects everything an `SftpServer` is configured with. Start with
+/// `SftpServer::builder`.
+pub struct SftpServerBuilder<T: Fs + Send + Sync> {
+    fs: T,
+    config: Config,
+    #[cfg(feature = "thrussh-server")]
+    ssh_config: thrussh::ServerConfig,
+    #[cfg(feature = "thrussh-server")]
+    provider: Option<Arc<dyn thrussh::FsProvider<T>>>,
+    #[cfg(feature = "thrussh-server")]
+    exec_handler: Option<Arc<dyn thrussh::ExecHandler>>,
+}
+
+impl<T: Fs + Send + Sync> SftpServerBuilder<T> {
+    pub fn new(fs: T) -> Self {
+        Self {
+            fs,
+            config: Config::default(),
+            #[cfg(feature = "thrussh-server")]
+            ssh_config: Default::default(),
+            #[cfg(feature = "thrussh-server")]
+            provider: None,
+            #[cfg(feature = "thrussh-server")]
+            exec_handler: None,
+        }
+    }
+    /// Replaces all SFTP settings at once.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+    pub fn max_handles(mut self, max_handles: Option<usize>) -> Self {
+        self.config.max_handles = max_handles;
+        self
+    }
+    pub fn max_packet_size(mut self, max_packet_size: u32) -> Self {
+        self.config.max_packet_size = max_packet_size;
+        self
+    }
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.read_only = read_only;
+        self
+    }
+    pub fn max_clients(mut self, max_clients: Option<usize>) -> Self {
+        self.config.max_clients = max_clients;
+        self
+    }
+    pub fn request_timeout(mut self, request_timeout: Option<Duration>) -> Self {
+        self.config.request_timeout = request_timeout;
+        self
+    }
+    /// Stops advertising and answering the extension `name`.
+    pub fn disable_extension(mut self, name: &str) -> Self {
+        self.config.disabled_extensions.push(name.to_string());
+        self
+    }
+    /// SSH settings used by `thrussh::start_server`.
+    #[cfg(feature = "thrussh-server")]
+    pub fn ssh_config(mut self, ssh_config: thrussh::ServerConfig) -> Self {
+        self.ssh_config = ssh_config;
+        self
+    }
+    /// Decides which keys `thrussh::start_server` accepts and which `Fs`
+    /// each client gets. Without a provider, every key is accepted and all
+    /// clients share the server's `Fs`.
+    #[cfg(feature = "thrussh-server")]
+    pub fn fs_provider(mut self, provider: Arc<dyn thrussh::FsProvider<T>>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+    /// Runs the commands in `thrussh::ServerConfig::exec_commands` that
+    /// clients ask `thrussh::start_server` to execute. Without a handler,
+    /// every exec request is refused.
+    #[cfg(feature = "thrussh-server")]
+    pub fn exec_handler(mut self, exec_handler: Arc<dyn thrussh::ExecHandler>) -> Self {
+        self.exec_handler = Some(exec_handler);
+        self
+    }
+    pub fn build(self) -> Arc<SftpServer<T>> {
+        Arc::new(SftpServer {
+            clients: RwLock::new(HashMap::new()),
+            client_count: Arc::new(AtomicUsize::new(0)),
+            metrics: Default::default(),
+            fs: Arc::new(self.fs),
+            capabilities: Default::default(),
+            config: Arc::new(self.config),
+            #[cfg(feature = "thrussh-server")]
+            ssh_config: self.ssh_config,
+            #[cfg(feature = "thrussh-server")]
+            provider: self.provider,
+            #[cfg(feature = "thrussh-server")]
+            exec_handler: self.exec_handler,
+        })
+    }
+}
+
+/// The kind of `packet`, and the extension name for extended requests, for
+/// logging.
+fn describe_request(packet: &SftpClientPacket) -> String {
+    let kind = metrics::REQUEST_KINDS[metrics::request_kind(packet)];
+    match packet {
+        SftpClientPacket::Extended { extended_request, .. } => format!("{} {}", kind, extended_request.name()),
+        _ => kind.to_string(),
+    }
+}
+
+/// The status code or the type of `resp`, for logging.
+fn describe_response(resp: &SftpServerPacket) -> String {
+    match resp {
+        SftpServerPacket::Status { status_code, .. } => format!("{:?}", status_code),
+        SftpServerPacket::Version { .. } => "Version".to_string(),
+        SftpServerPacket::Handle { .. } => "Handle".to_string(),
+        SftpServerPacket::Data { data, .. } => format!("Data ({} bytes)", data.0.len()),
+        SftpServerPacket::Name { names, .. } => format!("Name ({} entries)", names.len()),
+        SftpServerPacket::Attrs { .. } => "Attrs".to_string(),
+        SftpServerPacket::ExtendedReply { .. } => "ExtendedReply".to_string(),
+    }
+}
+
+/// Response to a packet that could not be parsed. The request id is taken
+/// from where it would be in any request but `Init`, if the packet is long
+/// enough to have one.
+pub(crate) fn bad_message_resp(packet: &[u8], err: anyhow::Error) -> SftpServerPacket {
+    let id = match packet.get(1..5) {
+        Some(id) => u32::from_be_bytes([id[0], id[1], id[2], id[3]]),
+        None => 0,
+    };
+    SftpServerPacket::Status {
+        id,
+        status_code: StatusCode::BadMessage,
+        error_message: error_message(err),
+        language_tag: "en".to_string(),
+    }
+}
+
+/// Requests a transport has in progress at once for one session. Each may
+/// hold a response of up to `Config::max_packet_size` until it is sent.
+pub(crate) const MAX_IN_FLIGHT: usize = 64;
+
+/// Answers the raw request `packet`, or `BadMessage` if it does not parse.
+/// The stream stays in sync either way, as its length prefix was valid.
+pub(crate) async fn respond<T: Fs + Send + Sync>(session: &SftpSession<T>, packet: Vec<u8>) -> SftpServerPacket {
+    match deserialize_packet::<SftpClientPacket>(&packet) {
+        Ok(packet) => session.process_concurrent(packet).await,
+        Err(err) => bad_message_resp(&packet, err),
+    }
+}
+
+/// Length of a `Name` response without its entries: type, id and count.
+const NAME_HEADER_LEN: usize = 1 + 4 + 4;
+
+/// Length of a `Data` response without its data: type, id and length.
+const DATA_HEADER_LEN: u32 = 1 + 4 + 4;
+
+/// Length of a `read-eof@thrusftp` reply without its data: that of a `Data`
+/// response and the flag.
+const READ_EOF_HEADER_LEN: u32 = DATA_HEADER_LEN + 1;
+
+/// Counts the bytes written to it.
+struct LenCounter(usize);
+
+impl std::io::Write for LenCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn serialized_len<S: Serialize>(value: &S) -> usize {
+    let mut counter = LenCounter(0);
+    match value.serialize(&mut counter) {
+        Ok(()) => counter.0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Drops `ATIME_EXTENDED_ATTR` and `MTIME_EXTENDED_ATTR` from the attributes
+/// in `resp`, for clients that did not ask for them.
+fn without_nanosecond_times(resp: SftpServerPacket) -> SftpServerPacket {
+    let strip = |attrs: &mut Attrs| attrs.extended_attrs.retain(|attr| {
+        attr.r#type != ATIME_EXTENDED_ATTR && attr.r#type != MTIME_EXTENDED_ATTR
+    });
+    match resp {
+        SftpServerPacket::Name { id, mut names } => {
+            names.iter_mut().for_each(|name| strip(&mut name.attrs));
+            SftpServerPacket::Name { id, names }
+        },
+        SftpServerPacket::Attrs { id, mut attrs } => {
+            strip(&mut attrs);
+            SftpServerPacket::Attrs { id, attrs }
+        },
+        resp => resp,
+    }
+}
+
+/// Drops what a client can do without: the `ls -l` line and extended
+/// attributes.
+fn shrink_name(name: &mut Name) {
+    name.longname.clear();
+    name.attrs.extended_attrs.clear();
+}
+
+/// Splits `names` into the entries that fit into one `Name` response of at
+/// most `max_len` bytes, and the rest. Entries that do not fit on their own
+/// are shrunk, and skipped if that is not enough.
+fn fit_names(names: Vec<Name>, max_len: usize) -> (Vec<Name>, Vec<Name>) {
+    let mut len = NAME_HEADER_LEN;
+    let mut fitting = Vec::new();
+    let mut names = names.into_iter();
+    while let Some(mut name) = names.next() {
+        let mut name_len = serialized_len(&name);
+        if NAME_HEADER_LEN + name_len > max_len {
+            shrink_name(&mut name);
+            name_len = serialized_len(&name);
+            if NAME_HEADER_LEN + name_len > max_len {
+                continue;
+            }
+        }
+        if len + name_len > max_len {
+            let mut rest = vec![name];
+            rest.extend(names);
+            return (fitting, rest);
+        }
+        len += name_len;
+        fitting.push(name);
+    }
+    (fitting, Vec::new())
+}
+
+/// Like `fit_names`, for a `readdir-from@thrusftp` reply of at most
+/// `max_len` bytes, which has the same header as a `Name` response and a
+/// cookie after the entries. Returns the entries that fit and the cookie to
+/// go on after the last entry taken or skipped, or `None` if there were no
+/// entries.
+fn fit_cookie_names(entries: Vec<(Name, String)>, max_len: usize) -> (Vec<Name>, Option<String>) {
+    let mut len = NAME_HEADER_LEN;
+    let mut fitting = Vec::new();
+    let mut cookie = None;
+    for (mut name, next) in entries {
+        let cookie_len = serialized_len(&next);
+        let mut name_len = serialized_len(&name);
+        if NAME_HEADER_LEN + name_len + cookie_len > max_len {
+            shrink_name(&mut name);
+            name_len = serialized_len(&name);
+            if NAME_HEADER_LEN + name_len + cookie_len > max_len {
+                cookie = Some(next);
+                continue;
+            }
+        }
+        if len + name_len + cookie_len > max_len {
+            break;
+        }
+        len += name_len;
+        fitting.push(name);
+        cookie = Some(next);
+    }
+    (fitting, cookie)
+}
+
+/// Makes `resp` fit into `max_len` bytes by shrinking its entries or
+/// attributes, or replaces it with a `Failure` if that is not enough.
+fn bound_response(resp: SftpServerPacket, max_len: usize) -> SftpServerPacket {
+    if serialized_len(&resp) <= max_len {
+        return resp;
+    }
+    let resp = match resp {
+        SftpServerPacket::Name { id, mut names } => {
+            names.iter_mut().for_each(shrink_name);
+            SftpServerPacket::Name { id, names }
+        },
+        SftpServerPacket::Attrs { id, mut attrs } => {
+            attrs.extended_attrs.clear();
+            SftpServerPacket::Attrs { id, attrs }
+        },
+        resp => resp,
+    };
+    if serialized_len(&resp) <= max_len {
+        return resp;
+    }
+    match resp {
+        SftpServerPacket::Version { .. } => resp,
+        SftpServerPacket::Status { id, .. }
+        | SftpServerPacket::Handle { id, .. }
+        | SftpServerPacket::Data { id, .. }
+        | SftpServerPacket::Name { id, .. }
+        | SftpServerPacket::Attrs { id, .. }
+        | SftpServerPacket::ExtendedReply { id, .. } => failure_resp(id, "Response too large"),
+    }
+}
+
+fn permission_denied_resp(id: u32, err: SftpError) -> SftpServerPacket {
+    SftpServerPacket::Status {
+        id,
+        status_code: StatusCode::PermissionDenied,
+        error_message: error_message(err),
+        language_tag: "en".to_string(),
+    }
+}
+
+fn failure_resp(id: u32, error_message: &str) -> SftpServerPacket {
+    SftpServerPacket::Status {
+        id,
+        status_code: StatusCode::Failure,
+        error_message: error_message.to_string(),
+        language_tag: "en".to_string(),
+    }
+}
+
+fn no_such_handle_resp(id: u32) -> SftpServerPacket {
+    failure_resp(id, "No such handle")
+}
+
+fn not_a_file_resp(id: u32) -> SftpServerPacket {
+    failure_resp(id, "Handle is not a file handle")
+}
+
+fn not_a_dir_resp(id: u32) -> SftpServerPacket {
+    failure_resp(id, "Handle is not a directory handle")
+}
+
 fn status_resp(id: u32, status_code: StatusCode) -> SftpServerPacket {
     SftpServerPacket::Status {
         id, status_code,
@@ -264,27 +1606,42 @@ fn status_resp(id: u32, status_code: StatusCode) -> SftpServerPacket {
     }
 }
 
-fn error_resp(id: u32, err: anyhow::Error) -> SftpServerPacket{
-    let mut status_code = StatusCode::Failure;
-    if let Some(ref io_err) = err.downcast_ref::<std::io::Error>() {
-        status_code = match io_err.kind() {
-            std::io::ErrorKind::NotFound => StatusCode::NoSuchFile,
-            std::io::ErrorKind::UnexpectedEof => StatusCode::Eof,
-            std::io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
-            std::io::ErrorKind::Unsupported => StatusCode::OpUnsupported,
-            std::io::ErrorKind::InvalidInput => StatusCode::BadMessage,
-            std::io::ErrorKind::InvalidData => StatusCode::BadMessage,
-            _ => StatusCode::Failure,
-        };
-    };
+/// Protocol version spoken by the server.
+const SFTP_VERSION: u32 = 3;
+
+/// Longest `error_message` sent, in bytes. Errors can quote what the client
+/// sent, such as a path, so their messages are not bounded otherwise.
+const MAX_ERROR_MESSAGE_LEN: usize = 1024;
+
+/// `err` as the `error_message` of a status, shortened to
+/// `MAX_ERROR_MESSAGE_LEN` at a character boundary if needed.
+fn error_message(err: impl std::fmt::Display) -> String {
+    let mut message = err.to_string();
+    if message.len() > MAX_ERROR_MESSAGE_LEN {
+        let ellipsis = "...";
+        let mut len = MAX_ERROR_MESSAGE_LEN - ellipsis.len();
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        message.truncate(len);
+        message.push_str(ellipsis);
+    }
+    message
+}
+
+/// Errors without a status code of their own in v3 (out of space, quota
+/// exceeded, is a directory, ...) are sent as `Failure`; the error message
+/// still names the exact cause.
+fn error_resp(id: u32, err: SftpError) -> SftpServerPacket{
     SftpServerPacket::Status {
-        id, status_code,
-        error_message: err.to_string(),
+        id,
+        status_code: err.status_code().for_version(SFTP_VERSION),
+        error_message: error_message(err),
         language_tag: "en".to_string(),
     }
 }
 
-fn result_resp<T>(id: u32, r: anyhow::Result<T>) -> SftpServerPacket {
+fn result_resp<T>(id: u32, r: thrusftp_protocol::Result<T>) -> SftpServerPacket {
     match r {
         Err(e) => error_resp(id, e),
         Ok(_) => status_resp(id, StatusCode::r#Ok),
@@ -0,0 +1,111 @@
+//! Counters an `SftpServer` keeps about the requests it answers, for
+//! exporting to whatever monitoring system is in use.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use thrusftp_protocol::types::{SftpClientPacket, SftpServerPacket, StatusCode};
+
+/// Names of the requests counted by `Metrics::requests`, in packet type
+/// order. All extended requests count as `extended`.
+pub const REQUEST_KINDS: [&str; 20] = [
+    "init", "open", "close", "read", "write", "lstat", "fstat", "setstat", "fsetstat", "opendir",
+    "readdir", "remove", "mkdir", "rmdir", "realpath", "stat", "rename", "readlink", "symlink", "extended",
+];
+
+const STATUS_CODES: usize = StatusCode::FileIsADirectory as usize + 1;
+
+/// Totals over all sessions of a server since it was built. Every counter
+/// is a plain atomic: updating them takes no locks and no allocations, and
+/// reading them while requests are answered is fine, though the values are
+/// not a consistent snapshot.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    requests: [AtomicU64; REQUEST_KINDS.len()],
+    statuses: [AtomicU64; STATUS_CODES],
+    open_handles: AtomicUsize,
+}
+
+impl Metrics {
+    /// Bytes sent to clients in `Data` responses and `read-eof@thrusftp`
+    /// replies.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+    /// Bytes of `Write` requests that succeeded.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+    /// Number of requests of each of the `REQUEST_KINDS`.
+    pub fn requests(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        REQUEST_KINDS.iter().zip(&self.requests)
+            .map(|(kind, count)| (*kind, count.load(Ordering::Relaxed)))
+    }
+    /// Number of `Status` responses with `status_code`, including `Ok`.
+    pub fn statuses(&self, status_code: StatusCode) -> u64 {
+        self.statuses[status_code as usize].load(Ordering::Relaxed)
+    }
+    /// File and directory handles currently open, over all sessions.
+    pub fn open_handles(&self) -> usize {
+        self.open_handles.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_request(&self, packet: &SftpClientPacket) {
+        self.requests[request_kind(packet)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `resp`, the answer to a request that wanted to write
+    /// `write_len` bytes.
+    pub(crate) fn record_response(&self, resp: &SftpServerPacket, write_len: usize) {
+        match resp {
+            SftpServerPacket::Data { data, .. } => self.record_read(data.0.len()),
+            SftpServerPacket::Status { status_code, .. } => {
+                self.statuses[*status_code as usize].fetch_add(1, Ordering::Relaxed);
+                if *status_code == StatusCode::r#Ok {
+                    self.bytes_written.fetch_add(write_len as u64, Ordering::Relaxed);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Counts `len` bytes read for a client.
+    pub(crate) fn record_read(&self, len: usize) {
+        self.bytes_read.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handles_changed(&self, before: usize, after: usize) {
+        if after > before {
+            self.open_handles.fetch_add(after - before, Ordering::Relaxed);
+        } else if before > after {
+            self.open_handles.fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Index of `packet` in `REQUEST_KINDS`.
+pub(crate) fn request_kind(packet: &SftpClientPacket) -> usize {
+    match packet {
+        SftpClientPacket::Init { .. } => 0,
+        SftpClientPacket::Open { .. } => 1,
+        SftpClientPacket::Close { .. } => 2,
+        SftpClientPacket::Read { .. } => 3,
+        SftpClientPacket::Write { .. } => 4,
+        SftpClientPacket::Lstat { .. } => 5,
+        SftpClientPacket::Fstat { .. } => 6,
+        SftpClientPacket::Setstat { .. } => 7,
+        SftpClientPacket::Fsetstat { .. } => 8,
+        SftpClientPacket::Opendir { .. } => 9,
+        SftpClientPacket::Readdir { .. } => 10,
+        SftpClientPacket::Remove { .. } => 11,
+        SftpClientPacket::Mkdir { .. } => 12,
+        SftpClientPacket::Rmdir { .. } => 13,
+        SftpClientPacket::Realpath { .. } => 14,
+        SftpClientPacket::Stat { .. } => 15,
+        SftpClientPacket::Rename { .. } => 16,
+        SftpClientPacket::Readlink { .. } => 17,
+        SftpClientPacket::Symlink { .. } => 18,
+        SftpClientPacket::Extended { .. } => 19,
+    }
+}
@@ -0,0 +1,86 @@
+//! Serving SFTP over any byte stream, without SSH: a unix socket, a TLS
+//! connection, a child process's stdio or an in-memory pipe.
+
+use std::collections::VecDeque;
+use futures::{FutureExt, StreamExt};
+use futures::stream::FuturesUnordered;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use anyhow::{anyhow, Result};
+
+use crate::{respond, SftpServer, SftpSession, MAX_IN_FLIGHT};
+use crate::codec::SftpCodec;
+use thrusftp_protocol::Fs;
+
+/// Bytes read from `reader` at a time.
+const READ_LEN: usize = 64 * 1024;
+
+/// Runs one SFTP session, from `Init` on, reading requests from `reader`
+/// and writing responses to `writer`. Returns once `reader` reaches its end
+/// and the requests read are answered, or with an error if the stream
+/// fails, a request is longer than `Config::max_packet_size`, or the server
+/// already serves `Config::max_clients` sessions.
+///
+/// Requests are answered concurrently, see
+/// `SftpSession::process_concurrent`, and each response is written as soon
+/// as it is ready. While `MAX_IN_FLIGHT` requests are in progress no more
+/// are read.
+///
+/// Handles the client left open are closed with `SftpSession::close_all`
+/// when the session ends, cleanly or not. Dropping the returned future ends
+/// the session at once: the requests in progress are cancelled, see
+/// `SftpSession::process`, and all handles are dropped without `close`.
+pub async fn serve_stream<T, R, W>(server: &SftpServer<T>, reader: R, writer: W) -> Result<()>
+where
+    T: Fs + Send + Sync,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut session = server.try_new_session().ok_or_else(|| anyhow!("too many clients"))?;
+    let res = serve_session(server, &session, reader, writer).await;
+    session.close_all().await;
+    res
+}
+
+async fn serve_session<T, R, W>(server: &SftpServer<T>, session: &SftpSession<T>, mut reader: R, mut writer: W) -> Result<()>
+where
+    T: Fs + Send + Sync,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut codec = SftpCodec::new(server.config.max_packet_size);
+    let mut buf = vec![0; READ_LEN];
+    let mut resp_buf = Vec::new();
+    // Requests read but not started yet, because too many are in progress.
+    let mut packets = VecDeque::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut eof = false;
+    loop {
+        while in_flight.len() < MAX_IN_FLIGHT {
+            match packets.pop_front() {
+                Some(packet) => in_flight.push(respond(session, packet)),
+                None => break,
+            }
+        }
+        if eof && in_flight.is_empty() {
+            return Ok(());
+        }
+        tokio::select! {
+            len = reader.read(&mut buf), if !eof && packets.is_empty() => {
+                match len? {
+                    0 => eof = true,
+                    len => packets.extend(codec.decode(&buf[..len])?),
+                }
+            },
+            Some(resp) = in_flight.next() => {
+                SftpCodec::encode_into(&resp, &mut resp_buf)?;
+                // Responses that are ready together go out in one write.
+                while let Some(Some(resp)) = in_flight.next().now_or_never() {
+                    SftpCodec::encode_into(&resp, &mut resp_buf)?;
+                }
+                writer.write_all(&resp_buf).await?;
+                writer.flush().await?;
+                resp_buf.clear();
+            },
+        }
+    }
+}
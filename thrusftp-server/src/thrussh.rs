@@ -1,63 +1,495 @@
 use thrussh::*;
 use thrussh::server::Session;
 use async_trait::async_trait;
-use std::convert::TryInto;
-use std::sync::Arc;
-use tokio::io::AsyncReadExt;
-use std::io::Write;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::sync::{oneshot, watch, Notify};
 
-use crate::SftpServer;
-use thrusftp_protocol::types::*;
+use crate::{respond, SftpServer, SftpSession, MAX_IN_FLIGHT};
+use crate::codec::SftpCodec;
 use thrusftp_protocol::Fs;
-use thrusftp_protocol::parse::{Serialize, Deserialize};
 use anyhow::Result;
 
-pub async fn start_server<T: 'static + Fs + Send + Sync>(server: Arc<SftpServer<T>>) {
-    let mut config = thrussh::server::Config::default();
-    config.connection_timeout = Some(std::time::Duration::from_secs(300));
-    config.auth_rejection_time = std::time::Duration::from_millis(300);
-    config.keys.push(thrussh_keys::key::KeyPair::generate_ed25519().unwrap());
-    let server = Server { server };
-    thrussh::server::run(Arc::new(config), "0.0.0.0:2222", server).await.unwrap();
+/// Capacity of the response buffer a connection keeps between responses.
+/// Larger buffers, needed for big `Data` responses, are freed after use.
+const RETAINED_RESP_BUF: usize = 64 * 1024;
+
+/// Data the client sent on a channel, until the task serving the channel
+/// takes it. Such channels have a manual window, see
+/// `Session::set_manual_window`, re-opened only for data that was taken,
+/// so this never holds more than the window, however slowly it is read.
+#[derive(Default)]
+struct ChannelInput {
+    state: Mutex<InputState>,
+    ready: Notify,
+}
+
+#[derive(Default)]
+struct InputState {
+    data: Vec<u8>,
+    ended: bool,
+}
+
+impl ChannelInput {
+    fn push(&self, data: &[u8]) {
+        self.state.lock().unwrap().data.extend_from_slice(data);
+        self.ready.notify_one();
+    }
+
+    /// Marks the input as complete, once the client sent EOF or closed the
+    /// channel.
+    fn end(&self) {
+        self.state.lock().unwrap().ended = true;
+        self.ready.notify_one();
+    }
+
+    /// All data received since the last call, waiting for some if there is
+    /// none, or `None` once the input is complete and everything was taken.
+    async fn take(&self) -> Option<Vec<u8>> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.data.is_empty() {
+                    return Some(std::mem::take(&mut state.data));
+                }
+                if state.ended {
+                    return None;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// Selects the `Fs` a client is served from, based on who authenticated.
+#[async_trait]
+pub trait FsProvider<T: Fs + Send + Sync>: Send + Sync {
+    /// Called for every public key the client offers. Returning an error
+    /// rejects the key.
+    async fn for_user(&self, user: &str, key: &thrussh_keys::key::PublicKey) -> Result<T>;
+}
+
+/// Runs the commands clients may execute besides SFTP, such as `scp`.
+#[async_trait]
+pub trait ExecHandler: Send + Sync {
+    /// Runs `command`, as sent by the client authenticated as `user`, whose
+    /// first word is one of `ServerConfig::exec_commands`. Talks to the
+    /// client through `channel` and returns the exit status sent to it once
+    /// done, after which the channel is closed.
+    async fn exec(&self, user: &str, command: &str, channel: ExecChannel) -> u32;
+}
+
+/// The channel an `ExecHandler` runs a command on.
+pub struct ExecChannel {
+    input: Arc<ChannelInput>,
+    handle: thrussh::server::Handle,
+    channel: ChannelId,
+}
+
+impl ExecChannel {
+    /// Next data the client sent, or `None` once it sent EOF or closed the
+    /// channel. Data the handler does not read yet is buffered up to the
+    /// channel's window, after which the client has to wait.
+    pub async fn read(&mut self) -> Option<Vec<u8>> {
+        let data = self.input.take().await?;
+        let _ = self.handle.window_consumed(self.channel, data.len() as u32).await;
+        Some(data)
+    }
+
+    /// Sends `data` to the command's standard output.
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.handle.data(self.channel, CryptoVec::from_slice(data)).await
+            .map_err(|_| anyhow::anyhow!("channel closed"))
+    }
+
+    /// Sends `data` to the command's standard error.
+    pub async fn write_stderr(&mut self, data: &[u8]) -> Result<()> {
+        self.handle.extended_data(self.channel, 1, CryptoVec::from_slice(data)).await
+            .map_err(|_| anyhow::anyhow!("channel closed"))
+    }
+}
+
+/// Settings for the SSH side of the server.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Address and port `start_server` listens on.
+    pub listen_addr: String,
+    /// Private keys the server identifies itself with, unencrypted, in
+    /// OpenSSH or PKCS#8 format. They are read when the server starts.
+    ///
+    /// All of them are offered at once, so keys of several algorithms, or
+    /// an old and a new key of the same one, can be served side by side: a
+    /// client takes the first algorithm in its own order of preference that
+    /// the server has a key for, and accepts the server if it knows that
+    /// key. To rotate a key, add the new one, let clients learn it, then
+    /// remove the old one.
+    ///
+    /// If this is empty, a new ed25519 key is generated on every start,
+    /// which clients that check host keys will not recognize.
+    pub host_keys: Vec<PathBuf>,
+    /// Time after which an idle SSH connection is closed, or `None` to keep
+    /// connections open indefinitely.
+    pub connection_timeout: Option<Duration>,
+    /// Minimum time it takes to reject an authentication attempt.
+    pub auth_rejection_time: Duration,
+    /// Number of failed authentication attempts after which the connection
+    /// is closed.
+    pub max_auth_attempts: usize,
+    /// Time without any SFTP packet after which the SFTP channel is closed,
+    /// or `None` to disable the check. Responses count as well as requests,
+    /// as does the client making room for more of them, so a long download
+    /// is not cut off while the client has nothing to send.
+    ///
+    /// This is independent of `connection_timeout`, which only sees SSH
+    /// traffic: SSH keepalives, including those sent for
+    /// `keepalive_interval`, keep the connection alive but do not count as
+    /// SFTP activity. Closing the channel drops the SFTP session and with it
+    /// all handles the client left open. Whichever of the two timeouts fires
+    /// first wins.
+    pub sftp_idle_timeout: Option<Duration>,
+    /// Time without any SFTP packet, in either direction, after which the
+    /// server sends an SSH keepalive, a `keepalive@openssh.com` global request, and
+    /// again after every further interval of quiet, or `None` to send none.
+    /// This keeps NAT and firewall state for idle sessions from expiring.
+    ///
+    /// Keepalives are SSH traffic, so an interval shorter than
+    /// `connection_timeout` keeps that timeout from closing a connection
+    /// with an SFTP session. They are not SFTP activity: `sftp_idle_timeout`
+    /// still closes the channel after that long without requests, whatever
+    /// the interval.
+    pub keepalive_interval: Option<Duration>,
+    /// Sent to clients that ask for a shell, before the channel is closed.
+    pub shell_message: String,
+    /// Programs clients may run with an exec request, matched against the
+    /// first word of the command, e.g. `scp`. Their requests go to the
+    /// `ExecHandler` set with `SftpServerBuilder::exec_handler`; all other
+    /// exec requests are refused, except those running `sftp-server` or
+    /// `internal-sftp`, which are served SFTP like the `sftp` subsystem.
+    pub exec_commands: Vec<String>,
+    /// Shown to clients before they authenticate, like OpenSSH's `Banner`,
+    /// e.g. a legal notice. Clients print it as is, so it should end with a
+    /// newline.
+    pub banner: Option<String>,
+    /// File to read `banner` from when the server starts, in its place.
+    pub banner_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:2222".to_string(),
+            host_keys: vec![],
+            connection_timeout: Some(Duration::from_secs(300)),
+            auth_rejection_time: Duration::from_millis(300),
+            max_auth_attempts: 10,
+            sftp_idle_timeout: None,
+            keepalive_interval: None,
+            shell_message: "Only SFTP allowed, bye\n".to_string(),
+            exec_commands: vec![],
+            banner: None,
+            banner_file: None,
+        }
+    }
+}
+
+/// Serves `server` with the SSH settings and `FsProvider` it was built with.
+/// Fails if a host key or the banner file cannot be loaded or the address
+/// cannot be bound.
+pub async fn start_server<T: 'static + Fs + Send + Sync>(server: Arc<SftpServer<T>>) -> Result<()> {
+    let server_config = server.ssh_config.clone();
+    let provider = server.provider.clone();
+    start_server_with_config(server, server_config, provider).await
+}
+
+pub async fn start_server_with_config<T: 'static + Fs + Send + Sync>(
+    server: Arc<SftpServer<T>>,
+    server_config: ServerConfig,
+    provider: Option<Arc<dyn FsProvider<T>>>,
+) -> Result<()> {
+    let mut keys = Vec::new();
+    for path in &server_config.host_keys {
+        let key = thrussh_keys::load_secret_key(path, None)
+            .map_err(|err| anyhow::anyhow!("cannot load host key {}: {}", path.display(), err))?;
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        keys.push(thrussh_keys::key::KeyPair::generate_ed25519().unwrap());
+    }
+    let banner = match server_config.banner_file {
+        Some(ref path) => Some(std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("cannot read banner {}: {}", path.display(), err))?),
+        None => server_config.banner.clone(),
+    };
+    let config = thrussh::server::Config {
+        connection_timeout: server_config.connection_timeout,
+        auth_rejection_time: server_config.auth_rejection_time,
+        max_auth_attempts: server_config.max_auth_attempts,
+        keys,
+        // thrussh only takes a `&'static str`; this is once per server.
+        auth_banner: banner.map(|banner| &*Box::leak(banner.into_boxed_str())),
+        ..Default::default()
+    };
+    let listen_addr = server_config.listen_addr.clone();
+    let server = Server {
+        exec_handler: server.exec_handler.clone(),
+        server,
+        provider,
+        ssh_config: Arc::new(server_config),
+    };
+    thrussh::server::run(Arc::new(config), &listen_addr, server).await?;
+    Ok(())
 }
 
 struct Server<T: Fs + Send + Sync> {
     server: Arc<SftpServer<T>>,
+    provider: Option<Arc<dyn FsProvider<T>>>,
+    exec_handler: Option<Arc<dyn ExecHandler>>,
+    ssh_config: Arc<ServerConfig>,
 }
 
 #[async_trait]
-impl<T: Fs + Send + Sync> thrussh::server::Server for Server<T> {
+impl<T: 'static + Fs + Send + Sync> thrussh::server::Server for Server<T> {
     type Handler = Client<T>;
     async fn new(&mut self, _: Option<std::net::SocketAddr>) -> Client<T> {
         Client {
-            recv_buf: Vec::new(),
-            handle: self.server.clone().create_client_handle("client").await,
+            sftp: None,
+            fs: None,
             server: self.server.clone(),
+            provider: self.provider.clone(),
+            exec_handler: self.exec_handler.clone(),
+            ssh_config: self.ssh_config.clone(),
+            user: String::new(),
+            exec_inputs: HashMap::new(),
+            activity: None,
+            sftp_channel: None,
         }
     }
 }
 
-struct Client<T: Fs + Send + Sync> {
-    recv_buf: Vec<u8>,
-    handle: String,
+struct Client<T: 'static + Fs + Send + Sync> {
+    /// The task serving the SFTP channel, if one is running.
+    sftp: Option<SftpTask>,
+    /// Filesystem chosen by the `FsProvider` for the last key it accepted.
+    /// thrussh asks again for the key of the signed request, so once the
+    /// client is authenticated, this is the one for the key it proved.
+    fs: Option<T>,
     server: Arc<SftpServer<T>>,
+    provider: Option<Arc<dyn FsProvider<T>>>,
+    exec_handler: Option<Arc<dyn ExecHandler>>,
+    ssh_config: Arc<ServerConfig>,
+    /// User name the client authenticated with.
+    user: String,
+    /// Where data the client sends on a channel running an exec request
+    /// goes, until it sends EOF.
+    exec_inputs: HashMap<ChannelId, Arc<ChannelInput>>,
+    /// Resets the idle timer and the keepalive timer of the SFTP channel,
+    /// if either is running. Shared with `serve_sftp`, which sends
+    /// responses.
+    activity: Option<Arc<watch::Sender<()>>>,
+    /// Channel the SFTP subsystem runs on.
+    sftp_channel: Option<ChannelId>,
+}
+
+/// The handler's end of `serve_sftp`. Dropping it, as happens when the
+/// channel or the whole connection goes away, cleanly or not, cancels the
+/// requests in progress and closes the handles the client left open.
+struct SftpTask {
+    /// Data the client sent. Ends when the client sends EOF: the channel is
+    /// closed once all requests are answered, like OpenSSH's sftp-server
+    /// exits when its input ends.
+    input: Arc<ChannelInput>,
+    /// Whether responses are waiting for the client's window. No new
+    /// requests are started meanwhile, so a client that reads its responses
+    /// slowly also slows down how fast the `Fs` is read from.
+    blocked: watch::Sender<bool>,
+    _cancel: oneshot::Sender<()>,
+}
+
+/// Serves `session` on `channel`: answers the requests arriving on `input`
+/// concurrently, sends each response as soon as it is ready and closes the
+/// channel once `input` ends and everything is answered, or a request is
+/// longer than `Config::max_packet_size`. Stops at once,
+/// cancelling the requests in progress, when `cancel` is dropped. Either
+/// way, the handles the client left open are closed. Every response sent
+/// is reported to `activity`, if given.
+async fn serve_sftp<T: 'static + Fs + Send + Sync>(
+    mut session: SftpSession<T>,
+    mut handle: thrussh::server::Handle,
+    channel: ChannelId,
+    input: Arc<ChannelInput>,
+    mut blocked: watch::Receiver<bool>,
+    activity: Option<Arc<watch::Sender<()>>>,
+    mut cancel: oneshot::Receiver<()>,
+) {
+    let answered = tokio::select! {
+        answered = answer_requests(&session, &mut handle, channel, &input, &mut blocked, activity.as_deref()) => answered,
+        _ = &mut cancel => false,
+    };
+    if answered {
+        let _ = handle.eof(channel).await;
+        let _ = handle.close(channel).await;
+    }
+    session.close_all().await;
+}
+
+/// The part of `serve_sftp` that answers requests. Returns `false` if the
+/// connection went away first.
+async fn answer_requests<T: Fs + Send + Sync>(
+    session: &SftpSession<T>,
+    handle: &mut thrussh::server::Handle,
+    channel: ChannelId,
+    input: &ChannelInput,
+    blocked: &mut watch::Receiver<bool>,
+    activity: Option<&watch::Sender<()>>,
+) -> bool {
+    let mut codec = SftpCodec::new(session.config.max_packet_size);
+    // Requests taken from `input` but not started yet, and how much of the
+    // window they take up.
+    let mut packets = VecDeque::new();
+    let mut taken = 0;
+    let mut in_flight = FuturesUnordered::new();
+    let mut resp_buf = Vec::new();
+    let mut input_ended = false;
+    loop {
+        let is_blocked = *blocked.borrow();
+        while !is_blocked && in_flight.len() < MAX_IN_FLIGHT {
+            match packets.pop_front() {
+                Some(packet) => in_flight.push(respond(session, packet)),
+                None => break,
+            }
+        }
+        // Only once all of it was started, so that requests waiting here
+        // hold the client back, too.
+        if packets.is_empty() && taken > 0 {
+            if handle.window_consumed(channel, taken).await.is_err() {
+                return false;
+            }
+            taken = 0;
+        }
+        if input_ended && packets.is_empty() && in_flight.is_empty() {
+            return true;
+        }
+        let accepting = !input_ended && packets.is_empty() && !is_blocked && in_flight.len() < MAX_IN_FLIGHT;
+        tokio::select! {
+            data = input.take(), if accepting => match data {
+                Some(data) => {
+                    taken = data.len() as u32;
+                    match codec.decode(&data) {
+                        Ok(decoded) => packets.extend(decoded),
+                        // There is no way to answer a request we do not
+                        // read, so end the session like OpenSSH's
+                        // sftp-server does.
+                        Err(_) => return true,
+                    }
+                },
+                None => input_ended = true,
+            },
+            Some(resp) = in_flight.next() => {
+                resp_buf.clear();
+                // A response that cannot be sent ends the session, as
+                // there is no other way to answer its request.
+                if SftpCodec::encode_into(&resp, &mut resp_buf).is_err() {
+                    return true;
+                }
+                if handle.data(channel, CryptoVec::from_slice(&resp_buf)).await.is_err() {
+                    return false;
+                }
+                if let Some(activity) = activity {
+                    let _ = activity.send(());
+                }
+                if resp_buf.capacity() > RETAINED_RESP_BUF {
+                    resp_buf = Vec::new();
+                }
+            },
+            changed = blocked.changed(), if is_blocked => if changed.is_err() {
+                return false;
+            },
+        }
+    }
+}
+
+/// Closes `channel` once `activity` has been quiet for `timeout`. Stops when
+/// the senders are dropped, i.e. when the channel was closed by other means.
+async fn idle_watchdog(mut handle: thrussh::server::Handle, channel: ChannelId, timeout: Duration, mut activity: watch::Receiver<()>) {
+    loop {
+        tokio::select! {
+            changed = activity.changed() => if changed.is_err() {
+                return;
+            },
+            _ = tokio::time::sleep(timeout) => {
+                let _ = handle.close(channel).await;
+                return;
+            },
+        }
+    }
+}
+
+/// Sends a keepalive every time `activity` has been quiet for `interval`.
+/// Stops when the senders are dropped or the connection is gone.
+async fn keepalive(mut handle: thrussh::server::Handle, channel: ChannelId, interval: Duration, mut activity: watch::Receiver<()>) {
+    loop {
+        tokio::select! {
+            changed = activity.changed() => if changed.is_err() {
+                return;
+            },
+            _ = tokio::time::sleep(interval) => if handle.keepalive(channel).await.is_err() {
+                return;
+            },
+        }
+    }
 }
 
 #[async_trait]
-impl<T: Fs + Send + Sync> thrussh::server::Handler for Client<T> {
+impl<T: 'static + Fs + Send + Sync> thrussh::server::Handler for Client<T> {
     type Error = anyhow::Error;
 
     async fn shell_request(self, channel: ChannelId, mut session: Session) -> Result<(Self, Session)> {
         session.channel_success(channel);
-        session.data(channel, CryptoVec::from_slice(b"Only SFTP allowed, bye\n"));
+        session.data(channel, CryptoVec::from_slice(self.ssh_config.shell_message.as_bytes()));
         session.flush()?;
         session.close(channel);
         Ok((self, session))
     }
 
-    async fn subsystem_request(self, channel: ChannelId, name: &str, mut session: Session) -> Result<(Self, Session)> {
+    async fn exec_request(mut self, channel: ChannelId, data: &[u8], mut session: Session) -> Result<(Self, Session)> {
+        let command = String::from_utf8_lossy(data).into_owned();
+        if is_sftp_server_command(&command) {
+            self.start_sftp(channel, &mut session);
+            return Ok((self, session));
+        }
+        let allowed = command.split_whitespace().next()
+            .is_some_and(|program| self.ssh_config.exec_commands.iter().any(|allowed| allowed == program));
+        let handler = match self.exec_handler {
+            Some(ref handler) if allowed => handler.clone(),
+            _ => {
+                session.channel_failure(channel);
+                session.close(channel);
+                return Ok((self, session));
+            },
+        };
+        let input = Arc::new(ChannelInput::default());
+        self.exec_inputs.insert(channel, input.clone());
+        session.set_manual_window(channel);
+        session.channel_success(channel);
+        let mut handle = session.handle();
+        let exec_channel = ExecChannel { input, handle: handle.clone(), channel };
+        let user = self.user.clone();
+        tokio::spawn(async move {
+            let status = handler.exec(&user, &command, exec_channel).await;
+            let _ = handle.exit_status_request(channel, status).await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+        });
+        Ok((self, session))
+    }
+
+    async fn subsystem_request(mut self, channel: ChannelId, name: &str, mut session: Session) -> Result<(Self, Session)> {
         match name {
-            "sftp" => session.channel_success(channel),
+            "sftp" => self.start_sftp(channel, &mut session),
             _ => {
                 session.channel_failure(channel);
                 session.close(channel);
@@ -66,42 +498,134 @@ impl<T: Fs + Send + Sync> thrussh::server::Handler for Client<T> {
         Ok((self, session))
     }
 
-    async fn auth_publickey(self, _: &str, _: &thrussh_keys::key::PublicKey) -> Result<(Self, thrussh::server::Auth)> {
-        Ok((self, thrussh::server::Auth::Accept))
+    async fn auth_publickey(mut self, user: &str, key: &thrussh_keys::key::PublicKey) -> Result<(Self, thrussh::server::Auth)> {
+        let provider = match self.provider {
+            Some(ref provider) => provider.clone(),
+            None => {
+                self.user = user.to_string();
+                return Ok((self, thrussh::server::Auth::Accept));
+            },
+        };
+        match provider.for_user(user, key).await {
+            Ok(fs) => {
+                self.fs = Some(fs);
+                self.user = user.to_string();
+                Ok((self, thrussh::server::Auth::Accept))
+            },
+            // A key accepted before, in a probe or a request whose
+            // signature turned out wrong, must not pick the filesystem.
+            Err(_) => {
+                self.fs = None;
+                self.user.clear();
+                Ok((self, thrussh::server::Auth::Reject))
+            },
+        }
     }
 
-    async fn data(mut self, channel: ChannelId, mut data: &[u8], mut session: Session) -> Result<(Self, Session)> {
-        while data.len() > 0 {
-            if self.recv_buf.len() < 4 {
-                let read_len = data.take((4 - self.recv_buf.len()) as u64).read_to_end(&mut self.recv_buf).await.unwrap();
-                data = &data[read_len..];
-            }
+    async fn channel_close(mut self, channel: ChannelId, session: Session) -> Result<(Self, Session)> {
+        if let Some(input) = self.exec_inputs.remove(&channel) {
+            input.end();
+        }
+        if self.sftp_channel == Some(channel) {
+            self.sftp_channel = None;
+            self.sftp = None;
+            self.activity = None;
+        }
+        Ok((self, session))
+    }
 
-            if self.recv_buf.len() >= 4 {
-                let len = u32::from_be_bytes(self.recv_buf[..4].try_into().unwrap()) as usize;
-                let needed = (len + 4) - self.recv_buf.len();
+    async fn channel_eof(mut self, channel: ChannelId, session: Session) -> Result<(Self, Session)> {
+        if let Some(input) = self.exec_inputs.remove(&channel) {
+            input.end();
+        }
+        if self.sftp_channel == Some(channel) {
+            if let Some(ref sftp) = self.sftp {
+                sftp.input.end();
+            }
+        }
+        Ok((self, session))
+    }
 
-                let read_len = data.take(needed as u64).read_to_end(&mut self.recv_buf).await.unwrap();
-                data = &data[read_len..];
-                if read_len == needed {
-                    let recv_buf = &self.recv_buf.as_slice();
-                    let packet = SftpClientPacket::deserialize(&mut &recv_buf[4..]).unwrap();
-                    self.recv_buf.clear();
+    async fn data(mut self, channel: ChannelId, data: &[u8], session: Session) -> Result<(Self, Session)> {
+        if let Some(input) = self.exec_inputs.get(&channel) {
+            input.push(data);
+            return Ok((self, session));
+        }
+        if self.sftp_channel != Some(channel) {
+            return Ok((self, session));
+        }
+        if let Some(ref activity) = self.activity {
+            let _ = activity.send(());
+        }
+        if let Some(ref sftp) = self.sftp {
+            let _ = sftp.blocked.send(session.has_pending_data(channel));
+            sftp.input.push(data);
+        }
+        Ok((self, session))
+    }
 
-                    let resp = self.server.clone().process(&self.handle, packet).await;
+    async fn window_adjusted(self, channel: ChannelId, _new_window_size: usize, mut session: Session) -> Result<(Self, Session)> {
+        session.flush_pending(channel);
+        if self.sftp_channel == Some(channel) {
+            if let Some(ref activity) = self.activity {
+                let _ = activity.send(());
+            }
+            if let Some(ref sftp) = self.sftp {
+                let _ = sftp.blocked.send(session.has_pending_data(channel));
+            }
+        }
+        Ok((self, session))
+    }
+}
 
-                    let mut tmp_buf = Vec::new();
-                    resp.serialize(&mut tmp_buf).unwrap();
+/// Whether `command` runs an SFTP server, like `/usr/lib/openssh/sftp-server`
+/// or `internal-sftp`, whatever its options. Clients that cannot request
+/// the `sftp` subsystem run one of these instead.
+fn is_sftp_server_command(command: &str) -> bool {
+    let program = command.split_whitespace().next().unwrap_or("");
+    matches!(program.rsplit('/').next(), Some("sftp-server") | Some("internal-sftp"))
+}
 
-                    let mut resp_buf = Vec::new();
-                    let resp_len = tmp_buf.len() as u32;
-                    resp_len.serialize(&mut resp_buf).unwrap();
-                    resp_buf.write_all(&tmp_buf).unwrap();
-                    session.data(channel, CryptoVec::from_slice(&resp_buf));
-                }
+impl<T: 'static + Fs + Send + Sync> Client<T> {
+    /// Serves SFTP on `channel`, for a subsystem or an exec request.
+    fn start_sftp(&mut self, channel: ChannelId, session: &mut Session) {
+        // The provider's filesystem is handed to the first session, so a
+        // provider-backed client cannot start a second one.
+        if self.sftp.is_some() || (self.fs.is_none() && self.provider.is_some()) {
+            session.channel_failure(channel);
+            session.close(channel);
+            return;
+        }
+        let sftp_session = match self.fs.take() {
+            Some(fs) => self.server.try_new_session_with_fs(fs),
+            None => self.server.try_new_session(),
+        };
+        let sftp_session = match sftp_session {
+            Some(sftp_session) => sftp_session,
+            None => {
+                session.extended_data(channel, 1, CryptoVec::from_slice(b"Too many clients, try again later\n"));
+                session.channel_failure(channel);
+                session.close(channel);
+                return;
+            },
+        };
+        let input = Arc::new(ChannelInput::default());
+        let (blocked, blocked_rx) = watch::channel(false);
+        let (cancel, cancel_rx) = oneshot::channel();
+        session.set_manual_window(channel);
+        if self.ssh_config.sftp_idle_timeout.is_some() || self.ssh_config.keepalive_interval.is_some() {
+            let (tx, rx) = watch::channel(());
+            if let Some(timeout) = self.ssh_config.sftp_idle_timeout {
+                tokio::spawn(idle_watchdog(session.handle(), channel, timeout, rx.clone()));
+            }
+            if let Some(interval) = self.ssh_config.keepalive_interval {
+                tokio::spawn(keepalive(session.handle(), channel, interval, rx));
             }
+            self.activity = Some(Arc::new(tx));
         }
-
-        Ok((self, session))
+        tokio::spawn(serve_sftp(sftp_session, session.handle(), channel, input.clone(), blocked_rx, self.activity.clone(), cancel_rx));
+        self.sftp = Some(SftpTask { input, blocked, _cancel: cancel });
+        self.sftp_channel = Some(channel);
+        session.channel_success(channel);
     }
 }
@@ -0,0 +1,134 @@
+//! The filesystem an `FsProvider` picks is the one for the key the client
+//! proved it holds, not one it only probed with.
+#![cfg(feature = "thrussh-server")]
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh::{AgentAuthError, ChannelMsg, CryptoVec};
+use thrussh_keys::PublicKeyBase64;
+use thrussh_keys::encoding::Encoding;
+use thrussh_keys::key::{self, KeyPair};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::Fs;
+use thrusftp_protocol::types::Attrs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server, FsProvider, ServerConfig};
+
+struct Client;
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, _: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+/// Gives each of two keys a filesystem with a directory named after it and
+/// rejects all others.
+struct Provider {
+    a: key::PublicKey,
+    b: key::PublicKey,
+}
+
+#[async_trait]
+impl FsProvider<MemFs> for Provider {
+    async fn for_user(&self, _user: &str, key: &key::PublicKey) -> anyhow::Result<MemFs> {
+        let name = if *key == self.a {
+            "/a"
+        } else if *key == self.b {
+            "/b"
+        } else {
+            return Err(anyhow::anyhow!("unknown key"));
+        };
+        let fs = MemFs::new();
+        fs.mkdir(name.to_string(), Attrs::default()).await?;
+        Ok(fs)
+    }
+}
+
+/// Answers the server accepting the probed key with a request for `.0`
+/// instead, signed with `.0`, as a client that only knows the public half
+/// of the probed key would.
+struct SwapKey(Arc<KeyPair>);
+
+impl thrussh::Signer for SwapKey {
+    type Error = AgentAuthError;
+    type Future = futures::future::Ready<(Self, Result<CryptoVec, Self::Error>)>;
+
+    fn auth_publickey_sign(self, _key: &key::PublicKey, to_sign: CryptoVec) -> Self::Future {
+        // `to_sign` is the session id, then the request for the probed key.
+        let session_id_len = u32::from_be_bytes(to_sign[..4].try_into().unwrap()) as usize;
+        let mut buf = CryptoVec::new();
+        buf.extend(&to_sign[..4 + session_id_len]);
+        buf.push(50); // SSH_MSG_USERAUTH_REQUEST
+        buf.extend_ssh_string(b"alice");
+        buf.extend_ssh_string(b"ssh-connection");
+        buf.extend_ssh_string(b"publickey");
+        buf.push(1);
+        buf.extend_ssh_string(self.0.name().as_bytes());
+        buf.extend_ssh_string(&self.0.public_key_bytes());
+        let res = self.0.add_self_signature(&mut buf).map(|()| buf).map_err(AgentAuthError::from);
+        futures::future::ready((self, res))
+    }
+}
+
+/// Sends `packet` and returns the type of the response.
+async fn request(channel: &mut thrussh::client::Channel, packet: &[u8]) -> u8 {
+    channel.data(packet).await.unwrap();
+    let mut resp = Vec::new();
+    while resp.len() < 5 {
+        match tokio::time::timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            Some(ChannelMsg::Data { data }) => resp.extend_from_slice(&data),
+            Some(ChannelMsg::Close) | None => panic!("channel closed"),
+            Some(_) => {},
+        }
+    }
+    resp[4]
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn signed_key_picks_the_filesystem() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        ..Default::default()
+    };
+    let a = KeyPair::generate_ed25519().unwrap();
+    let b = Arc::new(KeyPair::generate_ed25519().unwrap());
+    let provider = Provider { a: a.clone_public_key(), b: b.clone_public_key() };
+    let server = SftpServer::builder(MemFs::new())
+        .ssh_config(ssh_config)
+        .fs_provider(Arc::new(provider))
+        .build();
+    tokio::spawn(start_server(server));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let config = Arc::new(thrussh::client::Config::default());
+
+    // A key the provider does not know gets nowhere, even after a probe
+    // with one it does.
+    let mut session = thrussh::client::connect(config.clone(), ("127.0.0.1", port), Client).await.unwrap();
+    let unknown = Arc::new(KeyPair::generate_ed25519().unwrap());
+    let (_, authenticated) = session.authenticate_future("alice", a.clone_public_key(), SwapKey(unknown)).await;
+    assert!(!authenticated.unwrap());
+
+    // A known key gets its own filesystem, not that of the probed key.
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let (_, authenticated) = session.authenticate_future("alice", a.clone_public_key(), SwapKey(b)).await;
+    assert!(authenticated.unwrap());
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+    // `Init`, answered with `Version`.
+    assert_eq!(request(&mut channel, &[0, 0, 0, 5, 1, 0, 0, 0, 3]).await, 2);
+    // `Stat` of `/a` and `/b`, answered with `Status` and `Attrs`.
+    assert_eq!(request(&mut channel, &[0, 0, 0, 11, 17, 0, 0, 0, 1, 0, 0, 0, 2, b'/', b'a']).await, 101);
+    assert_eq!(request(&mut channel, &[0, 0, 0, 11, 17, 0, 0, 0, 2, 0, 0, 0, 2, b'/', b'b']).await, 105);
+}
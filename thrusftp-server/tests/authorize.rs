@@ -0,0 +1,102 @@
+mod common;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Operation, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::{SftpServer, SftpSession};
+use common::{Hooked, Hooks};
+
+/// Refuses everything below a `private` directory and writing `.lock`
+/// files.
+struct Policy;
+
+#[async_trait]
+impl Hooks<LocalFs> for Policy {
+    async fn authorize(&self, _fs: &LocalFs, op: Operation, path: &str) -> Result<()> {
+        if path.contains("/private") {
+            return Err(anyhow!("private").into());
+        }
+        if let Operation::Open(Pflags { write: true, .. }) = op {
+            if path.ends_with(".lock") {
+                return Err(anyhow!("lock files are read-only").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn status_code(packet: SftpServerPacket) -> Option<StatusCode> {
+    match packet {
+        SftpServerPacket::Status { status_code, .. } => Some(status_code),
+        _ => None,
+    }
+}
+
+fn pflags(write: bool) -> Pflags {
+    Pflags { read: !write, write, append: false, creat: write, trunc: false, excl: false }
+}
+
+#[tokio::test]
+async fn authorize_refuses_requests() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("private")).unwrap();
+    std::fs::write(dir.join("private/secret"), b"").unwrap();
+    std::fs::write(dir.join("a.lock"), b"").unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let mut session = SftpServer::new(Hooked(LocalFs::default(), Policy)).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let resp = session.process(SftpClientPacket::Stat { id: 1, path: path("private/secret") }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::PermissionDenied));
+
+    let resp = session.process(SftpClientPacket::Mkdir { id: 2, path: path("private/new"), attrs: Attrs::default() }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::PermissionDenied));
+    assert!(!dir.join("private/new").exists());
+
+    // Moving a file out of the private directory is refused for its old
+    // path, moving one in for its new path.
+    let resp = session.process(SftpClientPacket::Rename { id: 3, oldpath: path("private/secret"), newpath: path("secret") }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::PermissionDenied));
+    let resp = session.process(SftpClientPacket::Rename { id: 4, oldpath: path("a.lock"), newpath: path("private/a.lock") }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::PermissionDenied));
+    assert!(dir.join("a.lock").exists());
+
+    let resp = session.process(SftpClientPacket::Open { id: 5, filename: path("a.lock"), pflags: pflags(true), attrs: Attrs::default() }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::PermissionDenied));
+    let resp = session.process(SftpClientPacket::Open { id: 6, filename: path("a.lock"), pflags: pflags(false), attrs: Attrs::default() }).await;
+    assert!(matches!(resp, SftpServerPacket::Handle { id: 6, .. }));
+
+    let resp = session.process(SftpClientPacket::Mkdir { id: 7, path: path("public"), attrs: Attrs::default() }).await;
+    assert_eq!(status_code(resp), Some(StatusCode::r#Ok));
+}
+
+/// File names of the `Name` response to a `glob@thrusftp` for `pattern`.
+async fn glob(session: &mut SftpSession<Hooked<LocalFs, Policy>>, id: u32, pattern: String) -> Vec<String> {
+    let request = SftpClientPacket::Extended { id, extended_request: ExtendedRequest::ThrusftpGlob { pattern } };
+    match session.process(request).await {
+        SftpServerPacket::Name { names, .. } => names.into_iter().map(|name| name.filename).collect(),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn glob_leaves_out_refused_paths() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("private")).unwrap();
+    std::fs::create_dir_all(dir.join("public")).unwrap();
+    std::fs::write(dir.join("private/secret"), b"").unwrap();
+    std::fs::write(dir.join("public/file"), b"").unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let mut session = SftpServer::new(Hooked(LocalFs::default(), Policy)).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    // Neither the private directory itself nor what is in it.
+    assert_eq!(glob(&mut session, 1, path("*")).await, [path("public")]);
+    assert_eq!(glob(&mut session, 2, path("*/*")).await, [path("public/file")]);
+    assert_eq!(glob(&mut session, 3, path("p*/s*")).await, Vec::<String>::new());
+}
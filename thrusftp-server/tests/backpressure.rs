@@ -0,0 +1,89 @@
+//! A client sending requests faster than the `Fs` answers them is held back
+//! by the SSH channel's window instead of filling the server's memory.
+#![cfg(feature = "thrussh-server")]
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh_keys::key::{self, KeyPair};
+use tokio::sync::watch;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server, ServerConfig};
+use common::{Hooked, Hooks};
+
+/// `stat` waits until `.0` is `true`.
+struct GatedStats(watch::Receiver<bool>);
+
+#[async_trait]
+impl Hooks<MemFs> for GatedStats {
+    async fn stat(&self, fs: &MemFs, path: String) -> Result<Attrs> {
+        let mut gate = self.0.clone();
+        while !*gate.borrow() {
+            gate.changed().await.unwrap();
+        }
+        fs.stat(path).await
+    }
+}
+
+struct Client;
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, _: &key::PublicKey) -> std::result::Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_fs_holds_the_client_back() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        ..Default::default()
+    };
+    let (gate, gate_rx) = watch::channel(false);
+    let server = SftpServer::builder(Hooked(MemFs::new(), GatedStats(gate_rx))).ssh_config(ssh_config).build();
+    tokio::spawn(start_server(server));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+    assert!(session.authenticate_publickey("alice", key).await.unwrap());
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+
+    // `Init`, then 3 MiB of `Stat` requests for `/`, more than the window the
+    // server offers.
+    let mut requests = vec![0, 0, 0, 5, 1, 0, 0, 0, 3];
+    let mut id = 0u32;
+    while requests.len() < 3 << 20 {
+        id += 1;
+        requests.extend_from_slice(&[0, 0, 0, 10, 17]);
+        requests.extend_from_slice(&id.to_be_bytes());
+        requests.extend_from_slice(&[0, 0, 0, 1, b'/']);
+    }
+    let mut sending = tokio::spawn(async move {
+        channel.data(&requests[..]).await.unwrap();
+        channel
+    });
+
+    // The server takes what fits into its window, then stops re-opening it.
+    assert!(tokio::time::timeout(Duration::from_secs(1), &mut sending).await.is_err());
+
+    // Once the `Fs` answers, the window opens again.
+    gate.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(30), sending).await.unwrap().unwrap();
+}
@@ -0,0 +1,75 @@
+//! The banner, given inline or read from a file, reaches clients before
+//! they authenticate.
+#![cfg(feature = "thrussh-server")]
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server_with_config, ServerConfig};
+use tokio::sync::mpsc;
+
+/// Reports the banners it is shown.
+struct Client(mpsc::UnboundedSender<String>);
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn auth_banner(self, banner: &str, session: thrussh::client::Session) -> Result<(Self, thrussh::client::Session), Self::Error> {
+        let _ = self.0.send(banner.to_string());
+        Ok((self, session))
+    }
+
+    async fn check_server_key(self, _: &thrussh_keys::key::PublicKey) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+async fn banner_of(config: ServerConfig) -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = ServerConfig { listen_addr: format!("127.0.0.1:{}", port), ..config };
+    tokio::spawn(start_server_with_config(SftpServer::new(MemFs::new()), config, None));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client(tx)).await.unwrap();
+    // Shown even though authentication then fails.
+    assert!(!session.authenticate_password("alice", "wrong").await.unwrap());
+    tokio::time::timeout(Duration::from_secs(10), rx.recv()).await.unwrap().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn banner_is_shown_before_authentication() {
+    let config = ServerConfig { banner: Some("Authorized use only.\n".to_string()), ..Default::default() };
+    assert_eq!(banner_of(config).await, "Authorized use only.\n");
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("banner");
+    std::fs::write(&path, "From a file.\n").unwrap();
+    let config = ServerConfig {
+        banner: Some("Replaced by the file.\n".to_string()),
+        banner_file: Some(path.clone()),
+        ..Default::default()
+    };
+    assert_eq!(banner_of(config).await, "From a file.\n");
+}
+
+#[tokio::test]
+async fn missing_banner_file_is_an_error() {
+    let config = ServerConfig {
+        listen_addr: "127.0.0.1:0".to_string(),
+        banner_file: Some("/nonexistent/banner".into()),
+        ..Default::default()
+    };
+    let err = start_server_with_config(SftpServer::new(MemFs::new()), config, None).await.unwrap_err();
+    assert!(err.to_string().contains("/nonexistent/banner"), "{}", err);
+}
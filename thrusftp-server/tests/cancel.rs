@@ -0,0 +1,100 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+use common::{Hooked, Hooks};
+
+/// Reads never finish, each holding a clone of `.0` for as long as it
+/// runs, like a large read on a slow disk holding buffers.
+struct EndlessReads(Arc<()>);
+
+#[async_trait]
+impl Hooks<MemFs> for EndlessReads {
+    async fn read(&self, _fs: &MemFs, _handle: &mut <MemFs as Fs>::FileHandle, _offset: u64, _len: u32) -> Result<Vec<u8>> {
+        let _buffer = self.0.clone();
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn dropping_the_connection_cancels_a_read() {
+    let buffers = Arc::new(());
+    let server = Arc::new(SftpServer::new(Hooked(MemFs::new(), EndlessReads(buffers.clone()))));
+    // The `Fs` holds a clone of its own.
+    let idle = Arc::strong_count(&buffers);
+    let (mut client, stream) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (reader, writer) = tokio::io::split(stream);
+            serve_stream(&server, reader, writer).await
+        }
+    });
+
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let requests = [
+        SftpClientPacket::Init { version: 3, extensions: vec![].into() },
+        SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() },
+    ];
+    for request in &requests {
+        client.write_all(&SftpCodec::encode(request).unwrap()).await.unwrap();
+    }
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut responses = Vec::new();
+    let mut buf = vec![0; 1024];
+    while responses.len() < 2 {
+        let len = client.read(&mut buf).await.unwrap();
+        responses.extend(codec.decode(&buf[..len]).unwrap());
+    }
+    let handle = match deserialize_packet(&responses[1]).unwrap() {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let read = SftpClientPacket::Read { id: 2, handle, offset: 0, len: 256 * 1024 };
+    client.write_all(&SftpCodec::encode(&read).unwrap()).await.unwrap();
+    while Arc::strong_count(&buffers) == idle {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.metrics().open_handles(), 1);
+    assert_eq!(server.client_count(), 1);
+
+    // The task serving the connection goes away, say because the
+    // connection was dropped: the read, the handle and the session go
+    // with it.
+    serving.abort();
+    assert!(serving.await.unwrap_err().is_cancelled());
+    assert_eq!(Arc::strong_count(&buffers), idle);
+    assert_eq!(server.metrics().open_handles(), 0);
+    assert_eq!(server.client_count(), 0);
+}
+
+#[tokio::test]
+async fn timed_out_read_leaves_the_handle_usable() {
+    let server = SftpServer::builder(Hooked(MemFs::new(), EndlessReads(Arc::new(()))))
+        .request_timeout(Some(Duration::from_millis(50)))
+        .build();
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let handle = match session.process(SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    assert_eq!(server.metrics().open_handles(), 1);
+
+    // A read that times out is cancelled, and the handle stays usable.
+    let resp = session.process(SftpClientPacket::Read { id: 2, handle: handle.clone(), offset: 0, len: 10 }).await;
+    assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::Failure, .. }));
+    let resp = session.process(SftpClientPacket::Close { id: 3, handle }).await;
+    assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. }));
+    assert_eq!(server.metrics().open_handles(), 0);
+}
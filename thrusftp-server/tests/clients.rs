@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::Notify;
+use thrusftp_fs_local::{LocalFile, LocalFs};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, FsHandle, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use common::{Hooked, Hooks};
+
+/// Counts `close` calls, and holds `stat` until `.1` is notified.
+struct CountingCloses(Arc<AtomicUsize>, Arc<Notify>);
+
+#[async_trait]
+impl Hooks<LocalFs> for CountingCloses {
+    async fn close(&self, fs: &LocalFs, handle: FsHandle<LocalFile, tokio::fs::ReadDir>) -> Result<()> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        fs.close(handle).await
+    }
+    async fn stat(&self, fs: &LocalFs, path: String) -> Result<Attrs> {
+        self.1.notified().await;
+        fs.stat(path).await
+    }
+}
+
+#[tokio::test]
+async fn counts_live_sessions() {
+    let server = SftpServer::new(MemFs::new());
+    assert_eq!(server.client_count(), 0);
+
+    let first = server.new_session();
+    let second = server.new_session_with_fs(MemFs::new());
+    assert_eq!(server.client_count(), 2);
+    drop(first);
+    assert_eq!(server.client_count(), 1);
+    drop(second);
+    assert_eq!(server.client_count(), 0);
+
+    let handle = server.clone().create_client_handle("client").await;
+    assert_eq!(server.client_count(), 1);
+    server.remove_client_handle(&handle).await;
+    assert_eq!(server.client_count(), 0);
+}
+
+#[tokio::test]
+async fn refuses_clients_over_the_limit() {
+    let server = SftpServer::builder(MemFs::new()).max_clients(Some(2)).build();
+
+    let first = server.try_new_session().unwrap();
+    let _second = server.try_new_session_with_fs(MemFs::new()).unwrap();
+    assert!(server.try_new_session().is_none());
+    assert_eq!(server.client_count(), 2);
+
+    drop(first);
+    let _third = server.try_new_session().unwrap();
+    assert_eq!(server.client_count(), 2);
+}
+
+#[tokio::test]
+async fn removed_clients_have_their_handles_closed() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let closes = Arc::new(AtomicUsize::new(0));
+    let gate = Arc::new(Notify::new());
+    let fs = Hooked(LocalFs::new(dir).write_buffer(1024), CountingCloses(closes.clone(), gate.clone()));
+    let server = SftpServer::new(fs);
+    let client = server.clone().create_client_handle("client").await;
+    server.clone().process(&client, SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let open = SftpClientPacket::Open { id: 1, filename: "/upload".to_string(), pflags, attrs: Attrs::default() };
+    let handle = match server.clone().process(&client, open).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let write = SftpClientPacket::Write { id: 2, handle, offset: 0, data: b"uploaded".to_vec().into() };
+    server.clone().process(&client, write).await;
+
+    let stat = tokio::spawn({
+        let (server, client) = (server.clone(), client.clone());
+        async move { server.process(&client, SftpClientPacket::Stat { id: 3, path: "/".to_string() }).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let mut removing = tokio::spawn({
+        let server = server.clone();
+        async move { server.remove_client_handle(&client).await }
+    });
+
+    // The stat in progress is answered first.
+    assert!(tokio::time::timeout(Duration::from_millis(20), &mut removing).await.is_err());
+    assert_eq!(closes.load(Ordering::SeqCst), 0);
+    gate.notify_one();
+    assert!(matches!(stat.await.unwrap(), SftpServerPacket::Attrs { .. }));
+    removing.await.unwrap();
+    assert_eq!(closes.load(Ordering::SeqCst), 1);
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"uploaded");
+    assert_eq!(server.client_count(), 0);
+}
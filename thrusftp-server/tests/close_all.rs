@@ -0,0 +1,60 @@
+//! Handles left open are closed when a connection ends without `Close`.
+//! Counts this process's file descriptors, so it is the only test here.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+#[tokio::test]
+async fn dropped_connections_release_their_fds() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let server = SftpServer::new(LocalFs::new(dir).sync_on_close(true));
+    let fds = open_fds();
+
+    let (mut client, stream) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (reader, writer) = tokio::io::split(stream);
+            serve_stream(&server, reader, writer).await
+        }
+    });
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    client.write_all(&SftpCodec::encode(&SftpClientPacket::Init { version: 3, extensions: vec![].into() }).unwrap()).await.unwrap();
+    for id in 1..=3 {
+        let open = SftpClientPacket::Open { id, filename: format!("/file{}", id), pflags: pflags.clone(), attrs: Attrs::default() };
+        client.write_all(&SftpCodec::encode(&open).unwrap()).await.unwrap();
+    }
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut responses = 0;
+    let mut buf = vec![0; 1024];
+    while responses < 4 {
+        let len = client.read(&mut buf).await.unwrap();
+        responses += codec.decode(&buf[..len]).unwrap().len();
+    }
+    assert_eq!(server.metrics().open_handles(), 3);
+    assert_eq!(open_fds(), fds + 3);
+
+    // The client goes away without closing anything.
+    drop(client);
+    serving.await.unwrap().unwrap();
+    assert_eq!(server.metrics().open_handles(), 0);
+    assert_eq!(open_fds(), fds);
+
+    // So do the handles of a session that is simply dropped.
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let open = SftpClientPacket::Open { id: 1, filename: "/file1".to_string(), pflags, attrs: Attrs::default() };
+    assert!(matches!(session.process(open).await, SftpServerPacket::Handle { .. }));
+    assert_eq!(open_fds(), fds + 1);
+    drop(session);
+    assert_eq!(open_fds(), fds);
+}
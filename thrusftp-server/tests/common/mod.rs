@@ -0,0 +1,99 @@
+//! Helpers shared by the integration tests. Not every test uses all of them.
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use thrusftp_protocol::{Capabilities, Fs, FsHandle, Operation, Result};
+use thrusftp_protocol::types::*;
+
+/// What `Hooked` does with the calls a test wants to change, given the
+/// wrapped `Fs`. Each hook passes the call on unchanged by default.
+#[async_trait]
+pub trait Hooks<T: Fs + Send + Sync>: Send + Sync {
+    async fn authorize(&self, fs: &T, op: Operation, path: &str) -> Result<()> {
+        fs.authorize(op, path).await
+    }
+    async fn on_init(&self, fs: &T, client_extensions: &[Extension]) {
+        fs.on_init(client_extensions).await
+    }
+    async fn capabilities(&self, fs: &T) -> Capabilities {
+        fs.capabilities().await
+    }
+    async fn close(&self, fs: &T, handle: FsHandle<T::FileHandle, T::DirHandle>) -> Result<()> {
+        fs.close(handle).await
+    }
+    async fn read(&self, fs: &T, handle: &mut T::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>> {
+        fs.read(handle, offset, len).await
+    }
+    async fn readdir(&self, fs: &T, handle: &mut T::DirHandle) -> Result<Vec<Name>> {
+        fs.readdir(handle).await
+    }
+    async fn stat(&self, fs: &T, path: String) -> Result<Attrs> {
+        fs.stat(path).await
+    }
+}
+
+/// The `Fs` `.0`, with the calls `Hooks` covers going through `.1`. Every
+/// other call is passed on to `.0`.
+pub struct Hooked<T, H>(pub T, pub H);
+
+#[async_trait]
+impl<T: Fs + Send + Sync, H: Hooks<T>> Fs for Hooked<T, H> {
+    type FileHandle = T::FileHandle;
+    type DirHandle = T::DirHandle;
+
+    async fn authorize(&self, op: Operation, path: &str) -> Result<()> { self.1.authorize(&self.0, op, path).await }
+    async fn on_init(&self, client_extensions: &[Extension]) { self.1.on_init(&self.0, client_extensions).await }
+    async fn capabilities(&self) -> Capabilities { self.1.capabilities(&self.0).await }
+    async fn close(&self, handle: FsHandle<Self::FileHandle, Self::DirHandle>) -> Result<()> { self.1.close(&self.0, handle).await }
+    async fn read(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<Vec<u8>> { self.1.read(&self.0, handle, offset, len).await }
+    async fn readdir(&self, handle: &mut Self::DirHandle) -> Result<Vec<Name>> { self.1.readdir(&self.0, handle).await }
+    async fn stat(&self, path: String) -> Result<Attrs> { self.1.stat(&self.0, path).await }
+
+    async fn open(&self, filename: String, pflags: Pflags, attrs: Attrs) -> Result<Self::FileHandle> { self.0.open(filename, pflags, attrs).await }
+    async fn write(&self, handle: &mut Self::FileHandle, offset: u64, data: Vec<u8>) -> Result<()> { self.0.write(handle, offset, data).await }
+    async fn lstat(&self, path: String) -> Result<Attrs> { self.0.lstat(path).await }
+    async fn fstat(&self, handle: &mut Self::FileHandle) -> Result<Attrs> { self.0.fstat(handle).await }
+    async fn setstat(&self, path: String, attrs: Attrs) -> Result<()> { self.0.setstat(path, attrs).await }
+    async fn fsetstat(&self, handle: &mut Self::FileHandle, attrs: Attrs) -> Result<()> { self.0.fsetstat(handle, attrs).await }
+    async fn truncate(&self, handle: &mut Self::FileHandle, len: u64) -> Result<()> { self.0.truncate(handle, len).await }
+    async fn opendir(&self, path: String) -> Result<Self::DirHandle> { self.0.opendir(path).await }
+    async fn remove(&self, filename: String) -> Result<()> { self.0.remove(filename).await }
+    async fn mkdir(&self, path: String, attrs: Attrs) -> Result<()> { self.0.mkdir(path, attrs).await }
+    async fn rmdir(&self, path: String) -> Result<()> { self.0.rmdir(path).await }
+    async fn realpath(&self, path: String) -> Result<String> { self.0.realpath(path).await }
+    async fn home_directory(&self) -> Result<String> { self.0.home_directory().await }
+    async fn rename(&self, oldpath: String, newpath: String) -> Result<()> { self.0.rename(oldpath, newpath).await }
+    async fn readlink(&self, path: String) -> Result<String> { self.0.readlink(path).await }
+    async fn symlink(&self, linkpath: String, targetpath: String) -> Result<()> { self.0.symlink(linkpath, targetpath).await }
+    async fn posix_rename_supported(&self) -> bool { self.0.posix_rename_supported().await }
+    async fn posix_rename(&self, oldpath: String, newpath: String) -> Result<()> { self.0.posix_rename(oldpath, newpath).await }
+    async fn fsync_supported(&self) -> bool { self.0.fsync_supported().await }
+    async fn fsync(&self, handle: &mut Self::FileHandle) -> Result<()> { self.0.fsync(handle).await }
+    async fn fdatasync_supported(&self) -> bool { self.0.fdatasync_supported().await }
+    async fn fdatasync(&self, handle: &mut Self::FileHandle) -> Result<()> { self.0.fdatasync(handle).await }
+    async fn statvfs_supported(&self) -> bool { self.0.statvfs_supported().await }
+    async fn statvfs(&self, path: String) -> Result<FsStats> { self.0.statvfs(path).await }
+    async fn hash_algorithms(&self) -> Vec<HashAlgorithm> { self.0.hash_algorithms().await }
+    async fn hash(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64) -> Result<Vec<u8>> { self.0.hash(handle, algorithm, offset, len).await }
+    async fn hash_blocks(&self, handle: &mut Self::FileHandle, algorithm: HashAlgorithm, offset: u64, len: u64, block_size: u32) -> Result<Vec<Vec<u8>>> { self.0.hash_blocks(handle, algorithm, offset, len, block_size).await }
+    async fn hardlink_supported(&self) -> bool { self.0.hardlink_supported().await }
+    async fn hardlink(&self, oldpath: String, newpath: String) -> Result<()> { self.0.hardlink(oldpath, newpath).await }
+    async fn mknod_supported(&self) -> bool { self.0.mknod_supported().await }
+    async fn mknod(&self, path: String, mode: u32, dev: u64) -> Result<()> { self.0.mknod(path, mode, dev).await }
+    async fn utimens_supported(&self) -> bool { self.0.utimens_supported().await }
+    async fn utimens(&self, path: String, atime: Timespec, mtime: Timespec) -> Result<()> { self.0.utimens(path, atime, mtime).await }
+    async fn glob_supported(&self) -> bool { self.0.glob_supported().await }
+    async fn glob(&self, pattern: String) -> Result<Vec<Name>> { self.0.glob(pattern).await }
+    async fn users_groups_by_id_supported(&self) -> bool { self.0.users_groups_by_id_supported().await }
+    async fn resolve_ids(&self, uids: Vec<u32>, gids: Vec<u32>) -> Result<(Vec<String>, Vec<String>)> { self.0.resolve_ids(uids, gids).await }
+    async fn seek_hole_data_supported(&self) -> bool { self.0.seek_hole_data_supported().await }
+    async fn seek_hole_data(&self, handle: &mut Self::FileHandle, offset: u64, whence: SeekWhence) -> Result<u64> { self.0.seek_hole_data(handle, offset, whence).await }
+    async fn read_eof_supported(&self) -> bool { self.0.read_eof_supported().await }
+    async fn read_eof(&self, handle: &mut Self::FileHandle, offset: u64, len: u32) -> Result<(Vec<u8>, bool)> { self.0.read_eof(handle, offset, len).await }
+    async fn readdir_from_supported(&self) -> bool { self.0.readdir_from_supported().await }
+    async fn readdir_from(&self, path: String, cookie: String) -> Result<Vec<(Name, String)>> { self.0.readdir_from(path, cookie).await }
+    async fn remove_tree_supported(&self) -> bool { self.0.remove_tree_supported().await }
+    async fn remove_tree(&self, path: String) -> Result<()> { self.0.remove_tree(path).await }
+    async fn custom_extensions(&self) -> Vec<Extension> { self.0.custom_extensions().await }
+    async fn handle_extension(&self, name: String, data: Vec<u8>) -> Result<Vec<u8>> { self.0.handle_extension(name, data).await }
+}
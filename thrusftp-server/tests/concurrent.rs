@@ -0,0 +1,162 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+use common::{Hooked, Hooks};
+
+/// `stat` of `/slow` waits until `.0` is notified, like a stat on a hung
+/// network mount.
+struct SlowStat(Arc<Notify>);
+
+#[async_trait]
+impl Hooks<MemFs> for SlowStat {
+    async fn stat(&self, fs: &MemFs, path: String) -> Result<Attrs> {
+        if path == "/slow" {
+            self.0.notified().await;
+        }
+        fs.stat(path).await
+    }
+}
+
+async fn next_response(client: &mut tokio::io::DuplexStream, codec: &mut SftpCodec, queued: &mut Vec<Vec<u8>>) -> SftpServerPacket {
+    let mut buf = vec![0; 1024];
+    while queued.is_empty() {
+        let len = client.read(&mut buf).await.unwrap();
+        queued.extend(codec.decode(&buf[..len]).unwrap());
+    }
+    deserialize_packet(&queued.remove(0)).unwrap()
+}
+
+#[tokio::test]
+async fn slow_stat_does_not_hold_up_reads() {
+    let slow = Arc::new(Notify::new());
+    let server = Arc::new(SftpServer::new(Hooked(MemFs::new(), SlowStat(slow.clone()))));
+    let (mut client, stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (reader, writer) = tokio::io::split(stream);
+            serve_stream(&server, reader, writer).await
+        }
+    });
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut queued = Vec::new();
+
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let requests = [
+        SftpClientPacket::Init { version: 3, extensions: vec![].into() },
+        SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() },
+    ];
+    for request in &requests {
+        client.write_all(&SftpCodec::encode(request).unwrap()).await.unwrap();
+    }
+    assert!(matches!(next_response(&mut client, &mut codec, &mut queued).await, SftpServerPacket::Version { .. }));
+    let handle = match next_response(&mut client, &mut codec, &mut queued).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+
+    // Requests on one handle still run in the order they were sent, so the
+    // read sees the second write.
+    let requests = [
+        SftpClientPacket::Stat { id: 2, path: "/slow".to_string() },
+        SftpClientPacket::Write { id: 3, handle: handle.clone(), offset: 0, data: b"abc".to_vec().into() },
+        SftpClientPacket::Write { id: 4, handle: handle.clone(), offset: 0, data: b"xyz".to_vec().into() },
+        SftpClientPacket::Read { id: 5, handle, offset: 0, len: 3 },
+    ];
+    for request in &requests {
+        client.write_all(&SftpCodec::encode(request).unwrap()).await.unwrap();
+    }
+    for id in 3..=4 {
+        match next_response(&mut client, &mut codec, &mut queued).await {
+            SftpServerPacket::Status { id: resp_id, status_code: StatusCode::Ok, .. } => assert_eq!(resp_id, id),
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+    match next_response(&mut client, &mut codec, &mut queued).await {
+        SftpServerPacket::Data { id, data } => {
+            assert_eq!(id, 5);
+            assert_eq!(data.0, b"xyz");
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    slow.notify_one();
+    match next_response(&mut client, &mut codec, &mut queued).await {
+        SftpServerPacket::Status { id, status_code: StatusCode::NoSuchFile, .. } => assert_eq!(id, 2),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn overlapping_writes_on_one_handle() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let server = Arc::new(SftpServer::new(LocalFs::new(dir)));
+    let (mut client, stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            let (reader, writer) = tokio::io::split(stream);
+            serve_stream(&server, reader, writer).await
+        }
+    });
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut queued = Vec::new();
+
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let requests = [
+        SftpClientPacket::Init { version: 3, extensions: vec![].into() },
+        SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() },
+    ];
+    for request in &requests {
+        client.write_all(&SftpCodec::encode(request).unwrap()).await.unwrap();
+    }
+    assert!(matches!(next_response(&mut client, &mut codec, &mut queued).await, SftpServerPacket::Version { .. }));
+    let handle = match next_response(&mut client, &mut codec, &mut queued).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+
+    // All in flight at once: writes that each overlap the ones before, out
+    // of offset order, with reads in between. Each must act at its own
+    // offset, as if they had been sent one by one.
+    let mut expected = Vec::new();
+    let mut reads = HashMap::new();
+    for i in 0..64u32 {
+        let id = 2 + 2 * i;
+        let offset = (i as usize * 389) % 1000;
+        let data = vec![i as u8 + 1; 100];
+        if expected.len() < offset + data.len() {
+            expected.resize(offset + data.len(), 0);
+        }
+        expected[offset..offset + data.len()].copy_from_slice(&data);
+        let write = SftpClientPacket::Write { id, handle: handle.clone(), offset: offset as u64, data: data.into() };
+        let read = SftpClientPacket::Read { id: id + 1, handle: handle.clone(), offset: 0, len: 2000 };
+        client.write_all(&SftpCodec::encode(&write).unwrap()).await.unwrap();
+        client.write_all(&SftpCodec::encode(&read).unwrap()).await.unwrap();
+        reads.insert(id + 1, expected.clone());
+    }
+    for _ in 0..128 {
+        match next_response(&mut client, &mut codec, &mut queued).await {
+            SftpServerPacket::Status { status_code: StatusCode::Ok, .. } => {},
+            SftpServerPacket::Data { id, data } => assert_eq!(data.0, reads[&id], "read {}", id),
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+
+    client.write_all(&SftpCodec::encode(&SftpClientPacket::Close { id: 200, handle }).unwrap()).await.unwrap();
+    next_response(&mut client, &mut codec, &mut queued).await;
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), expected);
+}
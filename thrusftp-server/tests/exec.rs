@@ -0,0 +1,176 @@
+//! Runs allowed exec requests through an `ExecHandler`, serves SFTP to
+//! those running an sftp-server and refuses the rest.
+#![cfg(feature = "thrussh-server")]
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh::ChannelMsg;
+use thrussh_keys::key::{self, KeyPair};
+use tokio::sync::watch;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server, ExecChannel, ExecHandler, ServerConfig};
+
+/// Sends back the user, the command and the input in upper case.
+struct Upper;
+
+#[async_trait]
+impl ExecHandler for Upper {
+    async fn exec(&self, user: &str, command: &str, mut channel: ExecChannel) -> u32 {
+        let mut input = Vec::new();
+        while let Some(data) = channel.read().await {
+            input.extend(data);
+        }
+        let output = format!("{} {} {}", user, command, String::from_utf8_lossy(&input).to_uppercase());
+        channel.write(output.as_bytes()).await.unwrap();
+        channel.write_stderr(b"done").await.unwrap();
+        3
+    }
+}
+
+/// Once `.0` is `true`, reads all input and sends back how long it was.
+struct Count(watch::Receiver<bool>);
+
+#[async_trait]
+impl ExecHandler for Count {
+    async fn exec(&self, _user: &str, _command: &str, mut channel: ExecChannel) -> u32 {
+        let mut gate = self.0.clone();
+        while !*gate.borrow() {
+            gate.changed().await.unwrap();
+        }
+        let mut len = 0;
+        while let Some(data) = channel.read().await {
+            len += data.len();
+        }
+        channel.write(len.to_string().as_bytes()).await.unwrap();
+        0
+    }
+}
+
+struct Client;
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, _: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+/// Everything that arrives on `channel` until it is closed: standard output,
+/// standard error and the exit status.
+async fn collect(channel: &mut thrussh::client::Channel) -> (String, String, Option<u32>) {
+    let (mut stdout, mut stderr, mut exit_status) = (Vec::new(), Vec::new(), None);
+    loop {
+        match tokio::time::timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            Some(ChannelMsg::Data { data }) => stdout.extend_from_slice(&data),
+            Some(ChannelMsg::ExtendedData { data, .. }) => stderr.extend_from_slice(&data),
+            Some(ChannelMsg::ExitStatus { exit_status: status }) => exit_status = Some(status),
+            Some(ChannelMsg::Close) | None => break,
+            Some(_) => {},
+        }
+    }
+    (String::from_utf8(stdout).unwrap(), String::from_utf8(stderr).unwrap(), exit_status)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exec_and_shell() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        shell_message: "SFTP and upper only\n".to_string(),
+        exec_commands: vec!["upper".to_string()],
+        ..Default::default()
+    };
+    let server = SftpServer::builder(MemFs::new())
+        .ssh_config(ssh_config)
+        .exec_handler(Arc::new(Upper))
+        .build();
+    tokio::spawn(start_server(server));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+    assert!(session.authenticate_publickey("alice", key).await.unwrap());
+
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.exec(true, "upper -x").await.unwrap();
+    channel.data(&b"hello"[..]).await.unwrap();
+    channel.eof().await.unwrap();
+    let (stdout, stderr, exit_status) = collect(&mut channel).await;
+    assert_eq!(stdout, "alice upper -x HELLO");
+    assert_eq!(stderr, "done");
+    assert_eq!(exit_status, Some(3));
+
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.exec(true, "rm -rf /").await.unwrap();
+    assert_eq!(collect(&mut channel).await, (String::new(), String::new(), None));
+
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.request_shell(true).await.unwrap();
+    assert_eq!(collect(&mut channel).await.0, "SFTP and upper only\n");
+
+    // `Init` for version 3, answered with `Version`.
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.exec(true, "/usr/lib/openssh/sftp-server -l INFO").await.unwrap();
+    channel.data(&[0, 0, 0, 5, 1, 0, 0, 0, 3][..]).await.unwrap();
+    let mut resp = Vec::new();
+    while resp.len() < 9 {
+        match tokio::time::timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            Some(ChannelMsg::Data { data }) => resp.extend_from_slice(&data),
+            Some(ChannelMsg::Close) | None => panic!("channel closed"),
+            Some(_) => {},
+        }
+    }
+    assert_eq!(resp[4], 2);
+    assert_eq!(resp[5..9], [0, 0, 0, 3]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unread_input_holds_the_client_back() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        exec_commands: vec!["count".to_string()],
+        ..Default::default()
+    };
+    let (gate, gate_rx) = watch::channel(false);
+    let server = SftpServer::builder(MemFs::new())
+        .ssh_config(ssh_config)
+        .exec_handler(Arc::new(Count(gate_rx)))
+        .build();
+    tokio::spawn(start_server(server));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+    assert!(session.authenticate_publickey("alice", key).await.unwrap());
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.exec(true, "count").await.unwrap();
+
+    // More than the window the server offers.
+    let mut sending = tokio::spawn(async move {
+        channel.data(&vec![b'x'; 3 << 20][..]).await.unwrap();
+        channel.eof().await.unwrap();
+        channel
+    });
+    assert!(tokio::time::timeout(Duration::from_secs(1), &mut sending).await.is_err());
+
+    gate.send(true).unwrap();
+    let mut channel = tokio::time::timeout(Duration::from_secs(30), sending).await.unwrap().unwrap();
+    assert_eq!(collect(&mut channel).await, ((3 << 20).to_string(), String::new(), Some(0)));
+}
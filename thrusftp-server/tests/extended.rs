@@ -0,0 +1,34 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+#[tokio::test]
+async fn unknown_extension_is_unsupported() {
+    // SSH_FXP_EXTENDED, id 7, a name nobody implements and some payload,
+    // put together by hand as a client would send it.
+    let name = b"made-up@example.com";
+    let mut packet = vec![200, 0, 0, 0, 7];
+    packet.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    packet.extend_from_slice(name);
+    packet.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+    let request: SftpClientPacket = deserialize_packet(&packet).unwrap();
+    match &request {
+        SftpClientPacket::Extended { id: 7, extended_request: ExtendedRequest::Unknown { name, data } } => {
+            assert_eq!(name, "made-up@example.com");
+            assert_eq!(data.0, [0xde, 0xad, 0xbe, 0xef]);
+        },
+        request => panic!("unexpected request {:?}", request),
+    }
+
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    match session.process(request).await {
+        SftpServerPacket::Status { id, status_code, .. } => {
+            assert_eq!(id, 7);
+            assert_eq!(status_code, StatusCode::OpUnsupported);
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::{Config, SftpServer, SftpSession};
+
+fn open(id: u32, filename: &str) -> SftpClientPacket {
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    SftpClientPacket::Open { id, filename: filename.to_string(), pflags, attrs: Attrs::default() }
+}
+
+fn opendir(id: u32, path: &str) -> SftpClientPacket {
+    SftpClientPacket::Opendir { id, path: path.to_string() }
+}
+
+async fn handle(session: &mut SftpSession<MemFs>, request: SftpClientPacket) -> Handle {
+    match session.process(request).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+/// Checks that `resp` is a `Failure` saying `message`.
+fn assert_failure(resp: SftpServerPacket, message: &str) {
+    match resp {
+        SftpServerPacket::Status { status_code: StatusCode::Failure, error_message, .. } => assert_eq!(error_message, message),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn open_handles_are_limited() {
+    let config = Config { max_handles: Some(2), ..Default::default() };
+    let server = SftpServer::with_config(MemFs::new(), config);
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let file = handle(&mut session, open(1, "/file")).await;
+    handle(&mut session, opendir(2, "/")).await;
+    assert_failure(session.process(open(3, "/file")).await, "Too many open handles");
+    assert_failure(session.process(opendir(4, "/")).await, "Too many open handles");
+
+    // Closing one makes room for the next.
+    session.process(SftpClientPacket::Close { id: 5, handle: file }).await;
+    handle(&mut session, opendir(6, "/")).await;
+    assert_failure(session.process(open(7, "/file")).await, "Too many open handles");
+
+    // The limit is per session.
+    let mut other = server.new_session();
+    other.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    handle(&mut other, open(1, "/file")).await;
+}
+
+#[tokio::test]
+async fn handles_are_never_reused_and_do_not_show_the_path() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let mut seen = HashSet::new();
+    for id in 0..100 {
+        let handle = handle(&mut session, open(id, "/secret-name")).await;
+        assert!(!handle.contains("secret"), "{:?}", handle);
+        assert!(handle.len() <= 256);
+        assert!(seen.insert(handle.clone()), "{:?} handed out twice", handle);
+        if id % 2 == 0 {
+            session.process(SftpClientPacket::Close { id, handle }).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn unknown_and_mismatched_handles() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let file = handle(&mut session, open(1, "/file")).await;
+    let dir = handle(&mut session, opendir(2, "/")).await;
+
+    // A handle of the other kind.
+    assert_failure(session.process(SftpClientPacket::Readdir { id: 3, handle: file.clone() }).await, "Handle is not a directory handle");
+    assert_failure(session.process(SftpClientPacket::Read { id: 4, handle: dir.clone(), offset: 0, len: 10 }).await, "Handle is not a file handle");
+    let write = SftpClientPacket::Write { id: 5, handle: dir.clone(), offset: 0, data: b"data".to_vec().into() };
+    assert_failure(session.process(write).await, "Handle is not a file handle");
+    assert_failure(session.process(SftpClientPacket::Fstat { id: 6, handle: dir.clone() }).await, "Handle is not a file handle");
+
+    // A handle that was never handed out, or is closed.
+    session.process(SftpClientPacket::Close { id: 7, handle: dir.clone() }).await;
+    for handle in ["unknown".to_string(), dir] {
+        assert_failure(session.process(SftpClientPacket::Readdir { id: 8, handle: handle.clone() }).await, "No such handle");
+        assert_failure(session.process(SftpClientPacket::Read { id: 9, handle: handle.clone(), offset: 0, len: 10 }).await, "No such handle");
+        assert_failure(session.process(SftpClientPacket::Fstat { id: 10, handle: handle.clone() }).await, "No such handle");
+        assert_failure(session.process(SftpClientPacket::Close { id: 11, handle }).await, "No such handle");
+    }
+
+    // The file handle still works.
+    assert!(matches!(session.process(SftpClientPacket::Fstat { id: 12, handle: file }).await, SftpServerPacket::Attrs { .. }));
+}
@@ -0,0 +1,72 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+fn realpath(path: &str) -> SftpClientPacket {
+    SftpClientPacket::Realpath { id: 1, path: path.to_string() }
+}
+
+fn filename(resp: SftpServerPacket) -> String {
+    match resp {
+        SftpServerPacket::Name { mut names, .. } => names.remove(0).filename,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn clients_start_in_home() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("home/alice")).unwrap();
+
+    // Without a home, clients start at the root.
+    let mut session = SftpServer::new(LocalFs::new(dir)).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert_eq!(filename(session.process(realpath(".")).await), "/");
+    assert_eq!(filename(session.process(realpath("")).await), "/");
+
+    let mut session = SftpServer::new(LocalFs::new(dir).home("/home/alice")).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert_eq!(filename(session.process(realpath(".")).await), "/home/alice");
+    assert_eq!(filename(session.process(realpath("..")).await), "/home");
+    let mkdir = SftpClientPacket::Mkdir { id: 2, path: "uploads".to_string(), attrs: Attrs::default() };
+    assert!(matches!(session.process(mkdir).await, SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. }));
+    assert!(dir.join("home/alice/uploads").is_dir());
+
+    // Without a root, the home is a local path.
+    let home = std::fs::canonicalize(dir.join("home/alice")).unwrap();
+    let mut session = SftpServer::new(LocalFs::default().home(home.to_string_lossy())).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert_eq!(filename(session.process(realpath(".")).await), home.to_string_lossy());
+}
+
+#[tokio::test]
+async fn relative_paths_are_resolved_against_home() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("home/alice/sub")).unwrap();
+    std::fs::write(dir.join("home/alice/sub/file"), b"alice").unwrap();
+    std::fs::write(dir.join("file"), b"root").unwrap();
+    let mut session = SftpServer::new(LocalFs::new(dir).home("/home/alice")).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    assert_eq!(filename(session.process(realpath("sub/../sub/./file")).await), "/home/alice/sub/file");
+    assert_eq!(filename(session.process(realpath("../../file")).await), "/file");
+    let stat = |path: &str| SftpClientPacket::Stat { id: 2, path: path.to_string() };
+    match session.process(stat("sub/file")).await {
+        SftpServerPacket::Attrs { attrs, .. } => assert_eq!(attrs.size, Some(5)),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    // Absolute paths are left as they are.
+    match session.process(stat("/file")).await {
+        SftpServerPacket::Attrs { attrs, .. } => assert_eq!(attrs.size, Some(4)),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    let rename = SftpClientPacket::Rename { id: 3, oldpath: "sub/file".to_string(), newpath: "moved".to_string() };
+    assert!(matches!(session.process(rename).await, SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. }));
+    assert_eq!(std::fs::read(dir.join("home/alice/moved")).unwrap(), b"alice");
+    let symlink = SftpClientPacket::Symlink { id: 4, linkpath: "sub/link".to_string(), targetpath: "../moved".to_string() };
+    assert!(matches!(session.process(symlink).await, SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. }));
+    assert_eq!(std::fs::read(dir.join("home/alice/sub/link")).unwrap(), b"alice");
+}
@@ -0,0 +1,81 @@
+//! Serves an RSA and an ed25519 host key at once and checks that clients
+//! preferring either algorithm get the key they asked for.
+#![cfg(feature = "thrussh-server")]
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh_keys::key::{self, KeyPair, SignatureHash};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server_with_config, ServerConfig};
+use tokio::sync::mpsc;
+
+/// Reports the host key the server presented and accepts it.
+struct Client(mpsc::UnboundedSender<String>);
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, server_public_key: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
+        let _ = self.0.send(server_public_key.name().to_string());
+        Ok((self, true))
+    }
+}
+
+async fn host_key_for(port: u16, preferred: &'static [key::Name]) -> String {
+    let config = thrussh::client::Config {
+        preferred: thrussh::Preferred { key: preferred, ..Default::default() },
+        ..Default::default()
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let _session = thrussh::client::connect(Arc::new(config), ("127.0.0.1", port), Client(tx)).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), rx.recv()).await.unwrap().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_several_host_keys() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let keys = [
+        ("rsa", KeyPair::generate_rsa(2048, SignatureHash::SHA2_256).unwrap()),
+        ("ed25519", KeyPair::generate_ed25519().unwrap()),
+    ];
+    let mut host_keys = vec![];
+    for (name, key) in &keys {
+        let path = dir.join(name);
+        thrussh_keys::encode_pkcs8_pem(key, std::fs::File::create(&path).unwrap()).unwrap();
+        host_keys.push(path);
+    }
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        host_keys,
+        ..Default::default()
+    };
+    tokio::spawn(start_server_with_config(SftpServer::new(MemFs::new()), config, None));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(host_key_for(port, &[key::ED25519]).await, "ssh-ed25519");
+    assert_eq!(host_key_for(port, &[key::RSA_SHA2_256]).await, "rsa-sha2-256");
+    // A client that prefers RSA but also takes ed25519 gets RSA.
+    assert_eq!(host_key_for(port, &[key::RSA_SHA2_256, key::ED25519]).await, "rsa-sha2-256");
+}
+
+#[tokio::test]
+async fn missing_host_key_is_an_error() {
+    let config = ServerConfig {
+        listen_addr: "127.0.0.1:0".to_string(),
+        host_keys: vec!["/nonexistent/host_key".into()],
+        ..Default::default()
+    };
+    let err = start_server_with_config(SftpServer::new(MemFs::new()), config, None).await.unwrap_err();
+    assert!(err.to_string().contains("/nonexistent/host_key"), "{}", err);
+}
@@ -0,0 +1,91 @@
+//! The SFTP idle timeout counts responses as activity, so a client waiting
+//! for a long stream of them is not cut off.
+#![cfg(feature = "thrussh-server")]
+
+mod common;
+
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh::ChannelMsg;
+use thrussh_keys::key::{self, KeyPair};
+use tokio::time::Instant;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server, ServerConfig};
+use common::{Hooked, Hooks};
+
+/// The `n`th `stat` is answered `n` times `.2` after the first one started.
+struct PacedStats(OnceLock<Instant>, AtomicU32, Duration);
+
+#[async_trait]
+impl Hooks<MemFs> for PacedStats {
+    async fn stat(&self, fs: &MemFs, path: String) -> Result<Attrs> {
+        let n = self.1.fetch_add(1, Ordering::SeqCst) + 1;
+        let start = *self.0.get_or_init(Instant::now);
+        tokio::time::sleep_until(start + self.2 * n).await;
+        fs.stat(path).await
+    }
+}
+
+struct Client;
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, _: &key::PublicKey) -> std::result::Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn responses_keep_the_channel_open() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        sftp_idle_timeout: Some(Duration::from_millis(500)),
+        ..Default::default()
+    };
+    let step = Duration::from_millis(100);
+    let fs = Hooked(MemFs::new(), PacedStats(OnceLock::new(), AtomicU32::new(0), step));
+    tokio::spawn(start_server(SftpServer::builder(fs).ssh_config(ssh_config).build()));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+    assert!(session.authenticate_publickey("alice", key).await.unwrap());
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+
+    // `Init`, then 20 `Stat` requests for `/`, all sent at once. Their
+    // responses take two seconds, four times the idle timeout, to arrive.
+    let mut requests = vec![0, 0, 0, 5, 1, 0, 0, 0, 3];
+    for id in 1..=20u32 {
+        requests.extend_from_slice(&[0, 0, 0, 10, 17]);
+        requests.extend_from_slice(&id.to_be_bytes());
+        requests.extend_from_slice(&[0, 0, 0, 1, b'/']);
+    }
+    channel.data(&requests[..]).await.unwrap();
+
+    // `Version` and the 20 `Attrs`.
+    let mut codec = SftpCodec::new(256 * 1024);
+    let mut received = 0;
+    while received < 21 {
+        match tokio::time::timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            Some(ChannelMsg::Data { data }) => received += codec.decode(&data).unwrap().len(),
+            Some(ChannelMsg::Close) | None => panic!("channel closed after {} responses", received),
+            Some(_) => {},
+        }
+    }
+}
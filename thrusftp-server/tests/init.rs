@@ -0,0 +1,137 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use async_trait::async_trait;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::Capabilities;
+use thrusftp_protocol::types::*;
+use thrusftp_server::{Config, SftpServer};
+use common::{Hooked, Hooks};
+
+async fn advertised(config: Config) -> Vec<Extension> {
+    let mut session = SftpServer::with_config(MemFs::new(), config).new_session();
+    match session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await {
+        SftpServerPacket::Version { version: 3, extensions } => extensions.0,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn advertises_newline() {
+    let extensions = advertised(Config::default()).await;
+    let newline = extensions.iter().find(|ext| ext.name == "newline@vandyke.com").unwrap();
+    assert_eq!(newline.data, "\n");
+
+    let config = Config {
+        disabled_extensions: vec!["newline@vandyke.com".to_string()],
+        ..Default::default()
+    };
+    assert!(advertised(config).await.iter().all(|ext| ext.name != "newline@vandyke.com"));
+}
+
+/// Remembers what clients advertised and counts how often the `Fs` is
+/// asked for its capabilities.
+#[derive(Default)]
+struct Recording(Arc<Mutex<Vec<Vec<String>>>>, Arc<AtomicUsize>);
+
+#[async_trait]
+impl Hooks<MemFs> for Recording {
+    async fn on_init(&self, _fs: &MemFs, client_extensions: &[Extension]) {
+        self.0.lock().unwrap().push(client_extensions.iter().map(|ext| ext.name.clone()).collect());
+    }
+
+    async fn capabilities(&self, _fs: &MemFs) -> Capabilities {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        Capabilities { posix_rename: true, max_handles: Some(1), ..Default::default() }
+    }
+}
+
+#[tokio::test]
+async fn passes_client_extensions_to_fs() {
+    let recording = Recording::default();
+    let seen = recording.0.clone();
+    let server = SftpServer::new(Hooked(MemFs::new(), recording));
+    let extensions = vec![Extension { name: "copy-data".to_string(), data: "1".to_string() }];
+    let resp = server.new_session().process(SftpClientPacket::Init { version: 3, extensions: extensions.into() }).await;
+    assert!(matches!(resp, SftpServerPacket::Version { .. }));
+    let resp = server.new_session().process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    assert!(matches!(resp, SftpServerPacket::Version { .. }));
+
+    assert_eq!(*seen.lock().unwrap(), vec![vec!["copy-data".to_string()], vec![]]);
+}
+
+#[tokio::test]
+async fn requests_before_init_and_second_init_are_refused() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    let init = || SftpClientPacket::Init { version: 3, extensions: vec![].into() };
+
+    match session.process(SftpClientPacket::Opendir { id: 7, path: "/".to_string() }).await {
+        SftpServerPacket::Status { id: 7, status_code: StatusCode::Failure, .. } => {},
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    assert!(matches!(session.process(init()).await, SftpServerPacket::Version { .. }));
+    match session.process(init()).await {
+        SftpServerPacket::Status { status_code: StatusCode::BadMessage, .. } => {},
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    // The session stays usable after refusing the second `Init`.
+    assert!(matches!(
+        session.process(SftpClientPacket::Opendir { id: 8, path: "/".to_string() }).await,
+        SftpServerPacket::Handle { id: 8, .. }
+    ));
+}
+
+#[tokio::test]
+async fn capabilities_are_asked_once() {
+    let recording = Recording::default();
+    let asked = recording.1.clone();
+    let server = SftpServer::new(Hooked(MemFs::new(), recording));
+    let init = || SftpClientPacket::Init { version: 3, extensions: vec![].into() };
+
+    let mut session = server.new_session();
+    match session.process(init()).await {
+        SftpServerPacket::Version { extensions, .. } => {
+            assert!(extensions.0.iter().any(|ext| ext.name == "posix-rename@openssh.com"));
+            assert!(extensions.0.iter().all(|ext| ext.name != "statvfs@openssh.com"));
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    // Also not for extended requests, nor by other sessions on the same
+    // `Fs`.
+    let statvfs = SftpClientPacket::Extended { id: 1, extended_request: ExtendedRequest::OpensshStatvfs { path: "/".to_string() } };
+    assert!(matches!(session.process(statvfs).await, SftpServerPacket::Status { status_code: StatusCode::OpUnsupported, .. }));
+    server.new_session().process(init()).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 1);
+
+    // The `Fs`'s handle limit applies on top of the server's.
+    let opendir = |id| SftpClientPacket::Opendir { id, path: "/".to_string() };
+    assert!(matches!(session.process(opendir(2)).await, SftpServerPacket::Handle { .. }));
+    assert!(matches!(session.process(opendir(3)).await, SftpServerPacket::Status { status_code: StatusCode::Failure, .. }));
+
+    // A session with an `Fs` of its own asks that one.
+    let own = Hooked(MemFs::new(), Recording(Default::default(), asked.clone()));
+    server.new_session_with_fs(own).process(init()).await;
+    assert_eq!(asked.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn one_of_concurrent_inits_succeeds() {
+    let server = SftpServer::new(MemFs::new());
+    for _ in 0..100 {
+        let session = Arc::new(server.new_session());
+        let inits: Vec<_> = (0..4).map(|_| tokio::spawn({
+            let session = session.clone();
+            async move { session.process_concurrent(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await }
+        })).collect();
+        let mut versions = 0;
+        for init in inits {
+            match init.await.unwrap() {
+                SftpServerPacket::Version { .. } => versions += 1,
+                SftpServerPacket::Status { status_code: StatusCode::BadMessage, .. } => {},
+                resp => panic!("unexpected response {:?}", resp),
+            }
+        }
+        assert_eq!(versions, 1);
+    }
+}
@@ -0,0 +1,67 @@
+//! Keepalives keep an idle SFTP connection open past `connection_timeout`.
+#![cfg(feature = "thrussh-server")]
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use thrussh::ChannelMsg;
+use thrussh_keys::key::{self, KeyPair};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server, ServerConfig};
+
+struct Client;
+
+#[async_trait]
+impl thrussh::client::Handler for Client {
+    type Error = thrussh::Error;
+
+    async fn check_server_key(self, _: &key::PublicKey) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+/// Sends `packet` and returns the type of the response.
+async fn request(channel: &mut thrussh::client::Channel, packet: &[u8]) -> u8 {
+    channel.data(packet).await.unwrap();
+    let mut resp = Vec::new();
+    while resp.len() < 5 {
+        match tokio::time::timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            Some(ChannelMsg::Data { data }) => resp.extend_from_slice(&data),
+            Some(ChannelMsg::Close) | None => panic!("channel closed"),
+            Some(_) => {},
+        }
+    }
+    resp[4]
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keepalives_outlast_connection_timeout() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let ssh_config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        connection_timeout: Some(Duration::from_millis(500)),
+        keepalive_interval: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    tokio::spawn(start_server(SftpServer::builder(MemFs::new()).ssh_config(ssh_config).build()));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let config = Arc::new(thrussh::client::Config::default());
+    let mut session = thrussh::client::connect(config, ("127.0.0.1", port), Client).await.unwrap();
+    let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+    assert!(session.authenticate_publickey("alice", key).await.unwrap());
+    let mut channel = session.channel_open_session().await.unwrap();
+    channel.request_subsystem(true, "sftp").await.unwrap();
+
+    // `Init`, answered with `Version`.
+    assert_eq!(request(&mut channel, &[0, 0, 0, 5, 1, 0, 0, 0, 3]).await, 2);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    // `Realpath` of `.`, answered with `Name`.
+    assert_eq!(request(&mut channel, &[0, 0, 0, 10, 16, 0, 0, 0, 1, 0, 0, 0, 1, b'.']).await, 104);
+}
@@ -0,0 +1,46 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+#[tokio::test]
+async fn counts_requests_bytes_and_handles() {
+    let server = SftpServer::new(MemFs::new());
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    let open = SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() };
+    let handle = match session.process(open).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    assert_eq!(server.metrics().open_handles(), 1);
+
+    let data = b"hello world".to_vec().into();
+    session.process(SftpClientPacket::Write { id: 2, handle: handle.clone(), offset: 0, data }).await;
+    session.process(SftpClientPacket::Read { id: 3, handle: handle.clone(), offset: 6, len: 100 }).await;
+    session.process(SftpClientPacket::Stat { id: 4, path: "/missing".to_string() }).await;
+    // Writes that fail do not count as written.
+    let data = b"lost".to_vec().into();
+    session.process(SftpClientPacket::Write { id: 5, handle: "nope".to_string(), offset: 0, data }).await;
+
+    let metrics = server.metrics();
+    assert_eq!(metrics.bytes_written(), 11);
+    assert_eq!(metrics.bytes_read(), 5);
+    let requests: Vec<_> = metrics.requests().filter(|(_, count)| *count > 0).collect();
+    assert_eq!(requests, [("init", 1), ("open", 1), ("read", 1), ("write", 2), ("stat", 1)]);
+    assert_eq!(metrics.statuses(StatusCode::r#Ok), 1);
+    assert_eq!(metrics.statuses(StatusCode::NoSuchFile), 1);
+    assert_eq!(metrics.statuses(StatusCode::Failure), 1);
+
+    // A second session's counts add up, and dropping a session gives back
+    // the handles it left open.
+    let mut other = server.new_session();
+    other.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    other.process(SftpClientPacket::Opendir { id: 1, path: "/".to_string() }).await;
+    assert_eq!(server.metrics().open_handles(), 2);
+    session.process(SftpClientPacket::Close { id: 6, handle }).await;
+    assert_eq!(server.metrics().open_handles(), 1);
+    drop(other);
+    assert_eq!(server.metrics().open_handles(), 0);
+}
@@ -0,0 +1,98 @@
+//! Drives the server with OpenSSH's `sftp` client. Skipped if `sftp` or
+//! `ssh-keygen` is not installed.
+#![cfg(feature = "thrussh-server")]
+
+use std::path::Path;
+use std::time::Duration;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_server::SftpServer;
+use thrusftp_server::thrussh::{start_server_with_config, ServerConfig};
+use tokio::process::Command;
+
+fn installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Starts a server on a free loopback port and returns the port once it
+/// accepts connections.
+async fn start(fs: LocalFs) -> u16 {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config = ServerConfig {
+        listen_addr: format!("127.0.0.1:{}", port),
+        ..Default::default()
+    };
+    tokio::spawn(start_server_with_config(SftpServer::new(fs), config, None));
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return port;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("server did not start");
+}
+
+/// Runs `batch` with `sftp -b` and returns its output. Fails if any command
+/// in the batch failed.
+async fn sftp(dir: &Path, port: u16, batch: &str) -> String {
+    let key = dir.join("id_ed25519");
+    if !key.exists() {
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"]).arg(&key)
+            .status().await.unwrap();
+        assert!(status.success());
+    }
+    let batch_file = dir.join("batch");
+    std::fs::write(&batch_file, batch).unwrap();
+    let output = Command::new("sftp")
+        .arg("-b").arg(&batch_file)
+        .arg("-i").arg(&key)
+        .args(["-F", "/dev/null"])
+        .args(["-o", "BatchMode=yes", "-o", "IdentitiesOnly=yes"])
+        .args(["-o", "StrictHostKeyChecking=no", "-o", "UserKnownHostsFile=/dev/null"])
+        .arg("-P").arg(port.to_string())
+        .arg("user@127.0.0.1")
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(30), output).await
+        .expect("sftp did not exit").unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(output.status.success(), "sftp failed: {}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    stdout
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn openssh_client() {
+    if !installed("sftp") || !installed("ssh-keygen") {
+        eprintln!("sftp or ssh-keygen not installed, skipping");
+        return;
+    }
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let content: Vec<u8> = (0..=255).cycle().take(200_000).collect();
+    std::fs::write(dir.join("local"), &content).unwrap();
+
+    let port = start(LocalFs::default()).await;
+    let d = dir.to_string_lossy();
+    let output = sftp(&dir, port, &format!("\
+        mkdir {d}/remote\n\
+        put {d}/local {d}/remote/uploaded\n\
+        rename {d}/remote/uploaded {d}/remote/renamed\n\
+        ln -s {d}/remote/renamed {d}/link\n\
+        get {d}/link {d}/downloaded\n\
+        ls -l {d}/remote\n\
+        rm {d}/downloaded\n\
+        get {d}/remote/renamed {d}/downloaded\n\
+    ", d = d)).await;
+
+    assert!(dir.join("remote").is_dir());
+    assert!(!dir.join("remote/uploaded").exists());
+    assert_eq!(std::fs::read(dir.join("remote/renamed")).unwrap(), content);
+    assert_eq!(std::fs::read_link(dir.join("link")).unwrap(), dir.join("remote/renamed"));
+    assert_eq!(std::fs::read(dir.join("downloaded")).unwrap(), content);
+    let listing = output.lines()
+        .find(|line| !line.starts_with("sftp>") && line.ends_with("renamed"))
+        .unwrap_or_else(|| panic!("renamed file not listed: {}", output));
+    assert!(listing.starts_with("-rw"), "unexpected long name: {}", listing);
+    assert!(listing.contains(" 200000 "), "unexpected long name: {}", listing);
+}
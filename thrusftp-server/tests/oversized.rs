@@ -0,0 +1,90 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::parse::Serialize;
+use thrusftp_protocol::types::*;
+use thrusftp_server::{SftpServer, SftpSession};
+
+const MAX_PACKET_SIZE: u32 = 1024;
+
+async fn process(session: &mut SftpSession<MemFs>, packet: SftpClientPacket) -> SftpServerPacket {
+    let resp = session.process(packet).await;
+    let mut buf = Vec::new();
+    resp.serialize(&mut buf).unwrap();
+    assert!(buf.len() <= MAX_PACKET_SIZE as usize, "{} byte response", buf.len());
+    resp
+}
+
+async fn create(session: &mut SftpSession<MemFs>, path: String, data: &[u8]) {
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let handle = match process(session, SftpClientPacket::Open { id: 1, filename: path, pflags, attrs: Attrs::default() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    process(session, SftpClientPacket::Write { id: 2, handle: handle.clone(), offset: 0, data: data.to_vec().into() }).await;
+    process(session, SftpClientPacket::Close { id: 3, handle }).await;
+}
+
+#[tokio::test]
+async fn long_names() {
+    let server = SftpServer::builder(MemFs::new()).max_packet_size(MAX_PACKET_SIZE).build();
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    process(&mut session, SftpClientPacket::Mkdir { id: 1, path: "/dir".to_string(), attrs: Attrs::default() }).await;
+
+    let short: Vec<String> = (0..30).map(|i| format!("{:0>100}", i)).collect();
+    for name in &short {
+        create(&mut session, format!("/dir/{}", name), b"").await;
+    }
+    // Fits without its longname, but not with it.
+    let long = "l".repeat(600);
+    create(&mut session, format!("/dir/{}", long), b"").await;
+    // Does not fit at all.
+    let too_long = "x".repeat(2000);
+    create(&mut session, format!("/dir/{}", too_long), b"").await;
+
+    let handle = match process(&mut session, SftpClientPacket::Opendir { id: 4, path: "/dir".to_string() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let mut listed = Vec::new();
+    let mut batches = 0;
+    loop {
+        match process(&mut session, SftpClientPacket::Readdir { id: 5, handle: handle.clone() }).await {
+            SftpServerPacket::Name { names, .. } => {
+                assert!(!names.is_empty());
+                listed.extend(names);
+                batches += 1;
+            },
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => break,
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+    assert!(batches > 1);
+    let mut filenames: Vec<_> = listed.iter().map(|name| name.filename.clone()).collect();
+    filenames.sort();
+    let mut expected = short.clone();
+    expected.push(long.clone());
+    assert_eq!(filenames, expected);
+    let long_entry = listed.iter().find(|name| name.filename == long).unwrap();
+    assert!(long_entry.longname.is_empty());
+    assert!(listed.iter().filter(|name| name.filename != long).all(|name| !name.longname.is_empty()));
+
+    let resp = process(&mut session, SftpClientPacket::Realpath { id: 6, path: format!("/dir/{}", too_long) }).await;
+    assert!(matches!(resp, SftpServerPacket::Status { id: 6, status_code: StatusCode::Failure, .. }));
+}
+
+#[tokio::test]
+async fn large_reads_are_shortened() {
+    let server = SftpServer::builder(MemFs::new()).max_packet_size(MAX_PACKET_SIZE).build();
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    create(&mut session, "/file".to_string(), &[7; 4000]).await;
+    let pflags = Pflags { read: true, write: false, append: false, creat: false, trunc: false, excl: false };
+    let handle = match process(&mut session, SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    match process(&mut session, SftpClientPacket::Read { id: 2, handle, offset: 0, len: 4000 }).await {
+        SftpServerPacket::Data { data, .. } => assert_eq!(data.0, vec![7; MAX_PACKET_SIZE as usize - 9]),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
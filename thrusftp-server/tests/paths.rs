@@ -0,0 +1,66 @@
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+/// A request of every kind that takes a path, with `path` in each position.
+fn requests(path: &str) -> Vec<SftpClientPacket> {
+    let path = path.to_string();
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: false, excl: false };
+    vec![
+        SftpClientPacket::Open { id: 1, filename: path.clone(), pflags, attrs: Attrs::default() },
+        SftpClientPacket::Opendir { id: 2, path: path.clone() },
+        SftpClientPacket::Stat { id: 3, path: path.clone() },
+        SftpClientPacket::Lstat { id: 4, path: path.clone() },
+        SftpClientPacket::Setstat { id: 5, path: path.clone(), attrs: Attrs::default() },
+        SftpClientPacket::Remove { id: 6, filename: path.clone() },
+        SftpClientPacket::Mkdir { id: 7, path: path.clone(), attrs: Attrs::default() },
+        SftpClientPacket::Rmdir { id: 8, path: path.clone() },
+        SftpClientPacket::Rename { id: 9, oldpath: path.clone(), newpath: "/new".to_string() },
+        SftpClientPacket::Rename { id: 10, oldpath: "/file".to_string(), newpath: path.clone() },
+        SftpClientPacket::Symlink { id: 11, targetpath: "/file".to_string(), linkpath: path.clone() },
+        SftpClientPacket::Symlink { id: 12, targetpath: path.clone(), linkpath: "/link".to_string() },
+        SftpClientPacket::Readlink { id: 13, path: path.clone() },
+        SftpClientPacket::Extended {
+            id: 14,
+            extended_request: ExtendedRequest::OpensshPosixRename { oldpath: path, newpath: "/new".to_string() },
+        },
+    ]
+}
+
+async fn check(path: &str, expected: StatusCode) {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    session.process(SftpClientPacket::Mkdir { id: 0, path: "/file".to_string(), attrs: Attrs::default() }).await;
+    for request in requests(path) {
+        let described = format!("{:?}", request);
+        match session.process(request).await {
+            SftpServerPacket::Status { status_code, .. } if status_code == expected => {},
+            resp => panic!("{} answered with {:?}", described, resp),
+        }
+    }
+    for name in &["/new", "/link"] {
+        let resp = session.process(SftpClientPacket::Lstat { id: 15, path: name.to_string() }).await;
+        assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::NoSuchFile, .. }));
+    }
+}
+
+#[tokio::test]
+async fn empty_paths() {
+    check("", StatusCode::NoSuchFile).await;
+}
+
+#[tokio::test]
+async fn paths_with_nul() {
+    check("/fi\0le", StatusCode::BadMessage).await;
+    check("\0", StatusCode::BadMessage).await;
+}
+
+#[tokio::test]
+async fn empty_realpath_is_the_working_directory() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    match session.process(SftpClientPacket::Realpath { id: 1, path: String::new() }).await {
+        SftpServerPacket::Name { names, .. } => assert_eq!(names[0].filename, "/"),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
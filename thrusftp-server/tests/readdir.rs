@@ -0,0 +1,92 @@
+mod common;
+
+use async_trait::async_trait;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpSession;
+use std::sync::Arc;
+use common::{Hooked, Hooks};
+
+/// Ends listings with an empty batch instead of `UnexpectedEof`, and keeps
+/// returning empty batches after.
+struct EmptyBatches;
+
+#[async_trait]
+impl Hooks<MemFs> for EmptyBatches {
+    async fn readdir(&self, fs: &MemFs, handle: &mut <MemFs as Fs>::DirHandle) -> Result<Vec<Name>> {
+        match fs.readdir(handle).await {
+            Err(err) if err.status_code() == StatusCode::Eof => Ok(vec![]),
+            res => res,
+        }
+    }
+}
+
+async fn opendir<T: Fs + Send + Sync>(session: &mut SftpSession<T>, path: &str) -> Handle {
+    match session.process(SftpClientPacket::Opendir { id: 1, path: path.to_string() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+/// Reads `handle` to the end, checking that every `Name` has entries and
+/// the listing ends with `Eof`. Returns the number of entries.
+async fn list<T: Fs + Send + Sync>(session: &mut SftpSession<T>, handle: &str) -> usize {
+    let mut count = 0;
+    loop {
+        match session.process(SftpClientPacket::Readdir { id: 2, handle: handle.to_string() }).await {
+            SftpServerPacket::Name { names, .. } => {
+                assert!(!names.is_empty(), "empty Name response");
+                count += names.len();
+            },
+            SftpServerPacket::Status { status_code: StatusCode::Eof, .. } => return count,
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+}
+
+#[tokio::test]
+async fn empty_batch_ends_the_listing() {
+    let fs = MemFs::default();
+    fs.mkdir("/empty".to_string(), Attrs::default()).await.unwrap();
+    fs.mkdir("/full".to_string(), Attrs::default()).await.unwrap();
+    fs.mkdir("/full/a".to_string(), Attrs::default()).await.unwrap();
+    let fs = Arc::new(Hooked(fs, EmptyBatches));
+    assert_eq!(fs.read_dir_all("/full".to_string()).await.unwrap().len(), 1);
+    assert!(fs.read_dir_all("/empty".to_string()).await.unwrap().is_empty());
+    let mut session = SftpSession::new(fs, Default::default());
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let handle = opendir(&mut session, "/empty").await;
+    assert_eq!(list(&mut session, &handle).await, 0);
+    let handle = opendir(&mut session, "/full").await;
+    assert_eq!(list(&mut session, &handle).await, 1);
+}
+
+#[tokio::test]
+async fn directories_emptied_while_listed_end_with_eof() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("empty")).unwrap();
+    std::fs::create_dir_all(dir.join("emptied")).unwrap();
+    for i in 0..500 {
+        std::fs::write(dir.join("emptied").join(format!("file{}", i)), b"").unwrap();
+    }
+    let mut session = SftpSession::new(Arc::new(LocalFs::new(dir)), Default::default());
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let handle = opendir(&mut session, "/empty").await;
+    assert_eq!(list(&mut session, &handle).await, 0);
+
+    let handle = opendir(&mut session, "/emptied").await;
+    match session.process(SftpClientPacket::Readdir { id: 2, handle: handle.clone() }).await {
+        SftpServerPacket::Name { names, .. } => assert!(!names.is_empty()),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    for i in 0..500 {
+        std::fs::remove_file(dir.join("emptied").join(format!("file{}", i))).unwrap();
+    }
+    // Whatever the kernel had already listed is dropped as it is stat'ed.
+    assert!(list(&mut session, &handle).await < 500);
+}
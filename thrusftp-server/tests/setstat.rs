@@ -0,0 +1,110 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::parse::deserialize_packet;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+/// `SSH_FXP_SETSTAT` for `path` as it is on the wire, with an attribute
+/// flags word of zero.
+fn setstat_without_attrs(id: u32, path: &str) -> SftpClientPacket {
+    let mut packet = vec![9];
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&(path.len() as u32).to_be_bytes());
+    packet.extend_from_slice(path.as_bytes());
+    packet.extend_from_slice(&0u32.to_be_bytes());
+    deserialize_packet(&packet).unwrap()
+}
+
+fn assert_ok(resp: SftpServerPacket) {
+    match resp {
+        SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. } => {},
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn setstat_without_attrs_changes_nothing() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("file");
+    std::fs::write(&path, b"content").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+    let filename = path.to_string_lossy().into_owned();
+    let mut session = SftpServer::new(LocalFs::default()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    // Set a known time first, so a stray update would show.
+    let attrs = Attrs { atime_mtime: Some((1_000_000_000, 1_000_000_000)), ..Default::default() };
+    assert_ok(session.process(SftpClientPacket::Setstat { id: 1, path: filename.clone(), attrs }).await);
+    assert_ok(session.process(setstat_without_attrs(2, &filename)).await);
+
+    let pflags = Pflags { read: true, write: true, append: false, creat: false, trunc: false, excl: false };
+    let handle = match session.process(SftpClientPacket::Open { id: 3, filename, pflags, attrs: Attrs::default() }).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    assert_ok(session.process(SftpClientPacket::Fsetstat { id: 4, handle: handle.clone(), attrs: Attrs::default() }).await);
+    assert_ok(session.process(SftpClientPacket::Close { id: 5, handle }).await);
+
+    // Only the times were set; the size and mode are as they were.
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"content");
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+    assert_eq!((metadata.atime(), metadata.mtime()), (1_000_000_000, 1_000_000_000));
+}
+
+#[tokio::test]
+async fn setstat_without_attrs_on_mem_fs() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let attrs = Attrs { permissions: Some(0o750), ..Default::default() };
+    assert_ok(session.process(SftpClientPacket::Mkdir { id: 1, path: "/dir".to_string(), attrs }).await);
+    let before = match session.process(SftpClientPacket::Stat { id: 2, path: "/dir".to_string() }).await {
+        SftpServerPacket::Attrs { attrs, .. } => attrs,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+
+    assert_ok(session.process(setstat_without_attrs(3, "/dir")).await);
+    match session.process(SftpClientPacket::Stat { id: 4, path: "/dir".to_string() }).await {
+        SftpServerPacket::Attrs { attrs, .. } => {
+            assert_eq!(attrs.permissions, before.permissions);
+            assert_eq!(attrs.atime_mtime, before.atime_mtime);
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+/// Writes `abcdef` to `/file`, then shrinks and grows it with `Fsetstat`,
+/// reading it back each time.
+async fn fsetstat_size<T: thrusftp_protocol::Fs + Send + Sync>(fs: T) {
+    let mut session = SftpServer::new(fs).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let pflags = Pflags { read: true, write: true, append: false, creat: true, trunc: true, excl: false };
+    let open = SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() };
+    let handle = match session.process(open).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let data = b"abcdef".to_vec().into();
+    assert_ok(session.process(SftpClientPacket::Write { id: 2, handle: handle.clone(), offset: 0, data }).await);
+
+    for (id, size, expected) in [(3, 3, &b"abc"[..]), (5, 8, &b"abc\0\0\0\0\0"[..])] {
+        let attrs = Attrs { size: Some(size), ..Default::default() };
+        assert_ok(session.process(SftpClientPacket::Fsetstat { id, handle: handle.clone(), attrs }).await);
+        match session.process(SftpClientPacket::Read { id: id + 1, handle: handle.clone(), offset: 0, len: 100 }).await {
+            SftpServerPacket::Data { data, .. } => assert_eq!(data.0, expected),
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+}
+
+#[tokio::test]
+async fn fsetstat_size_shrinks_and_grows() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    fsetstat_size(LocalFs::new(dir)).await;
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), b"abc\0\0\0\0\0");
+    fsetstat_size(MemFs::new()).await;
+}
@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, FsHandle, Result, SftpError};
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+/// Fails every request with the status code named by the path's file name.
+struct Statuses;
+
+fn status(path: &str) -> SftpError {
+    let status_code = match path.rsplit('/').next().unwrap() {
+        "eof" => StatusCode::Eof,
+        "exists" => StatusCode::FileAlreadyExists,
+        _ => StatusCode::NoConnection,
+    };
+    SftpError::Status(status_code, format!("status for {}", path))
+}
+
+#[async_trait]
+impl Fs for Statuses {
+    type FileHandle = ();
+    type DirHandle = ();
+
+    async fn open(&self, filename: String, _: Pflags, _: Attrs) -> Result<()> { Err(status(&filename)) }
+    async fn close(&self, _: FsHandle<(), ()>) -> Result<()> { Ok(()) }
+    async fn read(&self, _: &mut (), _: u64, _: u32) -> Result<Vec<u8>> { Err(status("")) }
+    async fn write(&self, _: &mut (), _: u64, _: Vec<u8>) -> Result<()> { Err(status("")) }
+    async fn lstat(&self, path: String) -> Result<Attrs> { Err(status(&path)) }
+    async fn fstat(&self, _: &mut ()) -> Result<Attrs> { Err(status("")) }
+    async fn setstat(&self, path: String, _: Attrs) -> Result<()> { Err(status(&path)) }
+    async fn fsetstat(&self, _: &mut (), _: Attrs) -> Result<()> { Err(status("")) }
+    async fn opendir(&self, path: String) -> Result<()> { Err(status(&path)) }
+    async fn readdir(&self, _: &mut ()) -> Result<Vec<Name>> { Err(status("")) }
+    async fn remove(&self, filename: String) -> Result<()> { Err(status(&filename)) }
+    async fn mkdir(&self, path: String, _: Attrs) -> Result<()> { Err(status(&path)) }
+    async fn rmdir(&self, path: String) -> Result<()> { Err(status(&path)) }
+    async fn realpath(&self, path: String) -> Result<String> { Ok(path) }
+    async fn stat(&self, path: String) -> Result<Attrs> { Err(status(&path)) }
+    async fn rename(&self, oldpath: String, _: String) -> Result<()> { Err(status(&oldpath)) }
+    async fn readlink(&self, path: String) -> Result<String> { Err(status(&path)) }
+    async fn symlink(&self, linkpath: String, _: String) -> Result<()> { Err(status(&linkpath)) }
+}
+
+#[tokio::test]
+async fn fs_chooses_status_code() {
+    let mut session = SftpServer::new(Statuses).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    let resp = session.process(SftpClientPacket::Stat { id: 1, path: "/eof".to_string() }).await;
+    match resp {
+        SftpServerPacket::Status { id, status_code, error_message, .. } => {
+            assert_eq!(id, 1);
+            assert_eq!(status_code, StatusCode::Eof);
+            assert_eq!(error_message, "status for /eof");
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    let resp = session.process(SftpClientPacket::Mkdir { id: 2, path: "/none".to_string(), attrs: Attrs::default() }).await;
+    assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::NoConnection, .. }));
+
+    // Version 3 has no code for this, the server speaks version 3.
+    let resp = session.process(SftpClientPacket::Remove { id: 3, filename: "/exists".to_string() }).await;
+    assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::Failure, .. }));
+}
+
+#[tokio::test]
+async fn posix_rename_names_the_conflict() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    session.process(SftpClientPacket::Open { id: 1, filename: "/file".to_string(), pflags, attrs: Attrs::default() }).await;
+    for (id, path) in [(2, "/dir"), (3, "/full"), (4, "/full/entry")] {
+        session.process(SftpClientPacket::Mkdir { id, path: path.to_string(), attrs: Attrs::default() }).await;
+    }
+
+    for (id, oldpath, newpath, message) in [
+        (5, "/dir", "/file", "Not a directory"),
+        (6, "/file", "/dir", "Is a directory"),
+        (7, "/dir", "/full", "Directory not empty"),
+    ] {
+        let extended_request = ExtendedRequest::OpensshPosixRename { oldpath: oldpath.to_string(), newpath: newpath.to_string() };
+        match session.process(SftpClientPacket::Extended { id, extended_request }).await {
+            // Version 3 has no codes for these, so only the message tells
+            // them apart.
+            SftpServerPacket::Status { status_code: StatusCode::Failure, error_message, .. } => {
+                assert!(error_message.starts_with(message), "{}", error_message);
+            },
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+}
+
+#[tokio::test]
+async fn long_error_messages_are_shortened() {
+    let mut session = SftpServer::new(Statuses).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    // Two bytes per character, so the cut falls in the middle of one
+    // unless it is made at a boundary.
+    let path = format!("/{}", "é".repeat(5000));
+    match session.process(SftpClientPacket::Stat { id: 1, path: path.clone() }).await {
+        SftpServerPacket::Status { status_code, error_message, language_tag, .. } => {
+            assert_eq!(status_code, StatusCode::NoConnection);
+            assert!(error_message.len() <= 1024, "{}", error_message.len());
+            assert!(error_message.len() > 1000, "{}", error_message.len());
+            assert!(error_message.starts_with("status for /é"));
+            assert!(error_message.ends_with("..."));
+            assert_eq!(language_tag, "en");
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    // Short ones are left alone.
+    match session.process(SftpClientPacket::Stat { id: 2, path: "/short".to_string() }).await {
+        SftpServerPacket::Status { error_message, .. } => assert_eq!(error_message, "status for /short"),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
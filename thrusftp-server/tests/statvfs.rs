@@ -0,0 +1,28 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::{Config, SftpServer};
+
+async fn statvfs_flags(server: &SftpServer<LocalFs>) -> u64 {
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let request = SftpClientPacket::Extended { id: 1, extended_request: ExtendedRequest::OpensshStatvfs { path: "/".to_string() } };
+    match session.process(request).await {
+        SftpServerPacket::ExtendedReply { data, .. } => match ExtendedReply::parse(&ExtendedRequestType::OpensshStatvfs, &data.0).unwrap() {
+            ExtendedReply::Statvfs(stats) => stats.f_flag,
+            reply => panic!("unexpected reply {:?}", reply),
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn read_only_servers_report_a_read_only_filesystem() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+
+    let writable = SftpServer::new(LocalFs::new(dir));
+    let read_only = SftpServer::with_config(LocalFs::new(dir), Config { read_only: true, ..Default::default() });
+    let flags = statvfs_flags(&writable).await;
+    assert_eq!(flags & SSH2_FXE_STATVFS_ST_RDONLY, 0);
+    assert_eq!(statvfs_flags(&read_only).await, flags | SSH2_FXE_STATVFS_ST_RDONLY);
+}
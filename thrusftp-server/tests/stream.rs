@@ -0,0 +1,104 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::codec::SftpCodec;
+use thrusftp_protocol::parse::Deserialize;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use thrusftp_server::stream::serve_stream;
+
+#[tokio::test]
+async fn serves_until_eof() {
+    let server = SftpServer::new(MemFs::new());
+    let (mut client, server_end) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_end);
+    let serving = tokio::spawn(async move { serve_stream(&server, reader, writer).await });
+
+    let mut input = SftpCodec::encode(&SftpClientPacket::Init { version: 3, extensions: vec![].into() }).unwrap();
+    input.extend(SftpCodec::encode(&SftpClientPacket::Mkdir { id: 1, path: "/dir".to_string(), attrs: Attrs::default() }).unwrap());
+    // A request type that does not exist.
+    input.extend_from_slice(&[0, 0, 0, 5, 250, 0, 0, 0, 2]);
+    client.write_all(&input).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    serving.await.unwrap().unwrap();
+
+    let mut codec = SftpCodec::new(256 * 1024);
+    let responses: Vec<_> = codec.decode(&output).unwrap().into_iter()
+        .map(|packet| SftpServerPacket::deserialize(&mut &packet[..]).unwrap())
+        .collect();
+    assert!(matches!(responses[0], SftpServerPacket::Version { version: 3, .. }));
+    assert!(matches!(responses[1], SftpServerPacket::Status { id: 1, status_code: StatusCode::r#Ok, .. }));
+    assert!(matches!(responses[2], SftpServerPacket::Status { id: 2, status_code: StatusCode::BadMessage, .. }));
+    assert_eq!(responses.len(), 3);
+}
+
+#[tokio::test]
+async fn refuses_oversized_requests_and_extra_clients() {
+    let server = SftpServer::builder(MemFs::new()).max_packet_size(1024).max_clients(Some(1)).build();
+    let session = server.new_session();
+    let (_client, server_end) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_end);
+    assert!(serve_stream(&server, reader, writer).await.is_err());
+    drop(session);
+
+    let (mut client, server_end) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_end);
+    client.write_all(&[0, 0, 4, 1]).await.unwrap();
+    assert!(serve_stream(&server, reader, writer).await.is_err());
+}
+
+#[tokio::test]
+async fn misaligned_init_is_a_bad_message() {
+    let server = SftpServer::new(MemFs::new());
+    let (mut client, server_end) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_end);
+    let serving = tokio::spawn(async move { serve_stream(&server, reader, writer).await });
+
+    // Version 3 and an extension name without its data.
+    let mut packet = vec![1, 0, 0, 0, 3, 0, 0, 0, 1, b'x'];
+    let mut input = (packet.len() as u32).to_be_bytes().to_vec();
+    input.append(&mut packet);
+    client.write_all(&input).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    serving.await.unwrap().unwrap();
+
+    let packets = SftpCodec::new(256 * 1024).decode(&output).unwrap();
+    assert_eq!(packets.len(), 1);
+    match SftpServerPacket::deserialize(&mut &packets[0][..]).unwrap() {
+        SftpServerPacket::Status { status_code: StatusCode::BadMessage, error_message, .. } => {
+            assert!(error_message.contains("not a complete element"), "{}", error_message);
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn write_with_a_wrong_length_is_a_bad_message() {
+    let server = SftpServer::new(MemFs::new());
+    let (mut client, server_end) = tokio::io::duplex(64 * 1024);
+    let (reader, writer) = tokio::io::split(server_end);
+    let serving = tokio::spawn(async move { serve_stream(&server, reader, writer).await });
+
+    // Id 7, handle "0", offset 0, and a data length of 100 for 3 bytes.
+    let mut packet = vec![6, 0, 0, 0, 7, 0, 0, 0, 1, b'0', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 1, 2, 3];
+    let mut input = (packet.len() as u32).to_be_bytes().to_vec();
+    input.append(&mut packet);
+    client.write_all(&input).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    serving.await.unwrap().unwrap();
+
+    let packets = SftpCodec::new(256 * 1024).decode(&output).unwrap();
+    assert_eq!(packets.len(), 1);
+    match SftpServerPacket::deserialize(&mut &packets[0][..]).unwrap() {
+        SftpServerPacket::Status { id: 7, status_code: StatusCode::BadMessage, .. } => {},
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
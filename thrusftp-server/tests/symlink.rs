@@ -0,0 +1,70 @@
+mod common;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::{Operation, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::{SftpServer, SftpSession};
+use common::{Hooked, Hooks};
+
+/// Refuses every path outside of `.0`.
+struct Jail(String);
+
+#[async_trait]
+impl Hooks<LocalFs> for Jail {
+    async fn authorize(&self, _fs: &LocalFs, _op: Operation, path: &str) -> Result<()> {
+        if path != self.0 && !path.starts_with(&format!("{}/", self.0)) {
+            return Err(anyhow!("outside of the jail").into());
+        }
+        Ok(())
+    }
+}
+
+async fn symlink(session: &mut SftpSession<Hooked<LocalFs, Jail>>, linkpath: &str, targetpath: &str) -> StatusCode {
+    let linkpath = linkpath.to_string();
+    let targetpath = targetpath.to_string();
+    match session.process(SftpClientPacket::Symlink { id: 1, linkpath, targetpath }).await {
+        SftpServerPacket::Status { status_code, .. } => status_code,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+async fn readlink(session: &mut SftpSession<Hooked<LocalFs, Jail>>, path: &str) -> String {
+    match session.process(SftpClientPacket::Readlink { id: 2, path: path.to_string() }).await {
+        SftpServerPacket::Name { mut names, .. } => names.remove(0).filename,
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn targets_inside_and_outside_the_jail() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("jail/dir")).unwrap();
+    std::fs::write(dir.join("jail/dir/file"), b"inside").unwrap();
+    let root = dir.join("jail").to_string_lossy().into_owned();
+    let path = |name: &str| format!("{}/{}", root, name);
+
+    let jail = Hooked(LocalFs::default(), Jail(root.clone()));
+    let mut session = SftpServer::new(jail).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    // Relative targets are checked where they lead from the link's
+    // directory, and stored as they are.
+    assert_eq!(symlink(&mut session, &path("dir/relative"), "file").await, StatusCode::r#Ok);
+    assert_eq!(readlink(&mut session, &path("dir/relative")).await, "file");
+    assert_eq!(std::fs::read(dir.join("jail/dir/relative")).unwrap(), b"inside");
+    assert_eq!(symlink(&mut session, &path("dir/up"), "../dir/./file").await, StatusCode::r#Ok);
+    assert_eq!(readlink(&mut session, &path("dir/up")).await, "../dir/./file");
+    assert_eq!(symlink(&mut session, &path("escaping"), "../../outside").await, StatusCode::PermissionDenied);
+    assert_eq!(symlink(&mut session, &path("dir/escaping"), "./../../jail/../x").await, StatusCode::PermissionDenied);
+    assert!(std::fs::symlink_metadata(dir.join("jail/escaping")).is_err());
+
+    // Absolute targets are checked like any other path.
+    let target = path("dir/file");
+    assert_eq!(symlink(&mut session, &path("absolute"), &target).await, StatusCode::r#Ok);
+    assert_eq!(readlink(&mut session, &path("absolute")).await, target);
+    assert_eq!(symlink(&mut session, &path("passwd"), "/etc/passwd").await, StatusCode::PermissionDenied);
+    assert!(std::fs::symlink_metadata(dir.join("jail/passwd")).is_err());
+}
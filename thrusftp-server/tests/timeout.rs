@@ -0,0 +1,46 @@
+mod common;
+
+use std::time::Duration;
+use async_trait::async_trait;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::{Fs, Result};
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+use common::{Hooked, Hooks};
+
+/// `stat` of `/stalled` never returns, like on a hung network filesystem.
+struct Stalling;
+
+#[async_trait]
+impl Hooks<MemFs> for Stalling {
+    async fn stat(&self, fs: &MemFs, path: String) -> Result<Attrs> {
+        if path == "/stalled" {
+            std::future::pending::<()>().await;
+        }
+        fs.stat(path).await
+    }
+}
+
+fn stat(id: u32, path: &str) -> SftpClientPacket {
+    SftpClientPacket::Stat { id, path: path.to_string() }
+}
+
+#[tokio::test]
+async fn stalled_requests_time_out() {
+    let server = SftpServer::builder(Hooked(MemFs::default(), Stalling))
+        .request_timeout(Some(Duration::from_millis(100)))
+        .build();
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+
+    match session.process(stat(1, "/stalled")).await {
+        SftpServerPacket::Status { id, status_code, error_message, .. } => {
+            assert_eq!(id, 1);
+            assert_eq!(status_code, StatusCode::Failure);
+            assert_eq!(error_message, "Request timed out");
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    // The session is still usable.
+    assert!(matches!(session.process(stat(2, "/")).await, SftpServerPacket::Attrs { id: 2, .. }));
+}
@@ -0,0 +1,32 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+fn attr_types(resp: SftpServerPacket) -> Vec<String> {
+    match resp {
+        SftpServerPacket::Attrs { attrs, .. } => attrs.extended_attrs.into_iter().map(|attr| attr.r#type).collect(),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+}
+
+#[tokio::test]
+async fn nanosecond_times_only_for_clients_asking() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::write(dir.join("file"), b"").unwrap();
+    let server = SftpServer::new(LocalFs::new(dir));
+    let stat = SftpClientPacket::Stat { id: 1, path: "/file".to_string() };
+
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let types = attr_types(session.process(stat.clone()).await);
+    assert!(types.iter().any(|r#type| r#type == INO_EXTENDED_ATTR));
+    assert!(!types.iter().any(|r#type| r#type == ATIME_EXTENDED_ATTR || r#type == MTIME_EXTENDED_ATTR));
+
+    let mut session = server.new_session();
+    let extensions = vec![Extension { name: "utimens@thrusftp".to_string(), data: "1".to_string() }];
+    session.process(SftpClientPacket::Init { version: 3, extensions: extensions.into() }).await;
+    let types = attr_types(session.process(stat).await);
+    assert!(types.iter().any(|r#type| r#type == ATIME_EXTENDED_ATTR));
+    assert!(types.iter().any(|r#type| r#type == MTIME_EXTENDED_ATTR));
+}
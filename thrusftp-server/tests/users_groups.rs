@@ -0,0 +1,41 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_fs_mem::MemFs;
+use thrusftp_protocol::parse::Deserialize;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+fn request(uids: Vec<u32>, gids: Vec<u32>) -> SftpClientPacket {
+    SftpClientPacket::Extended {
+        id: 1,
+        extended_request: ExtendedRequest::OpensshUsersGroupsById { uids: IdList(uids), gids: IdList(gids) },
+    }
+}
+
+#[tokio::test]
+async fn resolves_names() {
+    let mut session = SftpServer::new(LocalFs::default()).new_session();
+    match session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await {
+        SftpServerPacket::Version { extensions, .. } => {
+            assert!(extensions.0.iter().any(|ext| ext.name == "users-groups-by-id@openssh.com"));
+        },
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    let unknown = 4_000_000_000;
+    let reply = match session.process(request(vec![0, unknown], vec![unknown, 0])).await {
+        SftpServerPacket::ExtendedReply { data, .. } => UsersGroupsByIdReply::deserialize(&mut &data.0[..]).unwrap(),
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    assert_eq!(reply.usernames.0, vec!["root".to_string(), String::new()]);
+    assert_eq!(reply.groupnames.0.len(), 2);
+    assert_eq!(reply.groupnames.0[0], "");
+    assert!(!reply.groupnames.0[1].is_empty());
+}
+
+#[tokio::test]
+async fn unsupported_without_fs_support() {
+    let mut session = SftpServer::new(MemFs::new()).new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let resp = session.process(request(vec![0], vec![])).await;
+    assert!(matches!(resp, SftpServerPacket::Status { status_code: StatusCode::OpUnsupported, .. }));
+}
@@ -0,0 +1,25 @@
+use thrusftp_fs_local::LocalFs;
+use thrusftp_protocol::types::*;
+use thrusftp_server::SftpServer;
+
+#[tokio::test]
+async fn buffered_writes_survive_a_dropped_session() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let server = SftpServer::new(LocalFs::new(dir).write_buffer(64 * 1024));
+    let mut session = server.new_session();
+    session.process(SftpClientPacket::Init { version: 3, extensions: vec![].into() }).await;
+    let pflags = Pflags { read: false, write: true, append: false, creat: true, trunc: false, excl: false };
+    let open = SftpClientPacket::Open { id: 1, filename: "/upload".to_string(), pflags, attrs: Attrs::default() };
+    let handle = match session.process(open).await {
+        SftpServerPacket::Handle { handle, .. } => handle,
+        resp => panic!("unexpected response {:?}", resp),
+    };
+    let write = SftpClientPacket::Write { id: 2, handle, offset: 0, data: b"uploaded".to_vec().into() };
+    assert!(matches!(session.process(write).await, SftpServerPacket::Status { status_code: StatusCode::r#Ok, .. }));
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"");
+
+    // The session goes away without `Close`.
+    drop(session);
+    assert_eq!(std::fs::read(dir.join("upload")).unwrap(), b"uploaded");
+}
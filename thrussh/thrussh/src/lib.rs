@@ -589,6 +589,15 @@ pub(crate) struct Channel {
     pub confirmed: bool,
     wants_reply: bool,
     pending_data: std::collections::VecDeque<(CryptoVec, Option<u32>, usize)>,
+    /// `CHANNEL_EOF` and `CHANNEL_CLOSE` messages sent while data was still
+    /// waiting for the window, to be sent once it is gone.
+    pending_after_data: Vec<u8>,
+    /// Whether the window is only re-opened for data the handler reports
+    /// as consumed, see `server::Session::set_manual_window`.
+    manual_window: bool,
+    /// Data consumed on a manual window channel that the other side's
+    /// window was not re-opened for yet.
+    consumed: u32,
 }
 
 #[derive(Debug)]
@@ -617,7 +626,15 @@ pub enum ChannelMsg {
     WindowAdjusted {
         new_size: u32,
     },
+    /// The handler is done with `len` bytes of data received on a channel
+    /// with a manual window, which may be re-opened by that much.
+    WindowConsumed {
+        len: u32,
+    },
     Success,
+    /// A keepalive global request, sent by servers; the channel it is sent
+    /// with is ignored.
+    Keepalive,
 }
 
 #[cfg(test)]
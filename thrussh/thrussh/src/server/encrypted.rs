@@ -324,14 +324,6 @@ impl Encrypted {
 
                 if is_real != 0 {
                     let pos0 = r.position;
-                    let sent_pk_ok = if let Some(CurrentRequest::PublicKey { sent_pk_ok, .. }) =
-                        auth_request.current
-                    {
-                        sent_pk_ok
-                    } else {
-                        false
-                    };
-
                     let signature = r.read_string().map_err(crate::Error::from)?;
                     debug!("signature = {:?}", signature);
                     let mut s = signature.reader(0);
@@ -341,18 +333,16 @@ impl Encrypted {
                     let sig = s.read_string().map_err(crate::Error::from)?;
                     let init = &buf[0..pos0];
 
-                    let is_valid = if sent_pk_ok && user == auth_user {
-                        true
-                    } else if auth_user.len() == 0 {
-                        auth_user.clear();
-                        auth_user.push_str(user);
-                        let h = handler.take().unwrap();
-                        let (h, auth) = h.auth_publickey(user, &pubkey).await?;
-                        *handler = Some(h);
-                        auth == Auth::Accept
-                    } else {
-                        false
-                    };
+                    // The handler is asked again even if it accepted this
+                    // key in a probe: the signed request may carry another
+                    // key than the probe did, and handlers that keep state
+                    // per key must see the one that is actually verified.
+                    auth_user.clear();
+                    auth_user.push_str(user);
+                    let h = handler.take().unwrap();
+                    let (h, auth) = h.auth_publickey(user, &pubkey).await?;
+                    *handler = Some(h);
+                    let is_valid = auth == Auth::Accept;
                     if is_valid {
                         let session_id = self.session_id.as_ref();
                         if SIGNATURE_BUFFER.with(|buf| {
@@ -554,7 +544,15 @@ impl Session {
 
                 let mut h = handler.take().unwrap();
                 if let Some(ref mut enc) = self.common.encrypted {
-                    if enc.adjust_window_size(channel_num, data, target) {
+                    if enc.has_manual_window(channel_num) {
+                        // Data beyond the window is ignored, see RFC 4254,
+                        // section 5.2. Here, the handler does not see it.
+                        if !enc.receive_data(channel_num, data.len()) {
+                            debug!("data beyond the window of {:?}", channel_num);
+                            *handler = Some(h);
+                            return Ok(self);
+                        }
+                    } else if enc.adjust_window_size(channel_num, data, target) {
                         let window = h.adjust_window(channel_num, self.target_window_size);
                         if window > 0 {
                             self.target_window_size = window
@@ -838,6 +836,9 @@ impl Session {
             confirmed: true,
             wants_reply: false,
             pending_data: std::collections::VecDeque::new(),
+            pending_after_data: Vec::new(),
+            manual_window: false,
+            consumed: 0,
         };
         match typ {
             b"session" => {
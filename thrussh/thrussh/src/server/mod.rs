@@ -153,7 +153,9 @@ pub trait Handler: Send + Sized {
 
     /// Check authentication using the "publickey" method. This method
     /// should just check whether the public key matches the
-    /// authorized ones. Thrussh then checks the signature. If the key
+    /// authorized ones. Thrussh then checks the signature. It is called
+    /// both for a query without a signature and for the signed request,
+    /// with the key each carries, which need not be the same. If the key
     /// is unknown, or the signature is invalid, Thrussh guarantees
     /// that rejection happens in constant time
     /// `config.auth_rejection_time`, except if this method takes more
@@ -541,9 +543,15 @@ where
                     Some((id, ChannelMsg::WindowAdjusted { new_size })) => {
                         debug!("window adjusted to {:?} for channel {:?}", new_size, id);
                     }
+                    Some((id, ChannelMsg::WindowConsumed { len })) => {
+                        session.window_consumed(id, len);
+                    }
                     Some((id, ChannelMsg::Success)) => {
                         debug!("channel success {:?}", id);
                     }
+                    Some((_, ChannelMsg::Keepalive)) => {
+                        session.keepalive_request();
+                    }
                     None => {
                         debug!("session.receiver: received None");
                     }
@@ -105,6 +105,24 @@ impl Handle {
             .await
             .map_err(|_| ())
     }
+
+    /// Report `len` bytes of data received on a channel with a manual
+    /// window as consumed, see `Session::set_manual_window`.
+    pub async fn window_consumed(&mut self, id: ChannelId, len: u32) -> Result<(), ()> {
+        self.sender
+            .send((id, ChannelMsg::WindowConsumed { len }))
+            .await
+            .map_err(|_| ())
+    }
+
+    /// Send a keepalive global request, to check that the client is still
+    /// there and keep idle connections open. `id` is ignored.
+    pub async fn keepalive(&mut self, id: ChannelId) -> Result<(), ()> {
+        self.sender
+            .send((id, ChannelMsg::Keepalive))
+            .await
+            .map_err(|_| ())
+    }
 }
 
 impl Session {
@@ -191,6 +209,27 @@ impl Session {
         }
     }
 
+    /// Stops re-opening the window of `channel` as data arrives on it.
+    /// Instead, it is re-opened for the data the handler reports as
+    /// consumed with `window_consumed` or `Handle::window_consumed`, so
+    /// that a handler that buffers data until it is ready for it never
+    /// holds more than the window, however slowly it consumes it. Data the
+    /// other side sends beyond the window is ignored.
+    pub fn set_manual_window(&mut self, channel: ChannelId) {
+        if let Some(ref mut enc) = self.common.encrypted {
+            enc.set_manual_window(channel)
+        }
+    }
+
+    /// Report `len` bytes of data received on a channel with a manual
+    /// window as consumed.
+    pub fn window_consumed(&mut self, channel: ChannelId, len: u32) {
+        let target = self.target_window_size;
+        if let Some(ref mut enc) = self.common.encrypted {
+            enc.window_consumed(channel, len, target)
+        }
+    }
+
     pub fn has_pending_data(&self, channel: ChannelId) -> bool {
         if let Some(ref enc) = self.common.encrypted {
             enc.has_pending_data(channel)
@@ -222,6 +261,18 @@ impl Session {
         }
     }
 
+    /// Send a `keepalive@openssh.com` global request. Clients answer it,
+    /// usually with a failure, which is ignored.
+    pub fn keepalive_request(&mut self) {
+        if let Some(ref mut enc) = self.common.encrypted {
+            push_packet!(enc.write, {
+                enc.write.push(msg::GLOBAL_REQUEST);
+                enc.write.extend_ssh_string(b"keepalive@openssh.com");
+                enc.write.push(1);
+            });
+        }
+    }
+
     /// Send a "failure" reply to a global request.
     pub fn request_failure(&mut self) {
         if let Some(ref mut enc) = self.common.encrypted {
@@ -125,7 +125,12 @@ impl<C> CommonSession<C> {
 
 impl Encrypted {
     pub fn byte(&mut self, channel: ChannelId, msg: u8) {
-        if let Some(channel) = self.channels.get(&channel) {
+        if let Some(channel) = self.channels.get_mut(&channel) {
+            // EOF and close must not overtake the data before them.
+            if (msg == msg::CHANNEL_EOF || msg == msg::CHANNEL_CLOSE) && !channel.pending_data.is_empty() {
+                channel.pending_after_data.push(msg);
+                return;
+            }
             push_packet!(self.write, {
                 self.write.push(msg);
                 self.write.push_u32_be(channel.recipient_channel);
@@ -152,6 +157,55 @@ impl Encrypted {
         }
     }
 
+    pub fn set_manual_window(&mut self, channel: ChannelId) {
+        if let Some(ref mut channel) = self.channels.get_mut(&channel) {
+            channel.manual_window = true;
+        }
+    }
+
+    pub fn has_manual_window(&self, channel: ChannelId) -> bool {
+        if let Some(ref channel) = self.channels.get(&channel) {
+            channel.manual_window
+        } else {
+            false
+        }
+    }
+
+    /// Takes `len` bytes of data received on a manual window channel off
+    /// its window. Returns `false` if they do not fit, in which case the
+    /// data is to be ignored.
+    pub fn receive_data(&mut self, channel: ChannelId, len: usize) -> bool {
+        if let Some(ref mut channel) = self.channels.get_mut(&channel) {
+            if len as u64 <= channel.sender_window_size as u64 {
+                channel.sender_window_size -= len as u32;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Re-opens the window of a manual window channel by `len` bytes the
+    /// handler is done with. Adjustments are only sent once they amount to
+    /// half of `target`, like for other channels.
+    pub fn window_consumed(&mut self, channel: ChannelId, len: u32, target: u32) {
+        if let Some(ref mut channel) = self.channels.get_mut(&channel) {
+            channel.consumed = channel.consumed.saturating_add(len);
+            if channel.consumed >= target / 2 {
+                debug!(
+                    "sender_window_size {:?}, consumed {:?}",
+                    channel.sender_window_size, channel.consumed
+                );
+                push_packet!(self.write, {
+                    self.write.push(msg::CHANNEL_WINDOW_ADJUST);
+                    self.write.push_u32_be(channel.recipient_channel);
+                    self.write.push_u32_be(channel.consumed);
+                });
+                channel.sender_window_size = channel.sender_window_size.saturating_add(channel.consumed);
+                channel.consumed = 0;
+            }
+        }
+    }
+
     pub fn adjust_window_size(&mut self, channel: ChannelId, data: &[u8], target: u32) -> bool {
         debug!("adjust_window_size");
         if let Some(ref mut channel) = self.channels.get_mut(&channel) {
@@ -189,10 +243,24 @@ impl Encrypted {
                     break;
                 }
             }
+            Self::flush_after_data(&mut self.write, channel);
         }
         pending_size
     }
 
+    /// Sends the messages held back by `byte` once no data is pending.
+    fn flush_after_data(write: &mut CryptoVec, channel: &mut Channel) {
+        if !channel.pending_data.is_empty() {
+            return;
+        }
+        for msg in channel.pending_after_data.drain(..) {
+            push_packet!(write, {
+                write.push(msg);
+                write.push_u32_be(channel.recipient_channel);
+            });
+        }
+    }
+
     pub fn flush_all_pending(&mut self) {
         for (_, channel) in self.channels.iter_mut() {
             while let Some((buf, a, from)) = channel.pending_data.pop_front() {
@@ -202,6 +270,7 @@ impl Encrypted {
                     break;
                 }
             }
+            Self::flush_after_data(&mut self.write, channel);
         }
     }
 
@@ -355,6 +424,9 @@ impl Encrypted {
                     confirmed: false,
                     wants_reply: false,
                     pending_data: std::collections::VecDeque::new(),
+                    pending_after_data: Vec::new(),
+                    manual_window: false,
+                    consumed: 0,
                 });
                 return ChannelId(self.last_channel_id.0);
             }